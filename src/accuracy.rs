@@ -0,0 +1,142 @@
+use cpu::Cpu;
+use hardware::{Apu, Hardware, Joypad, Ppu, Rom, Wram};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+
+struct TestCase {
+    name: String,
+    frames: u64,
+    hash: u64,
+    rom_path: PathBuf
+}
+
+// Reads a manifest of "NAME FRAMES HASH ROM" lines, where HASH is the
+// expected frame buffer hash (as printed by --pipe-input) after FRAMES frames.
+// ROM paths are relative to the manifest. Blank lines and anything after a
+// '#' are ignored.
+fn load_manifest(path: &Path) -> Vec<TestCase> {
+    let file = File::open(path).unwrap();
+    let base_dir = path.parent().unwrap_or(Path::new("."));
+    let mut test_cases = Vec::new();
+
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.unwrap();
+        let line = line.splitn(2, '#').next().unwrap().trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        let parts: Vec<&str> = line.splitn(4, char::is_whitespace).collect();
+
+        let test_case = if parts.len() == 4 {
+            parts[1].parse::<u64>().ok().and_then(|frames| {
+                u64::from_str_radix(parts[2], 16).ok().map(|hash| TestCase {
+                    name: parts[0].to_string(),
+                    frames: frames,
+                    hash: hash,
+                    rom_path: base_dir.join(parts[3].trim())
+                })
+            })
+        } else {
+            None
+        };
+
+        match test_case {
+            Some(test_case) => test_cases.push(test_case),
+            None => panic!("Invalid test case on line {}: {}", index + 1, line)
+        }
+    }
+
+    test_cases
+}
+
+// Baselines are "NAME pass" or "NAME fail" lines
+fn load_baseline(path: &Path) -> Option<HashMap<String, bool>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => return None
+    };
+
+    let mut baseline = HashMap::new();
+
+    for line in BufReader::new(file).lines() {
+        let line = line.unwrap();
+        let mut parts = line.split_whitespace();
+
+        if let (Some(name), Some(result)) = (parts.next(), parts.next()) {
+            baseline.insert(name.to_string(), result == "pass");
+        }
+    }
+
+    Some(baseline)
+}
+
+// Returns the hash of the last frame, or None if the emulator crashed
+fn run_test_case(test_case: &TestCase) -> Option<u64> {
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let rom = Rom::new(&test_case.rom_path);
+        let hardware = Hardware::new(rom, Wram::new(), Ppu::new(), Apu::new(), Joypad::new());
+        let mut cpu = Cpu::new(hardware);
+
+        while cpu.hardware().frame_count() < test_case.frames {
+            cpu.run_frame();
+        }
+
+        cpu.hardware().frame_buffer().hash()
+    }));
+
+    result.ok()
+}
+
+// Runs every test case in the manifest headlessly and prints a scoreboard,
+// compared against the results stored in a baseline file next to the
+// manifest. The baseline is written if there isn't one yet; delete it to
+// take a new one.
+pub fn run(manifest_path: &Path) {
+    let test_cases = load_manifest(manifest_path);
+    let baseline_path = manifest_path.with_extension("baseline");
+    let baseline = load_baseline(&baseline_path);
+
+    let mut results = Vec::new();
+
+    for test_case in test_cases.iter() {
+        let (passed, detail) = match run_test_case(test_case) {
+            Some(hash) if hash == test_case.hash => (true, String::new()),
+            Some(hash) => (false, format!("got {:016x}", hash)),
+            None => (false, "crashed".to_string())
+        };
+
+        let change = match baseline.as_ref().and_then(|baseline| baseline.get(&test_case.name)) {
+            Some(&was_passing) if was_passing && !passed => "REGRESSED",
+            Some(&was_passing) if !was_passing && passed => "fixed",
+            Some(_) => "",
+            None => "new"
+        };
+
+        info!("{:<32} {:<4} {:<9} {}", test_case.name, if passed { "pass" } else { "FAIL" }, change, detail);
+
+        results.push((&test_case.name, passed));
+    }
+
+    let pass_count = results.iter().filter(|&&(_, passed)| passed).count();
+
+    match baseline {
+        Some(ref baseline) => {
+            let baseline_count = baseline.values().filter(|&&passed| passed).count();
+            info!("Passed {}/{} (baseline: {}/{})", pass_count, results.len(), baseline_count, baseline.len());
+        },
+        None => {
+            let mut file = File::create(&baseline_path).unwrap();
+
+            for &(name, passed) in results.iter() {
+                writeln!(file, "{} {}", name, if passed { "pass" } else { "fail" }).unwrap();
+            }
+
+            info!("Passed {}/{} (baseline written to {})", pass_count, results.len(), baseline_path.display());
+        }
+    }
+}
@@ -1,17 +1,23 @@
 use cpu::memory_mode::MemoryMode;
 use cpu::value::Value;
 use cpu::Cpu;
-use hardware::HardwareAddress;
+use hardware::{Bus, HardwareAddress};
 use std::fmt::{self, Display, Formatter};
 use std::marker::PhantomData;
 use std::mem;
 
 pub trait Read<T: Value> : Display {
-    fn get(&self, cpu: &mut Cpu) -> T;
+    fn get<H: Bus>(&self, cpu: &mut Cpu<H>) -> T;
 }
 
 pub trait Write<T: Value> : Read<T> {
-    fn set(&self, cpu: &mut Cpu, value: T);
+    fn set<H: Bus>(&self, cpu: &mut Cpu<H>, value: T);
+
+    // The internal cycle of a read-modify-write instruction, between reading
+    // the old value and writing the new one
+    fn modify_cycle<H: Bus>(&self, cpu: &mut Cpu<H>, _old_value: T) {
+        cpu.io_cycle();
+    }
 }
 
 pub trait Address {
@@ -48,14 +54,24 @@ impl<T: Value, M: MemoryMode> Display for MemoryAccessor<T, M> {
 }
 
 impl<T: Value, M: MemoryMode> Read<T> for MemoryAccessor<T, M> {
-    fn get(&self, cpu: &mut Cpu) -> T {
-        cpu.hardware_mut().read::<T>(self.resolved_address)
+    fn get<H: Bus>(&self, cpu: &mut Cpu<H>) -> T {
+        cpu.hardware_mut().read_with_wrapping::<T>(self.resolved_address, M::wrapping())
     }
 }
 
 impl<T: Value, M: MemoryMode> Write<T> for MemoryAccessor<T, M> {
-    fn set(&self, cpu: &mut Cpu, value: T) {
-        cpu.hardware_mut().write::<T>(self.resolved_address, value)
+    fn set<H: Bus>(&self, cpu: &mut Cpu<H>, value: T) {
+        cpu.hardware_mut().write_with_wrapping::<T>(self.resolved_address, value, M::wrapping())
+    }
+
+    // In emulation mode, the old value is written back to memory during the
+    // modify cycle, as it would be on a 6502
+    fn modify_cycle<H: Bus>(&self, cpu: &mut Cpu<H>, old_value: T) {
+        if cpu.flags().emulation_mode {
+            self.set(cpu, old_value);
+        } else {
+            cpu.io_cycle();
+        }
     }
 }
 
@@ -88,7 +104,7 @@ impl<T: Value> Display for ImmediateAccessor<T> {
 }
 
 impl<T: Value> Read<T> for ImmediateAccessor<T> {
-    fn get(&self, _cpu: &mut Cpu) -> T {
+    fn get<H: Bus>(&self, _cpu: &mut Cpu<H>) -> T {
         self.value
     }
 }
@@ -2,12 +2,19 @@ use cpu::accessor::{ImmediateAccessor, MemoryAccessor, Read};
 use cpu::memory_mode::*;
 use cpu::value::Value;
 use cpu::Cpu;
+use hardware::Bus;
 use std::marker::PhantomData;
 
 pub trait AddressMode<T: Value> {
     type Output : Read<T>;
 
-    fn resolve(self, cpu: &mut Cpu) -> Self::Output;
+    fn resolve<H: Bus>(self, cpu: &mut Cpu<H>) -> Self::Output;
+
+    // Writes and read-modify-writes can't skip the indexing cycle, as the
+    // high byte of the address has to be fixed before anything is written
+    fn resolve_for_write<H: Bus>(self, cpu: &mut Cpu<H>) -> Self::Output where Self: Sized {
+        self.resolve(cpu)
+    }
 }
 
 #[derive(Default)]
@@ -44,7 +51,7 @@ pub type MemoryStackRelativeIndirectIndexedY<T> = Memory<T, StackRelativeIndirec
 impl<T: Value> AddressMode<T> for Immediate<T> {
     type Output = ImmediateAccessor<T>;
 
-    fn resolve(self, cpu: &mut Cpu) -> ImmediateAccessor<T> {
+    fn resolve<H: Bus>(self, cpu: &mut Cpu<H>) -> ImmediateAccessor<T> {
         ImmediateAccessor::new(cpu.read_next::<T>())
     }
 }
@@ -52,8 +59,13 @@ impl<T: Value> AddressMode<T> for Immediate<T> {
 impl<T: Value, M: MemoryMode> AddressMode<T> for Memory<T, M> {
     type Output = MemoryAccessor<T, M>;
 
-    fn resolve(self, cpu: &mut Cpu) -> MemoryAccessor<T, M> {
+    fn resolve<H: Bus>(self, cpu: &mut Cpu<H>) -> MemoryAccessor<T, M> {
         let (resolved_address, immediate_address) = M::resolve(cpu);
         MemoryAccessor::new(resolved_address, immediate_address)
     }
+
+    fn resolve_for_write<H: Bus>(self, cpu: &mut Cpu<H>) -> MemoryAccessor<T, M> {
+        let (resolved_address, immediate_address) = M::resolve_for_write(cpu);
+        MemoryAccessor::new(resolved_address, immediate_address)
+    }
 }
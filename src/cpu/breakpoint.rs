@@ -0,0 +1,52 @@
+use hardware::HardwareAddress;
+
+// Program counter breakpoints. A hit stops the CPU before the instruction
+// executes; the next check at the same address lets it through, so that
+// execution can resume from where it stopped.
+pub struct Breakpoints {
+    addresses: Vec<HardwareAddress>,
+    resume_address: Option<HardwareAddress>
+}
+
+impl Breakpoints {
+    pub fn new() -> Breakpoints {
+        Breakpoints {
+            addresses: Vec::new(),
+            resume_address: None
+        }
+    }
+
+    pub fn set(&mut self, address: HardwareAddress) {
+        if !self.addresses.contains(&address) {
+            self.addresses.push(address);
+        }
+    }
+
+    // Returns false if there was no breakpoint at the address
+    pub fn clear(&mut self, address: HardwareAddress) -> bool {
+        let count = self.addresses.len();
+        self.addresses.retain(|&existing| existing != address);
+        self.addresses.len() != count
+    }
+
+    pub fn list(&self) -> &[HardwareAddress] {
+        &self.addresses
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+    }
+
+    pub fn check(&mut self, address: HardwareAddress) -> bool {
+        if self.resume_address == Some(address) {
+            self.resume_address = None;
+            false
+        } else if self.addresses.contains(&address) {
+            self.resume_address = Some(address);
+            true
+        } else {
+            self.resume_address = None;
+            false
+        }
+    }
+}
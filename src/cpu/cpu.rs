@@ -1,23 +1,67 @@
 use cpu::accessor::*;
 use cpu::address_mode::*;
+use cpu::breakpoint::Breakpoints;
+use cpu::cycle_audit::CycleAudit;
+use cpu::disassembler;
+use cpu::EmulationError;
+use cpu::history::{History, HistoryEntry};
+use cpu::hotspots::Hotspots;
 use cpu::interrupt::*;
 use cpu::register::*;
+use cpu::run_condition::RunCondition;
+use cpu::state::CpuState;
+use cpu::trace_trigger::TraceTrigger;
+use cpu::tracer::Tracer;
 use cpu::value::Value;
-use hardware::{Hardware, HardwareAddress, MemoryAccess};
+use cpu::watch::Watch;
+use hardware::{Bus, Hardware, HardwareAddress, MemoryAccess};
+use std::collections::HashMap;
 use std::fmt::{self, Display, Formatter};
 use std::mem;
 use util::byte_access::ByteAccess;
+use util::json::JsonObject;
 
 const IO_CYCLES: u64 = 6;
 
 const RESET_VECTOR: u16 = 0xFFFC;
 
-pub struct Cpu {
-    hardware: Hardware,
+// Backward branches this short are assumed to be polling loops (e.g. waiting
+// for an NMI handler to set a flag) rather than useful work
+const SPIN_LOOP_BYTES: i8 = 8;
+
+const JSR_ABSOLUTE: u8 = 0x20;
+const JSL: u8 = 0x22;
+const JSR_INDEXED_INDIRECT: u8 = 0xFC;
+const RTI: u8 = 0x40;
+const RTS: u8 = 0x60;
+const RTL: u8 = 0x6B;
+
+type OpHandler<H> = fn(&mut Cpu<H>);
+
+pub struct Cpu<H: Bus = Hardware> {
+    hardware: H,
     regs: CpuRegisters,
-    flags: CpuFlags
+    flags: CpuFlags,
+    watches: Vec<Watch>,
+    trace_trigger: Option<TraceTrigger>,
+    tracer: Option<Tracer>,
+    breakpoints: Breakpoints,
+    breakpoint_hit: Option<HardwareAddress>,
+    error: Option<EmulationError>,
+    history: History,
+    hotspots: Option<Hotspots>,
+    cycle_audit: Option<CycleAudit>,
+    cop_hook: Option<Box<FnMut(&mut Cpu<H>, u8) -> bool + Send>>,
+    wdm_handlers: HashMap<u8, Box<FnMut(&mut Cpu<H>) + Send>>,
+    nmi_count: u64,
+    waiting: bool,
+    halted: bool,
+    idle_cycles: u64,
+    instruction_count: u64,
+    spin_loop: Option<(u16, u64)>
 }
 
+#[derive(Copy, Clone)]
 pub struct CpuRegisters {
     pub accumulator: u16,
     pub index_x: u16,
@@ -29,6 +73,7 @@ pub struct CpuRegisters {
     pub stack_pointer: u16,
 }
 
+#[derive(Copy, Clone)]
 pub struct CpuFlags {
     pub negative: bool,
     pub overflow: bool,
@@ -60,24 +105,274 @@ enum BlockMove {
     Positive
 }
 
-macro_rules! memory_size {
-    ($cpu:ident, $method:ident, $($param:ident),*) => {{
-        if $cpu.flags.memory_size {
-            $cpu.$method::<u8, $($param<u8>),*>($($param::<u8>::default()),*);
-        } else {
-            $cpu.$method::<u16, $($param<u16>),*>($($param::<u16>::default()),*);
-        }
+macro_rules! sized {
+    ($cpu:ident, $size:ty, $method:ident, $($param:ident),*) => {{
+        $cpu.$method::<$size, $($param<$size>),*>($($param::<$size>::default()),*);
     }}
 }
 
-macro_rules! index_size {
-    ($cpu:ident, $method:ident, $($param:ident),*) => {{
-        if $cpu.flags.index_size {
-            $cpu.$method::<u8, $($param<u8>),*>($($param::<u8>::default()),*);
-        } else {
-            $cpu.$method::<u16, $($param<u16>),*>($($param::<u16>::default()),*);
-        }
-    }}
+// Expands to a full op code table for one combination of accumulator ($m)
+// and index register ($x) sizes, so the M and X flags are only checked once
+// per instruction rather than inside every handler
+macro_rules! op_table {
+    ($m:ty, $x:ty) => {[
+        /* 00 */ |cpu| cpu.interrupt::<Break>(),
+        /* 01 */ |cpu| sized!(cpu, $m, or, MemoryDirectPageIndexedXIndirect),
+        /* 02 */ |cpu| cpu.coprocessor_interrupt(),
+        /* 03 */ |cpu| sized!(cpu, $m, or, MemoryStackRelative),
+        /* 04 */ |cpu| sized!(cpu, $m, test_and_set_bits, MemoryDirectPage),
+        /* 05 */ |cpu| sized!(cpu, $m, or, MemoryDirectPage),
+        /* 06 */ |cpu| sized!(cpu, $m, arithmetic_shift_left, MemoryDirectPage),
+        /* 07 */ |cpu| sized!(cpu, $m, or, MemoryDirectPageIndirectLong),
+        /* 08 */ |cpu| cpu.push::<u8, ProcessorState>(Default::default()),
+        /* 09 */ |cpu| sized!(cpu, $m, or, Immediate),
+        /* 0A */ |cpu| sized!(cpu, $m, arithmetic_shift_left, Accumulator),
+        /* 0B */ |cpu| cpu.push::<u16, DirectPage>(Default::default()),
+        /* 0C */ |cpu| sized!(cpu, $m, test_and_set_bits, MemoryAbsolute),
+        /* 0D */ |cpu| sized!(cpu, $m, or, MemoryAbsolute),
+        /* 0E */ |cpu| sized!(cpu, $m, arithmetic_shift_left, MemoryAbsolute),
+        /* 0F */ |cpu| sized!(cpu, $m, or, MemoryAbsoluteLong),
+        /* 10 */ |cpu| cpu.branch(BranchCondition::Plus),
+        /* 11 */ |cpu| sized!(cpu, $m, or, MemoryDirectPageIndirectIndexedY),
+        /* 12 */ |cpu| sized!(cpu, $m, or, MemoryDirectPageIndirect),
+        /* 13 */ |cpu| sized!(cpu, $m, or, MemoryStackRelativeIndirectIndexedY),
+        /* 14 */ |cpu| sized!(cpu, $m, test_and_reset_bits, MemoryDirectPage),
+        /* 15 */ |cpu| sized!(cpu, $m, or, MemoryDirectPageIndexedX),
+        /* 16 */ |cpu| sized!(cpu, $m, arithmetic_shift_left, MemoryDirectPageIndexedX),
+        /* 17 */ |cpu| sized!(cpu, $m, or, MemoryDirectPageIndirectLongIndexedY),
+        /* 18 */ |cpu| cpu.clear_carry(),
+        /* 19 */ |cpu| sized!(cpu, $m, or, MemoryAbsoluteIndexedY),
+        /* 1A */ |cpu| sized!(cpu, $m, increment, Accumulator),
+        /* 1B */ |cpu| cpu.transfer::<u16, Accumulator, StackPointer>(Default::default(), Default::default()),
+        /* 1C */ |cpu| sized!(cpu, $m, test_and_reset_bits, MemoryAbsolute),
+        /* 1D */ |cpu| sized!(cpu, $m, or, MemoryAbsoluteIndexedX),
+        /* 1E */ |cpu| sized!(cpu, $m, arithmetic_shift_left, MemoryAbsoluteIndexedX),
+        /* 1F */ |cpu| sized!(cpu, $m, or, MemoryAbsoluteLongIndexedX),
+        /* 20 */ |cpu| cpu.jump_to_subroutine(MemoryAbsolute::<u16>::default()),
+        /* 21 */ |cpu| sized!(cpu, $m, and, MemoryDirectPageIndexedXIndirect),
+        /* 22 */ |cpu| cpu.jump_to_subroutine_long(MemoryAbsoluteLong::<u16>::default()),
+        /* 23 */ |cpu| sized!(cpu, $m, and, MemoryStackRelative),
+        /* 24 */ |cpu| sized!(cpu, $m, bit_test, MemoryDirectPage),
+        /* 25 */ |cpu| sized!(cpu, $m, and, MemoryDirectPage),
+        /* 26 */ |cpu| sized!(cpu, $m, rotate_left, MemoryDirectPage),
+        /* 27 */ |cpu| sized!(cpu, $m, and, MemoryDirectPageIndirectLong),
+        /* 28 */ |cpu| cpu.pull::<u8, ProcessorState>(Default::default()),
+        /* 29 */ |cpu| sized!(cpu, $m, and, Immediate),
+        /* 2A */ |cpu| sized!(cpu, $m, rotate_left, Accumulator),
+        /* 2B */ |cpu| cpu.pull::<u16, DirectPage>(Default::default()),
+        /* 2C */ |cpu| sized!(cpu, $m, bit_test, MemoryAbsolute),
+        /* 2D */ |cpu| sized!(cpu, $m, and, MemoryAbsolute),
+        /* 2E */ |cpu| sized!(cpu, $m, rotate_left, MemoryAbsolute),
+        /* 2F */ |cpu| sized!(cpu, $m, and, MemoryAbsoluteLong),
+        /* 30 */ |cpu| cpu.branch(BranchCondition::Minus),
+        /* 31 */ |cpu| sized!(cpu, $m, and, MemoryDirectPageIndirectIndexedY),
+        /* 32 */ |cpu| sized!(cpu, $m, and, MemoryDirectPageIndirect),
+        /* 33 */ |cpu| sized!(cpu, $m, and, MemoryStackRelativeIndirectIndexedY),
+        /* 34 */ |cpu| sized!(cpu, $m, bit_test, MemoryDirectPageIndexedX),
+        /* 35 */ |cpu| sized!(cpu, $m, and, MemoryDirectPageIndexedX),
+        /* 36 */ |cpu| sized!(cpu, $m, rotate_left, MemoryDirectPageIndexedX),
+        /* 37 */ |cpu| sized!(cpu, $m, and, MemoryDirectPageIndirectLongIndexedY),
+        /* 38 */ |cpu| cpu.set_carry(),
+        /* 39 */ |cpu| sized!(cpu, $m, and, MemoryAbsoluteIndexedY),
+        /* 3A */ |cpu| sized!(cpu, $m, decrement, Accumulator),
+        /* 3B */ |cpu| cpu.transfer::<u16, StackPointer, Accumulator>(Default::default(), Default::default()),
+        /* 3C */ |cpu| sized!(cpu, $m, bit_test, MemoryAbsoluteIndexedX),
+        /* 3D */ |cpu| sized!(cpu, $m, and, MemoryAbsoluteIndexedX),
+        /* 3E */ |cpu| sized!(cpu, $m, rotate_left, MemoryAbsoluteIndexedX),
+        /* 3F */ |cpu| sized!(cpu, $m, and, MemoryAbsoluteLongIndexedX),
+        /* 40 */ |cpu| cpu.return_from_interrupt(),
+        /* 41 */ |cpu| sized!(cpu, $m, exclusive_or, MemoryDirectPageIndexedXIndirect),
+        /* 42 */ |cpu| cpu.wdm(),
+        /* 43 */ |cpu| sized!(cpu, $m, exclusive_or, MemoryStackRelative),
+        /* 44 */ |cpu| cpu.move_block(BlockMove::Positive),
+        /* 45 */ |cpu| sized!(cpu, $m, exclusive_or, MemoryDirectPage),
+        /* 46 */ |cpu| sized!(cpu, $m, logical_shift_right, MemoryDirectPage),
+        /* 47 */ |cpu| sized!(cpu, $m, exclusive_or, MemoryDirectPageIndirectLong),
+        /* 48 */ |cpu| sized!(cpu, $m, push, Accumulator),
+        /* 49 */ |cpu| sized!(cpu, $m, exclusive_or, Immediate),
+        /* 4A */ |cpu| sized!(cpu, $m, logical_shift_right, Accumulator),
+        /* 4B */ |cpu| cpu.push::<u8, ProgramBank>(Default::default()),
+        /* 4C */ |cpu| cpu.jump(MemoryAbsolute::<u16>::default()),
+        /* 4D */ |cpu| sized!(cpu, $m, exclusive_or, MemoryAbsolute),
+        /* 4E */ |cpu| sized!(cpu, $m, logical_shift_right, MemoryAbsolute),
+        /* 4F */ |cpu| sized!(cpu, $m, exclusive_or, MemoryAbsoluteLong),
+        /* 50 */ |cpu| cpu.branch(BranchCondition::OverflowClear),
+        /* 51 */ |cpu| sized!(cpu, $m, exclusive_or, MemoryDirectPageIndirectIndexedY),
+        /* 52 */ |cpu| sized!(cpu, $m, exclusive_or, MemoryDirectPageIndirect),
+        /* 53 */ |cpu| sized!(cpu, $m, exclusive_or, MemoryStackRelativeIndirectIndexedY),
+        /* 54 */ |cpu| cpu.move_block(BlockMove::Negative),
+        /* 55 */ |cpu| sized!(cpu, $m, exclusive_or, MemoryDirectPageIndexedX),
+        /* 56 */ |cpu| sized!(cpu, $m, logical_shift_right, MemoryDirectPageIndexedX),
+        /* 57 */ |cpu| sized!(cpu, $m, exclusive_or, MemoryDirectPageIndirectLongIndexedY),
+        /* 58 */ |cpu| cpu.clear_interrupt_disable(),
+        /* 59 */ |cpu| sized!(cpu, $m, exclusive_or, MemoryAbsoluteIndexedY),
+        /* 5A */ |cpu| sized!(cpu, $x, push, IndexY),
+        /* 5B */ |cpu| cpu.transfer::<u16, Accumulator, DirectPage>(Default::default(), Default::default()),
+        /* 5C */ |cpu| cpu.jump_long(MemoryAbsoluteLong::<u16>::default()),
+        /* 5D */ |cpu| sized!(cpu, $m, exclusive_or, MemoryAbsoluteIndexedX),
+        /* 5E */ |cpu| sized!(cpu, $m, logical_shift_right, MemoryAbsoluteIndexedX),
+        /* 5F */ |cpu| sized!(cpu, $m, exclusive_or, MemoryAbsoluteLongIndexedX),
+        /* 60 */ |cpu| cpu.return_from_subroutine(),
+        /* 61 */ |cpu| sized!(cpu, $m, add_with_carry, MemoryDirectPageIndexedXIndirect),
+        /* 62 */ |cpu| cpu.push_effective_address(MemoryProgramCounterRelative::<u16>::default()),
+        /* 63 */ |cpu| sized!(cpu, $m, add_with_carry, MemoryStackRelative),
+        /* 64 */ |cpu| sized!(cpu, $m, store_zero, MemoryDirectPage),
+        /* 65 */ |cpu| sized!(cpu, $m, add_with_carry, MemoryDirectPage),
+        /* 66 */ |cpu| sized!(cpu, $m, rotate_right, MemoryDirectPage),
+        /* 67 */ |cpu| sized!(cpu, $m, add_with_carry, MemoryDirectPageIndirectLong),
+        /* 68 */ |cpu| sized!(cpu, $m, pull, Accumulator),
+        /* 69 */ |cpu| sized!(cpu, $m, add_with_carry, Immediate),
+        /* 6A */ |cpu| sized!(cpu, $m, rotate_right, Accumulator),
+        /* 6B */ |cpu| cpu.return_from_subroutine_long(),
+        /* 6C */ |cpu| cpu.jump(MemoryAbsoluteIndirect::<u16>::default()),
+        /* 6D */ |cpu| sized!(cpu, $m, add_with_carry, MemoryAbsolute),
+        /* 6E */ |cpu| sized!(cpu, $m, rotate_right, MemoryAbsolute),
+        /* 6F */ |cpu| sized!(cpu, $m, add_with_carry, MemoryAbsoluteLong),
+        /* 70 */ |cpu| cpu.branch(BranchCondition::OverflowSet),
+        /* 71 */ |cpu| sized!(cpu, $m, add_with_carry, MemoryDirectPageIndirectIndexedY),
+        /* 72 */ |cpu| sized!(cpu, $m, add_with_carry, MemoryDirectPageIndirect),
+        /* 73 */ |cpu| sized!(cpu, $m, add_with_carry, MemoryStackRelativeIndirectIndexedY),
+        /* 74 */ |cpu| sized!(cpu, $m, store_zero, MemoryDirectPageIndexedX),
+        /* 75 */ |cpu| sized!(cpu, $m, add_with_carry, MemoryDirectPageIndexedX),
+        /* 76 */ |cpu| sized!(cpu, $m, rotate_right, MemoryDirectPageIndexedX),
+        /* 77 */ |cpu| sized!(cpu, $m, add_with_carry, MemoryDirectPageIndirectLongIndexedY),
+        /* 78 */ |cpu| cpu.set_interrupt_disable(),
+        /* 79 */ |cpu| sized!(cpu, $m, add_with_carry, MemoryAbsoluteIndexedY),
+        /* 7A */ |cpu| sized!(cpu, $x, pull, IndexY),
+        /* 7B */ |cpu| cpu.transfer::<u16, DirectPage, Accumulator>(Default::default(), Default::default()),
+        /* 7C */ |cpu| cpu.jump(MemoryAbsoluteIndexedXIndirect::<u16>::default()),
+        /* 7D */ |cpu| sized!(cpu, $m, add_with_carry, MemoryAbsoluteIndexedX),
+        /* 7E */ |cpu| sized!(cpu, $m, rotate_right, MemoryAbsoluteIndexedX),
+        /* 7F */ |cpu| sized!(cpu, $m, add_with_carry, MemoryAbsoluteLongIndexedX),
+        /* 80 */ |cpu| cpu.branch(BranchCondition::Always),
+        /* 81 */ |cpu| sized!(cpu, $m, store, Accumulator, MemoryDirectPageIndexedXIndirect),
+        /* 82 */ |cpu| cpu.branch_always_long(),
+        /* 83 */ |cpu| sized!(cpu, $m, store, Accumulator, MemoryStackRelative),
+        /* 84 */ |cpu| sized!(cpu, $x, store, IndexY, MemoryDirectPage),
+        /* 85 */ |cpu| sized!(cpu, $m, store, Accumulator, MemoryDirectPage),
+        /* 86 */ |cpu| sized!(cpu, $x, store, IndexX, MemoryDirectPage),
+        /* 87 */ |cpu| sized!(cpu, $m, store, Accumulator, MemoryDirectPageIndirectLong),
+        /* 88 */ |cpu| sized!(cpu, $x, decrement, IndexY),
+        /* 89 */ |cpu| sized!(cpu, $m, bit_test, Immediate),
+        /* 8A */ |cpu| sized!(cpu, $m, transfer, IndexX, Accumulator),
+        /* 8B */ |cpu| cpu.push::<u8, DataBank>(Default::default()),
+        /* 8C */ |cpu| sized!(cpu, $x, store, IndexY, MemoryAbsolute),
+        /* 8D */ |cpu| sized!(cpu, $m, store, Accumulator, MemoryAbsolute),
+        /* 8E */ |cpu| sized!(cpu, $x, store, IndexX, MemoryAbsolute),
+        /* 8F */ |cpu| sized!(cpu, $m, store, Accumulator, MemoryAbsoluteLong),
+        /* 90 */ |cpu| cpu.branch(BranchCondition::CarryClear),
+        /* 91 */ |cpu| sized!(cpu, $m, store, Accumulator, MemoryDirectPageIndirectIndexedY),
+        /* 92 */ |cpu| sized!(cpu, $m, store, Accumulator, MemoryDirectPageIndirect),
+        /* 93 */ |cpu| sized!(cpu, $m, store, Accumulator, MemoryStackRelativeIndirectIndexedY),
+        /* 94 */ |cpu| sized!(cpu, $x, store, IndexY, MemoryDirectPageIndexedX),
+        /* 95 */ |cpu| sized!(cpu, $m, store, Accumulator, MemoryDirectPageIndexedX),
+        /* 96 */ |cpu| sized!(cpu, $x, store, IndexX, MemoryDirectPageIndexedY),
+        /* 97 */ |cpu| sized!(cpu, $m, store, Accumulator, MemoryDirectPageIndirectLongIndexedY),
+        /* 98 */ |cpu| sized!(cpu, $m, transfer, IndexY, Accumulator),
+        /* 99 */ |cpu| sized!(cpu, $m, store, Accumulator, MemoryAbsoluteIndexedY),
+        /* 9A */ |cpu| cpu.transfer::<u16, IndexX, StackPointer>(Default::default(), Default::default()),
+        /* 9B */ |cpu| sized!(cpu, $x, transfer, IndexX, IndexY),
+        /* 9C */ |cpu| sized!(cpu, $m, store_zero, MemoryAbsolute),
+        /* 9D */ |cpu| sized!(cpu, $m, store, Accumulator, MemoryAbsoluteIndexedX),
+        /* 9E */ |cpu| sized!(cpu, $m, store_zero, MemoryAbsoluteIndexedX),
+        /* 9F */ |cpu| sized!(cpu, $m, store, Accumulator, MemoryAbsoluteLongIndexedX),
+        /* A0 */ |cpu| sized!(cpu, $x, load, IndexY, Immediate),
+        /* A1 */ |cpu| sized!(cpu, $m, load, Accumulator, MemoryDirectPageIndexedXIndirect),
+        /* A2 */ |cpu| sized!(cpu, $x, load, IndexX, Immediate),
+        /* A3 */ |cpu| sized!(cpu, $m, load, Accumulator, MemoryStackRelative),
+        /* A4 */ |cpu| sized!(cpu, $x, load, IndexY, MemoryDirectPage),
+        /* A5 */ |cpu| sized!(cpu, $m, load, Accumulator, MemoryDirectPage),
+        /* A6 */ |cpu| sized!(cpu, $x, load, IndexX, MemoryDirectPage),
+        /* A7 */ |cpu| sized!(cpu, $m, load, Accumulator, MemoryDirectPageIndirectLong),
+        /* A8 */ |cpu| sized!(cpu, $x, transfer, Accumulator, IndexY),
+        /* A9 */ |cpu| sized!(cpu, $m, load, Accumulator, Immediate),
+        /* AA */ |cpu| sized!(cpu, $x, transfer, Accumulator, IndexX),
+        /* AB */ |cpu| cpu.pull::<u8, DataBank>(Default::default()),
+        /* AC */ |cpu| sized!(cpu, $x, load, IndexY, MemoryAbsolute),
+        /* AD */ |cpu| sized!(cpu, $m, load, Accumulator, MemoryAbsolute),
+        /* AE */ |cpu| sized!(cpu, $x, load, IndexX, MemoryAbsolute),
+        /* AF */ |cpu| sized!(cpu, $m, load, Accumulator, MemoryAbsoluteLong),
+        /* B0 */ |cpu| cpu.branch(BranchCondition::CarrySet),
+        /* B1 */ |cpu| sized!(cpu, $m, load, Accumulator, MemoryDirectPageIndirectIndexedY),
+        /* B2 */ |cpu| sized!(cpu, $m, load, Accumulator, MemoryDirectPageIndirect),
+        /* B3 */ |cpu| sized!(cpu, $m, load, Accumulator, MemoryStackRelativeIndirectIndexedY),
+        /* B4 */ |cpu| sized!(cpu, $x, load, IndexY, MemoryDirectPageIndexedX),
+        /* B5 */ |cpu| sized!(cpu, $m, load, Accumulator, MemoryDirectPageIndexedX),
+        /* B6 */ |cpu| sized!(cpu, $x, load, IndexX, MemoryDirectPageIndexedY),
+        /* B7 */ |cpu| sized!(cpu, $m, load, Accumulator, MemoryDirectPageIndirectLongIndexedY),
+        /* B8 */ |cpu| cpu.clear_overflow(),
+        /* B9 */ |cpu| sized!(cpu, $m, load, Accumulator, MemoryAbsoluteIndexedY),
+        /* BA */ |cpu| sized!(cpu, $x, transfer, StackPointer, IndexX),
+        /* BB */ |cpu| sized!(cpu, $x, transfer, IndexY, IndexX),
+        /* BC */ |cpu| sized!(cpu, $x, load, IndexY, MemoryAbsoluteIndexedX),
+        /* BD */ |cpu| sized!(cpu, $m, load, Accumulator, MemoryAbsoluteIndexedX),
+        /* BE */ |cpu| sized!(cpu, $x, load, IndexX, MemoryAbsoluteIndexedY),
+        /* BF */ |cpu| sized!(cpu, $m, load, Accumulator, MemoryAbsoluteLongIndexedX),
+        /* C0 */ |cpu| sized!(cpu, $x, compare, IndexY, Immediate),
+        /* C1 */ |cpu| sized!(cpu, $m, compare, Accumulator, MemoryDirectPageIndexedXIndirect),
+        /* C2 */ |cpu| cpu.reset_processor_state(),
+        /* C3 */ |cpu| sized!(cpu, $m, compare, Accumulator, MemoryStackRelative),
+        /* C4 */ |cpu| sized!(cpu, $x, compare, IndexY, MemoryDirectPage),
+        /* C5 */ |cpu| sized!(cpu, $m, compare, Accumulator, MemoryDirectPage),
+        /* C6 */ |cpu| sized!(cpu, $m, decrement, MemoryDirectPage),
+        /* C7 */ |cpu| sized!(cpu, $m, compare, Accumulator, MemoryDirectPageIndirectLong),
+        /* C8 */ |cpu| sized!(cpu, $x, increment, IndexY),
+        /* C9 */ |cpu| sized!(cpu, $m, compare, Accumulator, Immediate),
+        /* CA */ |cpu| sized!(cpu, $x, decrement, IndexX),
+        /* CB */ |cpu| cpu.wait_for_interrupt(),
+        /* CC */ |cpu| sized!(cpu, $x, compare, IndexY, MemoryAbsolute),
+        /* CD */ |cpu| sized!(cpu, $m, compare, Accumulator, MemoryAbsolute),
+        /* CE */ |cpu| sized!(cpu, $m, decrement, MemoryAbsolute),
+        /* CF */ |cpu| sized!(cpu, $m, compare, Accumulator, MemoryAbsoluteLong),
+        /* D0 */ |cpu| cpu.branch(BranchCondition::NotEqual),
+        /* D1 */ |cpu| sized!(cpu, $m, compare, Accumulator, MemoryDirectPageIndirectIndexedY),
+        /* D2 */ |cpu| sized!(cpu, $m, compare, Accumulator, MemoryDirectPageIndirect),
+        /* D3 */ |cpu| sized!(cpu, $m, compare, Accumulator, MemoryStackRelativeIndirectIndexedY),
+        /* D4 */ |cpu| cpu.push_effective_address(MemoryDirectPageIndirect::<u16>::default()),
+        /* D5 */ |cpu| sized!(cpu, $m, compare, Accumulator, MemoryDirectPageIndexedX),
+        /* D6 */ |cpu| sized!(cpu, $m, decrement, MemoryDirectPageIndexedX),
+        /* D7 */ |cpu| sized!(cpu, $m, compare, Accumulator, MemoryDirectPageIndirectLongIndexedY),
+        /* D8 */ |cpu| cpu.clear_decimal_mode(),
+        /* D9 */ |cpu| sized!(cpu, $m, compare, Accumulator, MemoryAbsoluteIndexedY),
+        /* DA */ |cpu| sized!(cpu, $x, push, IndexX),
+        /* DB */ |cpu| cpu.stop(),
+        /* DC */ |cpu| cpu.jump_long(MemoryAbsoluteIndirectLong::<u16>::default()),
+        /* DD */ |cpu| sized!(cpu, $m, compare, Accumulator, MemoryAbsoluteIndexedX),
+        /* DE */ |cpu| sized!(cpu, $m, decrement, MemoryAbsoluteIndexedX),
+        /* DF */ |cpu| sized!(cpu, $m, compare, Accumulator, MemoryAbsoluteLongIndexedX),
+        /* E0 */ |cpu| sized!(cpu, $x, compare, IndexX, Immediate),
+        /* E1 */ |cpu| sized!(cpu, $m, subtract_with_carry, MemoryDirectPageIndexedXIndirect),
+        /* E2 */ |cpu| cpu.set_processor_state(),
+        /* E3 */ |cpu| sized!(cpu, $m, subtract_with_carry, MemoryStackRelative),
+        /* E4 */ |cpu| sized!(cpu, $x, compare, IndexX, MemoryDirectPage),
+        /* E5 */ |cpu| sized!(cpu, $m, subtract_with_carry, MemoryDirectPage),
+        /* E6 */ |cpu| sized!(cpu, $m, increment, MemoryDirectPage),
+        /* E7 */ |cpu| sized!(cpu, $m, subtract_with_carry, MemoryDirectPageIndirectLong),
+        /* E8 */ |cpu| sized!(cpu, $x, increment, IndexX),
+        /* E9 */ |cpu| sized!(cpu, $m, subtract_with_carry, Immediate),
+        /* EA */ |cpu| { debug!("NOP"); cpu.io_cycle(); },
+        /* EB */ |cpu| cpu.exchange_accumulators(),
+        /* EC */ |cpu| sized!(cpu, $x, compare, IndexX, MemoryAbsolute),
+        /* ED */ |cpu| sized!(cpu, $m, subtract_with_carry, MemoryAbsolute),
+        /* EE */ |cpu| sized!(cpu, $m, increment, MemoryAbsolute),
+        /* EF */ |cpu| sized!(cpu, $m, subtract_with_carry, MemoryAbsoluteLong),
+        /* F0 */ |cpu| cpu.branch(BranchCondition::Equal),
+        /* F1 */ |cpu| sized!(cpu, $m, subtract_with_carry, MemoryDirectPageIndirectIndexedY),
+        /* F2 */ |cpu| sized!(cpu, $m, subtract_with_carry, MemoryDirectPageIndirect),
+        /* F3 */ |cpu| sized!(cpu, $m, subtract_with_carry, MemoryStackRelativeIndirectIndexedY),
+        /* F4 */ |cpu| cpu.push_effective_address(MemoryAbsolute::<u16>::default()),
+        /* F5 */ |cpu| sized!(cpu, $m, subtract_with_carry, MemoryDirectPageIndexedX),
+        /* F6 */ |cpu| sized!(cpu, $m, increment, MemoryDirectPageIndexedX),
+        /* F7 */ |cpu| sized!(cpu, $m, subtract_with_carry, MemoryDirectPageIndirectLongIndexedY),
+        /* F8 */ |cpu| cpu.set_decimal_mode(),
+        /* F9 */ |cpu| sized!(cpu, $m, subtract_with_carry, MemoryAbsoluteIndexedY),
+        /* FA */ |cpu| sized!(cpu, $x, pull, IndexX),
+        /* FB */ |cpu| cpu.exchange_carry_and_emulation_bits(),
+        /* FC */ |cpu| cpu.jump_to_subroutine(MemoryAbsoluteIndexedXIndirect::<u16>::default()),
+        /* FD */ |cpu| sized!(cpu, $m, subtract_with_carry, MemoryAbsoluteIndexedX),
+        /* FE */ |cpu| sized!(cpu, $m, increment, MemoryAbsoluteIndexedX),
+        /* FF */ |cpu| sized!(cpu, $m, subtract_with_carry, MemoryAbsoluteLongIndexedX)
+    ]}
 }
 
 macro_rules! push_value {
@@ -90,8 +385,13 @@ macro_rules! push_value {
     }}
 }
 
-impl Cpu {
-    pub fn new(mut hardware: Hardware) -> Cpu {
+impl<H: Bus> Cpu<H> {
+    const OP_TABLE_M8_X8: [OpHandler<H>; 256] = op_table!(u8, u8);
+    const OP_TABLE_M8_X16: [OpHandler<H>; 256] = op_table!(u8, u16);
+    const OP_TABLE_M16_X8: [OpHandler<H>; 256] = op_table!(u16, u8);
+    const OP_TABLE_M16_X16: [OpHandler<H>; 256] = op_table!(u16, u16);
+
+    pub fn new(mut hardware: H) -> Cpu<H> {
         let program_counter = hardware.read::<u16>(HardwareAddress::new(0, RESET_VECTOR));
 
         Cpu {
@@ -118,315 +418,449 @@ impl Cpu {
                 zero: false,
                 carry: false,
                 emulation_mode: true
+            },
+            watches: Vec::new(),
+            trace_trigger: None,
+            tracer: None,
+            breakpoints: Breakpoints::new(),
+            breakpoint_hit: None,
+            error: None,
+            history: History::new(),
+            hotspots: None,
+            cycle_audit: None,
+            cop_hook: None,
+            wdm_handlers: HashMap::new(),
+            nmi_count: 0,
+            waiting: false,
+            halted: false,
+            idle_cycles: 0,
+            instruction_count: 0,
+            spin_loop: None
+        }
+    }
+}
+
+// The frame loop, interrupt and DMA polling, and the debugging features all
+// need the rest of the system. Only the instruction core runs on any bus.
+impl Cpu {
+    pub fn set_trace_trigger(&mut self, trace_trigger: TraceTrigger) {
+        self.hardware.set_write_trap(trace_trigger.write_trap());
+        self.trace_trigger = Some(trace_trigger);
+    }
+
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    pub fn flush_tracer(&mut self) {
+        if let Some(ref mut tracer) = self.tracer {
+            tracer.flush();
+        }
+    }
+
+    pub fn breakpoints(&self) -> &Breakpoints {
+        &self.breakpoints
+    }
+
+    pub fn breakpoints_mut(&mut self) -> &mut Breakpoints {
+        &mut self.breakpoints
+    }
+
+    // Set when a tick stopped at a breakpoint instead of executing anything
+    pub fn take_breakpoint_hit(&mut self) -> Option<HardwareAddress> {
+        self.breakpoint_hit.take()
+    }
+
+    // Set when the game did something that stopped emulation, such as STP
+    pub fn take_error(&mut self) -> Option<EmulationError> {
+        self.error.take()
+    }
+
+    // Continues past an instruction that halted the CPU
+    pub fn resume(&mut self) {
+        self.halted = false;
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    pub fn enable_hotspots(&mut self) {
+        self.hotspots = Some(Hotspots::new());
+    }
+
+    pub fn report_hotspots(&self, count: usize) {
+        if let Some(ref hotspots) = self.hotspots {
+            hotspots.report(count);
+        }
+    }
+
+    pub fn enable_cycle_audit(&mut self) {
+        self.cycle_audit = Some(CycleAudit::new());
+    }
+
+    pub fn report_cycle_audit(&self) {
+        if let Some(ref cycle_audit) = self.cycle_audit {
+            info!("Cycle audit: {} mismatched instructions", cycle_audit.mismatches());
+        }
+    }
+
+    // Master cycles spent in WAI or in short polling loops. This is a
+    // heuristic, but good enough to tell a game that is waiting for the next
+    // frame from one that is running out of time.
+    pub fn idle_cycles(&self) -> u64 {
+        self.idle_cycles
+    }
+
+    // Called with the signature byte whenever COP executes, before the
+    // vector is taken. Returning true skips the vector entirely, so the hook
+    // can stand in for the handler (e.g. to emulate a chip at a high level).
+    pub fn set_cop_hook(&mut self, hook: Box<FnMut(&mut Cpu, u8) -> bool + Send>) {
+        self.cop_hook = Some(hook);
+    }
+
+    // The frontend keeps its hook for the whole run, so only embedders that
+    // swap hooks need this
+    #[allow(dead_code)]
+    pub fn clear_cop_hook(&mut self) {
+        self.cop_hook = None;
+    }
+
+    // WDM is a two byte no-op on real hardware, so emulator-aware programs
+    // use its signature byte to call into the host. Each signature can have
+    // one handler; WDM with an unregistered signature does nothing.
+    pub fn set_wdm_handler(&mut self, signature: u8, handler: Box<FnMut(&mut Cpu) + Send>) {
+        self.wdm_handlers.insert(signature, handler);
+    }
+
+    pub fn add_watch(&mut self, watch: Watch) {
+        self.watches.push(watch);
+    }
+
+    pub fn watches(&self) -> &[Watch] {
+        &self.watches
+    }
+
+    // Runs until the PPU completes a frame (at the start of VBlank). Stops
+    // early at a breakpoint or watchpoint, which the caller should take.
+    // Also stops early when the game latches the controllers, leaving the
+    // joypad's poll request set so the caller can poll host input before
+    // the game reads it. Calling again carries on with the same frame.
+    pub fn run_frame(&mut self) {
+        let frame_count = self.hardware.frame_count();
+
+        // A request the caller didn't take would otherwise stop us straight away
+        self.hardware.joypad_mut().take_poll_request();
+
+        while self.hardware.frame_count() == frame_count && !self.stopped() {
+            self.tick();
+
+            if self.hardware.joypad().poll_requested() {
+                break;
+            }
+        }
+    }
+
+    // Runs until at least 'cycles' master cycles have passed. Stops early at
+    // a breakpoint or watchpoint, which the caller should take.
+    pub fn run_for_cycles(&mut self, cycles: u64) {
+        let start = self.hardware.master_cycles();
+
+        while self.hardware.master_cycles().wrapping_sub(start) < cycles && !self.stopped() {
+            self.tick();
+        }
+    }
+
+    fn stopped(&self) -> bool {
+        self.breakpoint_hit.is_some() || self.error.is_some() || self.hardware.has_watchpoint_hits()
+    }
+
+    // Stops early at a breakpoint or watchpoint, as run_frame does, and also
+    // if the CPU halts (as the condition may then never be met)
+    pub fn run_until(&mut self, condition: &RunCondition) {
+        while !condition.is_met(self) && !self.stopped() && !self.halted {
+            self.tick();
+        }
+    }
+
+    // Executes exactly one instruction, along with any interrupts or DMA that
+    // come before it. A breakpoint on that instruction doesn't stop it.
+    pub fn step(&mut self) {
+        let start_address = self.program_address();
+        let instruction_count = self.instruction_count;
+
+        while self.instruction_count == instruction_count {
+            self.tick();
+
+            if self.breakpoint_hit == Some(start_address) {
+                self.breakpoint_hit = None;
+            } else if self.breakpoint_hit.is_some() || self.halted {
+                break;
+            }
+        }
+    }
+
+    // Like step, but runs a JSR/JSL through to its return. Stops early at
+    // a breakpoint.
+    pub fn step_over(&mut self) {
+        let address = self.program_address();
+
+        let return_offset = match self.hardware.peek(address) {
+            Some(JSR_ABSOLUTE) | Some(JSR_INDEXED_INDIRECT) => 3,
+            Some(JSL) => 4,
+            _ => return self.step()
+        };
+
+        let return_address = HardwareAddress::new(address.bank(), address.offset().wrapping_add(return_offset));
+        let stack_pointer = self.regs.stack_pointer;
+
+        self.step();
+
+        while self.breakpoint_hit.is_none() && !self.halted &&
+            (self.program_address() != return_address || self.regs.stack_pointer != stack_pointer)
+        {
+            self.tick();
+        }
+    }
+
+    // Runs until a return instruction leaves the current subroutine (i.e.
+    // the stack pointer rises above its current level). Stops early at a
+    // breakpoint.
+    pub fn step_out(&mut self) {
+        let stack_pointer = self.regs.stack_pointer;
+
+        while self.breakpoint_hit.is_none() && !self.halted {
+            let op_code = self.hardware.peek(self.program_address());
+
+            self.step();
+
+            match op_code {
+                Some(RTS) | Some(RTL) | Some(RTI) if self.regs.stack_pointer > stack_pointer => break,
+                _ => ()
             }
         }
     }
 
     pub fn tick(&mut self) {
-        if self.hardware.regs().cpu_action_ready() {
-            // Check for interrupts and things
-            if self.hardware.regs_mut().check_and_reset_nmi() {
-                self.interrupt::<Nmi>();
-            } else if self.hardware.regs_mut().check_and_reset_irq() {
-                if !self.flags.interrupt_disable {
-                    self.interrupt::<Irq>();
-                } else {
-                    debug!("IRQ prevented by CPU 'I' flag");
-                }
-            } else if let Some(mask) = self.hardware.regs_mut().check_and_reset_dma() {
-                self.hardware.dma_transfer(mask);
+        if self.halted {
+            self.io_cycle();
+            return;
+        }
+
+        if self.waiting {
+            // WAI resumes once an interrupt is pending, even if IRQs are disabled
+            if self.hardware.nmi_polled() || self.hardware.irq_polled() {
+                self.waiting = false;
             } else {
-                panic!("Unknown CPU action requested");
+                self.io_cycle();
+                self.idle_cycles += IO_CYCLES;
+                return;
+            }
+        }
+
+        let instruction_address = HardwareAddress::new(self.regs.program_bank, self.regs.program_counter);
+
+        if let Some(ref mut trace_trigger) = self.trace_trigger {
+            let write_trapped = self.hardware.take_write_trap();
+            match trace_trigger.update(instruction_address, write_trapped, self.nmi_count) {
+                Some(true) => ::log::enable_trace_mode(),
+                Some(false) => ::log::disable_trace_mode(),
+                None => ()
             }
+        }
+
+        // Interrupts use the state polled during the previous instruction
+        if self.hardware.nmi_polled() && self.hardware.regs_mut().check_and_reset_nmi() {
+            self.nmi_count += 1;
+            self.interrupt::<Nmi>();
+        } else if let Some(mask) = self.hardware.regs_mut().check_and_reset_dma() {
+            self.hardware.dma_transfer(mask);
+        } else if self.hardware.irq_polled() && self.hardware.regs().irq_asserted() &&
+            !self.flags.interrupt_disable
+        {
+            self.interrupt::<Irq>();
         } else {
+            if !self.breakpoints.is_empty() && self.breakpoints.check(instruction_address) {
+                self.breakpoint_hit = Some(instruction_address);
+                return;
+            }
+
+            if self.tracer.is_some() && ::log::trace_mode_enabled() {
+                self.trace_instruction(instruction_address);
+            }
+
+            // Taken out while the instruction runs, as the audit needs the whole CPU
+            let mut cycle_audit = self.cycle_audit.take();
+
+            if let Some(ref mut cycle_audit) = cycle_audit {
+                cycle_audit.begin(self, instruction_address);
+            }
+
             // Otherwise, read an instruction from the PC location as normal
-            match self.read_next::<u8>() {
-                0x00 => self.interrupt::<Break>(),
-                0x01 => memory_size!(self, or, MemoryDirectPageIndexedXIndirect),
-                0x02 => self.interrupt::<Coprocessor>(),
-                0x03 => memory_size!(self, or, MemoryStackRelative),
-                0x04 => memory_size!(self, test_and_set_bits, MemoryDirectPage),
-                0x05 => memory_size!(self, or, MemoryDirectPage),
-                0x06 => memory_size!(self, arithmetic_shift_left, MemoryDirectPage),
-                0x07 => memory_size!(self, or, MemoryDirectPageIndirectLong),
-                0x08 => self.push::<u8, ProcessorState>(Default::default()),
-                0x09 => memory_size!(self, or, Immediate),
-                0x0A => memory_size!(self, arithmetic_shift_left, Accumulator),
-                0x0B => self.push::<u16, DirectPage>(Default::default()),
-                0x0C => memory_size!(self, test_and_set_bits, MemoryAbsolute),
-                0x0D => memory_size!(self, or, MemoryAbsolute),
-                0x0E => memory_size!(self, arithmetic_shift_left, MemoryAbsolute),
-                0x0F => memory_size!(self, or, MemoryAbsoluteLong),
-                0x10 => self.branch(BranchCondition::Plus),
-                0x11 => memory_size!(self, or, MemoryDirectPageIndirectIndexedY),
-                0x12 => memory_size!(self, or, MemoryDirectPageIndirect),
-                0x13 => memory_size!(self, or, MemoryStackRelativeIndirectIndexedY),
-                0x14 => memory_size!(self, test_and_reset_bits, MemoryDirectPage),
-                0x15 => memory_size!(self, or, MemoryDirectPageIndexedX),
-                0x16 => memory_size!(self, arithmetic_shift_left, MemoryDirectPageIndexedX),
-                0x17 => memory_size!(self, or, MemoryDirectPageIndirectLongIndexedY),
-                0x18 => self.clear_carry(),
-                0x19 => memory_size!(self, or, MemoryAbsoluteIndexedY),
-                0x1A => memory_size!(self, increment, Accumulator),
-                0x1B => self.transfer::<u16, Accumulator, StackPointer>(Default::default(), Default::default()),
-                0x1C => memory_size!(self, test_and_reset_bits, MemoryAbsolute),
-                0x1D => memory_size!(self, or, MemoryAbsoluteIndexedX),
-                0x1E => memory_size!(self, arithmetic_shift_left, MemoryAbsoluteIndexedX),
-                0x1F => memory_size!(self, or, MemoryAbsoluteLongIndexedX),
-                0x20 => self.jump_to_subroutine(MemoryAbsolute::<u16>::default()),
-                0x21 => memory_size!(self, and, MemoryDirectPageIndexedXIndirect),
-                0x22 => self.jump_to_subroutine_long(MemoryAbsoluteLong::<u16>::default()),
-                0x23 => memory_size!(self, and, MemoryStackRelative),
-                0x24 => memory_size!(self, bit_test, MemoryDirectPage),
-                0x25 => memory_size!(self, and, MemoryDirectPage),
-                0x26 => memory_size!(self, rotate_left, MemoryDirectPage),
-                0x27 => memory_size!(self, and, MemoryDirectPageIndirectLong),
-                0x28 => self.pull::<u8, ProcessorState>(Default::default()),
-                0x29 => memory_size!(self, and, Immediate),
-                0x2A => memory_size!(self, rotate_left, Accumulator),
-                0x2B => self.pull::<u16, DirectPage>(Default::default()),
-                0x2C => memory_size!(self, bit_test, MemoryAbsolute),
-                0x2D => memory_size!(self, and, MemoryAbsolute),
-                0x2E => memory_size!(self, rotate_left, MemoryAbsolute),
-                0x2F => memory_size!(self, and, MemoryAbsoluteLong),
-                0x30 => self.branch(BranchCondition::Minus),
-                0x31 => memory_size!(self, and, MemoryDirectPageIndirectIndexedY),
-                0x32 => memory_size!(self, and, MemoryDirectPageIndirect),
-                0x33 => memory_size!(self, and, MemoryStackRelativeIndirectIndexedY),
-                0x34 => memory_size!(self, bit_test, MemoryDirectPageIndexedX),
-                0x35 => memory_size!(self, and, MemoryDirectPageIndexedX),
-                0x36 => memory_size!(self, rotate_left, MemoryDirectPageIndexedX),
-                0x37 => memory_size!(self, and, MemoryDirectPageIndirectLongIndexedY),
-                0x38 => self.set_carry(),
-                0x39 => memory_size!(self, and, MemoryAbsoluteIndexedY),
-                0x3A => memory_size!(self, decrement, Accumulator),
-                0x3B => self.transfer::<u16, StackPointer, Accumulator>(Default::default(), Default::default()),
-                0x3C => memory_size!(self, bit_test, MemoryAbsoluteIndexedX),
-                0x3D => memory_size!(self, and, MemoryAbsoluteIndexedX),
-                0x3E => memory_size!(self, rotate_left, MemoryAbsoluteIndexedX),
-                0x3F => memory_size!(self, and, MemoryAbsoluteLongIndexedX),
-                0x40 => self.return_from_interrupt(),
-                0x41 => memory_size!(self, exclusive_or, MemoryDirectPageIndexedXIndirect),
-                0x42 => { debug!("WDM"); self.io_cycle(); },
-                0x43 => memory_size!(self, exclusive_or, MemoryStackRelative),
-                0x44 => self.move_block(BlockMove::Positive),
-                0x45 => memory_size!(self, exclusive_or, MemoryDirectPage),
-                0x46 => memory_size!(self, logical_shift_right, MemoryDirectPage),
-                0x47 => memory_size!(self, exclusive_or, MemoryDirectPageIndirectLong),
-                0x48 => memory_size!(self, push, Accumulator),
-                0x49 => memory_size!(self, exclusive_or, Immediate),
-                0x4A => memory_size!(self, logical_shift_right, Accumulator),
-                0x4B => self.push::<u8, ProgramBank>(Default::default()),
-                0x4C => self.jump(MemoryAbsolute::<u16>::default()),
-                0x4D => memory_size!(self, exclusive_or, MemoryAbsolute),
-                0x4E => memory_size!(self, logical_shift_right, MemoryAbsolute),
-                0x4F => memory_size!(self, exclusive_or, MemoryAbsoluteLong),
-                0x50 => self.branch(BranchCondition::OverflowClear),
-                0x51 => memory_size!(self, exclusive_or, MemoryDirectPageIndirectIndexedY),
-                0x52 => memory_size!(self, exclusive_or, MemoryDirectPageIndirect),
-                0x53 => memory_size!(self, exclusive_or, MemoryStackRelativeIndirectIndexedY),
-                0x54 => self.move_block(BlockMove::Negative),
-                0x55 => memory_size!(self, exclusive_or, MemoryDirectPageIndexedX),
-                0x56 => memory_size!(self, logical_shift_right, MemoryDirectPageIndexedX),
-                0x57 => memory_size!(self, exclusive_or, MemoryDirectPageIndirectLongIndexedY),
-                0x58 => self.clear_interrupt_disable(),
-                0x59 => memory_size!(self, exclusive_or, MemoryAbsoluteIndexedY),
-                0x5A => index_size!(self, push, IndexY),
-                0x5B => self.transfer::<u16, Accumulator, DirectPage>(Default::default(), Default::default()),
-                0x5C => self.jump_long(MemoryAbsoluteLong::<u16>::default()),
-                0x5D => memory_size!(self, exclusive_or, MemoryAbsoluteIndexedX),
-                0x5E => memory_size!(self, logical_shift_right, MemoryAbsoluteIndexedX),
-                0x5F => memory_size!(self, exclusive_or, MemoryAbsoluteLongIndexedX),
-                0x60 => self.return_from_subroutine(),
-                0x61 => memory_size!(self, add_with_carry, MemoryDirectPageIndexedXIndirect),
-                0x62 => self.push_effective_address(MemoryProgramCounterRelative::<u16>::default()),
-                0x63 => memory_size!(self, add_with_carry, MemoryStackRelative),
-                0x64 => memory_size!(self, store_zero, MemoryDirectPage),
-                0x65 => memory_size!(self, add_with_carry, MemoryDirectPage),
-                0x66 => memory_size!(self, rotate_right, MemoryDirectPage),
-                0x67 => memory_size!(self, add_with_carry, MemoryDirectPageIndirectLong),
-                0x68 => memory_size!(self, pull, Accumulator),
-                0x69 => memory_size!(self, add_with_carry, Immediate),
-                0x6A => memory_size!(self, rotate_right, Accumulator),
-                0x6B => self.return_from_subroutine_long(),
-                0x6C => self.jump(MemoryAbsoluteIndirect::<u16>::default()),
-                0x6D => memory_size!(self, add_with_carry, MemoryAbsolute),
-                0x6E => memory_size!(self, rotate_right, MemoryAbsolute),
-                0x6F => memory_size!(self, add_with_carry, MemoryAbsoluteLong),
-                0x70 => self.branch(BranchCondition::OverflowSet),
-                0x71 => memory_size!(self, add_with_carry, MemoryDirectPageIndirectIndexedY),
-                0x72 => memory_size!(self, add_with_carry, MemoryDirectPageIndirect),
-                0x73 => memory_size!(self, add_with_carry, MemoryStackRelativeIndirectIndexedY),
-                0x74 => memory_size!(self, store_zero, MemoryDirectPageIndexedX),
-                0x75 => memory_size!(self, add_with_carry, MemoryDirectPageIndexedX),
-                0x76 => memory_size!(self, rotate_right, MemoryDirectPageIndexedX),
-                0x77 => memory_size!(self, add_with_carry, MemoryDirectPageIndirectLongIndexedY),
-                0x78 => self.set_interrupt_disable(),
-                0x79 => memory_size!(self, add_with_carry, MemoryAbsoluteIndexedY),
-                0x7A => index_size!(self, pull, IndexY),
-                0x7B => self.transfer::<u16, DirectPage, Accumulator>(Default::default(), Default::default()),
-                0x7C => self.jump(MemoryAbsoluteIndexedXIndirect::<u16>::default()),
-                0x7D => memory_size!(self, add_with_carry, MemoryAbsoluteIndexedX),
-                0x7E => memory_size!(self, rotate_right, MemoryAbsoluteIndexedX),
-                0x7F => memory_size!(self, add_with_carry, MemoryAbsoluteLongIndexedX),
-                0x80 => self.branch(BranchCondition::Always),
-                0x81 => memory_size!(self, store, Accumulator, MemoryDirectPageIndexedXIndirect),
-                0x82 => self.branch_always_long(),
-                0x83 => memory_size!(self, store, Accumulator, MemoryStackRelative),
-                0x84 => index_size!(self, store, IndexY, MemoryDirectPage),
-                0x85 => memory_size!(self, store, Accumulator, MemoryDirectPage),
-                0x86 => index_size!(self, store, IndexX, MemoryDirectPage),
-                0x87 => memory_size!(self, store, Accumulator, MemoryDirectPageIndirectLong),
-                0x88 => index_size!(self, decrement, IndexY),
-                0x89 => memory_size!(self, bit_test, Immediate),
-                0x8A => memory_size!(self, transfer, IndexX, Accumulator),
-                0x8B => self.push::<u8, DataBank>(Default::default()),
-                0x8C => index_size!(self, store, IndexY, MemoryAbsolute),
-                0x8D => memory_size!(self, store, Accumulator, MemoryAbsolute),
-                0x8E => index_size!(self, store, IndexX, MemoryAbsolute),
-                0x8F => memory_size!(self, store, Accumulator, MemoryAbsoluteLong),
-                0x90 => self.branch(BranchCondition::CarryClear),
-                0x91 => memory_size!(self, store, Accumulator, MemoryDirectPageIndirectIndexedY),
-                0x92 => memory_size!(self, store, Accumulator, MemoryDirectPageIndirect),
-                0x93 => memory_size!(self, store, Accumulator, MemoryStackRelativeIndirectIndexedY),
-                0x94 => index_size!(self, store, IndexY, MemoryDirectPageIndexedX),
-                0x95 => memory_size!(self, store, Accumulator, MemoryDirectPageIndexedX),
-                0x96 => index_size!(self, store, IndexX, MemoryDirectPageIndexedY),
-                0x97 => memory_size!(self, store, Accumulator, MemoryDirectPageIndirectLongIndexedY),
-                0x98 => memory_size!(self, transfer, IndexY, Accumulator),
-                0x99 => memory_size!(self, store, Accumulator, MemoryAbsoluteIndexedY),
-                0x9A => self.transfer::<u16, IndexX, StackPointer>(Default::default(), Default::default()),
-                0x9B => index_size!(self, transfer, IndexX, IndexY),
-                0x9C => memory_size!(self, store_zero, MemoryAbsolute),
-                0x9D => memory_size!(self, store, Accumulator, MemoryAbsoluteIndexedX),
-                0x9E => memory_size!(self, store_zero, MemoryAbsoluteIndexedX),
-                0x9F => memory_size!(self, store, Accumulator, MemoryAbsoluteLongIndexedX),
-                0xA0 => index_size!(self, load, IndexY, Immediate),
-                0xA1 => memory_size!(self, load, Accumulator, MemoryDirectPageIndexedXIndirect),
-                0xA2 => index_size!(self, load, IndexX, Immediate),
-                0xA3 => memory_size!(self, load, Accumulator, MemoryStackRelative),
-                0xA4 => index_size!(self, load, IndexY, MemoryDirectPage),
-                0xA5 => memory_size!(self, load, Accumulator, MemoryDirectPage),
-                0xA6 => index_size!(self, load, IndexX, MemoryDirectPage),
-                0xA7 => memory_size!(self, load, Accumulator, MemoryDirectPageIndirectLong),
-                0xA8 => index_size!(self, transfer, Accumulator, IndexY),
-                0xA9 => memory_size!(self, load, Accumulator, Immediate),
-                0xAA => index_size!(self, transfer, Accumulator, IndexX),
-                0xAB => self.pull::<u8, DataBank>(Default::default()),
-                0xAC => index_size!(self, load, IndexY, MemoryAbsolute),
-                0xAD => memory_size!(self, load, Accumulator, MemoryAbsolute),
-                0xAE => index_size!(self, load, IndexX, MemoryAbsolute),
-                0xAF => memory_size!(self, load, Accumulator, MemoryAbsoluteLong),
-                0xB0 => self.branch(BranchCondition::CarrySet),
-                0xB1 => memory_size!(self, load, Accumulator, MemoryDirectPageIndirectIndexedY),
-                0xB2 => memory_size!(self, load, Accumulator, MemoryDirectPageIndirect),
-                0xB3 => memory_size!(self, load, Accumulator, MemoryStackRelativeIndirectIndexedY),
-                0xB4 => index_size!(self, load, IndexY, MemoryDirectPageIndexedX),
-                0xB5 => memory_size!(self, load, Accumulator, MemoryDirectPageIndexedX),
-                0xB6 => index_size!(self, load, IndexX, MemoryDirectPageIndexedY),
-                0xB7 => memory_size!(self, load, Accumulator, MemoryDirectPageIndirectLongIndexedY),
-                0xB8 => self.clear_overflow(),
-                0xB9 => memory_size!(self, load, Accumulator, MemoryAbsoluteIndexedY),
-                0xBA => index_size!(self, transfer, StackPointer, IndexX),
-                0xBB => index_size!(self, transfer, IndexY, IndexX),
-                0xBC => index_size!(self, load, IndexY, MemoryAbsoluteIndexedX),
-                0xBD => memory_size!(self, load, Accumulator, MemoryAbsoluteIndexedX),
-                0xBE => index_size!(self, load, IndexX, MemoryAbsoluteIndexedY),
-                0xBF => memory_size!(self, load, Accumulator, MemoryAbsoluteLongIndexedX),
-                0xC0 => index_size!(self, compare, IndexY, Immediate),
-                0xC1 => memory_size!(self, compare, Accumulator, MemoryDirectPageIndexedXIndirect),
-                0xC2 => self.reset_processor_state(),
-                0xC3 => memory_size!(self, compare, Accumulator, MemoryStackRelative),
-                0xC4 => index_size!(self, compare, IndexY, MemoryDirectPage),
-                0xC5 => memory_size!(self, compare, Accumulator, MemoryDirectPage),
-                0xC6 => memory_size!(self, decrement, MemoryDirectPage),
-                0xC7 => memory_size!(self, compare, Accumulator, MemoryDirectPageIndirectLong),
-                0xC8 => index_size!(self, increment, IndexY),
-                0xC9 => memory_size!(self, compare, Accumulator, Immediate),
-                0xCA => index_size!(self, decrement, IndexX),
-                0xCB => self.wait_for_interrupt(),
-                0xCC => index_size!(self, compare, IndexY, MemoryAbsolute),
-                0xCD => memory_size!(self, compare, Accumulator, MemoryAbsolute),
-                0xCE => memory_size!(self, decrement, MemoryAbsolute),
-                0xCF => memory_size!(self, compare, Accumulator, MemoryAbsoluteLong),
-                0xD0 => self.branch(BranchCondition::NotEqual),
-                0xD1 => memory_size!(self, compare, Accumulator, MemoryDirectPageIndirectIndexedY),
-                0xD2 => memory_size!(self, compare, Accumulator, MemoryDirectPageIndirect),
-                0xD3 => memory_size!(self, compare, Accumulator, MemoryStackRelativeIndirectIndexedY),
-                0xD4 => self.push_effective_address(MemoryDirectPageIndirect::<u16>::default()),
-                0xD5 => memory_size!(self, compare, Accumulator, MemoryDirectPageIndexedX),
-                0xD6 => memory_size!(self, decrement, MemoryDirectPageIndexedX),
-                0xD7 => memory_size!(self, compare, Accumulator, MemoryDirectPageIndirectLongIndexedY),
-                0xD8 => self.clear_decimal_mode(),
-                0xD9 => memory_size!(self, compare, Accumulator, MemoryAbsoluteIndexedY),
-                0xDA => index_size!(self, push, IndexX),
-                0xDB => self.stop(),
-                0xDC => self.jump_long(MemoryAbsoluteIndirectLong::<u16>::default()),
-                0xDD => memory_size!(self, compare, Accumulator, MemoryAbsoluteIndexedX),
-                0xDE => memory_size!(self, decrement, MemoryAbsoluteIndexedX),
-                0xDF => memory_size!(self, compare, Accumulator, MemoryAbsoluteLongIndexedX),
-                0xE0 => index_size!(self, compare, IndexX, Immediate),
-                0xE1 => memory_size!(self, subtract_with_carry, MemoryDirectPageIndexedXIndirect),
-                0xE2 => self.set_processor_state(),
-                0xE3 => memory_size!(self, subtract_with_carry, MemoryStackRelative),
-                0xE4 => index_size!(self, compare, IndexX, MemoryDirectPage),
-                0xE5 => memory_size!(self, subtract_with_carry, MemoryDirectPage),
-                0xE6 => memory_size!(self, increment, MemoryDirectPage),
-                0xE7 => memory_size!(self, subtract_with_carry, MemoryDirectPageIndirectLong),
-                0xE8 => index_size!(self, increment, IndexX),
-                0xE9 => memory_size!(self, subtract_with_carry, Immediate),
-                0xEA => { debug!("NOP"); self.io_cycle(); },
-                0xEB => self.exchange_accumulators(),
-                0xEC => index_size!(self, compare, IndexX, MemoryAbsolute),
-                0xED => memory_size!(self, subtract_with_carry, MemoryAbsolute),
-                0xEE => memory_size!(self, increment, MemoryAbsolute),
-                0xEF => memory_size!(self, subtract_with_carry, MemoryAbsoluteLong),
-                0xF0 => self.branch(BranchCondition::Equal),
-                0xF1 => memory_size!(self, subtract_with_carry, MemoryDirectPageIndirectIndexedY),
-                0xF2 => memory_size!(self, subtract_with_carry, MemoryDirectPageIndirect),
-                0xF3 => memory_size!(self, subtract_with_carry, MemoryStackRelativeIndirectIndexedY),
-                0xF4 => self.push_effective_address(MemoryAbsolute::<u16>::default()),
-                0xF5 => memory_size!(self, subtract_with_carry, MemoryDirectPageIndexedX),
-                0xF6 => memory_size!(self, increment, MemoryDirectPageIndexedX),
-                0xF7 => memory_size!(self, subtract_with_carry, MemoryDirectPageIndirectLongIndexedY),
-                0xF8 => self.set_decimal_mode(),
-                0xF9 => memory_size!(self, subtract_with_carry, MemoryAbsoluteIndexedY),
-                0xFA => index_size!(self, pull, IndexX),
-                0xFB => self.exchange_carry_and_emulation_bits(),
-                0xFC => self.jump_to_subroutine(MemoryAbsoluteIndexedXIndirect::<u16>::default()),
-                0xFD => memory_size!(self, subtract_with_carry, MemoryAbsoluteIndexedX),
-                0xFE => memory_size!(self, increment, MemoryAbsoluteIndexedX),
-                0xFF => memory_size!(self, subtract_with_carry, MemoryAbsoluteLongIndexedX),
-                op_code @ _ => panic!("Unrecognised op code: {:02X}", op_code)
-            };
+            let op_code = self.read_next::<u8>();
+
+            self.history.push(HistoryEntry {
+                address: instruction_address,
+                op_code: op_code,
+                regs: self.regs,
+                flags: self.flags
+            });
+
+            if let Some(ref mut hotspots) = self.hotspots {
+                hotspots.record(instruction_address, op_code);
+            }
+
+            self.op_table()[op_code as usize](self);
+            self.instruction_count += 1;
+
+            if let Some(ref mut cycle_audit) = cycle_audit {
+                cycle_audit.end(self);
+            }
+
+            self.cycle_audit = cycle_audit;
         }
 
-        debug!("A={:04X} X={:04X} Y={:04X} PC={:02X}:{:04X} DP={:04X} DB={:02X} SP={:04X} P={} E={} T={}",
+        debug!("{}", self);
+
+        if !self.watches.is_empty() {
+            self.check_watches(instruction_address);
+        }
+    }
+
+    fn trace_instruction(&mut self, instruction_address: HardwareAddress) {
+        let disassembly = disassembler::disassemble(self, instruction_address);
+        let bytes: Vec<String> = disassembly.bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+
+        let line = format!("{:06x} {:<11} {:<20} A:{:04x} X:{:04x} Y:{:04x} S:{:04x} D:{:04x} DB:{:02x} {} V:{:3} H:{:3} F:{:3} T:{}",
+            instruction_address.to_u32(),
+            bytes.join(" "),
+            disassembly.text,
             self.regs.accumulator,
             self.regs.index_x,
             self.regs.index_y,
-            self.regs.program_bank,
-            self.regs.program_counter,
+            self.regs.stack_pointer,
             self.regs.direct_page,
             self.regs.data_bank,
-            self.regs.stack_pointer,
-            self.flags,
-            self.flags.emulation_mode as u8,
-            self.hardware.clock());
+            self.flags.trace_format(),
+            self.hardware.scanline(),
+            self.hardware.dot(),
+            self.hardware.frame_count(),
+            self.hardware.master_cycles());
+
+        if let Some(ref mut tracer) = self.tracer {
+            tracer.write_line(&line);
+        }
+    }
+
+    // Reports the address of the instruction that changed each watched value
+    fn check_watches(&mut self, instruction_address: HardwareAddress) {
+        for watch in self.watches.iter_mut() {
+            if let Some(value) = watch.check(&mut self.hardware) {
+                info!("Watch: {} PC={} F={} V={} H={}",
+                    value,
+                    instruction_address,
+                    self.hardware.frame_count(),
+                    self.hardware.scanline(),
+                    self.hardware.dot());
+            }
+        }
+    }
+
+    // Soft reset: the CPU registers and flags go back to their power-on
+    // state (except A, which is left alone), along with the hardware
+    // registers. Memory and VRAM keep their contents.
+    pub fn reset(&mut self) {
+        self.hardware.reset();
+
+        self.regs.index_x &= 0x00FF;
+        self.regs.index_y &= 0x00FF;
+        self.regs.stack_pointer = 0x0100 | (self.regs.stack_pointer & 0x00FF);
+        self.regs.direct_page = 0;
+        self.regs.data_bank = 0;
+        self.regs.program_bank = 0;
+
+        self.flags.emulation_mode = true;
+        self.flags.memory_size = true;
+        self.flags.index_size = true;
+        self.flags.decimal_mode = false;
+        self.flags.interrupt_disable = true;
+
+        self.waiting = false;
+        self.halted = false;
+        self.spin_loop = None;
+        self.breakpoint_hit = None;
+        self.error = None;
+
+        self.regs.program_counter = self.hardware.read::<u16>(HardwareAddress::new(0, RESET_VECTOR));
     }
 
+    pub fn save_state(&self) -> CpuState {
+        CpuState {
+            regs: self.regs,
+            flags: self.flags,
+            waiting: self.waiting,
+            nmi_polled: self.hardware.nmi_polled(),
+            irq_polled: self.hardware.irq_polled()
+        }
+    }
+
+    // Restores registers and flags as they are, so the state must have come
+    // from a running CPU (e.g. 8-bit index registers already truncated)
+    pub fn load_state(&mut self, state: &CpuState) {
+        self.regs = state.regs;
+        self.flags = state.flags;
+        self.waiting = state.waiting;
+        self.hardware.set_polled_interrupts(state.nmi_polled, state.irq_polled);
+        self.spin_loop = None;
+    }
+
+    pub fn state_json(&self) -> JsonObject {
+        let mut regs = JsonObject::new();
+        regs.number("a", self.regs.accumulator);
+        regs.number("x", self.regs.index_x);
+        regs.number("y", self.regs.index_y);
+        regs.number("db", self.regs.data_bank);
+        regs.number("dp", self.regs.direct_page);
+        regs.number("pb", self.regs.program_bank);
+        regs.number("pc", self.regs.program_counter);
+        regs.number("sp", self.regs.stack_pointer);
+
+        let mut flags = JsonObject::new();
+        flags.boolean("n", self.flags.negative);
+        flags.boolean("v", self.flags.overflow);
+        flags.boolean("m", self.flags.memory_size);
+        flags.boolean("x", self.flags.index_size);
+        flags.boolean("d", self.flags.decimal_mode);
+        flags.boolean("i", self.flags.interrupt_disable);
+        flags.boolean("z", self.flags.zero);
+        flags.boolean("c", self.flags.carry);
+        flags.boolean("e", self.flags.emulation_mode);
+
+        let mut state = JsonObject::new();
+        state.object("regs", regs);
+        state.object("flags", flags);
+        state.object("hardware", self.hardware.state_json());
+        state
+    }
+}
+
+impl<H: Bus> Cpu<H> {
     /*
      * ACCESSORS
      */
 
-    pub fn hardware(&self) -> &Hardware {
+    pub fn hardware(&self) -> &H {
         &self.hardware
     }
 
-    pub fn hardware_mut(&mut self) -> &mut Hardware {
+    pub fn hardware_mut(&mut self) -> &mut H {
         &mut self.hardware
     }
 
+    fn program_address(&self) -> HardwareAddress {
+        HardwareAddress::new(self.regs.program_bank, self.regs.program_counter)
+    }
+
     pub fn regs(&self) -> &CpuRegisters {
         &self.regs
     }
@@ -443,6 +877,15 @@ impl Cpu {
         &mut self.flags
     }
 
+    // The P register as a byte
+    pub fn status(&mut self) -> u8 {
+        ProcessorState::default().get(self)
+    }
+
+    pub fn set_status(&mut self, value: u8) {
+        ProcessorState::default().set(self, value);
+    }
+
     /*
      * MEMORY READ/WRITE
      */
@@ -468,6 +911,23 @@ impl Cpu {
         }
     }
 
+    fn op_table(&self) -> &'static [OpHandler<H>; 256] {
+        match (self.flags.memory_size, self.flags.index_size) {
+            (true, true) => &Self::OP_TABLE_M8_X8,
+            (true, false) => &Self::OP_TABLE_M8_X16,
+            (false, true) => &Self::OP_TABLE_M16_X8,
+            (false, false) => &Self::OP_TABLE_M16_X16
+        }
+    }
+
+    // One instruction with none of tick's interrupt polling or debugging
+    // hooks, for driving the core over a test bus
+    #[cfg(test)]
+    pub fn execute_instruction(&mut self) {
+        let op_code = self.read_next::<u8>();
+        self.op_table()[op_code as usize](self);
+    }
+
     fn set_zero_and_negative<T>(&mut self, value: T) where T: Value {
         self.flags.zero = value.is_zero();
         self.flags.negative = value.is_negative();
@@ -485,6 +945,38 @@ impl Cpu {
      * INTERRUPTS
      */
 
+    fn wdm(&mut self) {
+        let signature = self.read_next::<u8>();
+        debug!("WDM #${:02X}", signature);
+
+        // As with the COP hook, the handler is taken out while it runs
+        if let Some(mut handler) = self.wdm_handlers.remove(&signature) {
+            handler(self);
+            self.wdm_handlers.entry(signature).or_insert(handler);
+        }
+    }
+
+    fn coprocessor_interrupt(&mut self) {
+        let signature = self.read_next::<u8>();
+        debug!("COP {:02X}", signature);
+
+        // The hook is taken out while it runs, as it gets mutable access to
+        // the CPU. It is put back unless it installed a replacement.
+        if let Some(mut hook) = self.cop_hook.take() {
+            let handled = hook(self, signature);
+
+            if self.cop_hook.is_none() {
+                self.cop_hook = Some(hook);
+            }
+
+            if handled {
+                return;
+            }
+        }
+
+        self.enter_interrupt::<Coprocessor>();
+    }
+
     fn interrupt<I: Interrupt>(&mut self) {
         if I::has_signature() {
             let signature = self.read_next::<u8>();
@@ -493,6 +985,10 @@ impl Cpu {
             debug!("{}", I::as_str());
         }
 
+        self.enter_interrupt::<I>();
+    }
+
+    fn enter_interrupt<I: Interrupt>(&mut self) {
         let processor_state = ProcessorState::default();
 
         self.io_cycle();
@@ -535,19 +1031,14 @@ impl Cpu {
         let accumulator = Accumulator::<T>::default();
         let lhs = accumulator.get(self);
         let rhs = accessor.get(self);
-        let carry = T::from_bool(self.flags.carry);
-
         let result = if self.flags.decimal_mode {
-            let lhs_decimal = lhs.to_decimal();
-            let rhs_decimal = rhs.to_decimal();
-            let decimal_result = lhs_decimal.add_value(rhs_decimal).add_value(carry);
-            self.flags.carry = !decimal_result.is_valid_decimal();
-            // TODO: Decimal mode overflow flag
-            let binary_result = decimal_result.to_binary();
-            debug!("Add (BIN): {:04X} + {:04X} = {:04X}", lhs, rhs, binary_result);
-            debug!("Add (DEC): {} + {} = {}", lhs_decimal, rhs_decimal, decimal_result);
-            binary_result
+            let (result, carry, overflow) = lhs.decimal_add(rhs, self.flags.carry);
+            self.flags.carry = carry;
+            self.flags.overflow = overflow;
+            debug!("Add (DEC): {:04X} + {:04X} = {:04X}", lhs, rhs, result);
+            result
         } else {
+            let carry = T::from_bool(self.flags.carry);
             let result = lhs.add_value(rhs).add_value(carry);
             self.flags.carry = result < lhs;
             self.flags.overflow = (!(lhs ^ rhs) & (rhs ^ result)).is_negative();
@@ -572,10 +1063,11 @@ impl Cpu {
     fn arithmetic_shift_left<T: Value, A: AddressMode<T>>(&mut self, parameter: A) 
         where A::Output: Write<T>
     {
-        let accessor = parameter.resolve(self);
+        let accessor = parameter.resolve_for_write(self);
         debug!("ASL {}", accessor);
-        let (result, carry) = accessor.get(self).left_shift_value();
-        self.io_cycle();
+        let value = accessor.get(self);
+        let (result, carry) = value.left_shift_value();
+        accessor.modify_cycle(self, value);
         accessor.set(self, result);
         self.flags.carry = carry;
         self.set_zero_and_negative(result);
@@ -609,15 +1101,38 @@ impl Cpu {
         };
 
         if should_branch {
-            self.regs.program_counter = (self.regs.program_counter as i16).wrapping_add(offset as i16) as u16;
+            let origin = self.regs.program_counter;
+            self.regs.program_counter = (origin as i16).wrapping_add(offset as i16) as u16;
             debug!("Branched to {:04X}", self.regs.program_counter);
             self.io_cycle();
-            // TODO: Emulation mode extra cycle?
+
+            // Emulation mode takes an extra cycle if the branch crosses a page
+            if self.flags.emulation_mode && (origin & 0xFF00) != (self.regs.program_counter & 0xFF00) {
+                self.io_cycle();
+            }
+
+            if offset < 0 && offset >= -SPIN_LOOP_BYTES {
+                self.track_spin_loop();
+            }
         } else {
             debug!("Branch not taken");
         }
     }
 
+    // Every full iteration of the same short loop counts as idle time
+    fn track_spin_loop(&mut self) {
+        let target = self.regs.program_counter;
+        let clock = self.hardware.master_cycles();
+
+        if let Some((previous_target, previous_clock)) = self.spin_loop {
+            if previous_target == target {
+                self.idle_cycles += clock.wrapping_sub(previous_clock);
+            }
+        }
+
+        self.spin_loop = Some((target, clock));
+    }
+
     fn branch_always_long(&mut self) {
         let offset = self.read_next::<u16>() as i16;
         debug!("BRL {:+}", offset);
@@ -663,10 +1178,11 @@ impl Cpu {
     fn decrement<T: Value, A: AddressMode<T>>(&mut self, parameter: A)
         where A::Output: Write<T>
     {
-        let accessor = parameter.resolve(self);
+        let accessor = parameter.resolve_for_write(self);
         debug!("DEC {}", accessor);
-        let result = accessor.get(self).subtract_value(T::from(1));
-        self.io_cycle();
+        let value = accessor.get(self);
+        let result = value.subtract_value(T::from(1));
+        accessor.modify_cycle(self, value);
         accessor.set(self, result);
         self.set_zero_and_negative(result);
     }
@@ -685,10 +1201,11 @@ impl Cpu {
     fn increment<T: Value, A: AddressMode<T>>(&mut self, parameter: A)
         where A::Output: Write<T>
     {
-        let accessor = parameter.resolve(self);
+        let accessor = parameter.resolve_for_write(self);
         debug!("INC {}", accessor);
-        let result = accessor.get(self).add_value(T::from(1));
-        self.io_cycle();
+        let value = accessor.get(self);
+        let result = value.add_value(T::from(1));
+        accessor.modify_cycle(self, value);
         accessor.set(self, result);
         self.set_zero_and_negative(result);
     }
@@ -743,10 +1260,11 @@ impl Cpu {
     fn logical_shift_right<T: Value, A: AddressMode<T>>(&mut self, parameter: A) 
         where A::Output: Write<T>
     {
-        let accessor = parameter.resolve(self);
+        let accessor = parameter.resolve_for_write(self);
         debug!("LSR {}", accessor);
-        let (result, carry) = accessor.get(self).right_shift_value();
-        self.io_cycle();
+        let value = accessor.get(self);
+        let (result, carry) = value.right_shift_value();
+        accessor.modify_cycle(self, value);
         accessor.set(self, result);
         self.flags.carry = carry;
         self.set_zero_and_negative(result);
@@ -758,27 +1276,31 @@ impl Cpu {
 
         debug!("MV{} ${:02X},${:02X}", block_move, src_bank, dst_bank);
 
+        // The data bank is left pointing at the destination
+        self.regs.data_bank = dst_bank;
+
         let value = self.hardware.read::<u8>(HardwareAddress::new(src_bank, self.regs.index_x));
         self.hardware.write(HardwareAddress::new(dst_bank, self.regs.index_y), value);
 
-        match block_move {
-            BlockMove::Negative => {
-                self.regs.index_x = self.regs.index_x.wrapping_add(1);
-                self.regs.index_y = self.regs.index_y.wrapping_add(1);
-            },
-            BlockMove::Positive => {
-                self.regs.index_x = self.regs.index_x.wrapping_sub(1);
-                self.regs.index_y = self.regs.index_x.wrapping_sub(1);
-            }
+        let step = match block_move {
+            BlockMove::Negative => 1,
+            BlockMove::Positive => 0xFFFF
         };
 
+        // With 8-bit index registers, only the low bytes of X and Y change
+        let index_mask = if self.flags.index_size { 0x00FF } else { 0xFFFF };
+        self.regs.index_x = self.regs.index_x.wrapping_add(step) & index_mask;
+        self.regs.index_y = self.regs.index_y.wrapping_add(step) & index_mask;
+
         self.regs.accumulator = self.regs.accumulator.wrapping_sub(1);
 
         self.io_cycle();
         self.io_cycle();
 
         if self.regs.accumulator != 0xFFFF {
-            // Repeat this operation next tick instead of advancing the program counter
+            // Repeat this operation next tick instead of advancing the program
+            // counter. As each byte is a separate instruction, a pending NMI or
+            // IRQ is taken between bytes and returns to the MVN/MVP opcode.
             self.regs.program_counter = self.regs.program_counter.wrapping_sub(3);
         }
     }
@@ -857,11 +1379,12 @@ impl Cpu {
     fn rotate_left<T: Value, A: AddressMode<T>>(&mut self, parameter: A)
         where A::Output: Write<T>
     {
-        let accessor = parameter.resolve(self);
+        let accessor = parameter.resolve_for_write(self);
         debug!("ROL {}", accessor);
         let old_carry = self.flags.carry;
-        let (result, new_carry) = accessor.get(self).left_rotate_value(old_carry);
-        self.io_cycle();
+        let value = accessor.get(self);
+        let (result, new_carry) = value.left_rotate_value(old_carry);
+        accessor.modify_cycle(self, value);
         accessor.set(self, result);
         self.flags.carry = new_carry;
         self.set_zero_and_negative(result);
@@ -870,11 +1393,12 @@ impl Cpu {
     fn rotate_right<T: Value, A: AddressMode<T>>(&mut self, parameter: A)
         where A::Output: Write<T>
     {
-        let accessor = parameter.resolve(self);
+        let accessor = parameter.resolve_for_write(self);
         debug!("ROR {}", accessor);
         let old_carry = self.flags.carry;
-        let (result, new_carry) = accessor.get(self).right_rotate_value(old_carry);
-        self.io_cycle();
+        let value = accessor.get(self);
+        let (result, new_carry) = value.right_rotate_value(old_carry);
+        accessor.modify_cycle(self, value);
         accessor.set(self, result);
         self.flags.carry = new_carry;
         self.set_zero_and_negative(result);
@@ -909,13 +1433,15 @@ impl Cpu {
 
     fn stop(&mut self) {
         debug!("STP");
-        panic!("Processor stopped!");
+        let address = HardwareAddress::new(self.regs.program_bank, self.regs.program_counter.wrapping_sub(1));
+        self.halted = true;
+        self.error = Some(EmulationError::ProcessorStopped(address));
     }
 
     fn store<T: Value, A: Read<T>, B: AddressMode<T>>(&mut self, register: A, parameter: B)
         where B::Output: Write<T>
     {
-        let accessor = parameter.resolve(self);
+        let accessor = parameter.resolve_for_write(self);
         debug!("ST{} {}", register, accessor);
         let value = register.get(self);
         accessor.set(self, value);
@@ -924,7 +1450,7 @@ impl Cpu {
     fn store_zero<T: Value, A: AddressMode<T>>(&mut self, parameter: A)
         where A::Output: Write<T>
     {
-        let accessor = parameter.resolve(self);
+        let accessor = parameter.resolve_for_write(self);
         debug!("STZ {}", accessor);
         accessor.set(self, T::from(0));
     }
@@ -935,19 +1461,14 @@ impl Cpu {
         let accumulator = Accumulator::<T>::default();
         let lhs = accumulator.get(self);
         let rhs = accessor.get(self);
-        let carry = T::from_bool(!self.flags.carry);
-
         let result = if self.flags.decimal_mode {
-            let lhs_decimal = lhs.to_decimal();
-            let rhs_decimal = rhs.to_decimal();
-            let decimal_result = lhs_decimal.subtract_value(rhs_decimal).subtract_value(carry);
-            self.flags.carry = decimal_result.is_valid_decimal();
-            // TODO: Decimal mode overflow flag
-            let binary_result = decimal_result.fix_underflow().to_binary();
-            debug!("Subtract (BIN): {:04X} - {:04X} = {:04X}", lhs, rhs, binary_result);
-            debug!("Subtract (DEC): {} - {} = {}", lhs_decimal, rhs_decimal, decimal_result);
-            binary_result
+            let (result, carry, overflow) = lhs.decimal_subtract(rhs, self.flags.carry);
+            self.flags.carry = carry;
+            self.flags.overflow = overflow;
+            debug!("Subtract (DEC): {:04X} - {:04X} = {:04X}", lhs, rhs, result);
+            result
         } else {
+            let carry = T::from_bool(!self.flags.carry);
             let result = lhs.subtract_value(rhs).subtract_value(carry);
             self.flags.carry = result <= lhs;
             self.flags.overflow = ((lhs ^ rhs) & (lhs ^ result)).is_negative();
@@ -961,11 +1482,11 @@ impl Cpu {
     fn test_and_reset_bits<T: Value, A: AddressMode<T>>(&mut self, parameter: A)
         where A::Output: Write<T>
     {
-        let accessor = parameter.resolve(self);
+        let accessor = parameter.resolve_for_write(self);
         debug!("TRB {}", accessor);
         let lhs = Accumulator::<T>::default().get(self);
         let rhs = accessor.get(self);
-        self.io_cycle();
+        accessor.modify_cycle(self, rhs);
         accessor.set(self, (!lhs) & rhs);
         self.flags.zero = (lhs & rhs).is_zero();
     }
@@ -973,11 +1494,11 @@ impl Cpu {
     fn test_and_set_bits<T: Value, A: AddressMode<T>>(&mut self, parameter: A)
         where A::Output: Write<T>
     {
-        let accessor = parameter.resolve(self);
+        let accessor = parameter.resolve_for_write(self);
         debug!("TSB {}", accessor);
         let lhs = Accumulator::<T>::default().get(self);
         let rhs = accessor.get(self);
-        self.io_cycle();
+        accessor.modify_cycle(self, rhs);
         accessor.set(self, lhs | rhs);
         self.flags.zero = (lhs & rhs).is_zero();
     }
@@ -996,7 +1517,9 @@ impl Cpu {
     
     fn wait_for_interrupt(&mut self) {
         debug!("WAI");
-        panic!("Interrupts not yet supported!");
+        self.io_cycle();
+        self.io_cycle();
+        self.waiting = true;
     }
 
     fn exchange_accumulators(&mut self) {
@@ -1016,6 +1539,46 @@ impl Cpu {
     }
 }
 
+impl Display for Cpu {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "A={:04X} X={:04X} Y={:04X} PC={:02X}:{:04X} DP={:04X} DB={:02X} SP={:04X} P={} E={} T={} F={} V={} H={}",
+            self.regs.accumulator,
+            self.regs.index_x,
+            self.regs.index_y,
+            self.regs.program_bank,
+            self.regs.program_counter,
+            self.regs.direct_page,
+            self.regs.data_bank,
+            self.regs.stack_pointer,
+            self.flags,
+            self.flags.emulation_mode as u8,
+            self.hardware.master_cycles(),
+            self.hardware.frame_count(),
+            self.hardware.scanline(),
+            self.hardware.dot())
+    }
+}
+
+impl CpuFlags {
+    // bsnes style: each flag letter is upper case when set
+    fn trace_format(&self) -> String {
+        let flags = [
+            (self.negative, 'n'),
+            (self.overflow, 'v'),
+            (self.memory_size, 'm'),
+            (self.index_size, 'x'),
+            (self.decimal_mode, 'd'),
+            (self.interrupt_disable, 'i'),
+            (self.zero, 'z'),
+            (self.carry, 'c')
+        ];
+
+        flags.iter()
+            .map(|&(set, letter)| if set { letter.to_ascii_uppercase() } else { letter })
+            .collect()
+    }
+}
+
 impl Display for CpuFlags {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         write!(f, "{}{}{}{}{}{}{}{}",
@@ -1062,3 +1625,236 @@ impl Display for BlockMove {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use cpu::run_condition::RunCondition;
+    use hardware::{HardwareAddress, RomBuilder};
+    use std::sync::{Arc, Mutex};
+    use super::Cpu;
+
+    const STACK_TOP: u16 = 0x1FFF;
+
+    // COP pushes PB, PC and P in native mode
+    const COP_STACK_BYTES: u16 = 4;
+
+    const COP_TWICE: [u8; 4] = [
+        0x02, 0x42, // cop #$42
+        0x02, 0x43  // cop #$43
+    ];
+
+    // Counts upwards at $0010 forever
+    const COUNTER_LOOP: [u8; 4] = [
+        0xE6, 0x10, // inc $10
+        0x80, 0xFC  // bra -4
+    ];
+
+    fn test_cpu(code: &[u8]) -> Cpu {
+        let mut cpu = RomBuilder::new().code(code).build_cpu();
+        cpu.run_until(&RunCondition::ProgramCounter(HardwareAddress::new(0x00, RomBuilder::code_address())));
+        cpu
+    }
+
+    // Runs the code through to the loop that follows it
+    fn run_to_end(cpu: &mut Cpu, code: &[u8]) {
+        let end = RomBuilder::code_address() + code.len() as u16;
+        cpu.run_until(&RunCondition::ProgramCounter(HardwareAddress::new(0x00, end)));
+    }
+
+    fn peek(cpu: &mut Cpu, bank: u8, offset: u16) -> u8 {
+        cpu.hardware_mut().peek(HardwareAddress::new(bank, offset)).unwrap()
+    }
+
+    // Records each signature, and handles only those in 'handled'
+    fn recording_hook(cpu: &mut Cpu, handled: &'static [u8]) -> Arc<Mutex<Vec<u8>>> {
+        let signatures = Arc::new(Mutex::new(Vec::new()));
+        let recorded = signatures.clone();
+
+        cpu.set_cop_hook(Box::new(move |_, signature| {
+            recorded.lock().unwrap().push(signature);
+            handled.contains(&signature)
+        }));
+
+        signatures
+    }
+
+    #[test]
+    fn hook_can_handle_cop() {
+        let mut cpu = test_cpu(&COP_TWICE);
+        let signatures = recording_hook(&mut cpu, &[0x42]);
+
+        cpu.step();
+        assert_eq!(cpu.regs().program_counter, RomBuilder::code_address() + 2);
+        assert_eq!(cpu.regs().stack_pointer, STACK_TOP);
+
+        // Not handled, so the vector is taken
+        cpu.step();
+        assert_eq!(cpu.regs().stack_pointer, STACK_TOP - COP_STACK_BYTES);
+
+        assert_eq!(*signatures.lock().unwrap(), vec![0x42, 0x43]);
+    }
+
+    #[test]
+    fn cleared_hook_is_not_called() {
+        let mut cpu = test_cpu(&COP_TWICE);
+        let signatures = recording_hook(&mut cpu, &[0x42, 0x43]);
+
+        cpu.clear_cop_hook();
+        cpu.step();
+        assert_eq!(cpu.regs().stack_pointer, STACK_TOP - COP_STACK_BYTES);
+
+        assert!(signatures.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn wdm_dispatches_by_signature() {
+        let mut cpu = test_cpu(&[
+            0x42, 0x01, // wdm #$01
+            0x42, 0x02, // wdm #$02
+            0x42, 0x01  // wdm #$01
+        ]);
+
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let recorded = calls.clone();
+
+        cpu.set_wdm_handler(0x01, Box::new(move |cpu| {
+            recorded.lock().unwrap().push(cpu.regs().program_counter);
+        }));
+
+        // The signature byte is consumed whether or not it has a handler
+        for offset in [2, 4, 6].iter() {
+            cpu.step();
+            assert_eq!(cpu.regs().program_counter, RomBuilder::code_address() + offset);
+        }
+
+        // The handler is put back after each call
+        let code_address = RomBuilder::code_address();
+        assert_eq!(*calls.lock().unwrap(), vec![code_address + 2, code_address + 6]);
+    }
+
+    #[test]
+    fn runs_for_at_least_the_given_cycles() {
+        let mut cpu = test_cpu(&COUNTER_LOOP);
+        let start = cpu.hardware().master_cycles();
+
+        cpu.run_for_cycles(1000);
+
+        // Each instruction runs to completion, so it can overshoot a little
+        let elapsed = cpu.hardware().master_cycles() - start;
+        assert!(elapsed >= 1000 && elapsed < 1100, "Ran for {} cycles", elapsed);
+    }
+
+    #[test]
+    fn run_for_cycles_stops_at_breakpoint() {
+        let mut cpu = test_cpu(&COUNTER_LOOP);
+        let address = HardwareAddress::new(0x00, RomBuilder::code_address() + 2);
+        let start = cpu.hardware().master_cycles();
+
+        cpu.breakpoints_mut().set(address);
+        cpu.run_for_cycles(1_000_000);
+
+        assert!(cpu.take_breakpoint_hit() == Some(address));
+        assert!(cpu.hardware().master_cycles() - start < 1000);
+    }
+
+    #[test]
+    fn mvn_moves_upwards_into_destination_bank() {
+        let code = [
+            0xC2, 0x30,       // rep #$30
+            0xA9, 0x02, 0x00, // lda #$0002
+            0xA2, 0x00, 0x80, // ldx #$8000
+            0xA0, 0x00, 0x10, // ldy #$1000
+            0x54, 0x7E, 0x00  // mvn $00,$7e
+        ];
+
+        let mut cpu = RomBuilder::new().code(&code).build_cpu();
+        run_to_end(&mut cpu, &code);
+
+        for offset in 0..3 {
+            assert_eq!(peek(&mut cpu, 0x7E, 0x1000 + offset), peek(&mut cpu, 0x00, 0x8000 + offset));
+        }
+
+        assert_eq!(cpu.regs().accumulator, 0xFFFF);
+        assert_eq!(cpu.regs().index_x, 0x8003);
+        assert_eq!(cpu.regs().index_y, 0x1003);
+        assert_eq!(cpu.regs().data_bank, 0x7E);
+    }
+
+    #[test]
+    fn mvp_wraps_8_bit_indexes() {
+        let code = [
+            0xA9, 0x11, 0x85, 0x01, // lda #$11; sta $01
+            0xA9, 0x22, 0x85, 0x00, // lda #$22; sta $00
+            0xA9, 0x33, 0x85, 0xFF, // lda #$33; sta $ff
+            0xC2, 0x20,             // rep #$20
+            0xA9, 0x02, 0x00,       // lda #$0002
+            0xA2, 0x01,             // ldx #$01
+            0xA0, 0x41,             // ldy #$41
+            0x44, 0x00, 0x00        // mvp $00,$00
+        ];
+
+        let mut cpu = RomBuilder::new().code(&code).build_cpu();
+        run_to_end(&mut cpu, &code);
+
+        assert_eq!(peek(&mut cpu, 0x7E, 0x0041), 0x11);
+        assert_eq!(peek(&mut cpu, 0x7E, 0x0040), 0x22);
+        assert_eq!(peek(&mut cpu, 0x7E, 0x003F), 0x33);
+
+        // Only the low bytes count down, so $0000 is followed by $00FF
+        assert_eq!(cpu.regs().accumulator, 0xFFFF);
+        assert_eq!(cpu.regs().index_x, 0x00FE);
+        assert_eq!(cpu.regs().index_y, 0x003E);
+        assert_eq!(cpu.regs().data_bank, 0x00);
+    }
+
+    #[test]
+    fn irq_during_block_move_returns_to_opcode() {
+        // The move takes longer than a frame, so the V-IRQ fires part way
+        // through it. The handler saves the return address at $7E:0000.
+        let code = [
+            0xA9, 0x10, 0x8D, 0x09, 0x42, // lda #16; sta $4209
+            0x9C, 0x0A, 0x42,             // stz $420a
+            0xA9, 0x20, 0x8D, 0x00, 0x42, // lda #$20; sta $4200
+            0xC2, 0x30,                   // rep #$30
+            0xA9, 0xFF, 0x1F,             // lda #$1fff
+            0xA2, 0x00, 0x80,             // ldx #$8000
+            0xA0, 0x00, 0x20,             // ldy #$2000
+            0x58,                         // cli
+            0x54, 0x7E, 0x00,             // mvn $00,$7e
+            0x78                          // sei
+        ];
+
+        let move_address = RomBuilder::code_address() + 25;
+
+        let mut cpu = RomBuilder::new()
+            .code(&code)
+            .irq_handler(&[
+                0x48,                         // pha
+                0xA3, 0x04,                   // lda $04,s
+                0x8F, 0x00, 0x00, 0x7E,       // sta $7e0000
+                0xAF, 0x11, 0x42, 0x00,       // lda $004211
+                0x68                          // pla
+            ])
+            .build_cpu();
+
+        run_to_end(&mut cpu, &code);
+
+        assert_eq!(peek(&mut cpu, 0x7E, 0x0000) as u16 | (peek(&mut cpu, 0x7E, 0x0001) as u16) << 8, move_address);
+
+        // Resuming at the opcode finishes the move without losing a byte
+        for offset in 0..0x2000 {
+            assert_eq!(peek(&mut cpu, 0x7E, 0x2000 + offset), peek(&mut cpu, 0x00, 0x8000 + offset));
+        }
+
+        assert_eq!(cpu.regs().accumulator, 0xFFFF);
+        assert_eq!(cpu.regs().index_x, 0xA000);
+        assert_eq!(cpu.regs().index_y, 0x4000);
+    }
+
+    // Fails to compile if an Rc, Cell or raw pointer creeps back into the core
+    #[test]
+    fn cpu_is_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<Cpu>();
+    }
+}
@@ -0,0 +1,463 @@
+use cpu::Cpu;
+use cpu::disassembler;
+use hardware::HardwareAddress;
+use std::collections::HashSet;
+
+// Cycles added to the base count, following the notes in the 65816 data sheet
+const NONE: u16 = 0x0000;
+// +1 if the accumulator/memory is 16-bit
+const MEMORY: u16 = 0x0001;
+// +2 if the accumulator/memory is 16-bit (read-modify-write)
+const MODIFY: u16 = 0x0002;
+// +1 if the index registers are 16-bit
+const INDEX: u16 = 0x0004;
+// +1 if the low byte of the direct page register is non-zero
+const DIRECT: u16 = 0x0008;
+// +1 if indexing by X/Y crosses a page, or the index registers are 16-bit
+const CROSS_X: u16 = 0x0010;
+const CROSS_Y: u16 = 0x0020;
+const CROSS_DIRECT_Y: u16 = 0x0040;
+// +1 if taken, and +1 more if taken across a page in emulation mode
+const BRANCH: u16 = 0x0080;
+// +1 in native mode (which pushes the program bank)
+const NATIVE: u16 = 0x0100;
+
+// Base cycle count and adjustments for each op code. MVN/MVP are per byte.
+static CYCLES: [(u8, u16); 256] = [
+    /* 00 */ (7, NATIVE),
+    /* 01 */ (6, MEMORY | DIRECT),
+    /* 02 */ (7, NATIVE),
+    /* 03 */ (4, MEMORY),
+    /* 04 */ (5, MODIFY | DIRECT),
+    /* 05 */ (3, MEMORY | DIRECT),
+    /* 06 */ (5, MODIFY | DIRECT),
+    /* 07 */ (6, MEMORY | DIRECT),
+    /* 08 */ (3, NONE),
+    /* 09 */ (2, MEMORY),
+    /* 0A */ (2, NONE),
+    /* 0B */ (4, NONE),
+    /* 0C */ (6, MODIFY),
+    /* 0D */ (4, MEMORY),
+    /* 0E */ (6, MODIFY),
+    /* 0F */ (5, MEMORY),
+    /* 10 */ (2, BRANCH),
+    /* 11 */ (5, MEMORY | DIRECT | CROSS_DIRECT_Y),
+    /* 12 */ (5, MEMORY | DIRECT),
+    /* 13 */ (7, MEMORY),
+    /* 14 */ (5, MODIFY | DIRECT),
+    /* 15 */ (4, MEMORY | DIRECT),
+    /* 16 */ (6, MODIFY | DIRECT),
+    /* 17 */ (6, MEMORY | DIRECT),
+    /* 18 */ (2, NONE),
+    /* 19 */ (4, MEMORY | CROSS_Y),
+    /* 1A */ (2, NONE),
+    /* 1B */ (2, NONE),
+    /* 1C */ (6, MODIFY),
+    /* 1D */ (4, MEMORY | CROSS_X),
+    /* 1E */ (7, MODIFY),
+    /* 1F */ (5, MEMORY),
+    /* 20 */ (6, NONE),
+    /* 21 */ (6, MEMORY | DIRECT),
+    /* 22 */ (8, NONE),
+    /* 23 */ (4, MEMORY),
+    /* 24 */ (3, MEMORY | DIRECT),
+    /* 25 */ (3, MEMORY | DIRECT),
+    /* 26 */ (5, MODIFY | DIRECT),
+    /* 27 */ (6, MEMORY | DIRECT),
+    /* 28 */ (4, NONE),
+    /* 29 */ (2, MEMORY),
+    /* 2A */ (2, NONE),
+    /* 2B */ (5, NONE),
+    /* 2C */ (4, MEMORY),
+    /* 2D */ (4, MEMORY),
+    /* 2E */ (6, MODIFY),
+    /* 2F */ (5, MEMORY),
+    /* 30 */ (2, BRANCH),
+    /* 31 */ (5, MEMORY | DIRECT | CROSS_DIRECT_Y),
+    /* 32 */ (5, MEMORY | DIRECT),
+    /* 33 */ (7, MEMORY),
+    /* 34 */ (4, MEMORY | DIRECT),
+    /* 35 */ (4, MEMORY | DIRECT),
+    /* 36 */ (6, MODIFY | DIRECT),
+    /* 37 */ (6, MEMORY | DIRECT),
+    /* 38 */ (2, NONE),
+    /* 39 */ (4, MEMORY | CROSS_Y),
+    /* 3A */ (2, NONE),
+    /* 3B */ (2, NONE),
+    /* 3C */ (4, MEMORY | CROSS_X),
+    /* 3D */ (4, MEMORY | CROSS_X),
+    /* 3E */ (7, MODIFY),
+    /* 3F */ (5, MEMORY),
+    /* 40 */ (6, NATIVE),
+    /* 41 */ (6, MEMORY | DIRECT),
+    /* 42 */ (2, NONE),
+    /* 43 */ (4, MEMORY),
+    /* 44 */ (7, NONE),
+    /* 45 */ (3, MEMORY | DIRECT),
+    /* 46 */ (5, MODIFY | DIRECT),
+    /* 47 */ (6, MEMORY | DIRECT),
+    /* 48 */ (3, MEMORY),
+    /* 49 */ (2, MEMORY),
+    /* 4A */ (2, NONE),
+    /* 4B */ (3, NONE),
+    /* 4C */ (3, NONE),
+    /* 4D */ (4, MEMORY),
+    /* 4E */ (6, MODIFY),
+    /* 4F */ (5, MEMORY),
+    /* 50 */ (2, BRANCH),
+    /* 51 */ (5, MEMORY | DIRECT | CROSS_DIRECT_Y),
+    /* 52 */ (5, MEMORY | DIRECT),
+    /* 53 */ (7, MEMORY),
+    /* 54 */ (7, NONE),
+    /* 55 */ (4, MEMORY | DIRECT),
+    /* 56 */ (6, MODIFY | DIRECT),
+    /* 57 */ (6, MEMORY | DIRECT),
+    /* 58 */ (2, NONE),
+    /* 59 */ (4, MEMORY | CROSS_Y),
+    /* 5A */ (3, INDEX),
+    /* 5B */ (2, NONE),
+    /* 5C */ (4, NONE),
+    /* 5D */ (4, MEMORY | CROSS_X),
+    /* 5E */ (7, MODIFY),
+    /* 5F */ (5, MEMORY),
+    /* 60 */ (6, NONE),
+    /* 61 */ (6, MEMORY | DIRECT),
+    /* 62 */ (6, NONE),
+    /* 63 */ (4, MEMORY),
+    /* 64 */ (3, MEMORY | DIRECT),
+    /* 65 */ (3, MEMORY | DIRECT),
+    /* 66 */ (5, MODIFY | DIRECT),
+    /* 67 */ (6, MEMORY | DIRECT),
+    /* 68 */ (4, MEMORY),
+    /* 69 */ (2, MEMORY),
+    /* 6A */ (2, NONE),
+    /* 6B */ (6, NONE),
+    /* 6C */ (5, NONE),
+    /* 6D */ (4, MEMORY),
+    /* 6E */ (6, MODIFY),
+    /* 6F */ (5, MEMORY),
+    /* 70 */ (2, BRANCH),
+    /* 71 */ (5, MEMORY | DIRECT | CROSS_DIRECT_Y),
+    /* 72 */ (5, MEMORY | DIRECT),
+    /* 73 */ (7, MEMORY),
+    /* 74 */ (4, MEMORY | DIRECT),
+    /* 75 */ (4, MEMORY | DIRECT),
+    /* 76 */ (6, MODIFY | DIRECT),
+    /* 77 */ (6, MEMORY | DIRECT),
+    /* 78 */ (2, NONE),
+    /* 79 */ (4, MEMORY | CROSS_Y),
+    /* 7A */ (4, INDEX),
+    /* 7B */ (2, NONE),
+    /* 7C */ (6, NONE),
+    /* 7D */ (4, MEMORY | CROSS_X),
+    /* 7E */ (7, MODIFY),
+    /* 7F */ (5, MEMORY),
+    /* 80 */ (2, BRANCH),
+    /* 81 */ (6, MEMORY | DIRECT),
+    /* 82 */ (4, NONE),
+    /* 83 */ (4, MEMORY),
+    /* 84 */ (3, INDEX | DIRECT),
+    /* 85 */ (3, MEMORY | DIRECT),
+    /* 86 */ (3, INDEX | DIRECT),
+    /* 87 */ (6, MEMORY | DIRECT),
+    /* 88 */ (2, NONE),
+    /* 89 */ (2, MEMORY),
+    /* 8A */ (2, NONE),
+    /* 8B */ (3, NONE),
+    /* 8C */ (4, INDEX),
+    /* 8D */ (4, MEMORY),
+    /* 8E */ (4, INDEX),
+    /* 8F */ (5, MEMORY),
+    /* 90 */ (2, BRANCH),
+    /* 91 */ (6, MEMORY | DIRECT),
+    /* 92 */ (5, MEMORY | DIRECT),
+    /* 93 */ (7, MEMORY),
+    /* 94 */ (4, INDEX | DIRECT),
+    /* 95 */ (4, MEMORY | DIRECT),
+    /* 96 */ (4, INDEX | DIRECT),
+    /* 97 */ (6, MEMORY | DIRECT),
+    /* 98 */ (2, NONE),
+    /* 99 */ (5, MEMORY),
+    /* 9A */ (2, NONE),
+    /* 9B */ (2, NONE),
+    /* 9C */ (4, MEMORY),
+    /* 9D */ (5, MEMORY),
+    /* 9E */ (5, MEMORY),
+    /* 9F */ (5, MEMORY),
+    /* A0 */ (2, INDEX),
+    /* A1 */ (6, MEMORY | DIRECT),
+    /* A2 */ (2, INDEX),
+    /* A3 */ (4, MEMORY),
+    /* A4 */ (3, INDEX | DIRECT),
+    /* A5 */ (3, MEMORY | DIRECT),
+    /* A6 */ (3, INDEX | DIRECT),
+    /* A7 */ (6, MEMORY | DIRECT),
+    /* A8 */ (2, NONE),
+    /* A9 */ (2, MEMORY),
+    /* AA */ (2, NONE),
+    /* AB */ (4, NONE),
+    /* AC */ (4, INDEX),
+    /* AD */ (4, MEMORY),
+    /* AE */ (4, INDEX),
+    /* AF */ (5, MEMORY),
+    /* B0 */ (2, BRANCH),
+    /* B1 */ (5, MEMORY | DIRECT | CROSS_DIRECT_Y),
+    /* B2 */ (5, MEMORY | DIRECT),
+    /* B3 */ (7, MEMORY),
+    /* B4 */ (4, INDEX | DIRECT),
+    /* B5 */ (4, MEMORY | DIRECT),
+    /* B6 */ (4, INDEX | DIRECT),
+    /* B7 */ (6, MEMORY | DIRECT),
+    /* B8 */ (2, NONE),
+    /* B9 */ (4, MEMORY | CROSS_Y),
+    /* BA */ (2, NONE),
+    /* BB */ (2, NONE),
+    /* BC */ (4, INDEX | CROSS_X),
+    /* BD */ (4, MEMORY | CROSS_X),
+    /* BE */ (4, INDEX | CROSS_Y),
+    /* BF */ (5, MEMORY),
+    /* C0 */ (2, INDEX),
+    /* C1 */ (6, MEMORY | DIRECT),
+    /* C2 */ (3, NONE),
+    /* C3 */ (4, MEMORY),
+    /* C4 */ (3, INDEX | DIRECT),
+    /* C5 */ (3, MEMORY | DIRECT),
+    /* C6 */ (5, MODIFY | DIRECT),
+    /* C7 */ (6, MEMORY | DIRECT),
+    /* C8 */ (2, NONE),
+    /* C9 */ (2, MEMORY),
+    /* CA */ (2, NONE),
+    /* CB */ (3, NONE),
+    /* CC */ (4, INDEX),
+    /* CD */ (4, MEMORY),
+    /* CE */ (6, MODIFY),
+    /* CF */ (5, MEMORY),
+    /* D0 */ (2, BRANCH),
+    /* D1 */ (5, MEMORY | DIRECT | CROSS_DIRECT_Y),
+    /* D2 */ (5, MEMORY | DIRECT),
+    /* D3 */ (7, MEMORY),
+    /* D4 */ (6, DIRECT),
+    /* D5 */ (4, MEMORY | DIRECT),
+    /* D6 */ (6, MODIFY | DIRECT),
+    /* D7 */ (6, MEMORY | DIRECT),
+    /* D8 */ (2, NONE),
+    /* D9 */ (4, MEMORY | CROSS_Y),
+    /* DA */ (3, INDEX),
+    /* DB */ (3, NONE),
+    /* DC */ (6, NONE),
+    /* DD */ (4, MEMORY | CROSS_X),
+    /* DE */ (7, MODIFY),
+    /* DF */ (5, MEMORY),
+    /* E0 */ (2, INDEX),
+    /* E1 */ (6, MEMORY | DIRECT),
+    /* E2 */ (3, NONE),
+    /* E3 */ (4, MEMORY),
+    /* E4 */ (3, INDEX | DIRECT),
+    /* E5 */ (3, MEMORY | DIRECT),
+    /* E6 */ (5, MODIFY | DIRECT),
+    /* E7 */ (6, MEMORY | DIRECT),
+    /* E8 */ (2, NONE),
+    /* E9 */ (2, MEMORY),
+    /* EA */ (2, NONE),
+    /* EB */ (3, NONE),
+    /* EC */ (4, INDEX),
+    /* ED */ (4, MEMORY),
+    /* EE */ (6, MODIFY),
+    /* EF */ (5, MEMORY),
+    /* F0 */ (2, BRANCH),
+    /* F1 */ (5, MEMORY | DIRECT | CROSS_DIRECT_Y),
+    /* F2 */ (5, MEMORY | DIRECT),
+    /* F3 */ (7, MEMORY),
+    /* F4 */ (5, NONE),
+    /* F5 */ (4, MEMORY | DIRECT),
+    /* F6 */ (6, MODIFY | DIRECT),
+    /* F7 */ (6, MEMORY | DIRECT),
+    /* F8 */ (2, NONE),
+    /* F9 */ (4, MEMORY | CROSS_Y),
+    /* FA */ (4, INDEX),
+    /* FB */ (2, NONE),
+    /* FC */ (8, NONE),
+    /* FD */ (4, MEMORY | CROSS_X),
+    /* FE */ (7, MODIFY),
+    /* FF */ (5, MEMORY)
+];
+
+const BRA: u8 = 0x80;
+
+// Checks the number of CPU cycles each instruction takes against the table
+// above, and logs each kind of mismatch the first time it's seen. This counts
+// cycles rather than master cycles, as the latter depend on memory speed.
+pub struct CycleAudit {
+    pending: Option<PendingInstruction>,
+    reported: HashSet<(u8, u64, u64)>,
+    mismatches: u64
+}
+
+struct PendingInstruction {
+    address: HardwareAddress,
+    op_code: u8,
+    expected: u64,
+    cpu_cycles: u64,
+    master_cycles: u64
+}
+
+// Operands are peeked rather than read, so as not to use up any cycles
+fn peek_operand(cpu: &mut Cpu, address: HardwareAddress, index: u16) -> Option<u8> {
+    let operand_address = HardwareAddress::new(address.bank(), address.offset().wrapping_add(index));
+    cpu.hardware_mut().peek(operand_address)
+}
+
+fn peek_word(cpu: &mut Cpu, address: HardwareAddress) -> Option<u16> {
+    let lower = cpu.hardware_mut().peek(address);
+    let upper = cpu.hardware_mut().peek(address.wrapping_add(1));
+    lower.and_then(|lower| upper.map(|upper| ((upper as u16) << 8) | lower as u16))
+}
+
+fn branch_taken(cpu: &Cpu, op_code: u8) -> bool {
+    let flags = cpu.flags();
+
+    match op_code {
+        0x10 => !flags.negative,
+        0x30 => flags.negative,
+        0x50 => !flags.overflow,
+        0x70 => flags.overflow,
+        0x90 => !flags.carry,
+        0xB0 => flags.carry,
+        0xD0 => !flags.zero,
+        0xF0 => flags.zero,
+        _ => op_code == BRA
+    }
+}
+
+fn page_crossed(cpu: &mut Cpu, address: HardwareAddress, timing: u16) -> Option<bool> {
+    let regs = *cpu.regs();
+
+    let base_offset = if (timing & CROSS_DIRECT_Y) != 0 {
+        peek_operand(cpu, address, 1).and_then(|direct_offset| {
+            let pointer = HardwareAddress::new(0, regs.direct_page.wrapping_add(direct_offset as u16));
+            peek_word(cpu, pointer)
+        })
+    } else {
+        peek_word(cpu, HardwareAddress::new(address.bank(), address.offset().wrapping_add(1)))
+    };
+
+    base_offset.map(|base_offset| {
+        let index = if (timing & CROSS_X) != 0 { regs.index_x } else { regs.index_y };
+        let base_address = HardwareAddress::new(regs.data_bank, base_offset);
+        let indexed_address = base_address.wrapping_add(index);
+        (base_address.to_u32() >> 8) != (indexed_address.to_u32() >> 8)
+    })
+}
+
+// Returns None if the count depends on something that can't be peeked
+fn expected_cycles(cpu: &mut Cpu, address: HardwareAddress, op_code: u8) -> Option<u64> {
+    let (base, timing) = CYCLES[op_code as usize];
+    let regs = *cpu.regs();
+    let flags = *cpu.flags();
+    let mut expected = base as u64;
+
+    if (timing & MEMORY) != 0 && !flags.memory_size {
+        expected += 1;
+    }
+
+    if (timing & MODIFY) != 0 && !flags.memory_size {
+        expected += 2;
+    }
+
+    if (timing & INDEX) != 0 && !flags.index_size {
+        expected += 1;
+    }
+
+    if (timing & DIRECT) != 0 && (regs.direct_page & 0x00FF) != 0 {
+        expected += 1;
+    }
+
+    if (timing & NATIVE) != 0 && !flags.emulation_mode {
+        expected += 1;
+    }
+
+    if (timing & (CROSS_X | CROSS_Y | CROSS_DIRECT_Y)) != 0 {
+        match page_crossed(cpu, address, timing) {
+            Some(crossed) => if crossed || !flags.index_size {
+                expected += 1;
+            },
+            None => return None
+        }
+    }
+
+    if (timing & BRANCH) != 0 && branch_taken(cpu, op_code) {
+        let offset = match peek_operand(cpu, address, 1) {
+            Some(offset) => offset as i8,
+            None => return None
+        };
+
+        let origin = address.offset().wrapping_add(2);
+        let target = (origin as i16).wrapping_add(offset as i16) as u16;
+
+        expected += 1;
+
+        if flags.emulation_mode && (origin & 0xFF00) != (target & 0xFF00) {
+            expected += 1;
+        }
+    }
+
+    Some(expected)
+}
+
+impl CycleAudit {
+    pub fn new() -> CycleAudit {
+        CycleAudit {
+            pending: None,
+            reported: HashSet::new(),
+            mismatches: 0
+        }
+    }
+
+    // Called just before the instruction at 'address' is fetched
+    pub fn begin(&mut self, cpu: &mut Cpu, address: HardwareAddress) {
+        self.pending = cpu.hardware_mut().peek(address).and_then(|op_code| {
+            expected_cycles(cpu, address, op_code).map(|expected| {
+                let clock = cpu.hardware().clock();
+
+                PendingInstruction {
+                    address: address,
+                    op_code: op_code,
+                    expected: expected,
+                    cpu_cycles: clock.cpu_cycles,
+                    master_cycles: clock.master_cycles
+                }
+            })
+        });
+    }
+
+    // Called once the instruction has been executed
+    pub fn end(&mut self, cpu: &Cpu) {
+        let pending = match self.pending.take() {
+            Some(pending) => pending,
+            None => return
+        };
+
+        let clock = cpu.hardware().clock();
+        let actual = clock.cpu_cycles.wrapping_sub(pending.cpu_cycles);
+
+        if actual == pending.expected {
+            return;
+        }
+
+        self.mismatches += 1;
+
+        if self.reported.insert((pending.op_code, pending.expected, actual)) {
+            warn!("Cycle audit: {} {:02X} {} took {} cycles ({} master cycles), expected {}",
+                pending.address,
+                pending.op_code,
+                disassembler::mnemonic(pending.op_code),
+                actual,
+                clock.master_cycles.wrapping_sub(pending.master_cycles),
+                pending.expected);
+        }
+    }
+
+    pub fn mismatches(&self) -> u64 {
+        self.mismatches
+    }
+}
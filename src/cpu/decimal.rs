@@ -1,53 +1,114 @@
-pub trait BinaryCodedDecimal {
-    fn to_decimal(self) -> Self;
-    fn to_binary(self) -> Self;
-    fn is_valid_decimal(self) -> bool;
-    fn fix_underflow(self) -> Self;
+pub trait BinaryCodedDecimal : Sized {
+    fn decimal_add(self, rhs: Self, carry: bool) -> (Self, bool, bool);
+    fn decimal_subtract(self, rhs: Self, carry: bool) -> (Self, bool, bool);
+}
+
+// Works one BCD digit at a time, the way the 65816 does, so results for
+// invalid BCD operands match hardware too. Subtraction adds the inverted
+// operand and adjusts digits that borrowed. Returns (result, carry, overflow).
+fn decimal_arithmetic(lhs: u32, rhs: u32, carry: bool, digits: u32, subtract: bool) -> (u32, bool, bool) {
+    let rhs = if subtract { !rhs & ((1 << (digits * 4)) - 1) } else { rhs };
+
+    let mut result = 0;
+    let mut carry = carry;
+    let mut overflow = false;
+
+    for digit in 0..digits {
+        let shift = digit * 4;
+        let mut sum = ((lhs >> shift) & 0x0F) + ((rhs >> shift) & 0x0F) + (carry as u32);
+
+        if digit == digits - 1 {
+            // Overflow comes from the top digit before it is decimal adjusted
+            let sign_bit = 1 << (shift + 3);
+            let unadjusted = result | (sum << shift);
+            overflow = (!(lhs ^ rhs) & (lhs ^ unadjusted) & sign_bit) != 0;
+        }
+
+        if subtract {
+            carry = sum > 0x0F;
+            if !carry {
+                sum = sum.wrapping_sub(0x06);
+            }
+        } else {
+            if sum > 0x09 {
+                sum += 0x06;
+            }
+            carry = sum > 0x0F;
+        }
+
+        result |= (sum & 0x0F) << shift;
+    }
+
+    (result, carry, overflow)
 }
 
 impl BinaryCodedDecimal for u8 {
-    fn to_decimal(self) -> Self {
-        ((self & 0xF0) >> 4) * 10 + (self & 0x0F)
+    fn decimal_add(self, rhs: Self, carry: bool) -> (Self, bool, bool) {
+        let (result, carry, overflow) = decimal_arithmetic(self as u32, rhs as u32, carry, 2, false);
+        (result as u8, carry, overflow)
     }
 
-    fn to_binary(self) -> Self {
-        (((self % 100) / 10) << 4) | (self % 10)
+    fn decimal_subtract(self, rhs: Self, carry: bool) -> (Self, bool, bool) {
+        let (result, carry, overflow) = decimal_arithmetic(self as u32, rhs as u32, carry, 2, true);
+        (result as u8, carry, overflow)
     }
+}
 
-    fn is_valid_decimal(self) -> bool {
-        self < 100
+impl BinaryCodedDecimal for u16 {
+    fn decimal_add(self, rhs: Self, carry: bool) -> (Self, bool, bool) {
+        let (result, carry, overflow) = decimal_arithmetic(self as u32, rhs as u32, carry, 4, false);
+        (result as u16, carry, overflow)
     }
 
-    fn fix_underflow(self) -> Self {
-        if self.is_valid_decimal() {
-            self
-        } else {
-            self.wrapping_add(100)
-        }
+    fn decimal_subtract(self, rhs: Self, carry: bool) -> (Self, bool, bool) {
+        let (result, carry, overflow) = decimal_arithmetic(self as u32, rhs as u32, carry, 4, true);
+        (result as u16, carry, overflow)
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::BinaryCodedDecimal;
 
-impl BinaryCodedDecimal for u16 {
-    fn to_decimal(self) -> Self {
-        ((self & 0xF000) >> 12) * 1000 + ((self & 0x0F00) >> 8) * 100 +
-            ((self & 0x00F0) >> 4) * 10 + (self & 0x000F)
+    // Expected values are as bsnes computes them
+
+    #[test]
+    fn add_carries_out_of_top_digit() {
+        assert_eq!(0x99u8.decimal_add(0x01, false), (0x00, true, false));
+        assert_eq!(0x99u8.decimal_add(0x00, true), (0x00, true, false));
     }
 
-    fn to_binary(self) -> Self {
-        (((self % 10000) / 1000) << 12) |(((self % 1000) / 100) << 8) |
-            (((self % 100) / 10) << 4) | (self % 10)
+    #[test]
+    fn add_adjusts_invalid_digits() {
+        assert_eq!(0x0Fu8.decimal_add(0x01, false), (0x16, false, false));
     }
-    
-    fn is_valid_decimal(self) -> bool {
-        self < 10000
+
+    #[test]
+    fn add_overflow_comes_from_unadjusted_result() {
+        assert_eq!(0x7Fu8.decimal_add(0x01, false), (0x86, false, true));
+        assert_eq!(0x79u8.decimal_add(0x01, false), (0x80, false, true));
     }
 
-    fn fix_underflow(self) -> Self {
-        if self.is_valid_decimal() {
-            self
-        } else {
-            self.wrapping_add(10000)
-        }
+    #[test]
+    fn add_carries_through_16_bit_digits() {
+        assert_eq!(0x0999u16.decimal_add(0x0001, false), (0x1000, false, false));
+        assert_eq!(0x9999u16.decimal_add(0x0001, false), (0x0000, true, false));
+    }
+
+    #[test]
+    fn subtract_borrows_across_digits() {
+        assert_eq!(0x10u8.decimal_subtract(0x01, true), (0x09, true, false));
+        assert_eq!(0x10u8.decimal_subtract(0x00, false), (0x09, true, false));
+    }
+
+    #[test]
+    fn subtract_borrows_out_of_top_digit() {
+        assert_eq!(0x00u8.decimal_subtract(0x01, true), (0x99, false, false));
+    }
+
+    #[test]
+    fn subtract_borrows_through_16_bit_digits() {
+        assert_eq!(0x1000u16.decimal_subtract(0x0001, true), (0x0999, true, false));
+        assert_eq!(0x0000u16.decimal_subtract(0x0001, true), (0x9999, false, false));
     }
 }
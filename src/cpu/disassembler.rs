@@ -0,0 +1,377 @@
+use cpu::Cpu;
+use hardware::HardwareAddress;
+
+// Lower-case mnemonics and hex, as used by bsnes and Mesen trace logs
+#[derive(Copy, Clone)]
+enum Operand {
+    Implied,
+    Accumulator,
+    ImmediateM,
+    ImmediateX,
+    Immediate8,
+    Immediate16,
+    Signature,
+    Absolute,
+    AbsoluteIndexedX,
+    AbsoluteIndexedXIndirect,
+    AbsoluteIndexedY,
+    AbsoluteIndirect,
+    AbsoluteIndirectLong,
+    AbsoluteLong,
+    AbsoluteLongIndexedX,
+    BlockMove,
+    DirectPage,
+    DirectPageIndexedX,
+    DirectPageIndexedXIndirect,
+    DirectPageIndexedY,
+    DirectPageIndirect,
+    DirectPageIndirectIndexedY,
+    DirectPageIndirectLong,
+    DirectPageIndirectLongIndexedY,
+    Relative,
+    RelativeLong,
+    StackRelative,
+    StackRelativeIndirectIndexedY
+}
+
+use self::Operand::*;
+
+static OP_CODES: [(&'static str, Operand); 256] = [
+    /* 00 */ ("brk", Signature),
+    /* 01 */ ("ora", DirectPageIndexedXIndirect),
+    /* 02 */ ("cop", Signature),
+    /* 03 */ ("ora", StackRelative),
+    /* 04 */ ("tsb", DirectPage),
+    /* 05 */ ("ora", DirectPage),
+    /* 06 */ ("asl", DirectPage),
+    /* 07 */ ("ora", DirectPageIndirectLong),
+    /* 08 */ ("php", Implied),
+    /* 09 */ ("ora", ImmediateM),
+    /* 0A */ ("asl", Accumulator),
+    /* 0B */ ("phd", Implied),
+    /* 0C */ ("tsb", Absolute),
+    /* 0D */ ("ora", Absolute),
+    /* 0E */ ("asl", Absolute),
+    /* 0F */ ("ora", AbsoluteLong),
+    /* 10 */ ("bpl", Relative),
+    /* 11 */ ("ora", DirectPageIndirectIndexedY),
+    /* 12 */ ("ora", DirectPageIndirect),
+    /* 13 */ ("ora", StackRelativeIndirectIndexedY),
+    /* 14 */ ("trb", DirectPage),
+    /* 15 */ ("ora", DirectPageIndexedX),
+    /* 16 */ ("asl", DirectPageIndexedX),
+    /* 17 */ ("ora", DirectPageIndirectLongIndexedY),
+    /* 18 */ ("clc", Implied),
+    /* 19 */ ("ora", AbsoluteIndexedY),
+    /* 1A */ ("inc", Accumulator),
+    /* 1B */ ("tcs", Implied),
+    /* 1C */ ("trb", Absolute),
+    /* 1D */ ("ora", AbsoluteIndexedX),
+    /* 1E */ ("asl", AbsoluteIndexedX),
+    /* 1F */ ("ora", AbsoluteLongIndexedX),
+    /* 20 */ ("jsr", Absolute),
+    /* 21 */ ("and", DirectPageIndexedXIndirect),
+    /* 22 */ ("jsl", AbsoluteLong),
+    /* 23 */ ("and", StackRelative),
+    /* 24 */ ("bit", DirectPage),
+    /* 25 */ ("and", DirectPage),
+    /* 26 */ ("rol", DirectPage),
+    /* 27 */ ("and", DirectPageIndirectLong),
+    /* 28 */ ("plp", Implied),
+    /* 29 */ ("and", ImmediateM),
+    /* 2A */ ("rol", Accumulator),
+    /* 2B */ ("pld", Implied),
+    /* 2C */ ("bit", Absolute),
+    /* 2D */ ("and", Absolute),
+    /* 2E */ ("rol", Absolute),
+    /* 2F */ ("and", AbsoluteLong),
+    /* 30 */ ("bmi", Relative),
+    /* 31 */ ("and", DirectPageIndirectIndexedY),
+    /* 32 */ ("and", DirectPageIndirect),
+    /* 33 */ ("and", StackRelativeIndirectIndexedY),
+    /* 34 */ ("bit", DirectPageIndexedX),
+    /* 35 */ ("and", DirectPageIndexedX),
+    /* 36 */ ("rol", DirectPageIndexedX),
+    /* 37 */ ("and", DirectPageIndirectLongIndexedY),
+    /* 38 */ ("sec", Implied),
+    /* 39 */ ("and", AbsoluteIndexedY),
+    /* 3A */ ("dec", Accumulator),
+    /* 3B */ ("tsc", Implied),
+    /* 3C */ ("bit", AbsoluteIndexedX),
+    /* 3D */ ("and", AbsoluteIndexedX),
+    /* 3E */ ("rol", AbsoluteIndexedX),
+    /* 3F */ ("and", AbsoluteLongIndexedX),
+    /* 40 */ ("rti", Implied),
+    /* 41 */ ("eor", DirectPageIndexedXIndirect),
+    /* 42 */ ("wdm", Signature),
+    /* 43 */ ("eor", StackRelative),
+    /* 44 */ ("mvp", BlockMove),
+    /* 45 */ ("eor", DirectPage),
+    /* 46 */ ("lsr", DirectPage),
+    /* 47 */ ("eor", DirectPageIndirectLong),
+    /* 48 */ ("pha", Implied),
+    /* 49 */ ("eor", ImmediateM),
+    /* 4A */ ("lsr", Accumulator),
+    /* 4B */ ("phk", Implied),
+    /* 4C */ ("jmp", Absolute),
+    /* 4D */ ("eor", Absolute),
+    /* 4E */ ("lsr", Absolute),
+    /* 4F */ ("eor", AbsoluteLong),
+    /* 50 */ ("bvc", Relative),
+    /* 51 */ ("eor", DirectPageIndirectIndexedY),
+    /* 52 */ ("eor", DirectPageIndirect),
+    /* 53 */ ("eor", StackRelativeIndirectIndexedY),
+    /* 54 */ ("mvn", BlockMove),
+    /* 55 */ ("eor", DirectPageIndexedX),
+    /* 56 */ ("lsr", DirectPageIndexedX),
+    /* 57 */ ("eor", DirectPageIndirectLongIndexedY),
+    /* 58 */ ("cli", Implied),
+    /* 59 */ ("eor", AbsoluteIndexedY),
+    /* 5A */ ("phy", Implied),
+    /* 5B */ ("tcd", Implied),
+    /* 5C */ ("jml", AbsoluteLong),
+    /* 5D */ ("eor", AbsoluteIndexedX),
+    /* 5E */ ("lsr", AbsoluteIndexedX),
+    /* 5F */ ("eor", AbsoluteLongIndexedX),
+    /* 60 */ ("rts", Implied),
+    /* 61 */ ("adc", DirectPageIndexedXIndirect),
+    /* 62 */ ("per", RelativeLong),
+    /* 63 */ ("adc", StackRelative),
+    /* 64 */ ("stz", DirectPage),
+    /* 65 */ ("adc", DirectPage),
+    /* 66 */ ("ror", DirectPage),
+    /* 67 */ ("adc", DirectPageIndirectLong),
+    /* 68 */ ("pla", Implied),
+    /* 69 */ ("adc", ImmediateM),
+    /* 6A */ ("ror", Accumulator),
+    /* 6B */ ("rtl", Implied),
+    /* 6C */ ("jmp", AbsoluteIndirect),
+    /* 6D */ ("adc", Absolute),
+    /* 6E */ ("ror", Absolute),
+    /* 6F */ ("adc", AbsoluteLong),
+    /* 70 */ ("bvs", Relative),
+    /* 71 */ ("adc", DirectPageIndirectIndexedY),
+    /* 72 */ ("adc", DirectPageIndirect),
+    /* 73 */ ("adc", StackRelativeIndirectIndexedY),
+    /* 74 */ ("stz", DirectPageIndexedX),
+    /* 75 */ ("adc", DirectPageIndexedX),
+    /* 76 */ ("ror", DirectPageIndexedX),
+    /* 77 */ ("adc", DirectPageIndirectLongIndexedY),
+    /* 78 */ ("sei", Implied),
+    /* 79 */ ("adc", AbsoluteIndexedY),
+    /* 7A */ ("ply", Implied),
+    /* 7B */ ("tdc", Implied),
+    /* 7C */ ("jmp", AbsoluteIndexedXIndirect),
+    /* 7D */ ("adc", AbsoluteIndexedX),
+    /* 7E */ ("ror", AbsoluteIndexedX),
+    /* 7F */ ("adc", AbsoluteLongIndexedX),
+    /* 80 */ ("bra", Relative),
+    /* 81 */ ("sta", DirectPageIndexedXIndirect),
+    /* 82 */ ("brl", RelativeLong),
+    /* 83 */ ("sta", StackRelative),
+    /* 84 */ ("sty", DirectPage),
+    /* 85 */ ("sta", DirectPage),
+    /* 86 */ ("stx", DirectPage),
+    /* 87 */ ("sta", DirectPageIndirectLong),
+    /* 88 */ ("dey", Implied),
+    /* 89 */ ("bit", ImmediateM),
+    /* 8A */ ("txa", Implied),
+    /* 8B */ ("phb", Implied),
+    /* 8C */ ("sty", Absolute),
+    /* 8D */ ("sta", Absolute),
+    /* 8E */ ("stx", Absolute),
+    /* 8F */ ("sta", AbsoluteLong),
+    /* 90 */ ("bcc", Relative),
+    /* 91 */ ("sta", DirectPageIndirectIndexedY),
+    /* 92 */ ("sta", DirectPageIndirect),
+    /* 93 */ ("sta", StackRelativeIndirectIndexedY),
+    /* 94 */ ("sty", DirectPageIndexedX),
+    /* 95 */ ("sta", DirectPageIndexedX),
+    /* 96 */ ("stx", DirectPageIndexedY),
+    /* 97 */ ("sta", DirectPageIndirectLongIndexedY),
+    /* 98 */ ("tya", Implied),
+    /* 99 */ ("sta", AbsoluteIndexedY),
+    /* 9A */ ("txs", Implied),
+    /* 9B */ ("txy", Implied),
+    /* 9C */ ("stz", Absolute),
+    /* 9D */ ("sta", AbsoluteIndexedX),
+    /* 9E */ ("stz", AbsoluteIndexedX),
+    /* 9F */ ("sta", AbsoluteLongIndexedX),
+    /* A0 */ ("ldy", ImmediateX),
+    /* A1 */ ("lda", DirectPageIndexedXIndirect),
+    /* A2 */ ("ldx", ImmediateX),
+    /* A3 */ ("lda", StackRelative),
+    /* A4 */ ("ldy", DirectPage),
+    /* A5 */ ("lda", DirectPage),
+    /* A6 */ ("ldx", DirectPage),
+    /* A7 */ ("lda", DirectPageIndirectLong),
+    /* A8 */ ("tay", Implied),
+    /* A9 */ ("lda", ImmediateM),
+    /* AA */ ("tax", Implied),
+    /* AB */ ("plb", Implied),
+    /* AC */ ("ldy", Absolute),
+    /* AD */ ("lda", Absolute),
+    /* AE */ ("ldx", Absolute),
+    /* AF */ ("lda", AbsoluteLong),
+    /* B0 */ ("bcs", Relative),
+    /* B1 */ ("lda", DirectPageIndirectIndexedY),
+    /* B2 */ ("lda", DirectPageIndirect),
+    /* B3 */ ("lda", StackRelativeIndirectIndexedY),
+    /* B4 */ ("ldy", DirectPageIndexedX),
+    /* B5 */ ("lda", DirectPageIndexedX),
+    /* B6 */ ("ldx", DirectPageIndexedY),
+    /* B7 */ ("lda", DirectPageIndirectLongIndexedY),
+    /* B8 */ ("clv", Implied),
+    /* B9 */ ("lda", AbsoluteIndexedY),
+    /* BA */ ("tsx", Implied),
+    /* BB */ ("tyx", Implied),
+    /* BC */ ("ldy", AbsoluteIndexedX),
+    /* BD */ ("lda", AbsoluteIndexedX),
+    /* BE */ ("ldx", AbsoluteIndexedY),
+    /* BF */ ("lda", AbsoluteLongIndexedX),
+    /* C0 */ ("cpy", ImmediateX),
+    /* C1 */ ("cmp", DirectPageIndexedXIndirect),
+    /* C2 */ ("rep", Immediate8),
+    /* C3 */ ("cmp", StackRelative),
+    /* C4 */ ("cpy", DirectPage),
+    /* C5 */ ("cmp", DirectPage),
+    /* C6 */ ("dec", DirectPage),
+    /* C7 */ ("cmp", DirectPageIndirectLong),
+    /* C8 */ ("iny", Implied),
+    /* C9 */ ("cmp", ImmediateM),
+    /* CA */ ("dex", Implied),
+    /* CB */ ("wai", Implied),
+    /* CC */ ("cpy", Absolute),
+    /* CD */ ("cmp", Absolute),
+    /* CE */ ("dec", Absolute),
+    /* CF */ ("cmp", AbsoluteLong),
+    /* D0 */ ("bne", Relative),
+    /* D1 */ ("cmp", DirectPageIndirectIndexedY),
+    /* D2 */ ("cmp", DirectPageIndirect),
+    /* D3 */ ("cmp", StackRelativeIndirectIndexedY),
+    /* D4 */ ("pei", DirectPageIndirect),
+    /* D5 */ ("cmp", DirectPageIndexedX),
+    /* D6 */ ("dec", DirectPageIndexedX),
+    /* D7 */ ("cmp", DirectPageIndirectLongIndexedY),
+    /* D8 */ ("cld", Implied),
+    /* D9 */ ("cmp", AbsoluteIndexedY),
+    /* DA */ ("phx", Implied),
+    /* DB */ ("stp", Implied),
+    /* DC */ ("jml", AbsoluteIndirectLong),
+    /* DD */ ("cmp", AbsoluteIndexedX),
+    /* DE */ ("dec", AbsoluteIndexedX),
+    /* DF */ ("cmp", AbsoluteLongIndexedX),
+    /* E0 */ ("cpx", ImmediateX),
+    /* E1 */ ("sbc", DirectPageIndexedXIndirect),
+    /* E2 */ ("sep", Immediate8),
+    /* E3 */ ("sbc", StackRelative),
+    /* E4 */ ("cpx", DirectPage),
+    /* E5 */ ("sbc", DirectPage),
+    /* E6 */ ("inc", DirectPage),
+    /* E7 */ ("sbc", DirectPageIndirectLong),
+    /* E8 */ ("inx", Implied),
+    /* E9 */ ("sbc", ImmediateM),
+    /* EA */ ("nop", Implied),
+    /* EB */ ("xba", Implied),
+    /* EC */ ("cpx", Absolute),
+    /* ED */ ("sbc", Absolute),
+    /* EE */ ("inc", Absolute),
+    /* EF */ ("sbc", AbsoluteLong),
+    /* F0 */ ("beq", Relative),
+    /* F1 */ ("sbc", DirectPageIndirectIndexedY),
+    /* F2 */ ("sbc", DirectPageIndirect),
+    /* F3 */ ("sbc", StackRelativeIndirectIndexedY),
+    /* F4 */ ("pea", Immediate16),
+    /* F5 */ ("sbc", DirectPageIndexedX),
+    /* F6 */ ("inc", DirectPageIndexedX),
+    /* F7 */ ("sbc", DirectPageIndirectLongIndexedY),
+    /* F8 */ ("sed", Implied),
+    /* F9 */ ("sbc", AbsoluteIndexedY),
+    /* FA */ ("plx", Implied),
+    /* FB */ ("xce", Implied),
+    /* FC */ ("jsr", AbsoluteIndexedXIndirect),
+    /* FD */ ("sbc", AbsoluteIndexedX),
+    /* FE */ ("inc", AbsoluteIndexedX),
+    /* FF */ ("sbc", AbsoluteLongIndexedX)
+];
+
+pub struct Disassembly {
+    pub bytes: Vec<u8>,
+    pub text: String
+}
+
+pub fn mnemonic(op_code: u8) -> &'static str {
+    OP_CODES[op_code as usize].0
+}
+
+// Decodes the instruction at 'address' without side effects. Bytes that
+// can't be peeked (i.e. I/O registers) are shown as zero.
+pub fn disassemble(cpu: &mut Cpu, address: HardwareAddress) -> Disassembly {
+    let memory_8_bit = cpu.flags().memory_size;
+    let index_8_bit = cpu.flags().index_size;
+
+    let op_code = peek(cpu, address, 0);
+    let (mnemonic, operand) = OP_CODES[op_code as usize];
+
+    let operand_length = match operand {
+        Implied | Accumulator => 0,
+        ImmediateM => if memory_8_bit { 1 } else { 2 },
+        ImmediateX => if index_8_bit { 1 } else { 2 },
+        Immediate8 | Signature | Relative | StackRelative | StackRelativeIndirectIndexedY |
+        DirectPage | DirectPageIndexedX | DirectPageIndexedXIndirect | DirectPageIndexedY |
+        DirectPageIndirect | DirectPageIndirectIndexedY | DirectPageIndirectLong |
+        DirectPageIndirectLongIndexedY => 1,
+        Immediate16 | Absolute | AbsoluteIndexedX | AbsoluteIndexedXIndirect | AbsoluteIndexedY |
+        AbsoluteIndirect | AbsoluteIndirectLong | RelativeLong | BlockMove => 2,
+        AbsoluteLong | AbsoluteLongIndexedX => 3
+    };
+
+    let mut bytes = vec![op_code];
+
+    for index in 0..operand_length {
+        bytes.push(peek(cpu, address, index + 1));
+    }
+
+    let value = bytes[1..].iter().rev().fold(0u32, |value, &byte| (value << 8) | byte as u32);
+    let next_offset = address.offset().wrapping_add(bytes.len() as u16);
+
+    let text = match operand {
+        Implied => format!("{}", mnemonic),
+        Accumulator => format!("{} a", mnemonic),
+        ImmediateM | ImmediateX | Immediate8 | Immediate16 if operand_length == 1 => format!("{} #${:02x}", mnemonic, value),
+        ImmediateM | ImmediateX | Immediate8 | Immediate16 => format!("{} #${:04x}", mnemonic, value),
+        Signature => format!("{} #${:02x}", mnemonic, value),
+        Absolute => format!("{} ${:04x}", mnemonic, value),
+        AbsoluteIndexedX => format!("{} ${:04x},x", mnemonic, value),
+        AbsoluteIndexedXIndirect => format!("{} (${:04x},x)", mnemonic, value),
+        AbsoluteIndexedY => format!("{} ${:04x},y", mnemonic, value),
+        AbsoluteIndirect => format!("{} (${:04x})", mnemonic, value),
+        AbsoluteIndirectLong => format!("{} [${:04x}]", mnemonic, value),
+        AbsoluteLong => format!("{} ${:06x}", mnemonic, value),
+        AbsoluteLongIndexedX => format!("{} ${:06x},x", mnemonic, value),
+        // Operand bytes are destination bank then source bank
+        BlockMove => format!("{} ${:02x},${:02x}", mnemonic, value >> 8, value & 0xFF),
+        DirectPage => format!("{} ${:02x}", mnemonic, value),
+        DirectPageIndexedX => format!("{} ${:02x},x", mnemonic, value),
+        DirectPageIndexedXIndirect => format!("{} (${:02x},x)", mnemonic, value),
+        DirectPageIndexedY => format!("{} ${:02x},y", mnemonic, value),
+        DirectPageIndirect => format!("{} (${:02x})", mnemonic, value),
+        DirectPageIndirectIndexedY => format!("{} (${:02x}),y", mnemonic, value),
+        DirectPageIndirectLong => format!("{} [${:02x}]", mnemonic, value),
+        DirectPageIndirectLongIndexedY => format!("{} [${:02x}],y", mnemonic, value),
+        Relative => format!("{} ${:04x}", mnemonic, next_offset.wrapping_add(value as u8 as i8 as u16)),
+        RelativeLong => format!("{} ${:04x}", mnemonic, next_offset.wrapping_add(value as u16)),
+        StackRelative => format!("{} ${:02x},s", mnemonic, value),
+        StackRelativeIndirectIndexedY => format!("{} (${:02x},s),y", mnemonic, value)
+    };
+
+    Disassembly {
+        bytes: bytes,
+        text: text
+    }
+}
+
+fn peek(cpu: &mut Cpu, address: HardwareAddress, index: u16) -> u8 {
+    let address = HardwareAddress::new(address.bank(), address.offset().wrapping_add(index));
+    cpu.hardware_mut().peek(address).unwrap_or(0x00)
+}
@@ -0,0 +1,18 @@
+use hardware::HardwareAddress;
+use std::fmt::{self, Display, Formatter};
+
+// Problems caused by the game rather than the emulator (e.g. a bad jump into
+// data), which the frontend can report and then carry on from
+#[derive(Copy, Clone)]
+pub enum EmulationError {
+    // STP halts the CPU until reset
+    ProcessorStopped(HardwareAddress)
+}
+
+impl Display for EmulationError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            EmulationError::ProcessorStopped(address) => write!(f, "Processor stopped by STP at {}", address)
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use cpu::cpu::{CpuFlags, CpuRegisters};
+use cpu::disassembler;
+use hardware::HardwareAddress;
+use std::collections::vec_deque::{self, VecDeque};
+use std::fmt::{self, Display, Formatter};
+
+const HISTORY_LENGTH: usize = 64;
+
+// The most recently executed instructions, so that a crash report shows how
+// the CPU got into the state it crashed in
+pub struct History {
+    entries: VecDeque<HistoryEntry>
+}
+
+// Registers are as they were before the instruction ran, apart from the PC
+// (which has already moved past the op code)
+#[derive(Copy, Clone)]
+pub struct HistoryEntry {
+    pub address: HardwareAddress,
+    pub op_code: u8,
+    pub regs: CpuRegisters,
+    pub flags: CpuFlags
+}
+
+impl History {
+    pub fn new() -> History {
+        History {
+            entries: VecDeque::with_capacity(HISTORY_LENGTH)
+        }
+    }
+
+    pub fn push(&mut self, entry: HistoryEntry) {
+        if self.entries.len() == HISTORY_LENGTH {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    // Oldest first
+    pub fn iter(&self) -> vec_deque::Iter<HistoryEntry> {
+        self.entries.iter()
+    }
+}
+
+impl Display for HistoryEntry {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{} {:02X} {} A={:04X} X={:04X} Y={:04X} DP={:04X} DB={:02X} SP={:04X} P={} E={}",
+            self.address,
+            self.op_code,
+            disassembler::mnemonic(self.op_code),
+            self.regs.accumulator,
+            self.regs.index_x,
+            self.regs.index_y,
+            self.regs.direct_page,
+            self.regs.data_bank,
+            self.regs.stack_pointer,
+            self.flags,
+            self.flags.emulation_mode as u8)
+    }
+}
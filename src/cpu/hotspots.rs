@@ -0,0 +1,62 @@
+use cpu::disassembler;
+use hardware::HardwareAddress;
+use std::collections::HashMap;
+
+const OP_CODE_COUNT: usize = 256;
+
+// Counts instructions executed, by op code and by the 256-byte page of
+// memory they were fetched from
+pub struct Hotspots {
+    op_codes: [u64; OP_CODE_COUNT],
+    pages: HashMap<(u8, u8), u64>
+}
+
+impl Hotspots {
+    pub fn new() -> Hotspots {
+        Hotspots {
+            op_codes: [0; OP_CODE_COUNT],
+            pages: HashMap::new()
+        }
+    }
+
+    pub fn record(&mut self, address: HardwareAddress, op_code: u8) {
+        self.op_codes[op_code as usize] += 1;
+        *self.pages.entry((address.bank(), (address.offset() >> 8) as u8)).or_insert(0) += 1;
+    }
+
+    // Logs the 'count' most executed op codes and pages
+    pub fn report(&self, count: usize) {
+        let total = self.op_codes.iter().fold(0, |total, &executions| total + executions);
+
+        let mut op_codes: Vec<(usize, u64)> = self.op_codes.iter()
+            .cloned()
+            .enumerate()
+            .filter(|&(_, executions)| executions > 0)
+            .collect();
+
+        op_codes.sort_by(|lhs, rhs| rhs.1.cmp(&lhs.1).then(lhs.0.cmp(&rhs.0)));
+
+        info!("Hottest op codes ({} instructions):", total);
+
+        for &(op_code, executions) in op_codes.iter().take(count) {
+            info!("  {:02X} {} {} ({:.1}%)",
+                op_code,
+                disassembler::mnemonic(op_code as u8),
+                executions,
+                100.0 * executions as f64 / total.max(1) as f64);
+        }
+
+        let mut pages: Vec<(&(u8, u8), &u64)> = self.pages.iter().collect();
+        pages.sort_by(|lhs, rhs| rhs.1.cmp(lhs.1).then(lhs.0.cmp(rhs.0)));
+
+        info!("Hottest pages:");
+
+        for &(&(bank, page), &executions) in pages.iter().take(count) {
+            info!("  {:02X}:{:02X}xx {} ({:.1}%)",
+                bank,
+                page,
+                executions,
+                100.0 * executions as f64 / total.max(1) as f64);
+        }
+    }
+}
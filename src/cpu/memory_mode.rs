@@ -1,11 +1,21 @@
 use cpu::Cpu;
-use hardware::HardwareAddress;
+use hardware::{Bus, HardwareAddress, Wrapping};
 use std::fmt::{self, Formatter};
 use util::byte_access::ByteAccess;
 
 pub trait MemoryMode {
-    fn resolve(cpu: &mut Cpu) -> (HardwareAddress, HardwareAddress);
+    fn resolve<H: Bus>(cpu: &mut Cpu<H>) -> (HardwareAddress, HardwareAddress);
     fn format(f: &mut Formatter, immediate: HardwareAddress) -> fmt::Result;
+
+    // Data accesses carry into the next bank, except those based on the
+    // direct page or stack, which stay within bank 0
+    fn wrapping() -> Wrapping {
+        Wrapping::Linear
+    }
+
+    fn resolve_for_write<H: Bus>(cpu: &mut Cpu<H>) -> (HardwareAddress, HardwareAddress) {
+        Self::resolve(cpu)
+    }
 }
 
 #[derive(Default)]
@@ -65,8 +75,41 @@ pub struct StackRelative;
 #[derive(Default)]
 pub struct StackRelativeIndirectIndexedY;
 
+// Indexing takes an extra cycle if the index registers are 16-bit, if the
+// index carries into the next page, or if the instruction writes to memory
+fn indexed_cycle<H: Bus>(cpu: &mut Cpu<H>, base: HardwareAddress, indexed: HardwareAddress, write: bool) {
+    let page_crossed = (base.to_u32() >> 8) != (indexed.to_u32() >> 8);
+
+    if write || !cpu.flags().index_size || page_crossed {
+        cpu.io_cycle();
+    }
+}
+
+// In emulation mode with DL = 0, indexing wraps within the direct page as it
+// would on a 6502. Otherwise, it wraps only within bank 0.
+fn direct_page_indexed<H: Bus>(cpu: &Cpu<H>, offset: u16, index: u16) -> u16 {
+    let direct_page = cpu.regs().direct_page;
+
+    if cpu.flags().emulation_mode && direct_page.lower() == 0 {
+        (direct_page & 0xFF00) | (offset.wrapping_add(index) & 0x00FF)
+    } else {
+        offset.wrapping_add(direct_page).wrapping_add(index)
+    }
+}
+
+// Pointers fetched from the direct page wrap within it in the same case
+fn direct_page_pointer<H: Bus>(cpu: &mut Cpu<H>, indirect: HardwareAddress) -> u16 {
+    let wrapping = if cpu.flags().emulation_mode && cpu.regs().direct_page.lower() == 0 {
+        Wrapping::Page
+    } else {
+        Wrapping::Bank
+    };
+
+    cpu.hardware_mut().read_with_wrapping::<u16>(indirect, wrapping)
+}
+
 impl MemoryMode for Absolute {
-    fn resolve(cpu: &mut Cpu) -> (HardwareAddress, HardwareAddress) {
+    fn resolve<H: Bus>(cpu: &mut Cpu<H>) -> (HardwareAddress, HardwareAddress) {
         let bank = cpu.regs().data_bank;
         let immediate = HardwareAddress::new(bank, cpu.read_next::<u16>());
         (immediate, immediate)
@@ -77,13 +120,24 @@ impl MemoryMode for Absolute {
     }
 }
 
-impl MemoryMode for AbsoluteIndexedX {
-    fn resolve(cpu: &mut Cpu) -> (HardwareAddress, HardwareAddress) {
+impl AbsoluteIndexedX {
+    fn resolve_indexed<H: Bus>(cpu: &mut Cpu<H>, write: bool) -> (HardwareAddress, HardwareAddress) {
         let bank = cpu.regs().data_bank;
         let immediate = HardwareAddress::new(bank, cpu.read_next::<u16>());
         let resolved = immediate.wrapping_add(cpu.regs().index_x);
+        indexed_cycle(cpu, immediate, resolved, write);
         (resolved, immediate)
     }
+}
+
+impl MemoryMode for AbsoluteIndexedX {
+    fn resolve<H: Bus>(cpu: &mut Cpu<H>) -> (HardwareAddress, HardwareAddress) {
+        AbsoluteIndexedX::resolve_indexed(cpu, false)
+    }
+
+    fn resolve_for_write<H: Bus>(cpu: &mut Cpu<H>) -> (HardwareAddress, HardwareAddress) {
+        AbsoluteIndexedX::resolve_indexed(cpu, true)
+    }
 
     fn format(f: &mut Formatter, immediate: HardwareAddress) -> fmt::Result {
         write!(f, "${:04X},X", immediate.offset())
@@ -91,7 +145,7 @@ impl MemoryMode for AbsoluteIndexedX {
 }
 
 impl MemoryMode for AbsoluteIndexedXIndirect {
-    fn resolve(cpu: &mut Cpu) -> (HardwareAddress, HardwareAddress) {
+    fn resolve<H: Bus>(cpu: &mut Cpu<H>) -> (HardwareAddress, HardwareAddress) {
         let program_bank = cpu.regs().program_bank;
         let immediate = HardwareAddress::new(program_bank, cpu.read_next::<u16>());
         // Wraps only within current bank
@@ -107,13 +161,24 @@ impl MemoryMode for AbsoluteIndexedXIndirect {
     }
 }
 
-impl MemoryMode for AbsoluteIndexedY {
-    fn resolve(cpu: &mut Cpu) -> (HardwareAddress, HardwareAddress) {
+impl AbsoluteIndexedY {
+    fn resolve_indexed<H: Bus>(cpu: &mut Cpu<H>, write: bool) -> (HardwareAddress, HardwareAddress) {
         let bank = cpu.regs().data_bank;
         let immediate = HardwareAddress::new(bank, cpu.read_next::<u16>());
         let resolved = immediate.wrapping_add(cpu.regs().index_y);
+        indexed_cycle(cpu, immediate, resolved, write);
         (resolved, immediate)
     }
+}
+
+impl MemoryMode for AbsoluteIndexedY {
+    fn resolve<H: Bus>(cpu: &mut Cpu<H>) -> (HardwareAddress, HardwareAddress) {
+        AbsoluteIndexedY::resolve_indexed(cpu, false)
+    }
+
+    fn resolve_for_write<H: Bus>(cpu: &mut Cpu<H>) -> (HardwareAddress, HardwareAddress) {
+        AbsoluteIndexedY::resolve_indexed(cpu, true)
+    }
 
     fn format(f: &mut Formatter, immediate: HardwareAddress) -> fmt::Result {
         write!(f, "${:04X},Y", immediate.offset())
@@ -121,7 +186,7 @@ impl MemoryMode for AbsoluteIndexedY {
 }
 
 impl MemoryMode for AbsoluteIndirect {
-    fn resolve(cpu: &mut Cpu) -> (HardwareAddress, HardwareAddress) {
+    fn resolve<H: Bus>(cpu: &mut Cpu<H>) -> (HardwareAddress, HardwareAddress) {
         let program_bank = cpu.regs().program_bank;
         // Address lookup is always in bank 0 (for whatever reason)
         let immediate = HardwareAddress::new(0, cpu.read_next::<u16>());
@@ -136,7 +201,7 @@ impl MemoryMode for AbsoluteIndirect {
 }
 
 impl MemoryMode for AbsoluteIndirectLong {
-    fn resolve(cpu: &mut Cpu) -> (HardwareAddress, HardwareAddress) {
+    fn resolve<H: Bus>(cpu: &mut Cpu<H>) -> (HardwareAddress, HardwareAddress) {
         let program_bank = cpu.regs().program_bank;
         let immediate = HardwareAddress::new(program_bank, cpu.read_next::<u16>());
         let resolved = cpu.hardware_mut().read::<HardwareAddress>(immediate);
@@ -149,7 +214,7 @@ impl MemoryMode for AbsoluteIndirectLong {
 }
 
 impl MemoryMode for AbsoluteLong {
-    fn resolve(cpu: &mut Cpu) -> (HardwareAddress, HardwareAddress) {
+    fn resolve<H: Bus>(cpu: &mut Cpu<H>) -> (HardwareAddress, HardwareAddress) {
         let immediate = cpu.read_next::<HardwareAddress>();
         (immediate, immediate)
     }
@@ -160,7 +225,7 @@ impl MemoryMode for AbsoluteLong {
 }
 
 impl MemoryMode for AbsoluteLongIndexedX {
-    fn resolve(cpu: &mut Cpu) -> (HardwareAddress, HardwareAddress) {
+    fn resolve<H: Bus>(cpu: &mut Cpu<H>) -> (HardwareAddress, HardwareAddress) {
         let immediate = cpu.read_next::<HardwareAddress>();
         let resolved = immediate.wrapping_add(cpu.regs().index_x);
         (resolved, immediate)
@@ -172,7 +237,7 @@ impl MemoryMode for AbsoluteLongIndexedX {
 }
 
 impl MemoryMode for DirectPage {
-    fn resolve(cpu: &mut Cpu) -> (HardwareAddress, HardwareAddress) {
+    fn resolve<H: Bus>(cpu: &mut Cpu<H>) -> (HardwareAddress, HardwareAddress) {
         let immediate = HardwareAddress::new(0, cpu.read_next::<u8>() as u16);
         let adjusted_offset = immediate.offset().wrapping_add(cpu.regs().direct_page);
         let resolved = HardwareAddress::new(0, adjusted_offset);
@@ -183,15 +248,17 @@ impl MemoryMode for DirectPage {
     fn format(f: &mut Formatter, immediate: HardwareAddress) -> fmt::Result {
         write!(f, "${:02X}", immediate.offset().lower())
     }
+
+    fn wrapping() -> Wrapping {
+        Wrapping::Bank
+    }
 }
 
 impl MemoryMode for DirectPageIndexedX {
-    fn resolve(cpu: &mut Cpu) -> (HardwareAddress, HardwareAddress) {
+    fn resolve<H: Bus>(cpu: &mut Cpu<H>) -> (HardwareAddress, HardwareAddress) {
         let immediate = HardwareAddress::new(0, cpu.read_next::<u8>() as u16);
-        // Wraps only within the current bank
-        let adjusted_offset = immediate.offset()
-            .wrapping_add(cpu.regs().direct_page)
-            .wrapping_add(cpu.regs().index_x);
+        let index = cpu.regs().index_x;
+        let adjusted_offset = direct_page_indexed(cpu, immediate.offset(), index);
         cpu.direct_page_cycle();
         let resolved = HardwareAddress::new(0, adjusted_offset);
         (resolved, immediate)
@@ -200,18 +267,20 @@ impl MemoryMode for DirectPageIndexedX {
     fn format(f: &mut Formatter, immediate: HardwareAddress) -> fmt::Result {
         write!(f, "${:02X},X", immediate.offset().lower())
     }
+
+    fn wrapping() -> Wrapping {
+        Wrapping::Bank
+    }
 }
 
 impl MemoryMode for DirectPageIndexedXIndirect {
-    fn resolve(cpu: &mut Cpu) -> (HardwareAddress, HardwareAddress) {
+    fn resolve<H: Bus>(cpu: &mut Cpu<H>) -> (HardwareAddress, HardwareAddress) {
         let immediate = HardwareAddress::new(0, cpu.read_next::<u8>() as u16);
-        // Wraps only within the current bank
-        let adjusted_offset = immediate.offset()
-            .wrapping_add(cpu.regs().direct_page)
-            .wrapping_add(cpu.regs().index_x);
+        let index = cpu.regs().index_x;
+        let adjusted_offset = direct_page_indexed(cpu, immediate.offset(), index);
         cpu.direct_page_cycle();
         let indirect = HardwareAddress::new(0, adjusted_offset);
-        let resolved_offset = cpu.hardware_mut().read::<u16>(indirect);
+        let resolved_offset = direct_page_pointer(cpu, indirect);
         let resolved = HardwareAddress::new(0, resolved_offset);
         (resolved, immediate)
     }
@@ -222,12 +291,10 @@ impl MemoryMode for DirectPageIndexedXIndirect {
 }
 
 impl MemoryMode for DirectPageIndexedY {
-    fn resolve(cpu: &mut Cpu) -> (HardwareAddress, HardwareAddress) {
+    fn resolve<H: Bus>(cpu: &mut Cpu<H>) -> (HardwareAddress, HardwareAddress) {
         let immediate = HardwareAddress::new(0, cpu.read_next::<u8>() as u16);
-        // Wraps only within the current bank
-        let adjusted_offset = immediate.offset()
-            .wrapping_add(cpu.regs().direct_page)
-            .wrapping_add(cpu.regs().index_y);
+        let index = cpu.regs().index_y;
+        let adjusted_offset = direct_page_indexed(cpu, immediate.offset(), index);
         cpu.direct_page_cycle();
         let resolved = HardwareAddress::new(0, adjusted_offset);
         (resolved, immediate)
@@ -236,15 +303,19 @@ impl MemoryMode for DirectPageIndexedY {
     fn format(f: &mut Formatter, immediate: HardwareAddress) -> fmt::Result {
         write!(f, "${:02X},Y", immediate.offset().lower())
     }
+
+    fn wrapping() -> Wrapping {
+        Wrapping::Bank
+    }
 }
 
 impl MemoryMode for DirectPageIndirect {
-    fn resolve(cpu: &mut Cpu) -> (HardwareAddress, HardwareAddress) {
+    fn resolve<H: Bus>(cpu: &mut Cpu<H>) -> (HardwareAddress, HardwareAddress) {
         let immediate = HardwareAddress::new(0, cpu.read_next::<u8>() as u16);
         let adjusted_offset = immediate.offset().wrapping_add(cpu.regs().direct_page);
         cpu.direct_page_cycle();
         let indirect = HardwareAddress::new(0, adjusted_offset);
-        let resolved_offset = cpu.hardware_mut().read::<u16>(indirect);
+        let resolved_offset = direct_page_pointer(cpu, indirect);
         let resolved = HardwareAddress::new(cpu.regs().data_bank, resolved_offset);
         (resolved, immediate)
     }
@@ -254,17 +325,28 @@ impl MemoryMode for DirectPageIndirect {
     }
 }
 
-impl MemoryMode for DirectPageIndirectIndexedY {
-    fn resolve(cpu: &mut Cpu) -> (HardwareAddress, HardwareAddress) {
+impl DirectPageIndirectIndexedY {
+    fn resolve_indexed<H: Bus>(cpu: &mut Cpu<H>, write: bool) -> (HardwareAddress, HardwareAddress) {
         let data_bank = cpu.regs().data_bank;
         let immediate = HardwareAddress::new(0, cpu.read_next::<u8>() as u16);
         let adjusted_offset = immediate.offset().wrapping_add(cpu.regs().direct_page);
         cpu.direct_page_cycle();
         let indirect = HardwareAddress::new(0, adjusted_offset);
-        let resolved = HardwareAddress::new(data_bank, cpu.hardware_mut().read::<u16>(indirect));
+        let resolved = HardwareAddress::new(data_bank, direct_page_pointer(cpu, indirect));
         let indexed = resolved.wrapping_add(cpu.regs().index_y);
+        indexed_cycle(cpu, resolved, indexed, write);
         (indexed, immediate)
     }
+}
+
+impl MemoryMode for DirectPageIndirectIndexedY {
+    fn resolve<H: Bus>(cpu: &mut Cpu<H>) -> (HardwareAddress, HardwareAddress) {
+        DirectPageIndirectIndexedY::resolve_indexed(cpu, false)
+    }
+
+    fn resolve_for_write<H: Bus>(cpu: &mut Cpu<H>) -> (HardwareAddress, HardwareAddress) {
+        DirectPageIndirectIndexedY::resolve_indexed(cpu, true)
+    }
 
     fn format(f: &mut Formatter, immediate: HardwareAddress) -> fmt::Result {
         write!(f, "(${:02X}),Y", immediate.offset().lower())
@@ -272,7 +354,7 @@ impl MemoryMode for DirectPageIndirectIndexedY {
 }
 
 impl MemoryMode for DirectPageIndirectLong {
-    fn resolve(cpu: &mut Cpu) -> (HardwareAddress, HardwareAddress) {
+    fn resolve<H: Bus>(cpu: &mut Cpu<H>) -> (HardwareAddress, HardwareAddress) {
         let immediate = HardwareAddress::new(0, cpu.read_next::<u8>() as u16);
         let adjusted_offset = immediate.offset().wrapping_add(cpu.regs().direct_page);
         cpu.direct_page_cycle();
@@ -287,7 +369,7 @@ impl MemoryMode for DirectPageIndirectLong {
 }
 
 impl MemoryMode for DirectPageIndirectLongIndexedY {
-    fn resolve(cpu: &mut Cpu) -> (HardwareAddress, HardwareAddress) {
+    fn resolve<H: Bus>(cpu: &mut Cpu<H>) -> (HardwareAddress, HardwareAddress) {
         let immediate = HardwareAddress::new(0, cpu.read_next::<u8>() as u16);
         let adjusted_offset = immediate.offset().wrapping_add(cpu.regs().direct_page);
         cpu.direct_page_cycle();
@@ -303,7 +385,7 @@ impl MemoryMode for DirectPageIndirectLongIndexedY {
 }
 
 impl MemoryMode for ProgramCounterRelative {
-    fn resolve(cpu: &mut Cpu) -> (HardwareAddress, HardwareAddress) {
+    fn resolve<H: Bus>(cpu: &mut Cpu<H>) -> (HardwareAddress, HardwareAddress) {
         let bank = cpu.regs().data_bank;
         let immediate = HardwareAddress::new(bank, cpu.read_next::<u16>());
         let adjusted_offset = cpu.regs().program_counter.wrapping_add(immediate.offset());
@@ -317,7 +399,7 @@ impl MemoryMode for ProgramCounterRelative {
 }
 
 impl MemoryMode for StackRelative {
-    fn resolve(cpu: &mut Cpu) -> (HardwareAddress, HardwareAddress) {
+    fn resolve<H: Bus>(cpu: &mut Cpu<H>) -> (HardwareAddress, HardwareAddress) {
         let immediate = HardwareAddress::new(0, cpu.read_next::<u8>() as u16);
         // TODO: Emulation mode stack location
         let adjusted_offset = cpu.regs().stack_pointer.wrapping_add(immediate.offset());
@@ -328,10 +410,14 @@ impl MemoryMode for StackRelative {
     fn format(f: &mut Formatter, immediate: HardwareAddress) -> fmt::Result {
         write!(f, "${:02X},S", immediate.offset().lower())
     }
+
+    fn wrapping() -> Wrapping {
+        Wrapping::Bank
+    }
 }
 
 impl MemoryMode for StackRelativeIndirectIndexedY {
-    fn resolve(cpu: &mut Cpu) -> (HardwareAddress, HardwareAddress) {
+    fn resolve<H: Bus>(cpu: &mut Cpu<H>) -> (HardwareAddress, HardwareAddress) {
         let data_bank = cpu.regs().data_bank;
         let immediate = HardwareAddress::new(0, cpu.read_next::<u8>() as u16);
         // TODO: Emulation mode stack location
@@ -346,3 +432,99 @@ impl MemoryMode for StackRelativeIndirectIndexedY {
         write!(f, "(${:02X},S),Y", immediate.offset().lower())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use cpu::run_condition::RunCondition;
+    use hardware::{HardwareAddress, RomBuilder};
+
+    // Pointer at $FF pointing to $1234 if it wraps within the page, or to
+    // $1334 if it carries into $0100
+    const POINTER_SETUP: [u8; 29] = [
+        0xA9, 0x34,       // lda #$34
+        0x85, 0xFF,       // sta $ff
+        0xA9, 0x12,       // lda #$12
+        0x85, 0x00,       // sta $00
+        0xA9, 0x13,       // lda #$13
+        0x8D, 0x00, 0x01, // sta $0100
+        0xA9, 0xAB,       // lda #$ab
+        0x8D, 0x34, 0x12, // sta $1234
+        0xA9, 0xCD,       // lda #$cd
+        0x8D, 0x34, 0x13, // sta $1334
+        0xA2, 0x00,       // ldx #$00
+        0xA0, 0x00,       // ldy #$00
+        0xA9, 0x00        // lda #$00
+    ];
+
+    const EMULATION_MODE: [u8; 2] = [
+        0x38, // sec
+        0xFB  // xce
+    ];
+
+    // Returns the accumulator after running 'load' (a two byte instruction)
+    fn load_through_pointer(emulation_mode: bool, load: [u8; 2]) -> u8 {
+        let mut code = Vec::new();
+
+        if emulation_mode {
+            code.extend_from_slice(&EMULATION_MODE);
+        }
+
+        code.extend_from_slice(&POINTER_SETUP);
+        code.extend_from_slice(&load);
+
+        let end = RomBuilder::code_address() + code.len() as u16;
+        let mut cpu = RomBuilder::new().code(&code).build_cpu();
+        cpu.run_until(&RunCondition::ProgramCounter(HardwareAddress::new(0x00, end)));
+
+        cpu.regs().accumulator as u8
+    }
+
+    #[test]
+    fn pointers_wrap_within_page_in_emulation_mode() {
+        assert_eq!(load_through_pointer(true, [0xA1, 0xFF]), 0xAB); // lda ($ff,x)
+        assert_eq!(load_through_pointer(true, [0xB2, 0xFF]), 0xAB); // lda ($ff)
+        assert_eq!(load_through_pointer(true, [0xB1, 0xFF]), 0xAB); // lda ($ff),y
+    }
+
+    // Stack relative pointers don't use the direct page, so they aren't
+    // wrapped within the page even in emulation mode
+    #[test]
+    fn stack_relative_pointers_cross_page_in_emulation_mode() {
+        let mut code = vec![
+            0xC2, 0x10,       // rep #$10
+            0xA2, 0xFF, 0x01, // ldx #$01ff
+            0x9A,             // txs
+            0xE2, 0x10        // sep #$10
+        ];
+        code.extend_from_slice(&EMULATION_MODE);
+        code.extend_from_slice(&[
+            0xA9, 0x34,       // lda #$34
+            0x8D, 0xFF, 0x01, // sta $01ff
+            0xA9, 0x12,       // lda #$12
+            0x8D, 0x00, 0x01, // sta $0100
+            0xA9, 0x13,       // lda #$13
+            0x8D, 0x00, 0x02, // sta $0200
+            0xA9, 0xAB,       // lda #$ab
+            0x8D, 0x34, 0x12, // sta $1234
+            0xA9, 0xCD,       // lda #$cd
+            0x8D, 0x34, 0x13, // sta $1334
+            0xA0, 0x00,       // ldy #$00
+            0xA9, 0x00,       // lda #$00
+            0xB3, 0x00        // lda ($00,s),y
+        ]);
+
+        let end = RomBuilder::code_address() + code.len() as u16;
+        let mut cpu = RomBuilder::new().code(&code).build_cpu();
+        cpu.run_until(&RunCondition::ProgramCounter(HardwareAddress::new(0x00, end)));
+
+        assert_eq!(cpu.regs().stack_pointer, 0x01FF);
+        assert_eq!(cpu.regs().accumulator as u8, 0xCD);
+    }
+
+    #[test]
+    fn pointers_cross_page_in_native_mode() {
+        assert_eq!(load_through_pointer(false, [0xA1, 0xFF]), 0xCD); // lda ($ff,x)
+        assert_eq!(load_through_pointer(false, [0xB2, 0xFF]), 0xCD); // lda ($ff)
+        assert_eq!(load_through_pointer(false, [0xB1, 0xFF]), 0xCD); // lda ($ff),y
+    }
+}
@@ -1,10 +1,29 @@
 mod accessor;
 mod address_mode;
+mod breakpoint;
 mod cpu;
+mod cycle_audit;
 mod decimal;
+mod disassembler;
+mod error;
+mod history;
+mod hotspots;
 mod interrupt;
 mod memory_mode;
 mod register;
+mod run_condition;
+mod state;
+mod trace_trigger;
+mod tracer;
 mod value;
+#[cfg(test)]
+mod vectors;
+mod watch;
 
 pub use self::cpu::Cpu;
+pub use self::error::EmulationError;
+pub use self::run_condition::RunCondition;
+pub use self::state::CpuState;
+pub use self::trace_trigger::{TraceCondition, TraceTrigger};
+pub use self::tracer::Tracer;
+pub use self::watch::Watch;
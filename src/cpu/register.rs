@@ -2,6 +2,7 @@ use cpu::accessor::{Read, Write};
 use cpu::address_mode::AddressMode;
 use cpu::value::Value;
 use cpu::Cpu;
+use hardware::Bus;
 use std::fmt::{self, Display, Formatter};
 use std::marker::PhantomData;
 
@@ -13,7 +14,7 @@ macro_rules! register_static {
         impl AddressMode<$field_type> for $struct_name {
             type Output = Self;
 
-            fn resolve(self, _cpu: &mut Cpu) -> Self {
+            fn resolve<H: Bus>(self, _cpu: &mut Cpu<H>) -> Self {
                 self
             }
         }
@@ -25,13 +26,13 @@ macro_rules! register_static {
         }
 
         impl Read<$field_type> for $struct_name {
-            fn get(&self, cpu: &mut Cpu) -> $field_type {
+            fn get<H: Bus>(&self, cpu: &mut Cpu<H>) -> $field_type {
                 cpu.regs().$field_name
             }
         }
 
         impl Write<$field_type> for $struct_name {
-            fn set(&self, cpu: &mut Cpu, value: $field_type) {
+            fn set<H: Bus>(&self, cpu: &mut Cpu<H>, value: $field_type) {
                 cpu.regs_mut().$field_name = value;
             }
         }
@@ -48,7 +49,7 @@ macro_rules! register_modal {
         impl<T: Value> AddressMode<T> for $struct_name<T> {
             type Output = Self;
 
-            fn resolve(self, _cpu: &mut Cpu) -> Self {
+            fn resolve<H: Bus>(self, _cpu: &mut Cpu<H>) -> Self {
                 self
             }
         }
@@ -60,13 +61,13 @@ macro_rules! register_modal {
         }
 
         impl<T: Value> Read<T> for $struct_name<T> {
-            fn get(&self, cpu: &mut Cpu) -> T {
+            fn get<H: Bus>(&self, cpu: &mut Cpu<H>) -> T {
                 T::from_modal(cpu.regs().$field_name)
             }
         }
 
         impl<T: Value> Write<T> for $struct_name<T> {
-            fn set(&self, cpu: &mut Cpu, value: T) {
+            fn set<H: Bus>(&self, cpu: &mut Cpu<H>, value: T) {
                 value.to_modal(&mut cpu.regs_mut().$field_name);
             }
         }
@@ -87,7 +88,7 @@ pub struct ProcessorState;
 impl AddressMode<u8> for ProcessorState {
     type Output = Self;
 
-    fn resolve(self, _cpu: &mut Cpu) -> Self {
+    fn resolve<H: Bus>(self, _cpu: &mut Cpu<H>) -> Self {
         self
     }
 }
@@ -99,7 +100,7 @@ impl Display for ProcessorState {
 }
 
 impl Read<u8> for ProcessorState {
-    fn get(&self, cpu: &mut Cpu) -> u8 {
+    fn get<H: Bus>(&self, cpu: &mut Cpu<H>) -> u8 {
         let flags = cpu.flags();
 
         if flags.emulation_mode {
@@ -117,7 +118,7 @@ impl Read<u8> for ProcessorState {
 }
 
 impl Write<u8> for ProcessorState {
-    fn set(&self, cpu: &mut Cpu, value: u8) {
+    fn set<H: Bus>(&self, cpu: &mut Cpu<H>, value: u8) {
         let truncate_index_regs = {
             let flags = cpu.flags_mut();
 
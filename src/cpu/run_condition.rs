@@ -0,0 +1,135 @@
+use cpu::Cpu;
+use hardware::HardwareAddress;
+
+pub enum RunCondition {
+    // Stops once the given number of frames have been completed
+    Frame(u64),
+    // Stops before executing the instruction at the given address
+    ProgramCounter(HardwareAddress),
+    // Stops once the byte at the given (non-I/O) address has the given value
+    MemoryEquals(HardwareAddress, u8)
+}
+
+impl RunCondition {
+    // Accepts "frame:N", "pc:BB:OOOO" or "mem:BB:OOOO=VV" (value in hex)
+    pub fn parse(value: &str) -> Option<RunCondition> {
+        let mut parts = value.splitn(2, ':');
+
+        match (parts.next(), parts.next()) {
+            (Some("frame"), Some(frame)) => frame.parse::<u64>().ok().map(RunCondition::Frame),
+            (Some("pc"), Some(address)) => HardwareAddress::parse(address).map(RunCondition::ProgramCounter),
+            (Some("mem"), Some(comparison)) => {
+                let mut parts = comparison.splitn(2, '=');
+
+                match (parts.next().and_then(HardwareAddress::parse), parts.next()) {
+                    (Some(address), Some(value)) => u8::from_str_radix(value, 16).ok()
+                        .map(|value| RunCondition::MemoryEquals(address, value)),
+                    _ => None
+                }
+            },
+            _ => None
+        }
+    }
+
+    pub fn is_met(&self, cpu: &mut Cpu) -> bool {
+        match *self {
+            RunCondition::Frame(frame) => cpu.hardware().frame_count() >= frame,
+            RunCondition::ProgramCounter(address) => {
+                let regs = cpu.regs();
+                regs.program_bank == address.bank() && regs.program_counter == address.offset()
+            },
+            RunCondition::MemoryEquals(address, value) => {
+                cpu.hardware_mut().peek(address) == Some(value)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cpu::Cpu;
+    use hardware::{HardwareAddress, RomBuilder};
+    use super::RunCondition;
+
+    fn test_cpu(code: &[u8]) -> Cpu {
+        RomBuilder::new().code(code).build_cpu()
+    }
+
+    fn code_address(offset: u16) -> HardwareAddress {
+        HardwareAddress::new(0x00, RomBuilder::code_address() + offset)
+    }
+
+    // Counts upwards at $0010 forever
+    const COUNTER_LOOP: [u8; 4] = [
+        0xE6, 0x10, // inc $10
+        0x80, 0xFC  // bra -4
+    ];
+
+    #[test]
+    fn parses_conditions() {
+        let address = HardwareAddress::new(0x7E, 0x0010);
+
+        assert!(match RunCondition::parse("frame:3") { Some(RunCondition::Frame(3)) => true, _ => false });
+        assert!(match RunCondition::parse("pc:7E:0010") { Some(RunCondition::ProgramCounter(a)) => a == address, _ => false });
+        assert!(match RunCondition::parse("mem:7E:0010=A5") { Some(RunCondition::MemoryEquals(a, 0xA5)) => a == address, _ => false });
+        assert!(RunCondition::parse("mem:7E:0010").is_none());
+        assert!(RunCondition::parse("line:5").is_none());
+    }
+
+    #[test]
+    fn stops_at_frame() {
+        let mut cpu = test_cpu(&COUNTER_LOOP);
+
+        cpu.run_until(&RunCondition::Frame(2));
+
+        assert_eq!(cpu.hardware().frame_count(), 2);
+    }
+
+    #[test]
+    fn stops_at_program_counter() {
+        let mut cpu = test_cpu(&[
+            0xEA, // nop
+            0xEA, // nop
+            0xE8  // inx
+        ]);
+
+        cpu.run_until(&RunCondition::ProgramCounter(code_address(2)));
+
+        assert_eq!(cpu.regs().program_counter, code_address(2).offset());
+        // Still as the init stub left it
+        assert_eq!(cpu.regs().index_x, 0xFF);
+    }
+
+    #[test]
+    fn stops_when_memory_equals() {
+        let mut cpu = test_cpu(&COUNTER_LOOP);
+        let address = HardwareAddress::new(0x7E, 0x0010);
+
+        cpu.run_until(&RunCondition::MemoryEquals(address, 0x05));
+
+        assert_eq!(cpu.hardware_mut().peek(address), Some(0x05));
+    }
+
+    #[test]
+    fn stops_at_breakpoint() {
+        let mut cpu = test_cpu(&COUNTER_LOOP);
+
+        cpu.breakpoints_mut().set(code_address(2));
+        cpu.run_until(&RunCondition::Frame(1));
+
+        assert!(cpu.take_breakpoint_hit() == Some(code_address(2)));
+        assert_eq!(cpu.hardware().frame_count(), 0);
+    }
+
+    #[test]
+    fn stops_when_processor_stops() {
+        let mut cpu = test_cpu(&[
+            0xDB // stp
+        ]);
+
+        cpu.run_until(&RunCondition::ProgramCounter(code_address(2)));
+
+        assert!(cpu.take_error().is_some());
+        assert_eq!(cpu.regs().program_counter, code_address(1).offset());
+    }
+}
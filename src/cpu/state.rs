@@ -0,0 +1,173 @@
+use cpu::cpu::{CpuFlags, CpuRegisters};
+
+// Serialized layout, all little-endian:
+//
+// 00: A, X, Y, D, PC, S (16 bits each)
+// 0C: DB, PB
+// 0E: flags (16 bits, one per bit, in the order of flag_bits)
+// 10: waiting on WAI, NMI polled, IRQ polled
+pub const STATE_SIZE: usize = 0x13;
+
+const FLAG_COUNT: usize = 11;
+
+// The CPU's own state, as opposed to that of the rest of the machine
+#[derive(Copy, Clone)]
+pub struct CpuState {
+    pub regs: CpuRegisters,
+    pub flags: CpuFlags,
+    pub waiting: bool,
+    pub nmi_polled: bool,
+    pub irq_polled: bool
+}
+
+impl CpuState {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(STATE_SIZE);
+
+        for &value in [
+            self.regs.accumulator,
+            self.regs.index_x,
+            self.regs.index_y,
+            self.regs.direct_page,
+            self.regs.program_counter,
+            self.regs.stack_pointer
+        ].iter() {
+            data.push(value as u8);
+            data.push((value >> 8) as u8);
+        }
+
+        data.push(self.regs.data_bank);
+        data.push(self.regs.program_bank);
+
+        let flags = flag_bits(&self.flags).iter()
+            .enumerate()
+            .fold(0u16, |bits, (index, &flag)| bits | ((flag as u16) << index));
+
+        data.push(flags as u8);
+        data.push((flags >> 8) as u8);
+
+        data.push(self.waiting as u8);
+        data.push(self.nmi_polled as u8);
+        data.push(self.irq_polled as u8);
+
+        data
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<CpuState, String> {
+        if data.len() != STATE_SIZE {
+            return Err(format!("CPU state must be {} bytes, got {}", STATE_SIZE, data.len()));
+        }
+
+        let word = |offset: usize| data[offset] as u16 | ((data[offset + 1] as u16) << 8);
+        let flags = word(0x0E);
+        let flag = |index: usize| flags & (1 << index) != 0;
+
+        Ok(CpuState {
+            regs: CpuRegisters {
+                accumulator: word(0x00),
+                index_x: word(0x02),
+                index_y: word(0x04),
+                direct_page: word(0x06),
+                program_counter: word(0x08),
+                stack_pointer: word(0x0A),
+                data_bank: data[0x0C],
+                program_bank: data[0x0D]
+            },
+            flags: CpuFlags {
+                negative: flag(0),
+                overflow: flag(1),
+                memory_size: flag(2),
+                index_size: flag(3),
+                unused_flag: flag(4),
+                break_flag: flag(5),
+                decimal_mode: flag(6),
+                interrupt_disable: flag(7),
+                zero: flag(8),
+                carry: flag(9),
+                emulation_mode: flag(10)
+            },
+            waiting: data[0x10] != 0,
+            nmi_polled: data[0x11] != 0,
+            irq_polled: data[0x12] != 0
+        })
+    }
+}
+
+fn flag_bits(flags: &CpuFlags) -> [bool; FLAG_COUNT] {
+    [
+        flags.negative,
+        flags.overflow,
+        flags.memory_size,
+        flags.index_size,
+        flags.unused_flag,
+        flags.break_flag,
+        flags.decimal_mode,
+        flags.interrupt_disable,
+        flags.zero,
+        flags.carry,
+        flags.emulation_mode
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use cpu::Cpu;
+    use hardware::RomBuilder;
+    use super::{CpuState, STATE_SIZE};
+
+    fn test_cpu() -> Cpu {
+        RomBuilder::new()
+            .code(&[
+                0xA9, 0x12,       // lda #$12
+                0xA2, 0x34,       // ldx #$34
+                0xA0, 0x56,       // ldy #$56
+                0x38,             // sec
+                0xC2, 0x30,       // rep #$30
+                0xA9, 0xCD, 0xAB, // lda #$abcd
+                0x1A,             // inc
+                0x4B,             // phk
+                0xAB              // plb
+            ])
+            .build_cpu()
+    }
+
+    #[test]
+    fn restores_saved_state() {
+        let mut cpu = test_cpu();
+
+        // Init stub, then the first four instructions of the test code
+        for _ in 0..15 {
+            cpu.step();
+        }
+
+        let saved = cpu.save_state().to_bytes();
+
+        for _ in 0..5 {
+            cpu.step();
+        }
+
+        assert!(cpu.save_state().to_bytes() != saved);
+
+        cpu.load_state(&CpuState::from_bytes(&saved).unwrap());
+
+        assert!(cpu.save_state().to_bytes() == saved);
+        assert_eq!(cpu.regs().accumulator & 0xFF, 0x12);
+        assert_eq!(cpu.regs().index_x, 0x34);
+        assert_eq!(cpu.regs().index_y, 0x56);
+        assert!(cpu.flags().carry);
+
+        // Execution carries on from the restored state
+        for _ in 0..3 {
+            cpu.step();
+        }
+
+        assert_eq!(cpu.regs().accumulator, 0xABCE);
+    }
+
+    #[test]
+    fn rejects_state_of_wrong_size() {
+        assert!(CpuState::from_bytes(&[0; STATE_SIZE - 1]).is_err());
+        assert!(CpuState::from_bytes(&[0; STATE_SIZE + 1]).is_err());
+        assert!(CpuState::from_bytes(&[0; STATE_SIZE]).is_ok());
+    }
+}
@@ -0,0 +1,83 @@
+use hardware::HardwareAddress;
+
+pub enum TraceCondition {
+    ProgramCounter(HardwareAddress),
+    MemoryWrite(HardwareAddress),
+    NmiCount(u64)
+}
+
+// Turns on trace mode when its condition is met, then turns it off again
+// after the given number of instructions (or never, if there is no limit).
+// Each trigger only fires once.
+pub struct TraceTrigger {
+    condition: TraceCondition,
+    instruction_limit: Option<u64>,
+    remaining: Option<u64>,
+    fired: bool
+}
+
+impl TraceCondition {
+    // Accepts "pc:BB:OOOO", "write:BB:OOOO" or "nmi:N"
+    pub fn parse(value: &str) -> Option<TraceCondition> {
+        let mut parts = value.splitn(2, ':');
+
+        match (parts.next(), parts.next()) {
+            (Some("pc"), Some(address)) => HardwareAddress::parse(address).map(TraceCondition::ProgramCounter),
+            (Some("write"), Some(address)) => HardwareAddress::parse(address).map(TraceCondition::MemoryWrite),
+            (Some("nmi"), Some(count)) => count.parse::<u64>().ok().map(TraceCondition::NmiCount),
+            _ => None
+        }
+    }
+}
+
+impl TraceTrigger {
+    pub fn new(condition: TraceCondition, instruction_limit: Option<u64>) -> TraceTrigger {
+        TraceTrigger {
+            condition: condition,
+            instruction_limit: instruction_limit,
+            remaining: None,
+            fired: false
+        }
+    }
+
+    pub fn write_trap(&self) -> Option<HardwareAddress> {
+        match self.condition {
+            TraceCondition::MemoryWrite(address) => Some(address),
+            _ => None
+        }
+    }
+
+    // Called before each instruction. Returns Some(true) when tracing should
+    // start and Some(false) when it should stop.
+    pub fn update(&mut self, program_counter: HardwareAddress, write_trapped: bool, nmi_count: u64) -> Option<bool> {
+        if let Some(remaining) = self.remaining {
+            if remaining == 0 {
+                self.remaining = None;
+                return Some(false);
+            }
+            self.remaining = Some(remaining - 1);
+            return None;
+        }
+
+        if self.fired {
+            return None;
+        }
+
+        let triggered = match self.condition {
+            TraceCondition::ProgramCounter(address) => program_counter == address,
+            TraceCondition::MemoryWrite(..) => write_trapped,
+            TraceCondition::NmiCount(count) => nmi_count >= count
+        };
+
+        if !triggered {
+            return None;
+        }
+
+        info!("Trace triggered at {}", program_counter);
+
+        self.fired = true;
+        self.remaining = self.instruction_limit;
+
+        Some(true)
+    }
+}
@@ -0,0 +1,29 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+// Writes one line per instruction, before it executes, in a format close
+// to bsnes/Mesen trace logs so that traces can be diffed against them:
+//
+// 008000 78          sei                  A:0000 X:0000 Y:0000 S:01ff D:0000 DB:00 nvMXdIzc V:  0 H:  0 F:  0 T:186
+//
+// Lines are only written while trace mode is enabled (see log.rs), so the
+// same runtime toggles and trace triggers apply to the file as to stdout.
+pub struct Tracer {
+    output: BufWriter<File>
+}
+
+impl Tracer {
+    pub fn new(file: File) -> Tracer {
+        Tracer {
+            output: BufWriter::new(file)
+        }
+    }
+
+    pub fn write_line(&mut self, line: &str) {
+        writeln!(self.output, "{}", line).unwrap();
+    }
+
+    pub fn flush(&mut self) {
+        self.output.flush().unwrap();
+    }
+}
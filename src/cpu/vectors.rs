@@ -0,0 +1,266 @@
+use cpu::Cpu;
+use hardware::{Bus, HardwareAddress};
+use std::collections::HashMap;
+use util::json_value::JsonValue;
+
+// Only the bus cycle count is compared, so any speed will do
+const ACCESS_CYCLES: u64 = 8;
+
+// A flat 24-bit memory with no I/O, so single instructions can be checked
+// against published test vectors (in the format of the SingleStepTests 65816
+// suite) without the rest of the system
+struct VectorBus {
+    memory: HashMap<u32, u8>,
+    master_cycles: u64,
+    bus_cycles: usize
+}
+
+impl VectorBus {
+    fn new() -> VectorBus {
+        VectorBus {
+            memory: HashMap::new(),
+            master_cycles: 0,
+            bus_cycles: 0
+        }
+    }
+}
+
+impl Bus for VectorBus {
+    fn read_u8(&mut self, address: HardwareAddress) -> u8 {
+        self.tick(ACCESS_CYCLES);
+        self.memory.get(&address.to_u32()).cloned().unwrap_or(0)
+    }
+
+    fn write_u8(&mut self, address: HardwareAddress, value: u8) {
+        self.tick(ACCESS_CYCLES);
+        self.memory.insert(address.to_u32(), value);
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.master_cycles += cycles;
+        self.bus_cycles += 1;
+    }
+
+    fn master_cycles(&self) -> u64 {
+        self.master_cycles
+    }
+
+    fn nmi_polled(&self) -> bool {
+        false
+    }
+
+    fn irq_polled(&self) -> bool {
+        false
+    }
+}
+
+// Applies the vector's initial state, executes one instruction and compares
+// the registers, memory and number of bus cycles against its final state
+pub fn run_vector(vector: &JsonValue) -> Result<(), String> {
+    let name = vector.get("name").and_then(JsonValue::as_str).unwrap_or("?");
+    let initial = vector.get("initial").ok_or_else(|| format!("{}: no initial state", name))?;
+    let expected = vector.get("final").ok_or_else(|| format!("{}: no final state", name))?;
+    let cycles = vector.get("cycles").and_then(JsonValue::as_array)
+        .ok_or_else(|| format!("{}: no cycles", name))?;
+
+    let mut cpu = Cpu::new(VectorBus::new());
+    apply_state(&mut cpu, initial).map_err(|error| format!("{}: {}", name, error))?;
+    cpu.hardware_mut().bus_cycles = 0;
+
+    cpu.execute_instruction();
+
+    let mut mismatches = compare_state(&mut cpu, expected).map_err(|error| format!("{}: {}", name, error))?;
+
+    if cpu.hardware().bus_cycles != cycles.len() {
+        mismatches.push(format!("cycles={} (expected {})", cpu.hardware().bus_cycles, cycles.len()));
+    }
+
+    if mismatches.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("{}: {}", name, mismatches.join(", ")))
+    }
+}
+
+fn number(state: &JsonValue, name: &str) -> Result<u32, String> {
+    state.get(name).and_then(JsonValue::as_u32).ok_or_else(|| format!("missing '{}'", name))
+}
+
+// Each RAM entry is an [address, value] pair
+fn ram(state: &JsonValue) -> Result<Vec<(u32, u8)>, String> {
+    let entries = state.get("ram").and_then(JsonValue::as_array).ok_or_else(|| "missing 'ram'".to_owned())?;
+
+    entries.iter().map(|entry| {
+        match entry.as_array() {
+            Some(pair) if pair.len() == 2 => {
+                match (pair[0].as_u32(), pair[1].as_u32()) {
+                    (Some(address), Some(value)) => Ok((address, value as u8)),
+                    _ => Err("invalid RAM entry".to_owned())
+                }
+            },
+            _ => Err("invalid RAM entry".to_owned())
+        }
+    }).collect()
+}
+
+fn apply_state(cpu: &mut Cpu<VectorBus>, state: &JsonValue) -> Result<(), String> {
+    let emulation_mode = number(state, "e")? != 0;
+
+    cpu.flags_mut().emulation_mode = emulation_mode;
+    cpu.set_status(number(state, "p")? as u8);
+
+    if emulation_mode {
+        cpu.flags_mut().memory_size = true;
+        cpu.flags_mut().index_size = true;
+    }
+
+    {
+        let regs = cpu.regs_mut();
+        regs.accumulator = number(state, "a")? as u16;
+        regs.index_x = number(state, "x")? as u16;
+        regs.index_y = number(state, "y")? as u16;
+        regs.stack_pointer = number(state, "s")? as u16;
+        regs.direct_page = number(state, "d")? as u16;
+        regs.data_bank = number(state, "dbr")? as u8;
+        regs.program_bank = number(state, "pbr")? as u8;
+        regs.program_counter = number(state, "pc")? as u16;
+    }
+
+    for (address, value) in ram(state)? {
+        cpu.hardware_mut().memory.insert(address, value);
+    }
+
+    Ok(())
+}
+
+fn compare_state(cpu: &mut Cpu<VectorBus>, state: &JsonValue) -> Result<Vec<String>, String> {
+    let regs = *cpu.regs();
+
+    let actual = [
+        ("pc", regs.program_counter as u32),
+        ("s", regs.stack_pointer as u32),
+        ("p", cpu.status() as u32),
+        ("a", regs.accumulator as u32),
+        ("x", regs.index_x as u32),
+        ("y", regs.index_y as u32),
+        ("dbr", regs.data_bank as u32),
+        ("d", regs.direct_page as u32),
+        ("pbr", regs.program_bank as u32),
+        ("e", cpu.flags().emulation_mode as u32)
+    ];
+
+    let mut mismatches = Vec::new();
+
+    for &(name, value) in actual.iter() {
+        let expected = number(state, name)?;
+
+        if value != expected {
+            mismatches.push(format!("{}=${:X} (expected ${:X})", name, value, expected));
+        }
+    }
+
+    for (address, expected) in ram(state)? {
+        let value = cpu.hardware().memory.get(&address).cloned().unwrap_or(0);
+
+        if value != expected {
+            mismatches.push(format!("[${:06X}]=${:02X} (expected ${:02X})", address, value, expected));
+        }
+    }
+
+    Ok(mismatches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::run_vector;
+    use util::json_value::JsonValue;
+
+    // LDA #$1234 in native mode with a 16-bit accumulator
+    const LOAD_IMMEDIATE: &'static str = r#"{
+        "name": "a9 n 1",
+        "initial": {"pc": 4096, "s": 8191, "p": 0, "a": 0, "x": 0, "y": 0, "dbr": 0, "d": 0, "pbr": 0, "e": 0,
+            "ram": [[4096, 169], [4097, 52], [4098, 18]]},
+        "final": {"pc": 4099, "s": 8191, "p": 0, "a": 4660, "x": 0, "y": 0, "dbr": 0, "d": 0, "pbr": 0, "e": 0,
+            "ram": [[4096, 169], [4097, 52], [4098, 18]]},
+        "cycles": [[4096, 169, "dp-remx-"], [4097, 52, "-p-remx-"], [4098, 18, "-p-remx-"]]
+    }"#;
+
+    // STA $10 in emulation mode, with a direct page that isn't page aligned
+    // (which costs an extra internal cycle)
+    const STORE_DIRECT_PAGE: &'static str = r#"{
+        "name": "85 e 1",
+        "initial": {"pc": 512, "s": 509, "p": 52, "a": 171, "x": 0, "y": 0, "dbr": 0, "d": 1, "pbr": 0, "e": 1,
+            "ram": [[512, 133], [513, 16]]},
+        "final": {"pc": 514, "s": 509, "p": 52, "a": 171, "x": 0, "y": 0, "dbr": 0, "d": 1, "pbr": 0, "e": 1,
+            "ram": [[512, 133], [513, 16], [17, 171]]},
+        "cycles": [[512, 133, "dp-reMX-"], [513, 16, "-p-reMX-"], [513, null, "-p-reMX-"], [17, 171, "-p-reMX-"]]
+    }"#;
+
+    fn vector(text: &str) -> JsonValue {
+        JsonValue::parse(text).unwrap()
+    }
+
+    #[test]
+    fn passes_matching_vectors() {
+        assert!(run_vector(&vector(LOAD_IMMEDIATE)).is_ok());
+        assert!(run_vector(&vector(STORE_DIRECT_PAGE)).is_ok());
+    }
+
+    #[test]
+    fn reports_mismatches() {
+        let text = LOAD_IMMEDIATE
+            .replace("\"a\": 4660", "\"a\": 4661")
+            .replace(", [4098, 18, \"-p-remx-\"]", "");
+        let error = run_vector(&vector(&text)).unwrap_err();
+
+        assert!(error.starts_with("a9 n 1: "));
+        assert!(error.contains("a=$1234 (expected $1235)"));
+        assert!(error.contains("cycles=3 (expected 2)"));
+    }
+
+    // Point SNAIL_CPU_VECTORS at a directory of vector files (one JSON array
+    // per op code and mode) and run with --features cpu-vectors
+    #[cfg(feature = "cpu-vectors")]
+    #[test]
+    fn published_vectors() {
+        use std::env;
+        use std::fs::{self, File};
+        use std::io::Read;
+        use std::path::PathBuf;
+
+        let directory = env::var("SNAIL_CPU_VECTORS").unwrap_or_else(|_| "tests/65816".to_owned());
+
+        let mut paths: Vec<PathBuf> = fs::read_dir(&directory)
+            .unwrap_or_else(|error| panic!("Could not read {}: {}", directory, error))
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().map_or(false, |extension| extension == "json"))
+            .collect();
+
+        paths.sort();
+
+        let mut failed = 0;
+
+        for path in paths {
+            let mut text = String::new();
+
+            File::open(&path)
+                .and_then(|mut file| file.read_to_string(&mut text))
+                .unwrap_or_else(|error| panic!("Could not read {}: {}", path.display(), error));
+
+            let vectors = JsonValue::parse(&text)
+                .unwrap_or_else(|error| panic!("Could not parse {}: {}", path.display(), error));
+
+            let failures: Vec<String> = vectors.as_array().unwrap_or(&[]).iter()
+                .filter_map(|vector| run_vector(vector).err())
+                .collect();
+
+            if let Some(first) = failures.first() {
+                println!("{}: {} failed, e.g. {}", path.display(), failures.len(), first);
+            }
+
+            failed += failures.len();
+        }
+
+        assert_eq!(failed, 0);
+    }
+}
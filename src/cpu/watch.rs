@@ -0,0 +1,110 @@
+use hardware::{Hardware, HardwareAddress};
+use std::fmt::{self, Display, Formatter};
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum WatchFormat {
+    Hex8,
+    Hex16,
+    Decimal8,
+    Decimal16
+}
+
+pub struct Watch {
+    address: HardwareAddress,
+    format: WatchFormat,
+    value: Option<u16>
+}
+
+impl WatchFormat {
+    pub fn parse(value: &str) -> Option<WatchFormat> {
+        match value {
+            "hex8" => Some(WatchFormat::Hex8),
+            "hex16" => Some(WatchFormat::Hex16),
+            "dec8" => Some(WatchFormat::Decimal8),
+            "dec16" => Some(WatchFormat::Decimal16),
+            _ => None
+        }
+    }
+
+    fn is_word(&self) -> bool {
+        *self == WatchFormat::Hex16 || *self == WatchFormat::Decimal16
+    }
+}
+
+impl Watch {
+    // Accepts "BB:OOOO" with an optional ",format" suffix (defaults to hex8)
+    pub fn parse(value: &str) -> Option<Watch> {
+        let mut parts = value.splitn(2, ',');
+
+        let address = match parts.next().and_then(HardwareAddress::parse) {
+            Some(address) => address,
+            None => return None
+        };
+
+        let format = match parts.next() {
+            Some(format) => match WatchFormat::parse(format) {
+                Some(format) => format,
+                None => return None
+            },
+            None => WatchFormat::Hex8
+        };
+
+        Some(Watch::new(address, format))
+    }
+
+    pub fn new(address: HardwareAddress, format: WatchFormat) -> Watch {
+        Watch {
+            address: address,
+            format: format,
+            value: None
+        }
+    }
+
+    // Returns the new value if it differs from the last time it was checked
+    pub fn check(&mut self, hardware: &mut Hardware) -> Option<String> {
+        let lower = hardware.peek(self.address);
+
+        let value = if self.format.is_word() {
+            let upper = hardware.peek(self.address.wrapping_add(1));
+            lower.and_then(|lower| upper.map(|upper| ((upper as u16) << 8) | (lower as u16)))
+        } else {
+            lower.map(|lower| lower as u16)
+        };
+
+        if value == self.value {
+            return None;
+        }
+
+        self.value = value;
+
+        // I/O registers can't be peeked, so just report them as unknown
+        let value = match value {
+            Some(value) => value,
+            None => return Some(format!("{}=??", self.address))
+        };
+
+        Some(match self.format {
+            WatchFormat::Hex8 => format!("{}={:02X}", self.address, value),
+            WatchFormat::Hex16 => format!("{}={:04X}", self.address, value),
+            WatchFormat::Decimal8 | WatchFormat::Decimal16 => format!("{}={}", self.address, value)
+        })
+    }
+}
+
+impl Display for WatchFormat {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", match *self {
+            WatchFormat::Hex8 => "hex8",
+            WatchFormat::Hex16 => "hex16",
+            WatchFormat::Decimal8 => "dec8",
+            WatchFormat::Decimal16 => "dec16"
+        })
+    }
+}
+
+// In the same format accepted by Watch::parse
+impl Display for Watch {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{},{}", self.address, self.format)
+    }
+}
@@ -0,0 +1,186 @@
+use cpu::{Cpu, CpuState};
+use hardware::MemoryRegion;
+use options::Options;
+use std::any::Any;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub fn panic_message(payload: &Box<Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Unknown error".to_string()
+    }
+}
+
+// Writes enough state to a new directory to make a crash reproducible from
+// a bug report, and returns the path of that directory. This runs while
+// handling a panic, so failures are returned rather than unwrapped.
+pub fn write_bundle(cpu: &Cpu, options: &Options, message: &str) -> io::Result<PathBuf> {
+    let path = create_unique_dir()?;
+
+    let arguments: Vec<String> = env::args().collect();
+
+    let hardware = cpu.hardware();
+
+    let mut report = File::create(path.join("report.txt"))?;
+    writeln!(report, "Error: {}", message)?;
+    writeln!(report, "Command line: {}", arguments.join(" "))?;
+    writeln!(report, "ROM checksum: {:04X}", hardware.rom().checksum())?;
+    writeln!(report, "CPU: {}", cpu)?;
+    writeln!(report, "PPU: F={} V={} H={}", hardware.frame_count(), hardware.scanline(), hardware.dot())?;
+    writeln!(report, "")?;
+    writeln!(report, "Last instructions (oldest first):")?;
+
+    for entry in cpu.history().iter() {
+        writeln!(report, "{}", entry)?;
+    }
+
+    let mut state = File::create(path.join("state.json"))?;
+    writeln!(state, "{}", cpu.state_json())?;
+
+    let mut settings = File::create(path.join("options.json"))?;
+    writeln!(settings, "{}", options.settings_json())?;
+
+    // cpu.bin, wram.bin and sram.bin can be loaded back with restore_bundle
+    let mut cpu_state = File::create(path.join("cpu.bin"))?;
+    cpu_state.write_all(&cpu.save_state().to_bytes())?;
+
+    let mut wram = File::create(path.join("wram.bin"))?;
+    wram.write_all(hardware.memory(MemoryRegion::Wram))?;
+
+    if let Some(coprocessor_data) = hardware.coprocessor().save_state() {
+        let mut coprocessor = File::create(path.join("coprocessor.bin"))?;
+        coprocessor.write_all(&coprocessor_data)?;
+    }
+
+    let sram_data = hardware.memory(MemoryRegion::Sram);
+
+    if !sram_data.is_empty() {
+        let mut sram = File::create(path.join("sram.bin"))?;
+        sram.write_all(sram_data)?;
+    }
+
+    Ok(path)
+}
+
+// Loads the CPU, WRAM and SRAM saved in a crash report into a freshly
+// booted machine, so the code that crashed can be stepped through again.
+// Everything else (PPU, APU, DMA) stays as it was at power-on.
+pub fn restore_bundle(cpu: &mut Cpu, path: &Path) -> Result<(), String> {
+    let state = CpuState::from_bytes(&read_file(&path.join("cpu.bin"))?)?;
+
+    restore_memory(cpu, MemoryRegion::Wram, &read_file(&path.join("wram.bin"))?)?;
+
+    // Only written for games that have SRAM
+    let sram_path = path.join("sram.bin");
+
+    if sram_path.exists() {
+        restore_memory(cpu, MemoryRegion::Sram, &read_file(&sram_path)?)?;
+    }
+
+    cpu.load_state(&state);
+
+    Ok(())
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, String> {
+    let mut data = Vec::new();
+
+    File::open(path)
+        .and_then(|mut file| file.read_to_end(&mut data))
+        .map_err(|error| format!("Could not read {}: {}", path.display(), error))?;
+
+    Ok(data)
+}
+
+fn restore_memory(cpu: &mut Cpu, region: MemoryRegion, data: &[u8]) -> Result<(), String> {
+    let memory = cpu.hardware_mut().memory_mut(region);
+
+    if data.len() != memory.len() {
+        return Err(format!("Expected {} bytes of memory, got {}", memory.len(), data.len()));
+    }
+
+    memory.copy_from_slice(data);
+
+    Ok(())
+}
+
+// Named after the current time, with a suffix if there was already a crash
+// in the same second
+fn create_unique_dir() -> io::Result<PathBuf> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0);
+    let mut suffix = 0;
+
+    loop {
+        let path = if suffix == 0 {
+            PathBuf::from(format!("crash-{}", timestamp))
+        } else {
+            PathBuf::from(format!("crash-{}-{}", timestamp, suffix))
+        };
+
+        match fs::create_dir(&path) {
+            Ok(()) => return Ok(path),
+            Err(ref error) if error.kind() == ErrorKind::AlreadyExists => suffix += 1,
+            Err(error) => return Err(error)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cpu::Cpu;
+    use hardware::{HardwareAddress, MemoryRegion, RomBuilder};
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::process;
+    use super::restore_bundle;
+
+    // Writes the files restore_bundle reads, as write_bundle would
+    fn write_state(cpu: &Cpu, name: &str, wram: &[u8]) -> PathBuf {
+        let path = env::temp_dir().join(format!("snailemu-{}-{}", process::id(), name));
+        fs::create_dir_all(&path).unwrap();
+        File::create(path.join("cpu.bin")).unwrap().write_all(&cpu.save_state().to_bytes()).unwrap();
+        File::create(path.join("wram.bin")).unwrap().write_all(wram).unwrap();
+        path
+    }
+
+    #[test]
+    fn restores_cpu_and_wram() {
+        let mut crashed = RomBuilder::new()
+            .code(&[
+                0xA9, 0x42, // lda #$42
+                0x85, 0x10  // sta $10
+            ])
+            .build_cpu();
+
+        crashed.run_frame();
+
+        let path = write_state(&crashed, "crash", crashed.hardware().memory(MemoryRegion::Wram));
+        let mut cpu = RomBuilder::new().build_cpu();
+        let result = restore_bundle(&mut cpu, &path);
+        fs::remove_dir_all(&path).unwrap();
+
+        assert!(result.is_ok());
+        assert_eq!(cpu.regs().program_counter, crashed.regs().program_counter);
+        assert_eq!(cpu.regs().accumulator, crashed.regs().accumulator);
+        assert_eq!(cpu.hardware_mut().peek(HardwareAddress::new(0x7E, 0x0010)), Some(0x42));
+    }
+
+    #[test]
+    fn rejects_truncated_wram() {
+        let crashed = RomBuilder::new().build_cpu();
+        let path = write_state(&crashed, "truncated", &[0; 16]);
+        let result = restore_bundle(&mut RomBuilder::new().build_cpu(), &path);
+        fs::remove_dir_all(&path).unwrap();
+
+        assert!(result.is_err());
+    }
+}
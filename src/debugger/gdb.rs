@@ -0,0 +1,363 @@
+use cpu::{Cpu, RunCondition};
+use hardware::HardwareAddress;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+// Sent by the debugger, outside of a packet, to stop a running target
+const INTERRUPT: u8 = 0x03;
+
+// How many master cycles to run between checks for an interrupt (well under
+// a frame, so no frames are skipped)
+const INTERRUPT_POLL_CYCLES: u64 = 50000;
+
+const SIGINT: &'static str = "S02";
+const SIGTRAP: &'static str = "S05";
+const SIGSTOP: &'static str = "S11";
+
+// A minimal GDB remote serial protocol stub. Memory addresses are 24-bit
+// (bank << 16 | offset). Registers are numbered as follows, each sent as
+// little-endian hex:
+//
+// 0: A (16)  1: X (16)  2: Y (16)  3: S (16)  4: D (16)
+// 5: DB (8)  6: PB (8)  7: PC (16)  8: P (8)  9: E (8)
+//
+// 'monitor run-to <condition>' runs until a condition is met (see
+// RunCondition::parse), a breakpoint or watchpoint is hit, or the CPU halts.
+// It can't be interrupted, and GDB needs 'flushregs' afterwards to see the
+// new registers.
+pub struct GdbStub {
+    stream: TcpStream
+}
+
+impl GdbStub {
+    pub fn listen(port: u16) -> io::Result<GdbStub> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        info!("Waiting for GDB connection on port {}", port);
+
+        let (stream, address) = listener.accept()?;
+        stream.set_nodelay(true)?;
+        info!("GDB connected from {}", address);
+
+        Ok(GdbStub {
+            stream: stream
+        })
+    }
+
+    // Serves requests until the debugger detaches or kills the session.
+    // 'on_frame' is called for each new frame while the CPU is running.
+    // Connection errors end the session, leaving the CPU where it stopped.
+    pub fn run(&mut self, cpu: &mut Cpu, on_frame: &mut FnMut(&Cpu)) -> io::Result<()> {
+        while let Some(packet) = self.read_packet()? {
+            debug!("GDB: {}", packet);
+
+            let (command, arguments) = packet.split_at(packet.char_indices().nth(1).map_or(packet.len(), |(index, _)| index));
+
+            let reply = match command {
+                "?" => SIGTRAP.to_string(),
+                "g" => (0..REGISTER_COUNT).map(|index| read_register(cpu, index)).collect(),
+                "p" => match usize::from_str_radix(arguments, 16) {
+                    Ok(index) if index < REGISTER_COUNT => read_register(cpu, index),
+                    _ => "E01".to_string()
+                },
+                "P" => write_register(cpu, arguments),
+                "m" => read_memory(cpu, arguments),
+                "M" => write_memory(cpu, arguments),
+                "Z" | "z" => set_breakpoint(cpu, arguments, command == "Z"),
+                "c" => self.resume(cpu, on_frame)?.to_string(),
+                "s" => {
+                    cpu.step();
+                    cpu.take_breakpoint_hit();
+                    SIGTRAP.to_string()
+                },
+                "H" => "OK".to_string(),
+                "q" if arguments.starts_with("Supported") => "PacketSize=1000".to_string(),
+                "q" if arguments == "Attached" => "1".to_string(),
+                "q" if arguments.starts_with("Rcmd,") => self.monitor(cpu, &arguments[5..], on_frame)?,
+                "k" => return Ok(()),
+                "D" => return self.write_packet("OK"),
+                // An empty reply means 'not supported'
+                _ => String::new()
+            };
+
+            self.write_packet(&reply)?;
+        }
+
+        Ok(())
+    }
+
+    fn resume(&mut self, cpu: &mut Cpu, on_frame: &mut FnMut(&Cpu)) -> io::Result<&'static str> {
+        let mut frame_count = cpu.hardware().frame_count();
+
+        loop {
+            cpu.run_for_cycles(INTERRUPT_POLL_CYCLES);
+
+            if cpu.take_breakpoint_hit().is_some() {
+                return Ok(SIGTRAP);
+            }
+
+            // Continuing again carries on past the instruction
+            if let Some(error) = cpu.take_error() {
+                warn!("{}", error);
+                cpu.resume();
+                return Ok(SIGSTOP);
+            }
+
+            if !cpu.hardware_mut().take_watchpoint_hits().is_empty() {
+                return Ok(SIGTRAP);
+            }
+
+            if cpu.hardware().frame_count() != frame_count {
+                frame_count = cpu.hardware().frame_count();
+                on_frame(cpu);
+            }
+
+            if self.interrupt_requested()? {
+                return Ok(SIGINT);
+            }
+        }
+    }
+
+    // Sends any output as its own packet, then returns the final reply
+    fn monitor(&mut self, cpu: &mut Cpu, command: &str, on_frame: &mut FnMut(&Cpu)) -> io::Result<String> {
+        let command = match decode_hex(command).and_then(|bytes| String::from_utf8(bytes).ok()) {
+            Some(command) => command,
+            None => return Ok("E01".to_string())
+        };
+
+        let mut parts = command.trim().splitn(2, ' ');
+
+        let condition = match (parts.next(), parts.next().map(str::trim).and_then(RunCondition::parse)) {
+            (Some("run-to"), Some(condition)) => condition,
+            _ => return Ok("E01".to_string())
+        };
+
+        cpu.run_until(&condition);
+        on_frame(cpu);
+
+        let reason = if cpu.take_breakpoint_hit().is_some() {
+            "breakpoint"
+        } else if let Some(error) = cpu.take_error() {
+            warn!("{}", error);
+            cpu.resume();
+            "error"
+        } else if !cpu.hardware_mut().take_watchpoint_hits().is_empty() {
+            "watchpoint"
+        } else if condition.is_met(cpu) {
+            "condition met"
+        } else {
+            "CPU halted"
+        };
+
+        let output = format!("Stopped at {:02X}:{:04X} ({})\n", cpu.regs().program_bank, cpu.regs().program_counter, reason);
+        self.write_packet(&format!("O{}", encode_hex(output.as_bytes())))?;
+
+        Ok("OK".to_string())
+    }
+
+    fn interrupt_requested(&mut self) -> io::Result<bool> {
+        let mut buffer = [0u8; 1];
+
+        self.stream.set_nonblocking(true)?;
+        let result = self.stream.read(&mut buffer);
+        self.stream.set_nonblocking(false)?;
+
+        match result {
+            Ok(1) => Ok(buffer[0] == INTERRUPT),
+            Ok(_) => Ok(false),
+            Err(ref error) if error.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(error) => Err(error)
+        }
+    }
+
+    // Returns None once the connection is closed
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        // Skip acks and anything else outside of a packet
+        loop {
+            match self.read_byte()? {
+                Some(b'$') => break,
+                Some(_) => (),
+                None => return Ok(None)
+            }
+        }
+
+        let mut data = Vec::new();
+
+        loop {
+            match self.read_byte()? {
+                Some(b'#') => break,
+                Some(byte) => data.push(byte),
+                None => return Ok(None)
+            }
+        }
+
+        // Checksum is not verified, as TCP already guarantees delivery
+        for _ in 0..2 {
+            if self.read_byte()?.is_none() {
+                return Ok(None);
+            }
+        }
+
+        self.stream.write_all(b"+")?;
+
+        Ok(Some(String::from_utf8_lossy(&data).into_owned()))
+    }
+
+    fn write_packet(&mut self, data: &str) -> io::Result<()> {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        write!(self.stream, "${}#{:02x}", data, checksum)
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut buffer = [0u8; 1];
+
+        match self.stream.read(&mut buffer) {
+            Ok(1) => Ok(Some(buffer[0])),
+            Ok(_) => Ok(None),
+            Err(ref error) if error.kind() == ErrorKind::ConnectionReset => Ok(None),
+            Err(error) => Err(error)
+        }
+    }
+}
+
+const REGISTER_COUNT: usize = 10;
+
+fn read_register(cpu: &mut Cpu, index: usize) -> String {
+    let (value, size) = match index {
+        0 => (cpu.regs().accumulator, 2),
+        1 => (cpu.regs().index_x, 2),
+        2 => (cpu.regs().index_y, 2),
+        3 => (cpu.regs().stack_pointer, 2),
+        4 => (cpu.regs().direct_page, 2),
+        5 => (cpu.regs().data_bank as u16, 1),
+        6 => (cpu.regs().program_bank as u16, 1),
+        7 => (cpu.regs().program_counter, 2),
+        8 => (cpu.status() as u16, 1),
+        9 => (cpu.flags().emulation_mode as u16, 1),
+        _ => unreachable!()
+    };
+
+    if size == 1 {
+        format!("{:02x}", value)
+    } else {
+        format!("{:02x}{:02x}", value & 0xFF, value >> 8)
+    }
+}
+
+// "n=value", with the value in little-endian hex
+fn write_register(cpu: &mut Cpu, arguments: &str) -> String {
+    let mut parts = arguments.splitn(2, '=');
+
+    let (index, bytes) = match (parts.next(), parts.next().and_then(decode_hex)) {
+        (Some(index), Some(bytes)) => match usize::from_str_radix(index, 16) {
+            Ok(index) => (index, bytes),
+            Err(_) => return "E01".to_string()
+        },
+        _ => return "E01".to_string()
+    };
+
+    let value = bytes.iter().rev().fold(0u16, |value, &byte| (value << 8) | byte as u16);
+
+    match index {
+        0 => cpu.regs_mut().accumulator = value,
+        1 => cpu.regs_mut().index_x = value,
+        2 => cpu.regs_mut().index_y = value,
+        3 => cpu.regs_mut().stack_pointer = value,
+        4 => cpu.regs_mut().direct_page = value,
+        5 => cpu.regs_mut().data_bank = value as u8,
+        6 => cpu.regs_mut().program_bank = value as u8,
+        7 => cpu.regs_mut().program_counter = value,
+        8 => cpu.set_status(value as u8),
+        // Switching modes has side effects, so E is read-only here
+        _ => return "E01".to_string()
+    }
+
+    "OK".to_string()
+}
+
+// "addr,length"
+fn read_memory(cpu: &mut Cpu, arguments: &str) -> String {
+    let (address, length) = match parse_range(arguments) {
+        Some(range) => range,
+        None => return "E01".to_string()
+    };
+
+    let mut reply = String::new();
+
+    for index in 0..length {
+        match cpu.hardware_mut().peek(to_address(address.wrapping_add(index))) {
+            Some(value) => reply.push_str(&format!("{:02x}", value)),
+            None if index > 0 => break,
+            None => return "E01".to_string()
+        }
+    }
+
+    reply
+}
+
+// "addr,length:data"
+fn write_memory(cpu: &mut Cpu, arguments: &str) -> String {
+    let mut parts = arguments.splitn(2, ':');
+
+    let (address, bytes) = match (parts.next().and_then(parse_range), parts.next().and_then(decode_hex)) {
+        (Some((address, length)), Some(bytes)) if bytes.len() == length as usize => (address, bytes),
+        _ => return "E01".to_string()
+    };
+
+    for (index, &value) in bytes.iter().enumerate() {
+        if !cpu.hardware_mut().poke(to_address(address.wrapping_add(index as u32)), value) {
+            return "E01".to_string();
+        }
+    }
+
+    "OK".to_string()
+}
+
+// "0,addr,kind" (only software breakpoints are supported)
+fn set_breakpoint(cpu: &mut Cpu, arguments: &str, insert: bool) -> String {
+    let mut parts = arguments.splitn(3, ',');
+
+    let address = match (parts.next(), parts.next().map(|address| u32::from_str_radix(address, 16))) {
+        (Some("0"), Some(Ok(address))) => to_address(address),
+        _ => return String::new()
+    };
+
+    if insert {
+        cpu.breakpoints_mut().set(address);
+    } else {
+        cpu.breakpoints_mut().clear(address);
+    }
+
+    "OK".to_string()
+}
+
+fn parse_range(value: &str) -> Option<(u32, u32)> {
+    let mut parts = value.splitn(2, ',');
+
+    match (parts.next(), parts.next()) {
+        (Some(address), Some(length)) => {
+            match (u32::from_str_radix(address, 16), u32::from_str_radix(length, 16)) {
+                (Ok(address), Ok(length)) => Some((address, length)),
+                _ => None
+            }
+        },
+        _ => None
+    }
+}
+
+fn decode_hex(value: &str) -> Option<Vec<u8>> {
+    if value.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..value.len()).step_by(2)
+        .map(|index| value.get(index..(index + 2)).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect()
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn to_address(address: u32) -> HardwareAddress {
+    HardwareAddress::new((address >> 16) as u8, address as u16)
+}
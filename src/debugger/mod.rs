@@ -0,0 +1,3 @@
+mod gdb;
+
+pub use self::gdb::GdbStub;
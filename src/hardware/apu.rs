@@ -1,15 +1,62 @@
 use super::hardware::HardwareBus;
+use std::fs::File;
+use std::io::{BufWriter, Write};
 
 pub struct Apu {
     ports: [u8; 4],
-    transfer_started: bool
+    transfer_started: bool,
+    trace: Option<BufWriter<File>>,
+    last_read: [Option<u8>; 4]
 }
 
 impl Apu {
     pub fn new() -> Apu {
         Apu {
             ports: [0xAA, 0x00, 0x00, 0x00],
-            transfer_started: false
+            transfer_started: false,
+            trace: None,
+            last_read: [None; 4]
+        }
+    }
+
+    // Logs CPU traffic on $2140-$2143 to a file, one access per line:
+    //
+    // F:  12 V:100 H: 42 T:1234567 W 2140 CC
+    //
+    // Drivers poll the ports in tight loops, so a read is only logged when
+    // it returns a different value to the last read of the same port.
+    pub fn set_trace_file(&mut self, file: File) {
+        self.trace = Some(BufWriter::new(file));
+    }
+
+    pub fn tracing(&self) -> bool {
+        self.trace.is_some()
+    }
+
+    pub fn trace_access(&mut self, timestamp: (u64, u16, u16, u64), port: usize, value: u8, write: bool) {
+        if !write {
+            if self.last_read[port] == Some(value) {
+                return;
+            }
+            self.last_read[port] = Some(value);
+        }
+
+        if let Some(ref mut trace) = self.trace {
+            let (frame, v, h, clock) = timestamp;
+            writeln!(trace, "F:{:4} V:{:3} H:{:3} T:{} {} {:04X} {:02X}",
+                frame,
+                v,
+                h,
+                clock,
+                if write { 'W' } else { 'R' },
+                0x2140 + port,
+                value).unwrap();
+        }
+    }
+
+    pub fn flush_trace(&mut self) {
+        if let Some(ref mut trace) = self.trace {
+            trace.flush().unwrap();
         }
     }
 }
@@ -0,0 +1,23 @@
+use std::fmt::{self, Display, Formatter};
+
+// A snapshot of emulated time. Master cycles run at 21.477MHz (NTSC), and
+// each CPU cycle takes 6, 8 or 12 of them depending on the region accessed.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Clock {
+    pub master_cycles: u64,
+    pub cpu_cycles: u64,
+    pub frame: u64,
+    pub scanline: u16,
+    pub dot: u16
+}
+
+impl Display for Clock {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "F={} V={} H={} MC={} CC={}",
+            self.frame,
+            self.scanline,
+            self.dot,
+            self.master_cycles,
+            self.cpu_cycles)
+    }
+}
@@ -0,0 +1,126 @@
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+
+// Anything that can be plugged into one of the two controller ports. Each
+// port has a latch line shared by both ports and two serial data lines.
+pub trait ControllerPort {
+    fn set_latch(&mut self, latch: bool);
+
+    // Clocks the next bit out of the device, with data line 1 in bit 0 and
+    // data line 2 (used by multitaps) in bit 1
+    fn read_serial(&mut self) -> u8;
+
+    fn handle_event(&mut self, _event: &Event) {
+        // Most devices don't take host input directly
+    }
+
+    fn set_buttons(&mut self, _buttons: ButtonState) {
+        // Only meaningful for devices with buttons
+    }
+}
+
+pub struct Unplugged;
+
+pub struct Gamepad {
+    button_state: ButtonState,
+    button_index: usize,
+    latch: bool
+}
+
+bitflags! {
+    pub flags ButtonState: u16 {
+        const B = 0x8000,
+        const Y = 0x4000,
+        const SELECT = 0x2000,
+        const START = 0x1000,
+        const UP = 0x0800,
+        const DOWN = 0x0400,
+        const LEFT = 0x0200,
+        const RIGHT = 0x0100,
+        const A = 0x0080,
+        const X = 0x0040,
+        const L = 0x0020,
+        const R = 0x0010
+    }
+}
+
+fn keycode_to_button(keycode: Keycode) -> ButtonState {
+    // All very subject to change
+    match keycode {
+        Keycode::Z => B,
+        Keycode::A => Y,
+        Keycode::Space => SELECT,
+        Keycode::Return => START,
+        Keycode::Up => UP,
+        Keycode::Down => DOWN,
+        Keycode::Left => LEFT,
+        Keycode::Right => RIGHT,
+        Keycode::X => A,
+        Keycode::S => X,
+        Keycode::Q => L,
+        Keycode::W => R,
+        _ => ButtonState::empty()
+    }
+}
+
+impl ControllerPort for Unplugged {
+    fn set_latch(&mut self, _latch: bool) {
+        // Nothing to latch
+    }
+
+    fn read_serial(&mut self) -> u8 {
+        0x00
+    }
+}
+
+impl Gamepad {
+    pub fn new() -> Gamepad {
+        Gamepad {
+            button_state: ButtonState::empty(),
+            button_index: 0,
+            latch: false
+        }
+    }
+}
+
+impl ControllerPort for Gamepad {
+    fn set_latch(&mut self, latch: bool) {
+        self.latch = latch;
+
+        if latch {
+            self.button_index = 0;
+        }
+    }
+
+    fn read_serial(&mut self) -> u8 {
+        // While latched, the pad keeps reloading and so always returns B
+        if self.latch {
+            return (self.button_state.contains(B)) as u8;
+        }
+
+        if self.button_index < 16 {
+            let mask = 0x8000 >> self.button_index;
+            self.button_index += 1;
+            (self.button_state.bits() & mask != 0) as u8
+        } else {
+            // A standard pad returns 1s once all of its bits have been read
+            0x01
+        }
+    }
+
+    fn handle_event(&mut self, event: &Event) {
+        match *event {
+            Event::KeyDown { keycode: Some(keycode), .. } => {
+                self.button_state.insert(keycode_to_button(keycode));
+            },
+            Event::KeyUp { keycode: Some(keycode), .. } => {
+                self.button_state.remove(keycode_to_button(keycode));
+            },
+            _ => ()
+        };
+    }
+
+    fn set_buttons(&mut self, buttons: ButtonState) {
+        self.button_state = buttons;
+    }
+}
@@ -0,0 +1,74 @@
+use super::hardware::{HardwareAddress, HardwareBus};
+
+// A cartridge chip (SA-1, SuperFX, DSP-n, CX4, S-DD1, etc.). The chip claims
+// the addresses it responds to, so the core bus routing never has to know
+// about individual chips.
+pub trait Coprocessor {
+    fn name(&self) -> &'static str;
+
+    // Returns the offset to pass to read/write if the chip responds at this
+    // address. Claimed addresses take priority over everything else.
+    fn map(&self, address: HardwareAddress) -> Option<usize>;
+
+    // Master clock cycles per access at a claimed address
+    fn cycles(&self, address: HardwareAddress) -> u64;
+
+    fn read(&mut self, offset: usize) -> u8;
+    fn write(&mut self, offset: usize, value: u8);
+
+    // Runs the chip for the given number of master clock cycles
+    fn tick(&mut self, cycles: u64);
+
+    // Internal state (registers and RAM) for crash reports
+    fn save_state(&self) -> Vec<u8>;
+}
+
+pub struct CoprocessorSlot {
+    chip: Option<Box<Coprocessor + Send>>
+}
+
+impl CoprocessorSlot {
+    pub fn new() -> CoprocessorSlot {
+        CoprocessorSlot {
+            chip: None
+        }
+    }
+
+    pub fn attach(&mut self, chip: Box<Coprocessor + Send>) {
+        info!("Coprocessor: {}", chip.name());
+        self.chip = Some(chip);
+    }
+
+    // Returns the chip offset and access cycles, if the address is claimed
+    pub fn map(&self, address: HardwareAddress) -> Option<(usize, u64)> {
+        match self.chip {
+            Some(ref chip) => chip.map(address).map(|offset| (offset, chip.cycles(address))),
+            None => None
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u64) {
+        if let Some(ref mut chip) = self.chip {
+            chip.tick(cycles);
+        }
+    }
+
+    pub fn save_state(&self) -> Option<Vec<u8>> {
+        self.chip.as_ref().map(|chip| chip.save_state())
+    }
+}
+
+impl HardwareBus for CoprocessorSlot {
+    fn read(&mut self, offset: usize) -> u8 {
+        match self.chip {
+            Some(ref mut chip) => chip.read(offset),
+            None => unreachable!()
+        }
+    }
+
+    fn write(&mut self, offset: usize, value: u8) {
+        if let Some(ref mut chip) = self.chip {
+            chip.write(offset, value);
+        }
+    }
+}
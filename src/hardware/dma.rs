@@ -1,5 +1,6 @@
 use super::hardware::{Hardware, HardwareAddress, HardwareBus};
 use util::byte_access::ByteAccess;
+use util::json::JsonObject;
 
 pub const DMA_CHANNEL_COUNT: usize = 8;
 
@@ -16,8 +17,7 @@ pub struct DmaChannel {
     source: HardwareAddress,
     hdma_indirect_address: HardwareAddress,
     hdma_table_address: HardwareAddress,
-    hdma_line_counter: HdmaLineCounter,
-    hdma_active: bool
+    hdma_line_counter: HdmaLineCounter
 }
 
 #[derive(Copy, Clone)]
@@ -60,10 +60,26 @@ impl DmaChannel {
             source: HardwareAddress::new(0x00, 0x0000),
             hdma_indirect_address: HardwareAddress::new(0x00, 0x0000),
             hdma_table_address: HardwareAddress::new(0x00, 0x0000),
-            hdma_line_counter: HdmaLineCounter::Repeat(0x7F),
-            hdma_active: false
+            hdma_line_counter: HdmaLineCounter::Repeat(0x7F)
         }
     }
+
+    pub fn state_json(&self) -> JsonObject {
+        let (line_counter_repeat, line_counter) = match self.hdma_line_counter {
+            HdmaLineCounter::Repeat(count) => (true, count),
+            HdmaLineCounter::Count(count) => (false, count)
+        };
+
+        let mut state = JsonObject::new();
+        state.number("control", self.raw_control_value);
+        state.number("destination", self.destination);
+        state.number("source", self.source.to_u32());
+        state.number("hdma_indirect_address", self.hdma_indirect_address.to_u32());
+        state.number("hdma_table_address", self.hdma_table_address.to_u32());
+        state.boolean("hdma_line_counter_repeat", line_counter_repeat);
+        state.number("hdma_line_counter", line_counter);
+        state
+    }
 }
 
 impl HardwareBus for DmaChannel {
@@ -142,11 +158,12 @@ pub fn dma_transfer(hardware: &mut Hardware, channel_mask: u8) {
             continue;
         }
 
+        // TODO: An HDMA transfer on the same channel during the DMA should
+        // terminate it, but HDMA tables are not processed yet, so $420C is
+        // only stored. Having the channel enabled in $420C is not enough on
+        // its own. The CPU is halted until the DMA ends, so no $420B/$420C
+        // write can land part-way through one.
         let mut channel = hardware.dma_channel(i).clone();
-        
-        if channel.hdma_active {
-            continue;
-        }
 
         hardware.tick(DMA_CYCLES);
 
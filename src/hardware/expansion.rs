@@ -0,0 +1,145 @@
+use super::hardware::HardwareBus;
+
+pub struct ExpansionSlot {
+    device: Option<Box<HardwareBus + Send>>,
+    cycles: u64
+}
+
+impl ExpansionSlot {
+    pub fn new(cycles: u64) -> ExpansionSlot {
+        ExpansionSlot {
+            device: None,
+            cycles: cycles
+        }
+    }
+
+    // No expansion device is emulated yet, so only tests attach one
+    #[cfg(test)]
+    pub fn attach(&mut self, device: Box<HardwareBus + Send>) {
+        self.device = Some(device);
+    }
+
+    #[cfg(test)]
+    pub fn detach(&mut self) -> Option<Box<HardwareBus + Send>> {
+        self.device.take()
+    }
+
+    pub fn is_attached(&self) -> bool {
+        self.device.is_some()
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    #[cfg(test)]
+    pub fn set_cycles(&mut self, cycles: u64) {
+        self.cycles = cycles;
+    }
+}
+
+impl HardwareBus for ExpansionSlot {
+    fn read(&mut self, offset: usize) -> u8 {
+        match self.device {
+            Some(ref mut device) => device.read(offset),
+            None => 0x00 // Not reached: Hardware reads open bus while the slot is empty
+        }
+    }
+
+    fn write(&mut self, offset: usize, value: u8) {
+        if let Some(ref mut device) = self.device {
+            device.write(offset, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hardware::{Bus, Hardware, HardwareAddress, RomBuilder};
+    use hardware::hardware::HardwareBus;
+
+    // Sixteen bytes of RAM, like a minimal B bus peripheral at $21F0-$21FF
+    struct TestDevice([u8; 16]);
+
+    impl HardwareBus for TestDevice {
+        fn read(&mut self, offset: usize) -> u8 {
+            self.0[offset & 0x0F]
+        }
+
+        fn write(&mut self, offset: usize, value: u8) {
+            self.0[offset & 0x0F] = value;
+        }
+    }
+
+    // Returns the value read and the master cycles taken
+    fn timed_read(hardware: &mut Hardware, address: HardwareAddress) -> (u8, u64) {
+        let start = hardware.master_cycles();
+        let value = hardware.read::<u8>(address);
+        (value, hardware.master_cycles() - start)
+    }
+
+    #[test]
+    fn b_bus_device_is_read_back_through_hardware() {
+        let mut hardware = RomBuilder::new().build_hardware();
+        hardware.expansion_b_bus_mut().attach(Box::new(TestDevice([0; 16])));
+
+        hardware.write::<u8>(HardwareAddress::new(0x00, 0x21F0), 0x12);
+        hardware.write::<u8>(HardwareAddress::new(0x80, 0x21FF), 0x34);
+
+        assert_eq!(hardware.read::<u8>(HardwareAddress::new(0x80, 0x21F0)), 0x12);
+        assert_eq!(hardware.read::<u8>(HardwareAddress::new(0x00, 0x21FF)), 0x34);
+
+        // $21C0-$21EF are not part of the expansion port
+        hardware.write::<u8>(HardwareAddress::new(0x00, 0x21E0), 0x56);
+        assert_eq!(hardware.read::<u8>(HardwareAddress::new(0x00, 0x21F0)), 0x12);
+    }
+
+    #[test]
+    fn a_bus_device_is_read_back_through_hardware() {
+        let mut hardware = RomBuilder::new().build_hardware();
+        hardware.expansion_a_bus_mut().attach(Box::new(TestDevice([0; 16])));
+
+        hardware.write::<u8>(HardwareAddress::new(0x00, 0x6003), 0x78);
+        assert_eq!(hardware.read::<u8>(HardwareAddress::new(0x00, 0x6003)), 0x78);
+    }
+
+    #[test]
+    fn access_cycles_can_be_changed() {
+        let mut hardware = RomBuilder::new().build_hardware();
+        let address = HardwareAddress::new(0x00, 0x21F0);
+
+        hardware.expansion_b_bus_mut().attach(Box::new(TestDevice([0x9A; 16])));
+        let (_, default_cycles) = timed_read(&mut hardware, address);
+
+        hardware.expansion_b_bus_mut().set_cycles(12);
+        assert_eq!(timed_read(&mut hardware, address), (0x9A, 12));
+        assert!(default_cycles != 12);
+    }
+
+    #[test]
+    fn detached_device_no_longer_responds() {
+        let mut hardware = RomBuilder::new().build_hardware();
+        let address = HardwareAddress::new(0x00, 0x21F0);
+
+        hardware.expansion_b_bus_mut().attach(Box::new(TestDevice([0x9A; 16])));
+        assert_eq!(hardware.read::<u8>(address), 0x9A);
+
+        let mut device = hardware.expansion_b_bus_mut().detach().unwrap();
+        assert_eq!(device.read(0), 0x9A);
+
+        hardware.write::<u8>(address, 0xBC);
+        assert_eq!(device.read(0), 0x9A);
+        assert!(hardware.expansion_b_bus_mut().detach().is_none());
+    }
+
+    #[test]
+    fn empty_slot_reads_open_bus() {
+        let mut hardware = RomBuilder::new().build_hardware();
+
+        for &address in [HardwareAddress::new(0x00, 0x21F0), HardwareAddress::new(0x00, 0x6003)].iter() {
+            // The write leaves $5A on the data bus
+            hardware.write::<u8>(HardwareAddress::new(0x7E, 0x0000), 0x5A);
+            assert_eq!(hardware.read::<u8>(address), 0x5A);
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use util::color::Color;
+
+pub const FRAME_WIDTH: usize = 512;
+pub const FRAME_HEIGHT: usize = 512;
+
+const BYTES_PER_PIXEL: usize = 4;
+const ROW_LENGTH: usize = FRAME_WIDTH * BYTES_PER_PIXEL;
+
+const VISIBLE_HEIGHT_NORMAL: usize = 448;
+const VISIBLE_HEIGHT_OVERSCAN: usize = 478;
+
+const FNV_OFFSET_BASIS: u64 = 0xCBF29CE484222325;
+const FNV_PRIME: u64 = 0x00000100000001B3;
+
+// Pixels are stored as 32-bit BGRA, with the alpha channel used for brightness
+pub struct FrameBuffer {
+    pixels: Vec<u8>,
+    mode: ScreenMode,
+    overscan: bool,
+    overscan_buffer: bool,
+    brightness: u8,
+    index: usize
+}
+
+pub enum ScreenMode {
+    Standard,
+    Interlace(InterlaceFrame)
+}
+
+pub enum InterlaceFrame {
+    Even,
+    Odd
+}
+
+impl FrameBuffer {
+    pub fn new() -> FrameBuffer {
+        FrameBuffer {
+            pixels: vec![0; ROW_LENGTH * FRAME_HEIGHT],
+            mode: ScreenMode::Standard,
+            overscan: false,
+            overscan_buffer: false,
+            brightness: 0xFF,
+            index: 0
+        }
+    }
+
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn row_length(&self) -> usize {
+        ROW_LENGTH
+    }
+
+    pub fn visible_height(&self) -> usize {
+        if self.overscan {
+            VISIBLE_HEIGHT_OVERSCAN
+        } else {
+            VISIBLE_HEIGHT_NORMAL
+        }
+    }
+
+    // FNV-1a hash of the visible part of the frame, for comparing frames
+    // between runs without storing them
+    pub fn hash(&self) -> u64 {
+        let visible = &self.pixels[..(ROW_LENGTH * self.visible_height())];
+
+        visible.iter().fold(FNV_OFFSET_BASIS, |hash, &byte| {
+            (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+        })
+    }
+
+    pub fn overscan(&self) -> bool {
+        self.overscan
+    }
+
+    pub fn set_overscan(&mut self, overscan: bool) {
+        // Wait until next frame to switch to overscan mode
+        self.overscan_buffer = overscan;
+    }
+
+    pub fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = brightness;
+    }
+
+    pub fn begin_frame(&mut self) {
+        self.overscan = self.overscan_buffer;
+
+        self.index = match self.mode {
+            // Skip the first row so we only render odd-numbered rows
+            ScreenMode::Interlace(InterlaceFrame::Odd) => ROW_LENGTH,
+            _ => 0
+        };
+    }
+
+    pub fn end_frame(&mut self) {
+        self.fill_non_interlace();
+    }
+
+    pub fn blit(&mut self, color: Color) {
+        let pixel = &mut self.pixels[self.index..(self.index + BYTES_PER_PIXEL)];
+        pixel[0] = color.blue() << 3;
+        pixel[1] = color.green() << 3;
+        pixel[2] = color.red() << 3;
+        pixel[3] = self.brightness;
+        self.index += BYTES_PER_PIXEL;
+    }
+
+    pub fn next_line(&mut self) {
+        self.fill_non_interlace();
+        self.index += ROW_LENGTH;
+    }
+
+    fn fill_non_interlace(&mut self) {
+        match self.mode {
+            ScreenMode::Standard => {
+                // Duplicate the previous row
+                if self.index >= ROW_LENGTH && self.index + ROW_LENGTH <= self.pixels.len() {
+                    self.pixels.copy_within((self.index - ROW_LENGTH)..self.index, self.index);
+                }
+            },
+            ScreenMode::Interlace(..) => {
+                // Skip the next row, so nothing to do here
+            }
+        }
+    }
+}
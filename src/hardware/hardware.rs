@@ -1,30 +1,88 @@
+use std::collections::HashMap;
 use std::fmt::{self, Display, Formatter};
-use std::rc::Rc;
+use std::mem;
+use std::time::Instant;
 use super::apu::Apu;
+use super::clock::Clock;
 use super::dma::{self, DmaChannel, DMA_CHANNEL_COUNT};
-use super::io_port::IoPort;
+use super::coprocessor::CoprocessorSlot;
+use super::expansion::ExpansionSlot;
+use super::frame_buffer::FrameBuffer;
 use super::joypad::Joypad;
 use super::ppu::Ppu;
+use super::profiler::{Profile, Profiler, Subsystem};
 use super::registers::HardwareRegs;
 use super::rom::{Rom, RomMode};
+use super::watchpoint::{Access, Watchpoint, WatchpointHit};
 use super::wram::Wram;
 use util::byte_access::ByteAccess;
+use util::json::JsonObject;
 
 const FAST_CYCLES: u64 = 6;
 const SLOW_CYCLES: u64 = 8;
 const EXTRA_SLOW_CYCLES: u64 = 12;
 
 pub trait MemoryAccess {
-    fn read(hardware: &mut Hardware, address: HardwareAddress) -> Self;
-    fn write(hardware: &mut Hardware, address: HardwareAddress, value: Self);
+    fn read<B: Bus>(bus: &mut B, address: HardwareAddress, wrapping: Wrapping) -> Self;
+    fn write<B: Bus>(bus: &mut B, address: HardwareAddress, value: Self, wrapping: Wrapping);
     fn size(&self) -> u16;
 }
 
+// How multi-byte accesses behave when they run past $FFFF
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum Wrapping {
+    // Wraps around within the same bank (direct page, stack and pointers)
+    Bank,
+    // Carries into the next bank (data accesses)
+    Linear,
+    // Wraps around within the same page (6502-style direct page pointers)
+    Page
+}
+
 pub trait HardwareBus {
     fn read(&mut self, offset: usize) -> u8;
     fn write(&mut self, offset: usize, value: u8);
 }
 
+// The system as the CPU sees it: the A bus, the clock and the interrupt lines.
+// Hardware is the real thing, but anything implementing this can run the CPU
+// core on its own (e.g. a flat memory for instruction test vectors).
+pub trait Bus: 'static {
+    // Byte accesses take as many master cycles as the address needs
+    fn read_u8(&mut self, address: HardwareAddress) -> u8;
+    fn write_u8(&mut self, address: HardwareAddress, value: u8);
+
+    // An internal cycle, where the CPU isn't using the bus
+    fn tick(&mut self, cycles: u64);
+
+    fn master_cycles(&self) -> u64;
+
+    // The CPU samples its interrupt lines before the final cycle of each
+    // instruction, so anything raised during that cycle waits for the next one
+    fn nmi_polled(&self) -> bool;
+    fn irq_polled(&self) -> bool;
+
+    fn read<T: MemoryAccess>(&mut self, address: HardwareAddress) -> T where Self: Sized {
+        T::read(self, address, Wrapping::Bank)
+    }
+
+    fn write<T: MemoryAccess>(&mut self, address: HardwareAddress, value: T) where Self: Sized {
+        T::write(self, address, value, Wrapping::Bank);
+    }
+
+    fn read_with_wrapping<T: MemoryAccess>(&mut self, address: HardwareAddress, wrapping: Wrapping) -> T
+        where Self: Sized
+    {
+        T::read(self, address, wrapping)
+    }
+
+    fn write_with_wrapping<T: MemoryAccess>(&mut self, address: HardwareAddress, value: T, wrapping: Wrapping)
+        where Self: Sized
+    {
+        T::write(self, address, value, wrapping);
+    }
+}
+
 pub struct Hardware {
     rom: Rom,
     wram: Wram,
@@ -34,10 +92,60 @@ pub struct Hardware {
     regs: HardwareRegs,
     dma_channels: [DmaChannel; DMA_CHANNEL_COUNT],
     open_bus: OpenBus,
-    clock: u64
+    expansion_b_bus: ExpansionSlot,
+    expansion_a_bus: ExpansionSlot,
+    coprocessor: CoprocessorSlot,
+    overclock: Overclock,
+    overclock_budget: u64,
+    cpu_speed_remainder: u64,
+    dma_active: bool,
+    write_trap: Option<HardwareAddress>,
+    write_trapped: bool,
+    watchpoints: Vec<Watchpoint>,
+    watchpoint_hits: Vec<WatchpointHit>,
+    nmi_polled: bool,
+    irq_polled: bool,
+    read_patches: HashMap<HardwareAddress, u8>,
+    profiler: Option<Profiler>,
+    master_cycles: u64,
+    cpu_cycles: u64
+}
+
+// None of these settings are accurate, so they are all disabled by default
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub struct Overclock {
+    pub fast_rom: bool,
+    pub extra_scanline_cycles: u64,
+    // CPU speed relative to the PPU, as a percentage (DMA is unaffected)
+    pub cpu_speed: u64
+}
+
+impl Default for Overclock {
+    fn default() -> Overclock {
+        Overclock {
+            fast_rom: false,
+            extra_scanline_cycles: 0,
+            cpu_speed: 100
+        }
+    }
+}
+
+// Memory that can be accessed directly, without charging any cycles
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum MemoryRegion {
+    Wram,
+    Sram
 }
 
-#[derive(Copy, Clone)]
+// Register contents at power-on, which some games rely on instead of
+// initialising everything themselves
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum RegisterDefaults {
+    Zeroed,
+    Accurate
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash)]
 pub struct HardwareAddress {
     bank: u8,
     offset: u16
@@ -49,7 +157,39 @@ struct MemoryLocation<'a> {
     cycles: u64
 }
 
-struct OpenBus;
+// Unmapped reads return the last value driven on the data bus (the MDR)
+struct OpenBus {
+    mdr: u8
+}
+
+// Bits of I/O registers that aren't driven when read, and so return open bus
+fn open_bus_mask(address: HardwareAddress) -> u8 {
+    let offset = address.offset();
+
+    if address.bank() & 0x40 != 0 {
+        return 0x00;
+    }
+
+    match offset {
+        0x2137 => 0xFF,
+        0x4016 => 0xFC,
+        0x4017 => 0xE0,
+        0x4210 => 0x70,
+        0x4211 => 0x7F,
+        0x4212 => 0x3E,
+        // Write-only PPU registers
+        _ if offset & 0xFFC0 == 0x2100 && offset < 0x2134 => 0xFF,
+        // Write-only WRAM address registers
+        _ if offset & 0xFFC0 == 0x2180 && offset != 0x2180 => 0xFF,
+        // Unused NES-style joypad registers
+        _ if offset & 0xFF80 == 0x4000 => 0xFF,
+        // Write-only and unused CPU registers
+        _ if offset & 0xFF80 == 0x4200 && (offset < 0x4210 || offset >= 0x4220) => 0xFF,
+        // $43xC-$43xE are unused
+        _ if offset & 0xFF80 == 0x4300 && offset & 0x000F >= 0x000C && offset & 0x000F != 0x000F => 0xFF,
+        _ => 0x00
+    }
+}
 
 #[inline]
 fn rom20(address: HardwareAddress) -> usize {
@@ -72,23 +212,151 @@ fn sram21(address: HardwareAddress) -> usize {
 }
 
 impl Hardware {
-    pub fn new(rom: Rom, wram: Wram, ppu: Ppu, apu: Apu, joypad: Joypad, io_port: Rc<IoPort>) -> Hardware {
+    pub fn new(rom: Rom, wram: Wram, ppu: Ppu, apu: Apu, joypad: Joypad) -> Hardware {
         Hardware {
             rom: rom,
             wram: wram,
             ppu: ppu,
             apu: apu,
             joypad: joypad,
-            regs: HardwareRegs::new(io_port),
+            regs: HardwareRegs::new(),
             dma_channels: [
                 DmaChannel::new(), DmaChannel::new(),
                 DmaChannel::new(), DmaChannel::new(),
                 DmaChannel::new(), DmaChannel::new(),
                 DmaChannel::new(), DmaChannel::new()
             ],
-            open_bus: OpenBus,
-            clock: 0
+            open_bus: OpenBus {
+                mdr: 0x00
+            },
+            expansion_b_bus: ExpansionSlot::new(FAST_CYCLES),
+            expansion_a_bus: ExpansionSlot::new(SLOW_CYCLES),
+            coprocessor: CoprocessorSlot::new(),
+            overclock: Overclock::default(),
+            overclock_budget: 0,
+            cpu_speed_remainder: 0,
+            dma_active: false,
+            write_trap: None,
+            write_trapped: false,
+            watchpoints: Vec::new(),
+            watchpoint_hits: Vec::new(),
+            nmi_polled: false,
+            irq_polled: false,
+            read_patches: HashMap::new(),
+            profiler: None,
+            master_cycles: 0,
+            cpu_cycles: 0
+        }
+    }
+
+    pub fn set_overclock(&mut self, overclock: Overclock) {
+        if overclock.fast_rom || overclock.extra_scanline_cycles > 0 || overclock.cpu_speed != 100 {
+            warn!("Overclocking enabled: emulation timing will not be accurate");
+        }
+        self.overclock = overclock;
+    }
+
+    pub fn rom(&self) -> &Rom {
+        &self.rom
+    }
+
+    pub fn memory(&self, region: MemoryRegion) -> &[u8] {
+        match region {
+            MemoryRegion::Wram => self.wram.bytes(),
+            MemoryRegion::Sram => self.rom.sram_bytes()
+        }
+    }
+
+    pub fn memory_mut(&mut self, region: MemoryRegion) -> &mut [u8] {
+        match region {
+            MemoryRegion::Wram => self.wram.bytes_mut(),
+            MemoryRegion::Sram => self.rom.sram_bytes_mut()
+        }
+    }
+
+    pub fn ppu(&self) -> &Ppu {
+        &self.ppu
+    }
+
+    pub fn ppu_mut(&mut self) -> &mut Ppu {
+        &mut self.ppu
+    }
+
+    pub fn apply_register_defaults(&mut self, defaults: RegisterDefaults) {
+        let (display_control, dma_value) = match defaults {
+            RegisterDefaults::Zeroed => (0x00, 0x00),
+            // Forced blank, and DMA registers read back as $FF until written
+            RegisterDefaults::Accurate => (0x80, 0xFF)
+        };
+
+        self.ppu.write(0x00, display_control);
+
+        for channel in self.dma_channels.iter_mut() {
+            for offset in 0x00..0x0B {
+                channel.write(offset, dma_value);
+            }
         }
+
+        // NMI, IRQ and joypad auto read are all disabled
+        self.regs.write(0x00, 0x00);
+    }
+
+    pub fn reset(&mut self) {
+        self.regs.reset();
+        self.nmi_polled = false;
+        self.irq_polled = false;
+        self.dma_active = false;
+
+        // Reset forces blank
+        self.ppu.write(0x00, 0x80);
+    }
+
+    // Overrides what the CPU reads from an address, without touching the ROM
+    // itself. Only that exact address is patched, not its mirrors.
+    pub fn add_read_patch(&mut self, address: HardwareAddress, value: u8) {
+        self.read_patches.insert(address, value);
+    }
+
+    // Only CPU writes to this exact address are trapped (not mirrors or DMA)
+    pub fn set_write_trap(&mut self, address: Option<HardwareAddress>) {
+        self.write_trap = address;
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    // In address order
+    pub fn read_patches(&self) -> Vec<(HardwareAddress, u8)> {
+        let mut patches: Vec<(HardwareAddress, u8)> = self.read_patches.iter()
+            .map(|(&address, &value)| (address, value))
+            .collect();
+        patches.sort_by_key(|&(address, _)| address.to_u32());
+        patches
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    // Accesses that matched a watchpoint since the last call. The CPU can't
+    // stop mid-instruction, so these are checked between instructions.
+    pub fn has_watchpoint_hits(&self) -> bool {
+        !self.watchpoint_hits.is_empty()
+    }
+
+    pub fn take_watchpoint_hits(&mut self) -> Vec<WatchpointHit> {
+        mem::replace(&mut self.watchpoint_hits, Vec::new())
+    }
+
+    pub fn take_write_trap(&mut self) -> bool {
+        let write_trapped = self.write_trapped;
+        self.write_trapped = false;
+        write_trapped
     }
 
     pub fn regs(&self) -> &HardwareRegs {
@@ -99,6 +367,10 @@ impl Hardware {
         &mut self.regs
     }
 
+    pub fn apu_mut(&mut self) -> &mut Apu {
+        &mut self.apu
+    }
+
     pub fn joypad(&self) -> &Joypad {
         &self.joypad
     }
@@ -115,16 +387,85 @@ impl Hardware {
         &mut self.dma_channels[index]
     }
 
-    pub fn clock(&self) -> u64 {
-        self.clock
+    // $21F0-$21FF (e.g. exp-port devices). Test-only until a real device
+    // is emulated.
+    #[cfg(test)]
+    pub fn expansion_b_bus_mut(&mut self) -> &mut ExpansionSlot {
+        &mut self.expansion_b_bus
+    }
+
+    // $6000-$7FFF in banks $00-$3F and $80-$BF, where not used for SRAM (e.g. BS-X)
+    #[cfg(test)]
+    pub fn expansion_a_bus_mut(&mut self) -> &mut ExpansionSlot {
+        &mut self.expansion_a_bus
+    }
+
+    // Cartridge chips, which can claim any address ahead of the usual mapping
+    pub fn coprocessor(&self) -> &CoprocessorSlot {
+        &self.coprocessor
     }
 
-    pub fn read<T: MemoryAccess>(&mut self, address: HardwareAddress) -> T {
-        T::read(self, address)
+    pub fn coprocessor_mut(&mut self) -> &mut CoprocessorSlot {
+        &mut self.coprocessor
     }
 
-    pub fn write<T: MemoryAccess>(&mut self, address: HardwareAddress, value: T) {
-        T::write(self, address, value);
+    pub fn enable_profiler(&mut self) {
+        self.profiler = Some(Profiler::new());
+    }
+
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profiler.as_mut().map(|profiler| profiler.take_profile())
+    }
+
+    fn profile_start(&self) -> Option<Instant> {
+        self.profiler.as_ref().map(|_| Instant::now())
+    }
+
+    fn profile_end(&mut self, subsystem: Subsystem, start: Option<Instant>) {
+        if let (Some(profiler), Some(start)) = (self.profiler.as_mut(), start) {
+            profiler.add(subsystem, start);
+        }
+    }
+
+    pub fn clock(&self) -> Clock {
+        Clock {
+            master_cycles: self.master_cycles,
+            cpu_cycles: self.cpu_cycles,
+            frame: self.ppu.frame_count(),
+            scanline: self.ppu.position().v(),
+            dot: self.ppu.position().h()
+        }
+    }
+
+    pub fn master_cycles(&self) -> u64 {
+        self.master_cycles
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.ppu.frame_count()
+    }
+
+    pub fn scanline(&self) -> u16 {
+        self.ppu.position().v()
+    }
+
+    pub fn dot(&self) -> u16 {
+        self.ppu.position().h()
+    }
+
+    pub fn lag_frames(&self) -> u64 {
+        self.regs.lag_frames()
+    }
+
+    pub fn frame_buffer(&self) -> &FrameBuffer {
+        self.ppu.frame_buffer()
+    }
+
+    // An external latch, as on the falling edge of $4201 bit 7 (which a
+    // light gun on port 2 also drives). Unlike a $2137 read, this doesn't
+    // depend on the latch bit, as that bit has just gone low.
+    pub fn latch_ppu_counters(&mut self) {
+        self.ppu.store_position();
     }
 
     // Used by DMA, etc. Does not cause any 'ticks' to occur.
@@ -132,20 +473,67 @@ impl Hardware {
         let value = self.byte_at(src).read();
         debug!("Transfer: {} <= {} (${:02X})", dst, src, value);
         self.byte_at(dst).write(value);
+
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(src, value, Access::Read);
+            self.check_watchpoints(dst, value, Access::Write);
+        }
     }
 
     pub fn dma_transfer(&mut self, channel_mask: u8) {
-        dma::dma_transfer(self, channel_mask)
+        self.dma_active = true;
+        dma::dma_transfer(self, channel_mask);
+        self.dma_active = false;
+    }
+
+    // Converts CPU cycles to PPU cycles, carrying over any fraction
+    fn scale_cpu_cycles(&mut self, cycles: u64) -> u64 {
+        if self.overclock.cpu_speed == 100 {
+            return cycles;
+        }
+
+        let scaled = cycles * 100 + self.cpu_speed_remainder;
+        self.cpu_speed_remainder = scaled % self.overclock.cpu_speed;
+        scaled / self.overclock.cpu_speed
+    }
+
+    pub fn set_polled_interrupts(&mut self, nmi: bool, irq: bool) {
+        self.nmi_polled = nmi;
+        self.irq_polled = irq;
     }
 
     pub fn tick(&mut self, cycles: u64) {
-        self.ppu.add_cycles(cycles);
+        self.nmi_polled = self.regs.nmi_pending();
+        self.irq_polled = self.regs.irq_asserted();
+
+        // Overclocked CPU cycles are 'free' as far as the PPU is concerned
+        let ppu_cycles = if self.dma_active { cycles } else { self.scale_cpu_cycles(cycles) };
+        let free_cycles = if self.dma_active { 0 } else { ppu_cycles.min(self.overclock_budget) };
+        self.overclock_budget -= free_cycles;
+
+        let start = self.profile_start();
+        self.coprocessor.tick(ppu_cycles - free_cycles);
+        self.profile_end(Subsystem::Coprocessor, start);
+
+        let start = self.profile_start();
+        self.ppu.add_cycles(ppu_cycles - free_cycles);
 
         while self.ppu.next_pixel() {
-            self.regs.update(&mut self.ppu, &self.joypad);
+            self.regs.update(&mut self.ppu, &mut self.joypad);
+
+            if self.ppu.position().h() == 0 {
+                self.overclock_budget = self.overclock.extra_scanline_cycles;
+            }
         }
 
-        self.clock = self.clock.wrapping_add(cycles);
+        self.profile_end(Subsystem::Ppu, start);
+
+        self.master_cycles = self.master_cycles.wrapping_add(cycles);
+        self.regs.step_alu();
+
+        if !self.dma_active {
+            self.cpu_cycles = self.cpu_cycles.wrapping_add(1);
+        }
     }
 
     fn read_u8(&mut self, address: HardwareAddress) -> u8 {
@@ -153,43 +541,139 @@ impl Hardware {
             let mut location = self.byte_at(address);
             (location.read(), location.cycles())
         };
+        let mask = open_bus_mask(address);
+        let value = (value & !mask) | (self.open_bus.mdr & mask);
+        let value = if self.read_patches.is_empty() {
+            value
+        } else {
+            self.read_patches.get(&address).cloned().unwrap_or(value)
+        };
+        self.open_bus.mdr = value;
         debug!("Read: {} => {:02X}", address, value);
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(address, value, Access::Read);
+        }
+        if self.apu.tracing() {
+            self.trace_apu_port(address, value, false);
+        }
         self.tick(cycles);
         value
     }
 
     fn write_u8(&mut self, address: HardwareAddress, value: u8) {
         debug!("Write: {} <= {:02X}", address, value);
+        self.open_bus.mdr = value;
+        if self.write_trap == Some(address) {
+            self.write_trapped = true;
+        }
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(address, value, Access::Write);
+        }
+        if self.apu.tracing() {
+            self.trace_apu_port(address, value, true);
+        }
         let cycles = {
             let mut location = self.byte_at(address);
             location.write(value);
             location.cycles()
         };
+        if self.regs.take_io_port_latch() {
+            self.latch_ppu_counters();
+        }
         self.tick(cycles);
     }
 
+    fn check_watchpoints(&mut self, address: HardwareAddress, value: u8, access: Access) {
+        if self.watchpoints.iter().any(|watchpoint| watchpoint.matches(address, access)) {
+            self.watchpoint_hits.push(WatchpointHit {
+                address: address,
+                value: value,
+                access: access,
+                scanline: self.ppu.position().v(),
+                dot: self.ppu.position().h()
+            });
+        }
+    }
+
+    fn trace_apu_port(&mut self, address: HardwareAddress, value: u8, write: bool) {
+        if address.bank() & 0x40 == 0 && address.offset() & 0xFFC0 == 0x2140 {
+            let timestamp = (self.ppu.frame_count(), self.ppu.position().v(), self.ppu.position().h(), self.master_cycles);
+            self.apu.trace_access(timestamp, (address.offset() & 0x0003) as usize, value, write);
+        }
+    }
+
+    pub fn state_json(&self) -> JsonObject {
+        let dma_channels = self.dma_channels.iter().map(|channel| channel.state_json()).collect();
+
+        let mut state = JsonObject::new();
+        state.number("clock", self.master_cycles);
+        state.object("regs", self.regs.state_json());
+        state.array("dma_channels", dma_channels);
+        state.object("ppu", self.ppu.state_json());
+        state
+    }
+
+    // Reads memory without ticking the clock, for debugging purposes. Returns
+    // None for I/O registers, as reading them can have side effects.
+    pub fn peek(&mut self, address: HardwareAddress) -> Option<u8> {
+        if self.is_io(address) {
+            None
+        } else if let Some(&value) = self.read_patches.get(&address) {
+            Some(value)
+        } else {
+            Some(self.byte_at(address).read())
+        }
+    }
+
+    // Writes memory without ticking the clock, for debugging purposes.
+    // Returns false for I/O registers, as writing them can have side effects.
+    pub fn poke(&mut self, address: HardwareAddress, value: u8) -> bool {
+        if self.is_io(address) {
+            false
+        } else {
+            self.byte_at(address).write(value);
+            true
+        }
+    }
+
+    fn is_io(&self, address: HardwareAddress) -> bool {
+        if address.bank() & 0x40 != 0 {
+            return false;
+        }
+
+        match address.offset() & 0xE000 {
+            0x2000 | 0x4000 => true,
+            0x6000 => self.rom.mode() != RomMode::HiRom || address.bank() & 0x20 != 0x20,
+            _ => false
+        }
+    }
+
     fn byte_at(&mut self, address: HardwareAddress) -> MemoryLocation {
         let bank = address.bank();
         let offset = address.offset();
 
-        let (bus, offset, cycles): (&mut HardwareBus, usize, u64) = if bank & 0x40 != 0 {
+        // TODO: ROM speed
+        let rom_cycles = if self.overclock.fast_rom { FAST_CYCLES } else { SLOW_CYCLES };
+
+        let (bus, offset, cycles): (&mut HardwareBus, usize, u64) = if let Some((offset, cycles)) = self.coprocessor.map(address) {
+            (&mut self.coprocessor, offset, cycles)
+        } else if bank & 0x40 != 0 {
             // Full ROM/RAM mode
             match bank {
                 0x7E => (self.wram.data(), offset as usize, SLOW_CYCLES),
                 0x7F => (self.wram.data(), 0x10000 | (offset as usize), SLOW_CYCLES),
                 _ => {
-                    // TODO: ROM speed
                     match self.rom.mode() {
                         RomMode::LoRom => {
                             if offset & 0x8000 != 0 {
-                                (self.rom.data(), rom20(address), SLOW_CYCLES)
+                                (self.rom.data(), rom20(address), rom_cycles)
                             } else if (bank & 0x70) == 0x70 {
                                 (self.rom.sram(), sram20(address), SLOW_CYCLES)
                             } else {
                                 (&mut self.open_bus, 0, FAST_CYCLES)
                             }
                         },
-                        RomMode::HiRom => (self.rom.data(), rom21(address), SLOW_CYCLES)
+                        RomMode::HiRom => (self.rom.data(), rom21(address), rom_cycles)
                     }
                 }
             }
@@ -203,6 +687,15 @@ impl Hardware {
                         0x2100 => (&mut self.ppu, (offset & 0x003F) as usize, FAST_CYCLES),
                         0x2140 => (&mut self.apu, (offset & 0x0003) as usize, FAST_CYCLES),
                         0x2180 => (&mut self.wram, (offset & 0x003F) as usize, FAST_CYCLES),
+                        0x21C0 if offset & 0x0030 == 0x0030 => {
+                            let cycles = self.expansion_b_bus.cycles();
+
+                            if self.expansion_b_bus.is_attached() {
+                                (&mut self.expansion_b_bus, (offset & 0x000F) as usize, cycles)
+                            } else {
+                                (&mut self.open_bus, 0, cycles)
+                            }
+                        },
                         _ => (&mut self.open_bus, 0, FAST_CYCLES)
                     }
                 },
@@ -222,18 +715,21 @@ impl Hardware {
                     // SRAM (but only in HiROM mode)
                     if self.rom.mode() == RomMode::HiRom && bank & 0x20 == 0x20 {
                         (self.rom.sram(), sram21(address), SLOW_CYCLES)
+                    } else if self.expansion_a_bus.is_attached() {
+                        let cycles = self.expansion_a_bus.cycles();
+                        let expansion_offset = 0x2000 * (bank & 0x3F) as usize + (offset & 0x1FFF) as usize;
+                        (&mut self.expansion_a_bus, expansion_offset, cycles)
                     } else {
-                        (&mut self.open_bus, 0, SLOW_CYCLES)
+                        (&mut self.open_bus, 0, self.expansion_a_bus.cycles())
                     }
                 },
                 _ => {
                     // ROM data
-                    // TODO: ROM speed
                     let rom_offset = match self.rom.mode() {
                         RomMode::LoRom => rom20(address),
                         RomMode::HiRom => rom21(address)
                     };
-                    (self.rom.data(), rom_offset, SLOW_CYCLES)
+                    (self.rom.data(), rom_offset, rom_cycles)
                 }
             }
         };
@@ -242,6 +738,32 @@ impl Hardware {
     }
 }
 
+impl Bus for Hardware {
+    fn read_u8(&mut self, address: HardwareAddress) -> u8 {
+        Hardware::read_u8(self, address)
+    }
+
+    fn write_u8(&mut self, address: HardwareAddress, value: u8) {
+        Hardware::write_u8(self, address, value);
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        Hardware::tick(self, cycles);
+    }
+
+    fn master_cycles(&self) -> u64 {
+        Hardware::master_cycles(self)
+    }
+
+    fn nmi_polled(&self) -> bool {
+        self.nmi_polled
+    }
+
+    fn irq_polled(&self) -> bool {
+        self.irq_polled
+    }
+}
+
 impl HardwareAddress {
     pub fn new(bank: u8, offset: u16) -> HardwareAddress {
         HardwareAddress {
@@ -250,6 +772,21 @@ impl HardwareAddress {
         }
     }
 
+    // Parses addresses in the same "BB:OOOO" hex format used for display
+    pub fn parse(value: &str) -> Option<HardwareAddress> {
+        let mut parts = value.splitn(2, ':');
+
+        match (parts.next(), parts.next()) {
+            (Some(bank), Some(offset)) => {
+                match (u8::from_str_radix(bank, 16), u16::from_str_radix(offset, 16)) {
+                    (Ok(bank), Ok(offset)) => Some(HardwareAddress::new(bank, offset)),
+                    _ => None
+                }
+            },
+            _ => None
+        }
+    }
+
     pub fn bank(&self) -> u8 {
         self.bank
     }
@@ -270,6 +807,18 @@ impl HardwareAddress {
         &mut self.offset
     }
 
+    pub fn to_u32(&self) -> u32 {
+        ((self.bank as u32) << 16) | (self.offset as u32)
+    }
+
+    pub fn add_with_wrapping(self, rhs: u16, wrapping: Wrapping) -> Self {
+        match wrapping {
+            Wrapping::Bank => Self::new(self.bank, self.offset.wrapping_add(rhs)),
+            Wrapping::Linear => self.wrapping_add(rhs),
+            Wrapping::Page => Self::new(self.bank, (self.offset & 0xFF00) | (self.offset.wrapping_add(rhs) & 0x00FF))
+        }
+    }
+
     pub fn wrapping_add(self, rhs: u16) -> Self {
         let mut bank = self.bank;
         let offset = self.offset.wrapping_add(rhs);
@@ -310,7 +859,7 @@ impl<'a> MemoryLocation<'a> {
 
 impl HardwareBus for OpenBus {
     fn read(&mut self, _offset: usize) -> u8 {
-        0
+        self.mdr
     }
 
     fn write(&mut self, _offset: usize, _value: u8) {
@@ -319,12 +868,12 @@ impl HardwareBus for OpenBus {
 }
 
 impl MemoryAccess for u8 {
-    fn read(hardware: &mut Hardware, address: HardwareAddress) -> u8 {
-        hardware.read_u8(address)
+    fn read<B: Bus>(bus: &mut B, address: HardwareAddress, _wrapping: Wrapping) -> u8 {
+        bus.read_u8(address)
     }
 
-    fn write(hardware: &mut Hardware, address: HardwareAddress, value: u8) {
-        hardware.write_u8(address, value);
+    fn write<B: Bus>(bus: &mut B, address: HardwareAddress, value: u8, _wrapping: Wrapping) {
+        bus.write_u8(address, value);
     }
 
     fn size(&self) -> u16 {
@@ -332,19 +881,16 @@ impl MemoryAccess for u8 {
     }
 }
 
-// TODO: Wrapping
 impl MemoryAccess for u16 {
-    fn read(hardware: &mut Hardware, address: HardwareAddress) -> u16 {
-        let lower = hardware.read_u8(address);
-        let upper_offset = address.offset().wrapping_add(1);
-        let upper = hardware.read_u8(HardwareAddress::new(address.bank(), upper_offset));
+    fn read<B: Bus>(bus: &mut B, address: HardwareAddress, wrapping: Wrapping) -> u16 {
+        let lower = bus.read_u8(address);
+        let upper = bus.read_u8(address.add_with_wrapping(1, wrapping));
         ((upper as u16) << 8) | (lower as u16)
     }
 
-    fn write(hardware: &mut Hardware, address: HardwareAddress, value: u16) {
-        hardware.write_u8(address, value.lower());
-        let upper_offset = address.offset().wrapping_add(1);
-        hardware.write_u8(HardwareAddress::new(address.bank(), upper_offset), value.upper());
+    fn write<B: Bus>(bus: &mut B, address: HardwareAddress, value: u16, wrapping: Wrapping) {
+        bus.write_u8(address, value.lower());
+        bus.write_u8(address.add_with_wrapping(1, wrapping), value.upper());
     }
 
     fn size(&self) -> u16 {
@@ -353,17 +899,15 @@ impl MemoryAccess for u16 {
 }
 
 impl MemoryAccess for HardwareAddress {
-    fn read(hardware: &mut Hardware, address: HardwareAddress) -> HardwareAddress {
-        let offset = hardware.read::<u16>(address);
-        let bank_address = HardwareAddress::new(address.bank(), address.offset().wrapping_add(2));
-        let bank = hardware.read::<u8>(bank_address);
+    fn read<B: Bus>(bus: &mut B, address: HardwareAddress, wrapping: Wrapping) -> HardwareAddress {
+        let offset = u16::read(bus, address, wrapping);
+        let bank = bus.read_u8(address.add_with_wrapping(2, wrapping));
         HardwareAddress::new(bank, offset)
     }
 
-    fn write(hardware: &mut Hardware, address: HardwareAddress, value: HardwareAddress) {
-        hardware.write(address, value.offset());
-        let bank_address = HardwareAddress::new(address.bank(), address.offset().wrapping_add(2));
-        hardware.write(bank_address, value.bank());
+    fn write<B: Bus>(bus: &mut B, address: HardwareAddress, value: HardwareAddress, wrapping: Wrapping) {
+        u16::write(bus, address, value.offset(), wrapping);
+        bus.write_u8(address.add_with_wrapping(2, wrapping), value.bank());
     }
 
     fn size(&self) -> u16 {
@@ -1,39 +1,86 @@
-use std::cell::Cell;
-
-pub const PPU_LATCH_BIT: u8 = 0x80;
+const PPU_LATCH_BIT: u8 = 0x80;
 
 pub struct IoPort {
-    value: Cell<u8>,
-    triggered: Cell<bool>
+    value: u8,
+    triggered: bool
 }
 
 impl IoPort {
     pub fn new() -> IoPort {
         IoPort {
-            value: Cell::new(0xC0),
-            triggered: Cell::new(false)
+            value: 0xC0,
+            triggered: false
         }
     }
 
     pub fn value(&self) -> u8 {
-        self.value.get()
+        self.value
     }
 
-    pub fn set_value(&self, value: u8) {
-        let old_value = self.value.get();
+    pub fn set_value(&mut self, value: u8) {
+        let old_value = self.value;
 
-        self.value.set(value);
+        self.value = value;
 
         if (old_value & PPU_LATCH_BIT) != 0 && (value & PPU_LATCH_BIT) == 0 {
-            self.triggered.set(true);
+            self.triggered = true;
         }
     }
 
+    pub fn latch_enabled(&self) -> bool {
+        self.value & PPU_LATCH_BIT != 0
+    }
+
     pub fn triggered(&self) -> bool {
-        self.triggered.get()
+        self.triggered
+    }
+
+    pub fn reset_trigger(&mut self) {
+        self.triggered = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hardware::{Bus, Hardware, HardwareAddress, RomBuilder};
+
+    const IO_PORT: u16 = 0x4201;
+    const STATUS_78: u16 = 0x213F;
+    const COUNTERS_LATCHED: u8 = 0x40;
+
+    fn write(hardware: &mut Hardware, offset: u16, value: u8) {
+        hardware.write::<u8>(HardwareAddress::new(0x00, offset), value);
+    }
+
+    fn counters_latched(hardware: &mut Hardware) -> bool {
+        hardware.read::<u8>(HardwareAddress::new(0x00, STATUS_78)) & COUNTERS_LATCHED != 0
     }
 
-    pub fn reset_trigger(&self) {
-        self.triggered.set(false);
+    #[test]
+    fn falling_edge_latches_counters() {
+        let mut hardware = RomBuilder::new().build_hardware();
+        assert!(!counters_latched(&mut hardware));
+
+        // Resets to $C0, so this is a 1 to 0 transition
+        write(&mut hardware, IO_PORT, 0x00);
+        assert!(counters_latched(&mut hardware));
+    }
+
+    #[test]
+    fn other_writes_do_not_latch_counters() {
+        let mut hardware = RomBuilder::new().build_hardware();
+
+        write(&mut hardware, IO_PORT, 0x80);
+        write(&mut hardware, IO_PORT, 0xFF);
+        assert!(!counters_latched(&mut hardware));
+
+        write(&mut hardware, IO_PORT, 0x00);
+        counters_latched(&mut hardware);
+
+        // Already low, and the flag is only cleared by $213F while high
+        write(&mut hardware, IO_PORT, 0x00);
+        write(&mut hardware, IO_PORT, 0x80);
+        counters_latched(&mut hardware);
+        assert!(!counters_latched(&mut hardware));
     }
 }
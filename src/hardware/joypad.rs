@@ -1,94 +1,97 @@
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use super::controller::{ButtonState, ControllerPort, Gamepad, Unplugged};
 use super::hardware::HardwareBus;
 
 pub const JOYPAD_COUNT: usize = 4;
 
-pub struct Joypad {
-    button_state: [ButtonState; JOYPAD_COUNT],
-    button_indexes: [usize; 2],
-    latch: bool
-}
+pub const PORT_COUNT: usize = 2;
 
-bitflags! {
-    pub flags ButtonState: u16 {
-        const B = 0x8000,
-        const Y = 0x4000,
-        const SELECT = 0x2000,
-        const START = 0x1000,
-        const UP = 0x0800,
-        const DOWN = 0x0400,
-        const LEFT = 0x0200,
-        const RIGHT = 0x0100,
-        const A = 0x0080,
-        const X = 0x0040,
-        const L = 0x0020,
-        const R = 0x0010
-    }
-}
+const AUTO_READ_BITS: usize = 16;
 
-fn keycode_to_button(keycode: Keycode) -> ButtonState {
-    // All very subject to change
-    match keycode {
-        Keycode::Z => B,
-        Keycode::A => Y,
-        Keycode::Space => SELECT,
-        Keycode::Return => START,
-        Keycode::Up => UP,
-        Keycode::Down => DOWN,
-        Keycode::Left => LEFT,
-        Keycode::Right => RIGHT,
-        Keycode::X => A,
-        Keycode::S => X,
-        Keycode::Q => L,
-        Keycode::W => R,
-        _ => ButtonState::empty()
-    }
+pub struct Joypad {
+    ports: [Box<ControllerPort + Send>; PORT_COUNT],
+    latch: bool,
+    poll_requested: bool,
+    serial_read: bool
 }
 
 impl Joypad {
     pub fn new() -> Joypad {
         Joypad {
-            button_state: [ButtonState::empty(); 4],
-            button_indexes: [0, 0],
-            latch: false
+            ports: [Box::new(Gamepad::new()), Box::new(Unplugged)],
+            latch: false,
+            poll_requested: false,
+            serial_read: false
         }
     }
 
-    pub fn read_button_state(&self) -> [u16; JOYPAD_COUNT] {
-        [
-            self.button_state[0].bits(),
-            self.button_state[1].bits(),
-            self.button_state[2].bits(),
-            self.button_state[3].bits()
-        ]
+    pub fn connect(&mut self, port: usize, device: Box<ControllerPort + Send>) {
+        self.ports[port] = device;
+    }
+
+    // Set when the game is about to sample the controllers, so the frontend
+    // can poll host input as late as possible
+    pub fn request_poll(&mut self) {
+        self.poll_requested = true;
+    }
+
+    pub fn poll_requested(&self) -> bool {
+        self.poll_requested
+    }
+
+    pub fn take_poll_request(&mut self) -> bool {
+        let poll_requested = self.poll_requested;
+        self.poll_requested = false;
+        poll_requested
+    }
+
+    pub fn take_serial_read(&mut self) -> bool {
+        let serial_read = self.serial_read;
+        self.serial_read = false;
+        serial_read
+    }
+
+    // Auto read strobes the latch and then clocks 16 bits out of each port.
+    // Data line 1 gives JOY1/JOY2 and data line 2 gives JOY3/JOY4.
+    pub fn auto_read(&mut self) -> [u16; JOYPAD_COUNT] {
+        let mut button_state = [0; JOYPAD_COUNT];
+
+        self.set_latch(true);
+        self.set_latch(false);
+
+        for _ in 0..AUTO_READ_BITS {
+            for (port_index, port) in self.ports.iter_mut().enumerate() {
+                let data = port.read_serial();
+                button_state[port_index] = (button_state[port_index] << 1) | (data & 0x01) as u16;
+                button_state[port_index + 2] = (button_state[port_index + 2] << 1) | ((data >> 1) & 0x01) as u16;
+            }
+        }
+
+        button_state
     }
 
     pub fn handle_event(&mut self, event: Event) {
-        match event {
-            Event::KeyDown { keycode: Some(keycode), .. } => {
-                self.button_state[0].insert(keycode_to_button(keycode));
-            },
-            Event::KeyUp { keycode: Some(keycode), .. } => {
-                self.button_state[0].remove(keycode_to_button(keycode));
-            },
-            _ => ()
-        };
+        // Host input only drives the device in port 1 for now
+        self.ports[0].handle_event(&event);
+    }
+
+    // Replaces host input, for when the emulator is driven by another program
+    pub fn set_buttons(&mut self, port: usize, buttons: ButtonState) {
+        self.ports[port].set_buttons(buttons);
     }
 
-    fn read_data_line_state(&mut self, port_offset: usize) -> u8 {
-        let button_index = self.button_indexes[port_offset];
-
-        if button_index < 16 {
-            let mask = 0x8000 >> button_index;
-            let data_line_1_bit = (self.button_state[port_offset].bits() & mask) != 0;
-            let data_line_2_bit = (self.button_state[port_offset + 2].bits() & mask) != 0;
-            self.button_indexes[port_offset] += 1;
-            ((data_line_2_bit as u8) << 1) | (data_line_1_bit as u8)
-        } else {
-            0x03
+    fn set_latch(&mut self, latch: bool) {
+        self.latch = latch;
+
+        for port in self.ports.iter_mut() {
+            port.set_latch(latch);
         }
     }
+
+    fn read_data_line_state(&mut self, port_index: usize) -> u8 {
+        self.serial_read = true;
+        self.ports[port_index].read_serial() & 0x03
+    }
 }
 
 impl HardwareBus for Joypad {
@@ -96,7 +99,7 @@ impl HardwareBus for Joypad {
         let value = match offset {
             0x16 => self.read_data_line_state(0),
             0x17 => 0x1C | self.read_data_line_state(1),
-            _ => 0x00 // TODO: Open bus
+            _ => 0x00 // Open bus, filled in by Hardware
         };
         debug!("NES joypad read: $40{:02X} => ${:02X}", offset, value);
         value
@@ -107,10 +110,9 @@ impl HardwareBus for Joypad {
         match offset {
             0x16 => {
                 let old_latch = self.latch;
-                self.latch = value & 0x01 != 0;
+                self.set_latch(value & 0x01 != 0);
                 if self.latch && !old_latch {
-                    self.button_indexes[0] = 0;
-                    self.button_indexes[1] = 0;
+                    self.request_poll();
                 }
             },
             _ => ()
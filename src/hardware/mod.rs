@@ -1,20 +1,33 @@
 mod apu;
+mod clock;
+mod controller;
+mod coprocessor;
 mod dma;
+mod expansion;
+mod frame_buffer;
 mod hardware;
 mod io_port;
 mod joypad;
+mod necdsp;
 mod ppu;
+mod profiler;
 mod registers;
 mod rom;
-mod screen;
+#[cfg(test)]
+mod rom_builder;
+mod watchpoint;
 mod wram;
 
 pub use self::apu::Apu;
-pub use self::hardware::{Hardware, HardwareAddress, MemoryAccess};
-pub use self::io_port::IoPort;
-pub use self::joypad::Joypad;
-pub use self::ppu::Ppu;
+pub use self::controller::{ButtonState, ControllerPort, Gamepad, Unplugged};
+pub use self::frame_buffer::{FrameBuffer, FRAME_HEIGHT, FRAME_WIDTH};
+pub use self::hardware::{Bus, Hardware, HardwareAddress, MemoryAccess, MemoryRegion, Overclock, RegisterDefaults, Wrapping};
+pub use self::joypad::{Joypad, PORT_COUNT};
+pub use self::necdsp::NecDsp;
+pub use self::ppu::{Ppu, RegisterLog};
 pub use self::registers::HardwareRegs;
 pub use self::rom::Rom;
-pub use self::screen::Screen;
+#[cfg(test)]
+pub use self::rom_builder::RomBuilder;
+pub use self::watchpoint::Watchpoint;
 pub use self::wram::Wram;
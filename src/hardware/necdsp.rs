@@ -0,0 +1,609 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use super::coprocessor::Coprocessor;
+use super::hardware::HardwareAddress;
+use super::rom::RomMode;
+
+// Low-level emulation of the NEC uPD77C25 (DSP-1/2/3/4) and uPD96050
+// (ST010/ST011), running the chip's own program from a user-supplied dump.
+// Dumps are the program ROM (24-bit words) followed by the data ROM (16-bit
+// words), both little-endian, as used by bsnes and snes9x.
+
+const MASTER_CLOCK_RATE: u64 = 21_477_272;
+
+const ACCESS_CYCLES: u64 = 8;
+
+// Offsets passed to read/write
+const DATA_REGISTER: usize = 0;
+const STATUS_REGISTER: usize = 1;
+const DATA_RAM: usize = 0x1000;
+
+// Status register bits
+const RQM: u16 = 0x8000;
+const DRS: u16 = 0x1000;
+const DRC: u16 = 0x0400;
+const SOC: u16 = 0x0200;
+const SIC: u16 = 0x0100;
+
+// Status register bits that the DSP program can't change
+const SR_READ_ONLY: u16 = 0x907C;
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum Revision {
+    Upd7725,
+    Upd96050
+}
+
+// Where the chip sits in the SNES memory map, which depends on the board
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum Mapping {
+    // $30-$3F:8000-FFFF (DR at $8000, SR at $C000)
+    LoRom,
+    // $60-$6F:0000-7FFF (DR at $0000, SR at $4000), for 2MB LoROM boards
+    LoRomLarge,
+    // $00-$1F:6000-7FFF (DR at $6000, SR at $7000)
+    HiRom,
+    // $60-$67:0000-3FFF (DR/SR alternate), with data RAM at $68-$6F
+    St01x
+}
+
+#[derive(Copy, Clone, Default)]
+struct Flags {
+    ov0: bool,
+    ov1: bool,
+    z: bool,
+    c: bool,
+    s0: bool,
+    s1: bool
+}
+
+pub struct NecDsp {
+    revision: Revision,
+    mapping: Mapping,
+    program_rom: Vec<u32>,
+    data_rom: Vec<u16>,
+    data_ram: Vec<u16>,
+    stack: Vec<u16>,
+    pc: u16,
+    rp: u16,
+    dp: u16,
+    sp: usize,
+    a: u16,
+    b: u16,
+    flag_a: Flags,
+    flag_b: Flags,
+    tr: u16,
+    trb: u16,
+    k: u16,
+    l: u16,
+    m: u16,
+    n: u16,
+    sr: u16,
+    dr: u16,
+    si: u16,
+    so: u16,
+    clock_budget: u64
+}
+
+impl Revision {
+    fn program_size(&self) -> usize {
+        match *self {
+            Revision::Upd7725 => 2048,
+            Revision::Upd96050 => 16384
+        }
+    }
+
+    fn data_rom_size(&self) -> usize {
+        match *self {
+            Revision::Upd7725 => 1024,
+            Revision::Upd96050 => 2048
+        }
+    }
+
+    fn data_ram_size(&self) -> usize {
+        match *self {
+            Revision::Upd7725 => 256,
+            Revision::Upd96050 => 2048
+        }
+    }
+
+    fn stack_size(&self) -> usize {
+        match *self {
+            Revision::Upd7725 => 4,
+            Revision::Upd96050 => 16
+        }
+    }
+
+    fn clock_rate(&self) -> u64 {
+        match *self {
+            Revision::Upd7725 => 7_600_000,
+            Revision::Upd96050 => 11_000_000
+        }
+    }
+
+    fn dump_size(&self) -> usize {
+        self.program_size() * 3 + self.data_rom_size() * 2
+    }
+}
+
+impl NecDsp {
+    pub fn load(path: &Path, rom_mode: RomMode, rom_size: usize) -> NecDsp {
+        let mut file = File::open(path).unwrap();
+        let mut dump = Vec::new();
+        file.read_to_end(&mut dump).unwrap();
+
+        let revision = if dump.len() == Revision::Upd7725.dump_size() {
+            Revision::Upd7725
+        } else if dump.len() == Revision::Upd96050.dump_size() {
+            Revision::Upd96050
+        } else {
+            panic!("DSP program ROM must be {} bytes (uPD77C25) or {} bytes (uPD96050), got {}",
+                Revision::Upd7725.dump_size(),
+                Revision::Upd96050.dump_size(),
+                dump.len());
+        };
+
+        let mapping = match (revision, rom_mode) {
+            (Revision::Upd96050, _) => Mapping::St01x,
+            (Revision::Upd7725, RomMode::HiRom) => Mapping::HiRom,
+            (Revision::Upd7725, RomMode::LoRom) if rom_size > 0x100000 => Mapping::LoRomLarge,
+            (Revision::Upd7725, RomMode::LoRom) => Mapping::LoRom
+        };
+
+        let (program_data, data_rom_data) = dump.split_at(revision.program_size() * 3);
+
+        let program_rom = program_data.chunks(3)
+            .map(|word| word[0] as u32 | ((word[1] as u32) << 8) | ((word[2] as u32) << 16))
+            .collect();
+
+        let data_rom = data_rom_data.chunks(2)
+            .map(|word| word[0] as u16 | ((word[1] as u16) << 8))
+            .collect();
+
+        NecDsp {
+            revision: revision,
+            mapping: mapping,
+            program_rom: program_rom,
+            data_rom: data_rom,
+            data_ram: vec![0; revision.data_ram_size()],
+            stack: vec![0; revision.stack_size()],
+            pc: 0,
+            rp: 0,
+            dp: 0,
+            sp: 0,
+            a: 0,
+            b: 0,
+            flag_a: Flags::default(),
+            flag_b: Flags::default(),
+            tr: 0,
+            trb: 0,
+            k: 0,
+            l: 0,
+            m: 0,
+            n: 0,
+            sr: 0,
+            dr: 0,
+            si: 0,
+            so: 0,
+            clock_budget: 0
+        }
+    }
+
+    fn step(&mut self) {
+        let op_code = self.program_rom[self.pc as usize];
+        self.pc = (self.pc + 1) & self.pc_mask();
+
+        match op_code >> 22 {
+            0 => self.exec_op(op_code),
+            1 => {
+                // RT: OP, then return
+                self.exec_op(op_code);
+                self.sp = self.sp.wrapping_sub(1) % self.stack.len();
+                self.pc = self.stack[self.sp];
+            },
+            2 => self.exec_jp(op_code),
+            3 => self.exec_ld(((op_code >> 6) & 0xFFFF) as u16, op_code & 0x0F),
+            _ => unreachable!()
+        }
+
+        // The multiplier runs continuously: M:N = K * L, as sign + 30 bits
+        let result = (self.k as i16 as i32) * (self.l as i16 as i32);
+        self.m = (result >> 15) as u16;
+        self.n = (result << 1) as u16;
+    }
+
+    fn exec_op(&mut self, op_code: u32) {
+        let pselect = (op_code >> 20) & 0x03;
+        let alu = (op_code >> 16) & 0x0F;
+        let asl = (op_code >> 15) & 0x01;
+        let dpl = (op_code >> 13) & 0x03;
+        let dphm = ((op_code >> 9) & 0x0F) as u16;
+        let rpdcr = (op_code >> 8) & 0x01;
+        let src = (op_code >> 4) & 0x0F;
+        let dst = op_code & 0x0F;
+
+        let idb = match src {
+            0x00 => self.trb,
+            0x01 => self.a,
+            0x02 => self.b,
+            0x03 => self.tr,
+            0x04 => self.dp,
+            0x05 => self.rp,
+            0x06 => self.data_rom[self.rp as usize],
+            0x07 => if self.flag_a.s1 { 0x7FFF } else { 0x8000 },
+            0x08 => {
+                self.sr |= RQM;
+                self.dr
+            },
+            0x09 => self.dr,
+            0x0A => self.sr,
+            0x0B | 0x0C => self.si,
+            0x0D => self.k,
+            0x0E => self.l,
+            0x0F => self.data_ram[self.dp as usize],
+            _ => unreachable!()
+        };
+
+        if alu != 0 {
+            let p = match pselect {
+                0 => self.data_ram[self.dp as usize],
+                1 => idb,
+                2 => self.m,
+                3 => self.n,
+                _ => unreachable!()
+            };
+
+            // ADC/SBB take their carry from the other accumulator's flags
+            let (q, mut flags, carry) = if asl == 0 {
+                (self.a, self.flag_a, self.flag_b.c)
+            } else {
+                (self.b, self.flag_b, self.flag_a.c)
+            };
+
+            let result = self.alu(alu, p, q, carry, &mut flags);
+
+            if asl == 0 {
+                self.a = result;
+                self.flag_a = flags;
+            } else {
+                self.b = result;
+                self.flag_b = flags;
+            }
+        }
+
+        self.exec_ld(idb, dst);
+
+        let dp_low = match dpl {
+            1 => (self.dp + 1) & 0x0F,
+            2 => self.dp.wrapping_sub(1) & 0x0F,
+            3 => 0,
+            _ => self.dp & 0x0F
+        };
+
+        self.dp = ((self.dp & !0x0F) | dp_low) ^ (dphm << 4);
+        self.dp &= self.dp_mask();
+
+        if rpdcr != 0 {
+            self.rp = self.rp.wrapping_sub(1) & self.rp_mask();
+        }
+    }
+
+    fn alu(&self, alu: u32, p: u16, q: u16, carry: bool, flags: &mut Flags) -> u16 {
+        let carry = carry as u32;
+
+        let (result, arithmetic) = match alu {
+            0x01 => (q | p, false),
+            0x02 => (q & p, false),
+            0x03 => (q ^ p, false),
+            0x04 => self.subtract(q, p, 0, flags),
+            0x05 => self.add(q, p, 0, flags),
+            0x06 => self.subtract(q, p, carry, flags),
+            0x07 => self.add(q, p, carry, flags),
+            0x08 => self.subtract(q, 1, 0, flags),
+            0x09 => self.add(q, 1, 0, flags),
+            0x0A => (!q, false),
+            0x0B => {
+                flags.c = q & 0x0001 != 0;
+                ((q >> 1) | (q & 0x8000), false)
+            },
+            0x0C => {
+                flags.c = q & 0x8000 != 0;
+                ((q << 1) | carry as u16, false)
+            },
+            0x0D => ((q << 2) | 0x0003, false),
+            0x0E => ((q << 4) | 0x000F, false),
+            0x0F => ((q << 8) | (q >> 8), false),
+            _ => unreachable!()
+        };
+
+        flags.s0 = result & 0x8000 != 0;
+        flags.z = result == 0;
+
+        // OV1 tracks overflow across a run of operations, with S1 holding the
+        // sign the result would have had without it
+        if !flags.ov1 {
+            flags.s1 = flags.s0;
+        }
+
+        if arithmetic {
+            flags.ov1 = if flags.ov0 && flags.ov1 {
+                flags.s1 == flags.s0
+            } else {
+                flags.ov0 || flags.ov1
+            };
+        } else {
+            flags.ov0 = false;
+            flags.ov1 = false;
+
+            if alu != 0x0B && alu != 0x0C {
+                flags.c = false;
+            }
+        }
+
+        result
+    }
+
+    fn add(&self, q: u16, p: u16, carry: u32, flags: &mut Flags) -> (u16, bool) {
+        let sum = q as u32 + p as u32 + carry;
+        let result = sum as u16;
+        flags.c = sum > 0xFFFF;
+        flags.ov0 = (q ^ result) & (p ^ result) & 0x8000 != 0;
+        (result, true)
+    }
+
+    fn subtract(&self, q: u16, p: u16, carry: u32, flags: &mut Flags) -> (u16, bool) {
+        let difference = q as i32 - p as i32 - carry as i32;
+        let result = difference as u16;
+        flags.c = difference < 0;
+        flags.ov0 = (q ^ result) & (q ^ p) & 0x8000 != 0;
+        (result, true)
+    }
+
+    fn exec_jp(&mut self, op_code: u32) {
+        let branch = (op_code >> 13) & 0x01FF;
+
+        let target = match self.revision {
+            Revision::Upd7725 => ((op_code >> 2) & 0x07FF) as u16,
+            Revision::Upd96050 => {
+                (self.pc & 0x2000) | (((op_code & 0x03) as u16) << 11) | ((op_code >> 2) & 0x07FF) as u16
+            }
+        };
+
+        let condition = match branch {
+            0x000 => {
+                // JMPSO
+                self.pc = self.so & self.pc_mask();
+                return;
+            },
+            0x080 => !self.flag_a.c,
+            0x082 => self.flag_a.c,
+            0x084 => !self.flag_b.c,
+            0x086 => self.flag_b.c,
+            0x088 => !self.flag_a.z,
+            0x08A => self.flag_a.z,
+            0x08C => !self.flag_b.z,
+            0x08E => self.flag_b.z,
+            0x090 => !self.flag_a.ov0,
+            0x092 => self.flag_a.ov0,
+            0x094 => !self.flag_b.ov0,
+            0x096 => self.flag_b.ov0,
+            0x098 => !self.flag_a.ov1,
+            0x09A => self.flag_a.ov1,
+            0x09C => !self.flag_b.ov1,
+            0x09E => self.flag_b.ov1,
+            0x0A0 => !self.flag_a.s0,
+            0x0A2 => self.flag_a.s0,
+            0x0A4 => !self.flag_b.s0,
+            0x0A6 => self.flag_b.s0,
+            0x0A8 => !self.flag_a.s1,
+            0x0AA => self.flag_a.s1,
+            0x0AC => !self.flag_b.s1,
+            0x0AE => self.flag_b.s1,
+            0x0B0 => self.dp & 0x0F == 0x00,
+            0x0B1 => self.dp & 0x0F != 0x00,
+            0x0B2 => self.dp & 0x0F == 0x0F,
+            0x0B3 => self.dp & 0x0F != 0x0F,
+            0x0B4 => self.sr & SIC == 0,
+            0x0B6 => self.sr & SIC != 0,
+            0x0B8 => self.sr & SOC == 0,
+            0x0BA => self.sr & SOC != 0,
+            0x0BC => self.sr & RQM == 0,
+            0x0BE => self.sr & RQM != 0,
+            0x100 => {
+                self.pc = target & !0x2000 & self.pc_mask();
+                return;
+            },
+            0x101 => {
+                self.pc = (target | 0x2000) & self.pc_mask();
+                return;
+            },
+            0x140 | 0x141 => {
+                self.stack[self.sp] = self.pc;
+                self.sp = (self.sp + 1) % self.stack.len();
+                let target = if branch == 0x141 { target | 0x2000 } else { target & !0x2000 };
+                self.pc = target & self.pc_mask();
+                return;
+            },
+            _ => {
+                warn!("Unknown DSP branch condition: {:03X}", branch);
+                false
+            }
+        };
+
+        if condition {
+            self.pc = target & self.pc_mask();
+        }
+    }
+
+    fn exec_ld(&mut self, value: u16, dst: u32) {
+        match dst {
+            0x00 => (),
+            0x01 => self.a = value,
+            0x02 => self.b = value,
+            0x03 => self.tr = value,
+            0x04 => self.dp = value & self.dp_mask(),
+            0x05 => self.rp = value & self.rp_mask(),
+            0x06 => {
+                self.dr = value;
+                self.sr |= RQM;
+            },
+            0x07 => self.sr = (self.sr & SR_READ_ONLY) | (value & !SR_READ_ONLY),
+            0x08 | 0x09 => self.so = value,
+            0x0A => self.k = value,
+            0x0B => {
+                self.k = value;
+                self.l = self.data_rom[self.rp as usize];
+            },
+            0x0C => {
+                self.l = value;
+                self.k = self.data_ram[(self.dp | 0x40) as usize & (self.data_ram.len() - 1)];
+            },
+            0x0D => self.l = value,
+            0x0E => self.trb = value,
+            0x0F => self.data_ram[self.dp as usize] = value,
+            _ => unreachable!()
+        }
+    }
+
+    fn read_dr(&mut self) -> u8 {
+        if self.sr & DRC != 0 {
+            // 8-bit transfers
+            self.sr &= !RQM;
+            self.dr as u8
+        } else if self.sr & DRS == 0 {
+            self.sr |= DRS;
+            self.dr as u8
+        } else {
+            self.sr &= !(RQM | DRS);
+            (self.dr >> 8) as u8
+        }
+    }
+
+    fn write_dr(&mut self, value: u8) {
+        if self.sr & DRC != 0 {
+            // 8-bit transfers
+            self.sr &= !RQM;
+            self.dr = (self.dr & 0xFF00) | value as u16;
+        } else if self.sr & DRS == 0 {
+            self.sr |= DRS;
+            self.dr = (self.dr & 0xFF00) | value as u16;
+        } else {
+            self.sr &= !(RQM | DRS);
+            self.dr = ((value as u16) << 8) | (self.dr & 0x00FF);
+        }
+    }
+
+    fn pc_mask(&self) -> u16 {
+        (self.revision.program_size() - 1) as u16
+    }
+
+    fn rp_mask(&self) -> u16 {
+        (self.revision.data_rom_size() - 1) as u16
+    }
+
+    fn dp_mask(&self) -> u16 {
+        (self.revision.data_ram_size() - 1) as u16
+    }
+
+    fn registers(&self) -> [u16; 17] {
+        [
+            self.pc, self.rp, self.dp, self.sp as u16,
+            self.a, self.b, self.tr, self.trb,
+            self.k, self.l, self.m, self.n,
+            self.sr, self.dr, self.si, self.so,
+            (self.flag_a.to_bits() as u16) | ((self.flag_b.to_bits() as u16) << 8)
+        ]
+    }
+}
+
+impl Flags {
+    fn to_bits(&self) -> u8 {
+        (self.ov0 as u8) | ((self.ov1 as u8) << 1) | ((self.z as u8) << 2) |
+            ((self.c as u8) << 3) | ((self.s0 as u8) << 4) | ((self.s1 as u8) << 5)
+    }
+}
+
+impl Coprocessor for NecDsp {
+    fn name(&self) -> &'static str {
+        match self.revision {
+            Revision::Upd7725 => "NEC uPD77C25 (LLE)",
+            Revision::Upd96050 => "NEC uPD96050 (LLE)"
+        }
+    }
+
+    fn map(&self, address: HardwareAddress) -> Option<usize> {
+        let bank = address.bank() & 0x7F;
+        let offset = address.offset();
+
+        match self.mapping {
+            Mapping::LoRom if bank & 0x70 == 0x30 && offset & 0x8000 != 0 => {
+                Some(if offset & 0x4000 == 0 { DATA_REGISTER } else { STATUS_REGISTER })
+            },
+            Mapping::LoRomLarge if bank & 0x70 == 0x60 && offset & 0x8000 == 0 => {
+                Some(if offset & 0x4000 == 0 { DATA_REGISTER } else { STATUS_REGISTER })
+            },
+            Mapping::HiRom if bank & 0x60 == 0x00 && offset & 0xE000 == 0x6000 => {
+                Some(if offset & 0x1000 == 0 { DATA_REGISTER } else { STATUS_REGISTER })
+            },
+            Mapping::St01x if bank & 0x78 == 0x60 && offset & 0xC000 == 0 => {
+                Some(if offset & 0x0001 == 0 { DATA_REGISTER } else { STATUS_REGISTER })
+            },
+            Mapping::St01x if bank & 0x78 == 0x68 && offset & 0x8000 == 0 => {
+                Some(DATA_RAM + (offset & 0x0FFF) as usize)
+            },
+            _ => None
+        }
+    }
+
+    fn cycles(&self, _address: HardwareAddress) -> u64 {
+        ACCESS_CYCLES
+    }
+
+    fn read(&mut self, offset: usize) -> u8 {
+        match offset {
+            DATA_REGISTER => self.read_dr(),
+            STATUS_REGISTER => (self.sr >> 8) as u8,
+            _ => {
+                let word = self.data_ram[(offset - DATA_RAM) >> 1];
+                if offset & 0x01 == 0 { word as u8 } else { (word >> 8) as u8 }
+            }
+        }
+    }
+
+    fn write(&mut self, offset: usize, value: u8) {
+        match offset {
+            DATA_REGISTER => self.write_dr(value),
+            STATUS_REGISTER => (),
+            _ => {
+                let word = &mut self.data_ram[(offset - DATA_RAM) >> 1];
+                *word = if offset & 0x01 == 0 {
+                    (*word & 0xFF00) | value as u16
+                } else {
+                    (*word & 0x00FF) | ((value as u16) << 8)
+                };
+            }
+        }
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.clock_budget += cycles * self.revision.clock_rate();
+
+        while self.clock_budget >= MASTER_CLOCK_RATE {
+            self.clock_budget -= MASTER_CLOCK_RATE;
+            self.step();
+        }
+    }
+
+    // Registers, then the stack, then data RAM, all as 16-bit little-endian
+    fn save_state(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+
+        for &word in self.registers().iter().chain(self.stack.iter()).chain(self.data_ram.iter()) {
+            data.push(word as u8);
+            data.push((word >> 8) as u8);
+        }
+
+        data
+    }
+}
@@ -1,8 +1,8 @@
 use super::background_mode::{Priority, ScreenLayer};
 use super::ppu::Ppu;
 use super::vram::TILE_MAP_COUNT;
-use super::window::WindowMask;
-use util::byte_access::WriteTwice;
+use super::window::{Window, WindowMask};
+use util::byte_access::SharedLatch;
 use util::color::Color;
 
 const TILE_MAP_SIZE: usize = 32;
@@ -15,8 +15,8 @@ pub struct BackgroundLayer {
     chr_4_offset: usize,
     chr_16_offset: usize,
     chr_256_offset: usize,
-    scroll_x: WriteTwice<u16>,
-    scroll_y: WriteTwice<u16>,
+    scroll_x: u16,
+    scroll_y: u16,
     window_mask: WindowMask
 }
 
@@ -43,8 +43,8 @@ impl BackgroundLayer {
             chr_4_offset: 0,
             chr_16_offset: 0,
             chr_256_offset: 0,
-            scroll_x: WriteTwice::new(0x0000, 0x03FF),
-            scroll_y: WriteTwice::new(0x0000, 0x03FF),
+            scroll_x: 0x0000,
+            scroll_y: 0x0000,
             window_mask: WindowMask::new()
         }
     }
@@ -100,12 +100,16 @@ impl BackgroundLayer {
         self.chr_256_offset = (value as usize) * 128;
     }
 
-    pub fn set_scroll_x(&mut self, value: u8) {
-        self.scroll_x.write(value);
+    // The lowest 3 bits of the horizontal scroll come from the existing
+    // value rather than the latch
+    pub fn set_scroll_x(&mut self, value: u8, latch: &mut SharedLatch) {
+        let word = latch.write(value);
+        let fine_scroll = (self.scroll_x >> 8) & 0x0007;
+        self.scroll_x = ((word & 0xFFF8) | fine_scroll) & 0x03FF;
     }
 
-    pub fn set_scroll_y(&mut self, value: u8) {
-        self.scroll_y.write(value);
+    pub fn set_scroll_y(&mut self, value: u8, latch: &mut SharedLatch) {
+        self.scroll_y = latch.write(value) & 0x03FF;
     }
 
     pub fn set_window_mask_options(&mut self, value: u8) {
@@ -116,6 +120,10 @@ impl BackgroundLayer {
         self.window_mask.set_operator(value);
     }
 
+    pub fn update_window_mask(&mut self, window1: &Window, window2: &Window) {
+        self.window_mask.update(window1, window2);
+    }
+
     pub fn color_at(&self, ppu: &Ppu, screen_x: usize, screen_y: usize, screen_layer: ScreenLayer, pixel_options: &PixelOptions)
         -> Option<(Color, Priority, bool)>
     {
@@ -124,12 +132,12 @@ impl BackgroundLayer {
             ScreenLayer::SubScreen => self.sub_screen_enabled
         };
 
-        if !enabled || self.window_mask.contains(ppu, screen_x) {
+        if !enabled || self.window_mask.contains(screen_x) {
             return None;
         }
 
-        let pos_x = screen_x + (self.scroll_x.value() as usize);
-        let pos_y = screen_y + (self.scroll_y.value() as usize);
+        let pos_x = screen_x + (self.scroll_x as usize);
+        let pos_y = screen_y + (self.scroll_y as usize);
 
         // TODO: 16x16 tiles
         let tile_x = (pos_x / 8) % (TILE_MAP_SIZE * 2);
@@ -4,7 +4,7 @@ use super::ppu::Ppu;
 use util::color::Color;
 
 pub struct BackgroundMode {
-    mode_fn: Box<ModeFn>,
+    mode_fn: ModeFn,
     pseudo_hi_res: bool,
     prev_clip: Cell<bool>
 }
@@ -17,7 +17,7 @@ pub enum ScreenLayer {
 
 pub type Priority = u8;
 
-type ModeFn = Fn(&Ppu, usize, usize, ScreenLayer) -> Option<Pixel>;
+type ModeFn = fn(&Ppu, usize, usize, ScreenLayer) -> Option<Pixel>;
 
 type Pixel = (Color, bool);
 
@@ -48,7 +48,7 @@ fn resolve_pixel(maybe_pixel: Option<Pixel>, ppu: &Ppu) -> Pixel {
 impl BackgroundMode {
     pub fn new() -> BackgroundMode {
         BackgroundMode {
-            mode_fn: Box::new(mode_0),
+            mode_fn: mode_0,
             pseudo_hi_res: false,
             prev_clip: Cell::new(false)
         }
@@ -57,7 +57,7 @@ impl BackgroundMode {
     pub fn set_mode(&mut self, value: u8) {
         let mode = value & 0x07;
 
-        self.mode_fn = Box::new(match mode {
+        self.mode_fn = match mode {
             0 => mode_0,
             1 => if value & 0x08 != 0 { mode_1_high_priority } else { mode_1_low_priority },
             2 => mode_2,
@@ -67,7 +67,7 @@ impl BackgroundMode {
             6 => mode_6,
             7 => mode_7,
             _ => panic!("Mode {} not yet supported", mode)
-        });
+        };
 
         self.pseudo_hi_res = mode == 5 || mode == 6;
     }
@@ -83,14 +83,14 @@ impl BackgroundMode {
         if self.pseudo_hi_res {
             let sub_screen_pixel = sub_screen_fn();
             let (sub_screen_color, _) = resolve_pixel(sub_screen_pixel, ppu);
-            let clip = color_math.clip(ppu, color_math_enabled, screen_x);
-            let even_color = color_math.apply(ppu, screen_x, sub_screen_color, self.prev_clip.get(), || main_screen_pixel);
-            let odd_color = color_math.apply(ppu, screen_x, main_screen_color, clip, || sub_screen_pixel);
+            let clip = color_math.clip(color_math_enabled, screen_x);
+            let even_color = color_math.apply(screen_x, sub_screen_color, self.prev_clip.get(), || main_screen_pixel);
+            let odd_color = color_math.apply(screen_x, main_screen_color, clip, || sub_screen_pixel);
             self.prev_clip.set(clip);
             (even_color, odd_color)
         } else {
-            let clip = color_math.clip(ppu, color_math_enabled, screen_x);
-            let final_color = color_math.apply(ppu, screen_x, main_screen_color, clip, sub_screen_fn);
+            let clip = color_math.clip(color_math_enabled, screen_x);
+            let final_color = color_math.apply(screen_x, main_screen_color, clip, sub_screen_fn);
             (final_color, final_color)
         }
     }
@@ -194,6 +194,7 @@ fn mode_2(ppu: &Ppu, screen_x: usize, screen_y: usize, screen_layer: ScreenLayer
         color_mode: ColorMode::Color16,
         ..Default::default()
     });
+    try_pixel!(bg1_pixel, 1);
     try_pixel!(object_pixel, 2);
     let bg2_pixel = ppu.bg2().color_at(ppu, screen_x, screen_y, screen_layer, &PixelOptions {
         color_mode: ColorMode::Color16,
@@ -214,6 +215,7 @@ fn mode_3(ppu: &Ppu, screen_x: usize, screen_y: usize, screen_layer: ScreenLayer
         color_mode: ColorMode::Color256,
         ..Default::default()
     });
+    try_pixel!(bg1_pixel, 1);
     try_pixel!(object_pixel, 2);
     let bg2_pixel = ppu.bg2().color_at(ppu, screen_x, screen_y, screen_layer, &PixelOptions {
         color_mode: ColorMode::Color16,
@@ -256,6 +258,7 @@ fn mode_5(ppu: &Ppu, screen_x: usize, screen_y: usize, screen_layer: ScreenLayer
         always_wide: true,
         ..Default::default()
     });
+    try_pixel!(bg1_pixel, 1);
     try_pixel!(object_pixel, 2);
     let bg2_pixel = ppu.bg2().color_at(ppu, screen_x, screen_y, screen_layer, &PixelOptions {
         color_mode: ColorMode::Color4,
@@ -278,6 +281,7 @@ fn mode_6(ppu: &Ppu, screen_x: usize, screen_y: usize, screen_layer: ScreenLayer
         always_wide: true,
         ..Default::default()
     });
+    try_pixel!(bg1_pixel, 1);
     try_pixel!(object_pixel, 2);
     try_pixel!(object_pixel, 1);
     try_pixel!(bg1_pixel);
@@ -296,3 +300,185 @@ fn mode_7(ppu: &Ppu, screen_x: usize, screen_y: usize, screen_layer: ScreenLayer
     try_pixel!(object_pixel);
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use hardware::hardware::HardwareBus;
+    use super::{Priority, ScreenLayer};
+    use super::super::ppu::Ppu;
+    use util::color::Color;
+
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    enum Layer {
+        Bg1,
+        Bg2,
+        Bg3,
+        Bg4,
+        Obj
+    }
+
+    use self::Layer::*;
+
+    const BG3_PRIORITY: u8 = 0x08;
+
+    const MODE_0: [(Layer, Priority); 12] = [
+        (Obj, 3), (Bg1, 1), (Bg2, 1), (Obj, 2), (Bg1, 0), (Bg2, 0),
+        (Obj, 1), (Bg3, 1), (Bg4, 1), (Obj, 0), (Bg3, 0), (Bg4, 0)
+    ];
+
+    const MODE_1_HIGH_PRIORITY: [(Layer, Priority); 10] = [
+        (Bg3, 1), (Obj, 3), (Bg1, 1), (Bg2, 1), (Obj, 2),
+        (Bg1, 0), (Bg2, 0), (Obj, 1), (Obj, 0), (Bg3, 0)
+    ];
+
+    const MODE_1_LOW_PRIORITY: [(Layer, Priority); 10] = [
+        (Obj, 3), (Bg1, 1), (Bg2, 1), (Obj, 2), (Bg1, 0),
+        (Bg2, 0), (Obj, 1), (Bg3, 1), (Obj, 0), (Bg3, 0)
+    ];
+
+    // Modes 2 to 5
+    const TWO_LAYER_MODE: [(Layer, Priority); 8] = [
+        (Obj, 3), (Bg1, 1), (Obj, 2), (Bg2, 1), (Obj, 1), (Bg1, 0), (Obj, 0), (Bg2, 0)
+    ];
+
+    const MODE_6: [(Layer, Priority); 6] = [
+        (Obj, 3), (Bg1, 1), (Obj, 2), (Obj, 1), (Bg1, 0), (Obj, 0)
+    ];
+
+    // Mode 7 has no tile priority
+    const MODE_7: [(Layer, Priority); 5] = [
+        (Obj, 3), (Obj, 2), (Obj, 1), (Bg1, 0), (Obj, 0)
+    ];
+
+    // Draws the given layers at the top left pixel of the main screen. Every
+    // layer has a different palette, so each has a color of its own.
+    fn top_left_color(mode: u8, layers: &[(Layer, Priority)]) -> Option<Color> {
+        let mut ppu = Ppu::new();
+
+        // CGRAM entry N holds color N
+        ppu.write(0x21, 0x00);
+        for index in 0..256 {
+            ppu.write(0x22, index as u8);
+            ppu.write(0x22, 0x00);
+        }
+
+        ppu.write(0x05, mode);
+
+        // Tile maps at words $0400, $0800, $0C00 and $1000, BG characters
+        // at word $2000 and object characters at word $6000
+        for (register, &tile_map) in [0x07, 0x08, 0x09, 0x0A].iter().zip([0x04, 0x08, 0x0C, 0x10].iter()) {
+            ppu.write(*register, tile_map);
+        }
+        ppu.write(0x0B, 0x22);
+        ppu.write(0x0C, 0x22);
+        ppu.write(0x01, 0x03);
+
+        ppu.write(0x15, 0x80);
+        write_vram(&mut ppu, 0x2000, 0x00FF);
+        write_vram(&mut ppu, 0x6000, 0x00FF);
+
+        let mut main_screen = 0x00;
+
+        for &(layer, priority) in layers {
+            let (main_screen_bit, palette) = match layer {
+                Bg1 => (0x01, 0),
+                Bg2 => (0x02, 1),
+                Bg3 => (0x04, 2),
+                Bg4 => (0x08, 3),
+                Obj => (0x10, 0)
+            };
+
+            main_screen |= main_screen_bit;
+
+            match layer {
+                Obj => {
+                    ppu.write(0x02, 0x00);
+                    ppu.write(0x03, 0x00);
+                    for &value in [0x00, 0x00, 0x00, priority << 4].iter() {
+                        ppu.write(0x04, value);
+                    }
+                },
+                _ => {
+                    let tile_map_address = 0x0400 * (palette + 1);
+                    let tile = ((priority as u16) << 13) | ((palette as u16) << 10);
+                    write_vram(&mut ppu, tile_map_address, tile);
+
+                    // Mode 7 tile 0 is at the start of VRAM, and its first
+                    // pixel is in the upper byte of the first word
+                    if layer == Bg1 {
+                        write_vram(&mut ppu, 0x0000, 0x0100);
+                    }
+                }
+            }
+        }
+
+        ppu.write(0x2C, main_screen);
+
+        (ppu.background_mode().mode_fn)(&ppu, 0, 0, ScreenLayer::MainScreen).map(|(color, _)| color)
+    }
+
+    fn write_vram(ppu: &mut Ppu, address: u16, value: u16) {
+        ppu.write(0x16, address as u8);
+        ppu.write(0x17, (address >> 8) as u8);
+        ppu.write(0x18, value as u8);
+        ppu.write(0x19, (value >> 8) as u8);
+    }
+
+    // Checks every pair of layers, front to back
+    fn assert_layer_order(mode: u8, order: &[(Layer, Priority)]) {
+        let solo_colors: Vec<(Layer, Color)> = order.iter()
+            .map(|&(layer, priority)| {
+                let color = top_left_color(mode, &[(layer, priority)])
+                    .expect("Layer should be visible on its own");
+                (layer, color)
+            })
+            .collect();
+
+        let layer_for = |color: Color| {
+            solo_colors.iter().find(|&&(_, solo_color)| solo_color == color).map(|&(layer, _)| layer)
+        };
+
+        for &(layer, color) in solo_colors.iter() {
+            assert!(layer_for(color) == Some(layer), "Mode {:02X}: {:?} shares a color with another layer", mode, layer);
+        }
+
+        for (index, &front) in order.iter().enumerate() {
+            for &back in order[(index + 1)..].iter().filter(|back| back.0 != front.0) {
+                let winner = top_left_color(mode, &[back, front]).and_then(&layer_for);
+                assert_eq!(winner, Some(front.0), "Mode {:02X}: {:?} should be in front of {:?}", mode, front, back);
+            }
+        }
+    }
+
+    #[test]
+    fn mode_0_layer_order() {
+        assert_layer_order(0x00, &MODE_0);
+        assert_layer_order(0x00 | BG3_PRIORITY, &MODE_0);
+    }
+
+    #[test]
+    fn mode_1_layer_order() {
+        assert_layer_order(0x01, &MODE_1_LOW_PRIORITY);
+        assert_layer_order(0x01 | BG3_PRIORITY, &MODE_1_HIGH_PRIORITY);
+    }
+
+    #[test]
+    fn mode_2_to_5_layer_order() {
+        for mode in 2..6 {
+            assert_layer_order(mode, &TWO_LAYER_MODE);
+            assert_layer_order(mode | BG3_PRIORITY, &TWO_LAYER_MODE);
+        }
+    }
+
+    #[test]
+    fn mode_6_layer_order() {
+        assert_layer_order(0x06, &MODE_6);
+        assert_layer_order(0x06 | BG3_PRIORITY, &MODE_6);
+    }
+
+    #[test]
+    fn mode_7_layer_order() {
+        assert_layer_order(0x07, &MODE_7);
+        assert_layer_order(0x07 | BG3_PRIORITY, &MODE_7);
+    }
+}
@@ -25,6 +25,14 @@ impl Cgram {
         self.byte_selector = ByteSelector::Lower;
     }
 
+    // Bit 7 of the upper byte is open bus
+    pub fn read_with_open_bus(&mut self, open_bus: u8) -> u8 {
+        match self.byte_selector {
+            ByteSelector::Lower => self.read(),
+            ByteSelector::Upper => (self.read() & 0x7F) | (open_bus & 0x80)
+        }
+    }
+
     pub fn read(&mut self) -> u8 {
         match self.byte_selector {
             ByteSelector::Lower => {
@@ -59,6 +67,17 @@ impl Cgram {
         };
     }
 
+    pub fn bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(COLOR_COUNT * 2);
+
+        for color in self.colors.iter() {
+            bytes.push(color.lower());
+            bytes.push(color.upper());
+        }
+
+        bytes
+    }
+
     pub fn color(&self, index: usize) -> Color {
         self.colors[index]
     }
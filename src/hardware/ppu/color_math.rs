@@ -1,5 +1,4 @@
-use super::ppu::Ppu;
-use super::window::WindowMask;
+use super::window::{Window, WindowMask};
 use util::color::Color;
 
 pub struct ColorMath {
@@ -90,55 +89,57 @@ impl ColorMath {
         self.window_mask.set_operator(value);
     }
 
-    pub fn clip(&self, ppu: &Ppu, enabled: bool, screen_x: usize) -> bool {
-        !enabled || self.apply_window_logic(self.prevent, ppu, screen_x)
+    pub fn update_window_mask(&mut self, window1: &Window, window2: &Window) {
+        self.window_mask.update(window1, window2);
     }
 
-    pub fn apply<F>(&self, ppu: &Ppu, screen_x: usize, lhs: Color, clip: bool, sub_screen_fn: F) -> Color
+    pub fn clip(&self, enabled: bool, screen_x: usize) -> bool {
+        !enabled || self.apply_window_logic(self.prevent, screen_x)
+    }
+
+    pub fn apply<F>(&self, screen_x: usize, lhs: Color, clip: bool, sub_screen_fn: F) -> Color
         where F: Fn() -> Option<(Color, bool)>
     {
+        // Main screen is clipped to black regardless of whether color math is enabled
+        let clip_to_black = self.apply_window_logic(self.clip_to_black, screen_x);
+
+        let lhs = if clip_to_black { Color::default() } else { lhs };
+
         if clip {
             return lhs;
         }
 
-        let (rhs, divisor) = match self.source {
-            ColorMathSource::FixedColor => (self.fixed_color, self.divisor),
+        let (rhs, halve) = match self.source {
+            ColorMathSource::FixedColor => (self.fixed_color, true),
             ColorMathSource::SubScreen => {
-                let maybe_color = sub_screen_fn();
-
-                // Don't apply divisor if we fall back to fixed colour (for whatever reason)
-                match maybe_color {
-                    Some((subscreen_color, _)) => (subscreen_color, self.divisor),
-                    None => (self.fixed_color, 1)
+                // Sub screen backdrop is the fixed colour, which is never halved
+                match sub_screen_fn() {
+                    Some((subscreen_color, _)) => (subscreen_color, true),
+                    None => (self.fixed_color, false)
                 }
             }
         };
 
+        // Halving is also skipped if the main screen was clipped to black
+        let divisor = if halve && !clip_to_black { self.divisor } else { 1 };
+
         let operator = match self.operation {
             ColorMathOperator::Add => u8::saturating_add,
             ColorMathOperator::Subtract => u8::saturating_sub
         };
 
-        if self.apply_window_logic(self.clip_to_black, ppu, screen_x) {
-            Color::new(
-                operator(0, rhs.red()),
-                operator(0, rhs.green()),
-                operator(0, rhs.blue())
-            )
-        } else {
-            Color::new(
-                operator(lhs.red(), rhs.red()) / divisor,
-                operator(lhs.green(), rhs.green()) / divisor,
-                operator(lhs.blue(), rhs.blue()) / divisor
-            )
-        }
+        Color::new(
+            operator(lhs.red(), rhs.red()) / divisor,
+            operator(lhs.green(), rhs.green()) / divisor,
+            operator(lhs.blue(), rhs.blue()) / divisor
+        )
     }
 
-    fn apply_window_logic(&self, logic: ColorMathWindowOperator, ppu: &Ppu, screen_x: usize) -> bool {
+    fn apply_window_logic(&self, logic: ColorMathWindowOperator, screen_x: usize) -> bool {
         match logic {
             ColorMathWindowOperator::Never => false,
-            ColorMathWindowOperator::Outside => !self.window_mask.contains(ppu, screen_x),
-            ColorMathWindowOperator::Inside => self.window_mask.contains(ppu, screen_x),
+            ColorMathWindowOperator::Outside => !self.window_mask.contains(screen_x),
+            ColorMathWindowOperator::Inside => self.window_mask.contains(screen_x),
             ColorMathWindowOperator::Always => true
         }
     }
@@ -155,3 +156,67 @@ impl From<u8> for ColorMathWindowOperator {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::ColorMath;
+    use util::color::Color;
+
+    fn main_color() -> Color {
+        Color::new(8, 8, 8)
+    }
+
+    fn sub_color() -> Color {
+        Color::new(4, 4, 4)
+    }
+
+    // Adds the sub screen with halving enabled, over a fixed colour of 2
+    fn halving_sub_screen_math(clip_to_black: bool) -> ColorMath {
+        let mut color_math = ColorMath::new();
+        color_math.set_source(if clip_to_black { 0xC2 } else { 0x02 });
+        color_math.set_operation(0x40);
+        color_math.adjust_fixed_color(0xE2);
+        color_math
+    }
+
+    #[test]
+    fn halves_sub_screen_pixels() {
+        let color_math = halving_sub_screen_math(false);
+        let color = color_math.apply(0, main_color(), false, || Some((sub_color(), false)));
+        assert!(color == Color::new(6, 6, 6));
+    }
+
+    #[test]
+    fn fixed_color_backdrop_is_not_halved() {
+        let color_math = halving_sub_screen_math(false);
+        let color = color_math.apply(0, main_color(), false, || None);
+        assert!(color == Color::new(10, 10, 10));
+    }
+
+    #[test]
+    fn transparent_sub_screen_shows_fixed_color() {
+        let mut color_math = ColorMath::new();
+        color_math.set_source(0x02);
+        color_math.set_operation(0x80);
+        color_math.adjust_fixed_color(0x23);
+        color_math.adjust_fixed_color(0x45);
+        color_math.adjust_fixed_color(0x87);
+
+        let color = color_math.apply(0, main_color(), false, || None);
+        assert!(color == Color::new(5, 3, 1));
+    }
+
+    #[test]
+    fn clipped_main_screen_is_not_halved() {
+        let color_math = halving_sub_screen_math(true);
+        let color = color_math.apply(0, main_color(), false, || Some((sub_color(), false)));
+        assert!(color == sub_color());
+    }
+
+    #[test]
+    fn clips_to_black_without_color_math() {
+        let color_math = halving_sub_screen_math(true);
+        let color = color_math.apply(0, main_color(), true, || Some((sub_color(), false)));
+        assert!(color == Color::default());
+    }
+}
@@ -6,7 +6,9 @@ mod mode_7;
 mod oam;
 mod object_layer;
 mod ppu;
+mod register_log;
 mod vram;
 mod window;
 
 pub use self::ppu::Ppu;
+pub use self::register_log::RegisterLog;
@@ -1,17 +1,35 @@
 use super::background_mode::Priority;
 use super::ppu::Ppu;
-use util::byte_access::WriteTwice;
-use std::mem;
+use util::byte_access::SharedLatch;
 use util::color::Color;
 
 const CHR_SIZE: usize = 8;
 const FIELD_SIZE: isize = (CHR_SIZE * 128) as isize;
+const SCREEN_SIZE: usize = 256;
 
 pub struct Mode7 {
-    scroll_x_raw: WriteTwice<u16>,
-    scroll_y_raw: WriteTwice<u16>,
+    screen_over: ScreenOver,
+    flip_x: bool,
+    flip_y: bool,
+    scroll_x_raw: u16,
+    scroll_y_raw: u16,
     scroll_x: isize,
-    scroll_y: isize
+    scroll_y: isize,
+    // 8.8 signed fixed point
+    matrix_a: isize,
+    matrix_b: isize,
+    matrix_c: isize,
+    matrix_d: isize,
+    center_x: isize,
+    center_y: isize
+}
+
+// What is drawn outside the 1024x1024 field
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum ScreenOver {
+    Wrap,
+    Transparent,
+    Tile0
 }
 
 #[inline]
@@ -29,43 +47,108 @@ fn signed_scroll_value(raw_value: u16) -> isize {
 impl Mode7 {
     pub fn new() -> Mode7 {
         Mode7 {
-            scroll_x_raw: WriteTwice::new(0x0000, 0x1FFF),
-            scroll_y_raw: WriteTwice::new(0x0000, 0x1FFF),
+            screen_over: ScreenOver::Wrap,
+            flip_x: false,
+            flip_y: false,
+            scroll_x_raw: 0x0000,
+            scroll_y_raw: 0x0000,
             scroll_x: 0,
-            scroll_y: 0
+            scroll_y: 0,
+            matrix_a: 0,
+            matrix_b: 0,
+            matrix_c: 0,
+            matrix_d: 0,
+            center_x: 0,
+            center_y: 0
         }
     }
 
-    pub fn set_scroll_x(&mut self, value: u8) {
-        self.scroll_x_raw.write(value);
-        self.scroll_x = signed_scroll_value(self.scroll_x_raw.value());
-        debug!("Mode 7 Scroll X: {:04X} => {:04X} ({})", self.scroll_x_raw.value(), self.scroll_x, self.scroll_x);
+    pub fn set_settings(&mut self, value: u8) {
+        self.screen_over = match value & 0xC0 {
+            0x80 => ScreenOver::Transparent,
+            0xC0 => ScreenOver::Tile0,
+            _ => ScreenOver::Wrap
+        };
+        self.flip_y = value & 0x02 != 0;
+        self.flip_x = value & 0x01 != 0;
+        debug!("Mode 7 Settings: {:?} X Flip={} Y Flip={}", self.screen_over, self.flip_x, self.flip_y);
+    }
+
+    pub fn set_scroll_x(&mut self, value: u8, latch: &mut SharedLatch) {
+        self.scroll_x_raw = latch.write(value) & 0x1FFF;
+        self.scroll_x = signed_scroll_value(self.scroll_x_raw);
+        debug!("Mode 7 Scroll X: {:04X} => {:04X} ({})", self.scroll_x_raw, self.scroll_x, self.scroll_x);
+    }
+
+    pub fn set_scroll_y(&mut self, value: u8, latch: &mut SharedLatch) {
+        self.scroll_y_raw = latch.write(value) & 0x1FFF;
+        self.scroll_y = signed_scroll_value(self.scroll_y_raw);
+        debug!("Mode 7 Scroll Y: {:04X} => {:04X} ({})", self.scroll_y_raw, self.scroll_y, self.scroll_y);
+    }
+
+    // Returns the full 16-bit value, as the PPU multiplier also uses it
+    pub fn set_matrix_a(&mut self, value: u8, latch: &mut SharedLatch) -> u16 {
+        let raw_value = latch.write(value);
+        self.matrix_a = (raw_value as i16) as isize;
+        debug!("Mode 7 Matrix A: {:04X}", raw_value);
+        raw_value
+    }
+
+    pub fn set_matrix_b(&mut self, value: u8, latch: &mut SharedLatch) {
+        let raw_value = latch.write(value);
+        self.matrix_b = (raw_value as i16) as isize;
+        debug!("Mode 7 Matrix B: {:04X}", raw_value);
+    }
+
+    pub fn set_matrix_c(&mut self, value: u8, latch: &mut SharedLatch) {
+        let raw_value = latch.write(value);
+        self.matrix_c = (raw_value as i16) as isize;
+        debug!("Mode 7 Matrix C: {:04X}", raw_value);
+    }
+
+    pub fn set_matrix_d(&mut self, value: u8, latch: &mut SharedLatch) {
+        let raw_value = latch.write(value);
+        self.matrix_d = (raw_value as i16) as isize;
+        debug!("Mode 7 Matrix D: {:04X}", raw_value);
+    }
+
+    pub fn set_center_x(&mut self, value: u8, latch: &mut SharedLatch) {
+        let raw_value = latch.write(value) & 0x1FFF;
+        self.center_x = signed_scroll_value(raw_value);
+        debug!("Mode 7 Center X: {:04X} ({})", raw_value, self.center_x);
     }
 
-    pub fn set_scroll_y(&mut self, value: u8) {
-        self.scroll_y_raw.write(value);
-        self.scroll_y = signed_scroll_value(self.scroll_y_raw.value());
-        debug!("Mode 7 Scroll Y: {:04X} => {:04X} ({})", self.scroll_y_raw.value(), self.scroll_y, self.scroll_y);
+    pub fn set_center_y(&mut self, value: u8, latch: &mut SharedLatch) {
+        let raw_value = latch.write(value) & 0x1FFF;
+        self.center_y = signed_scroll_value(raw_value);
+        debug!("Mode 7 Center Y: {:04X} ({})", raw_value, self.center_y);
     }
 
     pub fn color_at(&self, ppu: &Ppu, screen_x: usize, screen_y: usize)
         -> Option<(Color, Priority, bool)>
     {
-        let signed_pos_x = (screen_x as isize) + self.scroll_x;
-        let signed_pos_y = (screen_y as isize) + self.scroll_y;
+        // Flipping mirrors the whole screen, before the transform is applied
+        let screen_x = if self.flip_x { SCREEN_SIZE - 1 - screen_x } else { screen_x };
+        let screen_y = if self.flip_y { SCREEN_SIZE - 1 - screen_y } else { screen_y };
 
-        if signed_pos_x < 0 || signed_pos_y < 0 || signed_pos_x >= FIELD_SIZE || signed_pos_y >= FIELD_SIZE {
-            // TODO: *May* be character 0, depending on settings
-            return None;
-        }
+        // Rotate/scale the scrolled screen position around the center point
+        let offset_x = (screen_x as isize) + self.scroll_x - self.center_x;
+        let offset_y = (screen_y as isize) + self.scroll_y - self.center_y;
 
-        let pos_x = signed_pos_x as usize;
-        let pos_y = signed_pos_y as usize;
+        let signed_pos_x = ((self.matrix_a * offset_x + self.matrix_b * offset_y) >> 8) + self.center_x;
+        let signed_pos_y = ((self.matrix_c * offset_x + self.matrix_d * offset_y) >> 8) + self.center_y;
 
-        let tile_x = pos_x / CHR_SIZE;
-        let tile_y = pos_y / CHR_SIZE;
+        let outside = signed_pos_x < 0 || signed_pos_y < 0 || signed_pos_x >= FIELD_SIZE || signed_pos_y >= FIELD_SIZE;
 
-        let character = ppu.vram().mode_7_chr_at(tile_x, tile_y);
+        // Wrapping takes the position modulo the field size
+        let pos_x = (signed_pos_x & (FIELD_SIZE - 1)) as usize;
+        let pos_y = (signed_pos_y & (FIELD_SIZE - 1)) as usize;
+
+        let character = match self.screen_over {
+            ScreenOver::Transparent if outside => return None,
+            ScreenOver::Tile0 if outside => ppu.vram().mode_7_chr(0),
+            _ => ppu.vram().mode_7_chr_at(pos_x / CHR_SIZE, pos_y / CHR_SIZE)
+        };
 
         let color_index = character.pixel_at(pos_x % CHR_SIZE, pos_y % CHR_SIZE);
 
@@ -76,3 +159,122 @@ impl Mode7 {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use hardware::hardware::HardwareBus;
+    use super::super::ppu::Ppu;
+    use util::color::Color;
+
+    const IDENTITY: [u16; 4] = [0x0100, 0x0000, 0x0000, 0x0100];
+
+    // CGRAM entry N holds color N, and VRAM is written a byte at a time
+    fn ppu() -> Ppu {
+        let mut ppu = Ppu::new();
+
+        ppu.write(0x21, 0x00);
+        for index in 0..256 {
+            ppu.write(0x22, index as u8);
+            ppu.write(0x22, 0x00);
+        }
+
+        ppu.write(0x15, 0x80);
+        ppu
+    }
+
+    // Writes through the Mode 7 latch, lower byte first
+    fn write_latched(ppu: &mut Ppu, register: usize, value: u16) {
+        ppu.write(register, value as u8);
+        ppu.write(register, (value >> 8) as u8);
+    }
+
+    fn set_matrix(ppu: &mut Ppu, matrix: [u16; 4]) {
+        for (register, &value) in (0x1B..0x1F).zip(matrix.iter()) {
+            write_latched(ppu, register, value);
+        }
+    }
+
+    fn set_vram_address(ppu: &mut Ppu, address: usize) {
+        ppu.write(0x16, address as u8);
+        ppu.write(0x17, (address >> 8) as u8);
+    }
+
+    // Places 'tile' in the tile map at the given field position, and sets
+    // the matching pixel of that tile to 'color_index'
+    fn set_pixel(ppu: &mut Ppu, pos_x: usize, pos_y: usize, tile: u8, color_index: u8) {
+        set_vram_address(ppu, (pos_y / 8) * 128 + pos_x / 8);
+        ppu.write(0x18, tile);
+
+        set_vram_address(ppu, tile as usize * 64 + (pos_y % 8) * 8 + pos_x % 8);
+        ppu.write(0x19, color_index);
+    }
+
+    fn color_at(ppu: &Ppu, screen_x: usize, screen_y: usize) -> Option<Color> {
+        ppu.mode_7().color_at(ppu, screen_x, screen_y).map(|(color, _, _)| color)
+    }
+
+    fn color(ppu: &Ppu, index: usize) -> Option<Color> {
+        Some(ppu.cgram().color(index))
+    }
+
+    #[test]
+    fn scales_by_matrix() {
+        let mut ppu = ppu();
+        set_pixel(&mut ppu, 20, 10, 1, 0x11);
+
+        // Each screen pixel covers two field pixels in each direction
+        set_matrix(&mut ppu, [0x0200, 0x0000, 0x0000, 0x0200]);
+
+        assert!(color_at(&ppu, 10, 5) == color(&ppu, 0x11));
+        assert!(color_at(&ppu, 20, 10) == None);
+    }
+
+    #[test]
+    fn rotates_around_center() {
+        let mut ppu = ppu();
+        set_pixel(&mut ppu, 116, 124, 1, 0x22);
+
+        // A quarter turn around (128, 112): each screen offset (x, y) from the
+        // center samples the field at offset (-y, x)
+        set_matrix(&mut ppu, [0x0000, 0xFF00, 0x0100, 0x0000]);
+        write_latched(&mut ppu, 0x1F, 128);
+        write_latched(&mut ppu, 0x20, 112);
+
+        assert!(color_at(&ppu, 140, 124) == color(&ppu, 0x22));
+        assert!(color_at(&ppu, 116, 124) == None);
+    }
+
+    #[test]
+    fn applies_screen_over_setting() {
+        let mut ppu = ppu();
+        set_matrix(&mut ppu, IDENTITY);
+
+        // Field pixel (1023, 0) in tile 1, and the same pixel of tile 0
+        set_pixel(&mut ppu, 1023, 0, 1, 0x33);
+        set_pixel(&mut ppu, 7, 8, 0, 0x44);
+
+        // Scroll one pixel left of the field
+        write_latched(&mut ppu, 0x0D, 0x1FFF);
+
+        ppu.write(0x1A, 0x00);
+        assert!(color_at(&ppu, 0, 0) == color(&ppu, 0x33));
+
+        ppu.write(0x1A, 0x80);
+        assert!(color_at(&ppu, 0, 0) == None);
+
+        ppu.write(0x1A, 0xC0);
+        assert!(color_at(&ppu, 0, 0) == color(&ppu, 0x44));
+    }
+
+    #[test]
+    fn flips_screen() {
+        let mut ppu = ppu();
+        set_matrix(&mut ppu, IDENTITY);
+        set_pixel(&mut ppu, 255, 255, 1, 0x55);
+
+        assert!(color_at(&ppu, 0, 0) == None);
+
+        ppu.write(0x1A, 0x03);
+        assert!(color_at(&ppu, 0, 0) == color(&ppu, 0x55));
+    }
+}
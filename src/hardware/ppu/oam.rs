@@ -1,18 +1,23 @@
 use std::slice::Iter;
-use util::byte_access::{ByteAccess, ByteSelector};
+use util::byte_access::ByteAccess;
 
 const LOWER_TABLE_SIZE: usize = 256;
-const UPPER_TABLE_SIZE: usize = 16;
+const UPPER_TABLE_SIZE: usize = 32;
+
+// The byte address is 10 bits. Everything from $200 up is the upper table,
+// which is only 32 bytes long, so it repeats until the address wraps to 0.
+const UPPER_TABLE_START: usize = 0x200;
+const UPPER_TABLE_MASK: usize = 0x1F;
+const ADDRESS_MASK: usize = 0x3FF;
 
 const OBJECT_COUNT: usize = 128;
 
 pub struct Oam {
     lower_table: Vec<u16>,
-    upper_table: Vec<u16>,
+    upper_table: Vec<u8>,
+    reload_address: usize,
     address: usize,
     lower_table_write_buffer: u8,
-    table_selector: TableSelector,
-    byte_selector: ByteSelector,
     objects: Vec<Object>
 }
 
@@ -35,51 +40,36 @@ pub enum SizeSelector {
     Large
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
-enum TableSelector {
-    Lower,
-    Upper
-}
-
-#[inline]
-fn absolute_offset(offset: usize, byte_selector: ByteSelector) -> usize {
-    (offset << 1) | if byte_selector == ByteSelector::Upper { 1 } else { 0 }
-}
-
 impl Oam {
     pub fn new() -> Oam {
         Oam {
             lower_table: vec![0; LOWER_TABLE_SIZE],
             upper_table: vec![0; UPPER_TABLE_SIZE],
+            reload_address: 0,
             address: 0,
             lower_table_write_buffer: 0x00,
-            table_selector: TableSelector::Lower,
-            byte_selector: ByteSelector::Lower,
             objects: vec![Default::default(); OBJECT_COUNT]
         }
     }
 
+    // Writing either half of the word address ($2102/$2103) reloads the
+    // internal byte address from both halves
     pub fn set_address(&mut self, value: u8) {
-        self.address = value as usize;
-        self.byte_selector = ByteSelector::Lower;
+        self.reload_address = (self.reload_address & 0x100) | (value as usize);
+        self.address = self.reload_address << 1;
     }
 
     pub fn set_table(&mut self, value: u8) {
-        self.table_selector = match value & 0x01 {
-            0x01 => TableSelector::Upper,
-            _ => TableSelector::Lower
-        };
+        self.reload_address = (self.reload_address & 0xFF) | (((value & 0x01) as usize) << 8);
+        self.address = self.reload_address << 1;
     }
 
     pub fn read(&mut self) -> u8 {
-        let value = match self.table_selector {
-            TableSelector::Lower => {
-                self.lower_table[self.address].get(self.byte_selector)
-            },
-            TableSelector::Upper => {
-                let offset = self.address % UPPER_TABLE_SIZE;
-                self.upper_table[offset].get(self.byte_selector)
-            }
+        let value = if self.address < UPPER_TABLE_START {
+            let word = self.lower_table[self.address >> 1];
+            if self.address & 1 == 0 { word.lower() } else { word.upper() }
+        } else {
+            self.upper_table[self.address & UPPER_TABLE_MASK]
         };
 
         self.increment_address();
@@ -88,59 +78,51 @@ impl Oam {
     }
 
     pub fn write(&mut self, value: u8) {
-        debug!("OAM Write ({:?} Table, {:?} Byte): {:02X} <= {:02X}",
-            self.table_selector,
-            self.byte_selector,
-            self.address,
-            value);
-
-        match self.table_selector {
-            TableSelector::Lower => {
-                // Value is not actually written to lower table until upper byte is written
-                match self.byte_selector {
-                    ByteSelector::Lower => self.lower_table_write_buffer = value,
-                    ByteSelector::Upper => {
-                        let word_value = &mut self.lower_table[self.address];
-                        word_value.set_lower(self.lower_table_write_buffer);
-                        word_value.set_upper(value);
-                    }
-                };
-                let absolute_offset = absolute_offset(self.address, self.byte_selector);
-                self.update_cache_lower(absolute_offset, value);
-            },
-            TableSelector::Upper => {
-                let offset = self.address % UPPER_TABLE_SIZE;
-                self.upper_table[offset].set(self.byte_selector, value);
-                let absolute_offset = absolute_offset(offset, self.byte_selector);
-                self.update_cache_upper(absolute_offset, value);
+        debug!("OAM Write: {:03X} <= {:02X}", self.address, value);
+
+        if self.address < UPPER_TABLE_START {
+            // Value is not actually written to lower table until upper byte is written
+            if self.address & 1 == 0 {
+                self.lower_table_write_buffer = value;
+            } else {
+                let lower = self.lower_table_write_buffer;
+                let word_value = &mut self.lower_table[self.address >> 1];
+                word_value.set_lower(lower);
+                word_value.set_upper(value);
+                let address = self.address;
+                self.update_cache_lower(address - 1, lower);
+                self.update_cache_lower(address, value);
             }
-        };
+        } else {
+            // Upper table bytes are written immediately, and mirrored up to $3FF
+            let offset = self.address & UPPER_TABLE_MASK;
+            self.upper_table[offset] = value;
+            self.update_cache_upper(offset, value);
+        }
 
         self.increment_address();
     }
 
+    // Lower table followed by upper table, in the order they are written
+    pub fn bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(LOWER_TABLE_SIZE * 2 + UPPER_TABLE_SIZE);
+
+        for word in self.lower_table.iter() {
+            bytes.push(word.lower());
+            bytes.push(word.upper());
+        }
+
+        bytes.extend_from_slice(&self.upper_table);
+
+        bytes
+    }
+
     pub fn iter_objects(&self) -> Iter<Object> {
         self.objects.iter()
     }
 
     fn increment_address(&mut self) {
-        match self.byte_selector {
-            ByteSelector::Lower => {
-                self.byte_selector = ByteSelector::Upper;
-            },
-            ByteSelector::Upper => {
-                self.byte_selector = ByteSelector::Lower;
-                self.address = self.address + 1;
-
-                if self.address == LOWER_TABLE_SIZE {
-                    self.address = 0;
-                    self.table_selector = match self.table_selector {
-                        TableSelector::Lower => TableSelector::Upper,
-                        TableSelector::Upper => TableSelector::Lower
-                    };
-                }
-            }
-        }
+        self.address = (self.address + 1) & ADDRESS_MASK;
     }
 
     fn update_cache_lower(&mut self, byte_address: usize, value: u8) {
@@ -182,8 +164,9 @@ impl Oam {
             let object = &mut self.objects[first_object_index + i];
             let bits = (value & (0x03 << (i * 2))) >> (i * 2);
 
-            // Set only sign bit for x position (this is even more awkward)
-            object.pos_x = (((((bits & 0x01) as u16) << 15) | (((object.pos_x as i16) as u16) & 0x00FF)) as i16) as isize;
+            // X position is 9-bit signed, with bit 8 held in the upper table
+            let lower_x = object.pos_x & 0xFF;
+            object.pos_x = if bits & 0x01 != 0 { lower_x - 0x100 } else { lower_x };
 
             object.size_selector = match bits & 0x02 {
                 0x02 => SizeSelector::Large,
@@ -210,3 +193,114 @@ impl Default for SizeSelector {
         SizeSelector::Small
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Oam, SizeSelector, LOWER_TABLE_SIZE};
+
+    const UPPER_TABLE_BYTE: usize = LOWER_TABLE_SIZE * 2;
+
+    // Sets the word address through $2102/$2103
+    fn set_word_address(oam: &mut Oam, address: u16) {
+        oam.set_address(address as u8);
+        oam.set_table((address >> 8) as u8);
+    }
+
+    #[test]
+    fn address_write_reloads_byte_address() {
+        let mut oam = Oam::new();
+
+        set_word_address(&mut oam, 0x0001);
+        oam.write(0x12);
+        oam.write(0x34);
+        oam.write(0x56);
+
+        // Rewriting either half goes back to the start of the word
+        oam.set_address(0x01);
+        assert_eq!(oam.read(), 0x12);
+        oam.set_table(0x00);
+        assert_eq!(oam.read(), 0x12);
+        assert_eq!(oam.read(), 0x34);
+
+        set_word_address(&mut oam, 0x0100);
+        oam.write(0x78);
+        assert_eq!(oam.bytes()[UPPER_TABLE_BYTE], 0x78);
+    }
+
+    #[test]
+    fn lower_table_written_on_odd_byte() {
+        let mut oam = Oam::new();
+
+        set_word_address(&mut oam, 0x0000);
+        oam.write(0x12);
+
+        assert_eq!(oam.bytes()[0], 0x00);
+        assert_eq!(oam.iter_objects().next().unwrap().pos_x, 0);
+
+        oam.write(0x34);
+
+        assert_eq!(oam.bytes()[0], 0x12);
+        assert_eq!(oam.bytes()[1], 0x34);
+
+        let object = oam.iter_objects().next().unwrap();
+        assert_eq!(object.pos_x, 0x12);
+        assert_eq!(object.pos_y, 0x34);
+    }
+
+    #[test]
+    fn upper_table_byte_updates_four_objects() {
+        let mut oam = Oam::new();
+
+        // Lower X position of 0x20 for objects 0-3
+        for object_index in 0..4 {
+            set_word_address(&mut oam, object_index * 2);
+            oam.write(0x20);
+            oam.write(0x00);
+        }
+
+        // Object 0: 00, object 1: 11, object 2: 01, object 3: 10
+        set_word_address(&mut oam, 0x0100);
+        oam.write(0x9C);
+
+        let objects: Vec<_> = oam.iter_objects().take(4).cloned().collect();
+
+        assert_eq!(objects[0].pos_x, 0x20);
+        assert_eq!(objects[0].size_selector, SizeSelector::Small);
+        assert_eq!(objects[1].pos_x, 0x20 - 0x100);
+        assert_eq!(objects[1].size_selector, SizeSelector::Large);
+        assert_eq!(objects[2].pos_x, 0x20 - 0x100);
+        assert_eq!(objects[2].size_selector, SizeSelector::Small);
+        assert_eq!(objects[3].pos_x, 0x20);
+        assert_eq!(objects[3].size_selector, SizeSelector::Large);
+
+        // The lower X position is kept when bit 8 is cleared again
+        set_word_address(&mut oam, 0x0100);
+        oam.write(0x00);
+        assert_eq!(oam.iter_objects().nth(1).unwrap().pos_x, 0x20);
+    }
+
+    #[test]
+    fn upper_table_mirrors_above_220_and_wraps_at_3ff() {
+        let mut oam = Oam::new();
+
+        set_word_address(&mut oam, 0x0110);
+        oam.write(0xAB);
+
+        assert_eq!(oam.bytes()[UPPER_TABLE_BYTE], 0xAB);
+
+        set_word_address(&mut oam, 0x0100);
+        assert_eq!(oam.read(), 0xAB);
+
+        // $3FE-$3FF are the last two upper table bytes, then back to $000
+        set_word_address(&mut oam, 0x01FF);
+        oam.write(0xCD);
+        oam.write(0xEF);
+        oam.write(0x12);
+        oam.write(0x34);
+
+        assert_eq!(oam.bytes()[UPPER_TABLE_BYTE + 0x1E], 0xCD);
+        assert_eq!(oam.bytes()[UPPER_TABLE_BYTE + 0x1F], 0xEF);
+        assert_eq!(oam.bytes()[0], 0x12);
+        assert_eq!(oam.bytes()[1], 0x34);
+    }
+}
@@ -1,7 +1,7 @@
 use super::background_mode::{Priority, ScreenLayer};
 use super::oam::SizeSelector;
 use super::ppu::Ppu;
-use super::window::WindowMask;
+use super::window::{Window, WindowMask};
 use util::color::Color;
 
 const TABLE_SIZE: usize = 256;
@@ -77,6 +77,10 @@ impl ObjectLayer {
         self.window_mask.set_operator(value);
     }
 
+    pub fn update_window_mask(&mut self, window1: &Window, window2: &Window) {
+        self.window_mask.update(window1, window2);
+    }
+
     pub fn color_at(&self, ppu: &Ppu, screen_x: usize, screen_y: usize, screen_layer: ScreenLayer)
         -> Option<(Color, Priority, bool)>
     {
@@ -85,7 +89,7 @@ impl ObjectLayer {
             ScreenLayer::SubScreen => self.sub_screen_enabled
         };
 
-        if !enabled || self.window_mask.contains(ppu, screen_x) {
+        if !enabled || self.window_mask.contains(screen_x) {
             return None;
         }
 
@@ -1,7 +1,5 @@
+use hardware::frame_buffer::FrameBuffer;
 use hardware::hardware::HardwareBus;
-use hardware::io_port::{IoPort, PPU_LATCH_BIT};
-use hardware::screen::Screen;
-use std::rc::Rc;
 use super::background_layer::BackgroundLayer;
 use super::background_mode::BackgroundMode;
 use super::cgram::Cgram;
@@ -9,10 +7,13 @@ use super::color_math::ColorMath;
 use super::mode_7::Mode7;
 use super::oam::Oam;
 use super::object_layer::ObjectLayer;
+use super::register_log::{RegisterHistory, RegisterLog, RegisterWrite};
 use super::vram::Vram;
 use super::window::Window;
-use util::byte_access::{ReadTwice, WriteTwice};
+use util::byte_access::{ReadTwice, SharedLatch};
 use util::color::Color;
+use util::fill_pattern::FillPattern;
+use util::json::JsonObject;
 
 const DOTS_PER_LINE: usize = 340;
 const TOTAL_SCANLINES: usize = 262;
@@ -34,11 +35,12 @@ const CHIP_VERSION_5C77: u8 = 1;
 const CHIP_VERSION_5C78: u8 = 3;
 
 pub struct Ppu {
-    screen: Screen,
-    io_port: Rc<IoPort>,
+    frame_buffer: FrameBuffer,
+    counter_latch_enabled: bool,
     position: Position,
     stored_position: StoredPosition,
     force_blank: bool,
+    brightness: u8,
     hblank: bool,
     vblank: bool,
     oam: Oam,
@@ -50,6 +52,10 @@ pub struct Ppu {
     bg3: BackgroundLayer,
     bg4: BackgroundLayer,
     mode_7: Mode7,
+    bg_latch: SharedLatch,
+    mode_7_latch: SharedLatch,
+    ppu1_open_bus: u8,
+    ppu2_open_bus: u8,
     object_layer: ObjectLayer,
     window1: Window,
     window2: Window,
@@ -57,7 +63,12 @@ pub struct Ppu {
     backdrop_color_math_enabled: bool,
     multiplication: Multiplication,
     cycles: u64,
-    next_pixel_cycles: u64
+    next_pixel_cycles: u64,
+    frame_count: u64,
+    register_history: RegisterHistory,
+    register_log: Option<RegisterLog>,
+    completed_register_log: Option<RegisterLog>,
+    record_frame: Option<u64>
 }
 
 pub struct Position {
@@ -72,15 +83,15 @@ struct StoredPosition {
 }
 
 struct Multiplication {
-    lhs: WriteTwice<u16>,
+    lhs: u16,
     result: u32
 }
 
 impl Ppu {
-    pub fn new(screen: Screen, io_port: Rc<IoPort>) -> Ppu {
+    pub fn new() -> Ppu {
         Ppu {
-            screen: screen,
-            io_port: io_port,
+            frame_buffer: FrameBuffer::new(),
+            counter_latch_enabled: true,
             position: Position {
                 h: 0,
                 v: 0
@@ -91,6 +102,7 @@ impl Ppu {
                 stored: false
             },
             force_blank: true,
+            brightness: 0x0F,
             hblank: true,
             vblank: true,
             oam: Oam::new(),
@@ -102,17 +114,26 @@ impl Ppu {
             bg3: BackgroundLayer::new(),
             bg4: BackgroundLayer::new(),
             mode_7: Mode7::new(),
+            bg_latch: SharedLatch::new(),
+            mode_7_latch: SharedLatch::new(),
+            ppu1_open_bus: 0x00,
+            ppu2_open_bus: 0x00,
             object_layer: ObjectLayer::new(),
             window1: Window::new(),
             window2: Window::new(),
             color_math: ColorMath::new(),
             backdrop_color_math_enabled: false,
             multiplication: Multiplication {
-                lhs: WriteTwice::new(0x0000, 0xFFFF),
+                lhs: 0x0000,
                 result: 0x00000000
             },
             cycles: 0,
-            next_pixel_cycles: STANDARD_PIXEL_CYCLES
+            next_pixel_cycles: STANDARD_PIXEL_CYCLES,
+            frame_count: 0,
+            register_history: RegisterHistory::new(),
+            register_log: None,
+            completed_register_log: None,
+            record_frame: None
         }
     }
 
@@ -126,6 +147,43 @@ impl Ppu {
         self.stored_position.stored = true;
     }
 
+    pub fn frame_buffer(&self) -> &FrameBuffer {
+        &self.frame_buffer
+    }
+
+    // Mirrors the PPU latch bit of the CPU's IO port ($4201)
+    pub fn set_counter_latch_enabled(&mut self, enabled: bool) {
+        self.counter_latch_enabled = enabled;
+    }
+
+    pub fn state_json(&self) -> JsonObject {
+        let mut state = JsonObject::new();
+        state.number("h", self.position.h());
+        state.number("v", self.position.v());
+        state.number("stored_h", self.stored_position.h.value());
+        state.number("stored_v", self.stored_position.v.value());
+        state.boolean("stored", self.stored_position.stored);
+        state.boolean("force_blank", self.force_blank);
+        state.number("brightness", self.brightness);
+        state.boolean("hblank", self.hblank);
+        state.boolean("vblank", self.vblank);
+        state.number("vram_address", self.vram.address() as u64);
+        state.number("frame_count", self.frame_count);
+        state
+    }
+
+    pub fn fill_vram(&mut self, pattern: FillPattern) {
+        self.vram.fill(pattern);
+    }
+
+    // Used by $2137 reads and by devices driving the external latch line (e.g. light guns)
+    pub fn latch_counters(&mut self) {
+        // Store current H and V counter values if IO port latch is 'high'
+        if self.counter_latch_enabled {
+            self.store_position();
+        }
+    }
+
     pub fn oam(&self) -> &Oam {
         &self.oam
     }
@@ -166,14 +224,6 @@ impl Ppu {
         &self.object_layer
     }
 
-    pub fn window1(&self) -> &Window {
-        &self.window1
-    }
-
-    pub fn window2(&self) -> &Window {
-        &self.window2
-    }
-
     pub fn color_math(&self) -> &ColorMath {
         &self.color_math
     }
@@ -193,7 +243,7 @@ impl Ppu {
 
         self.cycles -= self.next_pixel_cycles;
 
-        let vblank_start = match self.screen.overscan() {
+        let vblank_start = match self.frame_buffer.overscan() {
             false => VBLANK_START_NORMAL,
             true => VBLANK_START_OVERSCAN
         };
@@ -201,7 +251,8 @@ impl Ppu {
         if self.position.v >= DISPLAY_TOP && self.position.v < vblank_start &&
             self.position.h >= DISPLAY_LEFT && self.position.h < DISPLAY_RIGHT
         {
-            let (even_color, odd_color) = if !self.force_blank {
+            // Nothing is visible at brightness 0, so skip layer evaluation entirely
+            let (even_color, odd_color) = if !self.force_blank && self.brightness != 0 {
                 let screen_x = self.position.h - DISPLAY_LEFT;
                 let screen_y = self.position.v - DISPLAY_TOP;
                 self.background_mode.color_at(self, screen_x, screen_y)
@@ -210,8 +261,8 @@ impl Ppu {
             };
 
             // Blit two pixels because we are always in 'pseudo-HD'
-            self.screen.blit(even_color);
-            self.screen.blit(odd_color);
+            self.frame_buffer.blit(even_color);
+            self.frame_buffer.blit(odd_color);
         }
 
         self.position.h += 1;
@@ -221,12 +272,19 @@ impl Ppu {
             self.position.v += 1;
 
             if self.position.v == DISPLAY_TOP {
-                self.screen.begin_frame();
+                self.frame_buffer.begin_frame();
             } else if self.position.v < vblank_start {
-                self.screen.next_line();
+                self.frame_buffer.next_line();
             } else if !self.vblank {
-                self.screen.end_frame();
+                self.frame_buffer.end_frame();
                 self.vblank = true;
+                self.frame_count += 1;
+
+                if self.register_log.is_some() {
+                    self.completed_register_log = self.register_log.take();
+                } else if self.record_frame == Some(self.frame_count) {
+                    self.register_log = Some(self.recording_preamble());
+                }
             } else if self.position.v == TOTAL_SCANLINES {
                 self.position.v = 0;
                 self.vblank = false;
@@ -247,53 +305,203 @@ impl Ppu {
         self.vblank
     }
 
+    // VRAM can only be written while the PPU is not rendering
+    fn vram_accessible(&self) -> bool {
+        self.force_blank || self.vblank
+    }
+
     pub fn hblank(&self) -> bool {
         self.hblank
     }
+
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    // Records every register write from the start of VBlank once frame_count
+    // reaches 'frame', until the start of the next VBlank
+    pub fn record_frame(&mut self, frame: u64) {
+        if frame == self.frame_count {
+            self.register_log = Some(self.recording_preamble());
+        } else {
+            self.record_frame = Some(frame);
+        }
+    }
+
+    pub fn take_register_log(&mut self) -> Option<RegisterLog> {
+        self.completed_register_log.take()
+    }
+
+    // Plays a recorded frame into this PPU, which should be freshly created,
+    // then runs until the end of that frame
+    pub fn replay(&mut self, log: &RegisterLog) -> Result<(), String> {
+        for write in log.writes() {
+            if write.v as usize >= TOTAL_SCANLINES || write.h as usize >= DOTS_PER_LINE {
+                return Err(format!("Register write outside of frame: V={} H={}", write.v, write.h));
+            }
+
+            while self.position.v != write.v as usize || self.position.h != write.h as usize {
+                self.step_dot();
+            }
+
+            self.write(write.register as usize, write.value);
+        }
+
+        let frame_count = self.frame_count;
+
+        while self.frame_count == frame_count {
+            self.step_dot();
+        }
+
+        Ok(())
+    }
+
+    fn step_dot(&mut self) {
+        self.cycles = self.next_pixel_cycles;
+        self.next_pixel();
+    }
+
+    // Writes that rebuild the current PPU state from scratch: memory is
+    // uploaded under forced blank, then each register is restored
+    fn recording_preamble(&self) -> RegisterLog {
+        let mut writes = vec![(0x00, 0x80), (0x15, 0x80), (0x16, 0x00), (0x17, 0x00)];
+
+        for (index, &byte) in self.vram.bytes().iter().enumerate() {
+            writes.push((if index & 1 == 0 { 0x18 } else { 0x19 }, byte));
+        }
+
+        writes.push((0x21, 0x00));
+
+        for byte in self.cgram.bytes() {
+            writes.push((0x22, byte));
+        }
+
+        writes.push((0x02, 0x00));
+        writes.push((0x03, 0x00));
+
+        for byte in self.oam.bytes() {
+            writes.push((0x04, byte));
+        }
+
+        writes.extend(self.register_history.restore());
+
+        if let Some(value) = self.register_history.last(0x00) {
+            writes.push((0x00, value));
+        }
+
+        let mut log = RegisterLog::new();
+
+        for (register, value) in writes {
+            log.push(RegisterWrite {
+                v: self.position.v as u16,
+                h: self.position.h as u16,
+                register: register,
+                value: value
+            });
+        }
+
+        log
+    }
+
+    fn update_window_masks(&mut self) {
+        self.bg1.update_window_mask(&self.window1, &self.window2);
+        self.bg2.update_window_mask(&self.window1, &self.window2);
+        self.bg3.update_window_mask(&self.window1, &self.window2);
+        self.bg4.update_window_mask(&self.window1, &self.window2);
+        self.object_layer.update_window_mask(&self.window1, &self.window2);
+        self.color_math.update_window_mask(&self.window1, &self.window2);
+    }
 }
 
 impl HardwareBus for Ppu {
     fn read(&mut self, offset: usize) -> u8 {
         match offset {
-            0x34 => self.multiplication.result as u8,
-            0x35 => self.multiplication.result.wrapping_shr(8) as u8,
-            0x36 => self.multiplication.result.wrapping_shr(16) as u8,
+            // Reads from each PPU chip update that chip's open bus, which
+            // supplies any bits its registers don't drive
+            0x34 => {
+                self.ppu1_open_bus = self.multiplication.result as u8;
+                self.ppu1_open_bus
+            },
+            0x35 => {
+                self.ppu1_open_bus = self.multiplication.result.wrapping_shr(8) as u8;
+                self.ppu1_open_bus
+            },
+            0x36 => {
+                self.ppu1_open_bus = self.multiplication.result.wrapping_shr(16) as u8;
+                self.ppu1_open_bus
+            },
             0x37 => {
-                // Store current H and V counter values if IO port latch is 'high'
-                if self.io_port.value() & PPU_LATCH_BIT != 0 {
-                    self.store_position();
-                }
-                0x00 // TODO: Open bus
-            },
-            0x38 => self.oam.read(),
-            0x39 => self.vram.read_low_byte(),
-            0x3A => self.vram.read_high_byte(),
-            0x3B => self.cgram.read(),
-            0x3C => self.stored_position.h.read(),
-            0x3D => self.stored_position.v.read(),
+                self.latch_counters();
+                0x00 // Open bus, filled in by Hardware
+            },
+            0x38 => {
+                self.ppu1_open_bus = self.oam.read();
+                self.ppu1_open_bus
+            },
+            0x39 => {
+                self.ppu2_open_bus = self.vram.read_low_byte();
+                self.ppu2_open_bus
+            },
+            0x3A => {
+                self.ppu2_open_bus = self.vram.read_high_byte();
+                self.ppu2_open_bus
+            },
+            0x3B => {
+                self.ppu2_open_bus = self.cgram.read_with_open_bus(self.ppu2_open_bus);
+                self.ppu2_open_bus
+            },
+            // Bits 1-7 of the upper byte are open bus
+            0x3C => {
+                self.ppu2_open_bus = self.stored_position.h.read_with_open_bus(self.ppu2_open_bus);
+                self.ppu2_open_bus
+            },
+            0x3D => {
+                self.ppu2_open_bus = self.stored_position.v.read_with_open_bus(self.ppu2_open_bus);
+                self.ppu2_open_bus
+            },
+            // $213E belongs to PPU1, so bit 4 is PPU1 open bus
             0x3E => {
                 // TODO: Time over flag
                 // TODO: Range over flag
-                CHIP_VERSION_5C77
+                self.ppu1_open_bus = CHIP_VERSION_5C77 | (self.ppu1_open_bus & 0x10);
+                self.ppu1_open_bus
             },
             0x3F => {
-                let mut value = 0x00;
+                // Bit 5 is open bus
+                let mut value = self.ppu2_open_bus & 0x20;
                 // TODO: Interlace field
                 if self.stored_position.stored {
                     value |= 0x40;
                 }
+                // Latch flag is only cleared while the IO port latch is 'high'
+                if self.counter_latch_enabled {
+                    self.stored_position.stored = false;
+                }
                 self.stored_position.h.reset_byte_selector();
                 self.stored_position.v.reset_byte_selector();
-                value | CHIP_VERSION_5C78
+                self.ppu2_open_bus = value | CHIP_VERSION_5C78;
+                self.ppu2_open_bus
             },
-            _ => 0x00 // TODO: Open bus
+            _ => 0x00 // Open bus, filled in by Hardware
         }
     }
 
     fn write(&mut self, offset: usize, value: u8) {
+        self.register_history.record(offset as u8, value);
+
+        if let Some(ref mut log) = self.register_log {
+            log.push(RegisterWrite {
+                v: self.position.v as u16,
+                h: self.position.h as u16,
+                register: offset as u8,
+                value: value
+            });
+        }
+
         match offset {
             0x00 => {
-                self.screen.set_brightness(((value & 0x0F) << 4) | 0x0F);
+                self.brightness = value & 0x0F;
+                self.frame_buffer.set_brightness((self.brightness << 4) | 0x0F);
                 self.force_blank = value & 0x80 != 0;
             },
             0x01 => self.object_layer.set_config(value),
@@ -319,34 +527,58 @@ impl HardwareBus for Ppu {
                 self.bg3.set_chr_offset(value & 0x0F);
                 self.bg4.set_chr_offset((value & 0xF0) >> 4);
             },
+            // BG1 scroll registers double as Mode 7 scroll registers, which
+            // use the Mode 7 latch instead
             0x0D => {
-                self.bg1.set_scroll_x(value);
-                self.mode_7.set_scroll_x(value);
+                self.bg1.set_scroll_x(value, &mut self.bg_latch);
+                self.mode_7.set_scroll_x(value, &mut self.mode_7_latch);
             },
             0x0E => {
-                self.bg1.set_scroll_y(value);
-                self.mode_7.set_scroll_y(value);
-            },
-            0x0F => self.bg2.set_scroll_x(value),
-            0x10 => self.bg2.set_scroll_y(value),
-            0x11 => self.bg3.set_scroll_x(value),
-            0x12 => self.bg3.set_scroll_y(value),
-            0x13 => self.bg4.set_scroll_x(value),
-            0x14 => self.bg4.set_scroll_y(value),
+                self.bg1.set_scroll_y(value, &mut self.bg_latch);
+                self.mode_7.set_scroll_y(value, &mut self.mode_7_latch);
+            },
+            0x0F => self.bg2.set_scroll_x(value, &mut self.bg_latch),
+            0x10 => self.bg2.set_scroll_y(value, &mut self.bg_latch),
+            0x11 => self.bg3.set_scroll_x(value, &mut self.bg_latch),
+            0x12 => self.bg3.set_scroll_y(value, &mut self.bg_latch),
+            0x13 => self.bg4.set_scroll_x(value, &mut self.bg_latch),
+            0x14 => self.bg4.set_scroll_y(value, &mut self.bg_latch),
             0x15 => self.vram.set_port_control(value),
             0x16 => self.vram.set_lower_address_byte(value),
             0x17 => self.vram.set_upper_address_byte(value),
-            0x18 => self.vram.write_low_byte(value),
-            0x19 => self.vram.write_high_byte(value),
-            0x1B => self.multiplication.lhs.write(value),
+            0x18 => {
+                if self.vram_accessible() {
+                    self.vram.write_low_byte(value);
+                } else {
+                    debug!("VRAM write ignored during active display");
+                    self.vram.skip_low_byte();
+                }
+            },
+            0x19 => {
+                if self.vram_accessible() {
+                    self.vram.write_high_byte(value);
+                } else {
+                    debug!("VRAM write ignored during active display");
+                    self.vram.skip_high_byte();
+                }
+            },
+            0x1A => self.mode_7.set_settings(value),
+            0x1B => self.multiplication.lhs = self.mode_7.set_matrix_a(value, &mut self.mode_7_latch),
             0x1C => {
+                self.mode_7.set_matrix_b(value, &mut self.mode_7_latch);
+
+                // Only the upper byte of M7B (the byte just written) is used.
                 // Multiplication is signed and result is only 24-bit, which complicates things...
-                let lhs = (self.multiplication.lhs.value() as i16) as i32;
+                let lhs = (self.multiplication.lhs as i16) as i32;
                 let rhs = (value as i8) as i32;
                 let result = (lhs * rhs) as u32;
                 // Drag the sign bit to the right so it sits at bit 23
                 self.multiplication.result = ((result & 0x80000000) >> 8) | (result & 0x007FFFFF);
             },
+            0x1D => self.mode_7.set_matrix_c(value, &mut self.mode_7_latch),
+            0x1E => self.mode_7.set_matrix_d(value, &mut self.mode_7_latch),
+            0x1F => self.mode_7.set_center_x(value, &mut self.mode_7_latch),
+            0x20 => self.mode_7.set_center_y(value, &mut self.mode_7_latch),
             0x21 => self.cgram.set_address(value),
             0x22 => self.cgram.write(value),
             0x23 => {
@@ -403,11 +635,17 @@ impl HardwareBus for Ppu {
             0x33 => {
                 // TODO: Mode 7 EXTBG
                 // TODO: Pseudo-hi-res mode
-                self.screen.set_overscan(value & 0x04 != 0);
+                self.frame_buffer.set_overscan(value & 0x04 != 0);
                 // TODO: Interlace settings
             },
             _ => ()
         }
+
+        // Each layer's combined window mask is rebuilt here rather than
+        // worked out for every pixel, as these registers rarely change
+        if offset >= 0x23 && offset <= 0x2B {
+            self.update_window_masks();
+        }
     }
 }
 
@@ -420,3 +658,174 @@ impl Position {
         self.v as u16
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use hardware::{Bus, HardwareAddress, RomBuilder};
+    use hardware::hardware::HardwareBus;
+    use std::env;
+    use std::fs;
+    use std::process;
+    use super::{Ppu, RegisterLog};
+
+    // Sets CGRAM entry 0 to $7FFF and leaves the address pointing at it
+    fn ppu_with_white_color() -> Ppu {
+        let mut ppu = Ppu::new();
+        ppu.write(0x21, 0x00);
+        ppu.write(0x22, 0xFF);
+        ppu.write(0x22, 0x7F);
+        ppu.write(0x21, 0x00);
+        ppu
+    }
+
+    #[test]
+    fn cgram_upper_byte_uses_open_bus() {
+        let mut ppu = ppu_with_white_color();
+        assert_eq!(ppu.read(0x3B), 0xFF);
+        assert_eq!(ppu.read(0x3B), 0xFF);
+
+        // A VRAM read of $00 in between the two bytes clears bit 7
+        ppu.write(0x21, 0x00);
+        ppu.read(0x3B);
+        ppu.read(0x3A);
+        assert_eq!(ppu.read(0x3B), 0x7F);
+    }
+
+    #[test]
+    fn vram_reads_update_open_bus() {
+        let mut ppu = Ppu::new();
+        ppu.write(0x15, 0x80);
+        ppu.write(0x16, 0x00);
+        ppu.write(0x17, 0x00);
+        ppu.write(0x18, 0x00);
+        ppu.write(0x19, 0x20);
+        ppu.write(0x16, 0x00);
+        ppu.write(0x17, 0x00);
+
+        // Bit 5 of $213F is open bus
+        ppu.read(0x39);
+        assert_eq!(ppu.read(0x3F) & 0x20, 0x00);
+        ppu.read(0x3A);
+        assert_eq!(ppu.read(0x3F) & 0x20, 0x20);
+    }
+
+    // Sets the multiplication result to the given byte
+    fn multiply(ppu: &mut Ppu, value: u8) {
+        ppu.write(0x1B, value);
+        ppu.write(0x1B, 0x00);
+        ppu.write(0x1C, 0x01);
+    }
+
+    // Runs until the start of the next VBlank
+    fn run_frame(ppu: &mut Ppu) {
+        let frame_count = ppu.frame_count();
+
+        while ppu.frame_count() == frame_count {
+            ppu.step_dot();
+        }
+    }
+
+    #[test]
+    fn replayed_frame_matches_recording() {
+        let mut ppu = Ppu::new();
+
+        // Fill VRAM and CGRAM with a pattern during forced blank, then show
+        // BG1 in mode 1
+        ppu.write(0x00, 0x80);
+        ppu.write(0x15, 0x80);
+        ppu.write(0x16, 0x00);
+        ppu.write(0x17, 0x00);
+
+        for index in 0..0x8000 {
+            ppu.write(0x18, (index * 7) as u8);
+            ppu.write(0x19, (index / 3) as u8);
+        }
+
+        ppu.write(0x21, 0x00);
+
+        for index in 0..0x200 {
+            ppu.write(0x22, (index * 13) as u8);
+        }
+
+        ppu.write(0x05, 0x01);
+        ppu.write(0x07, 0x7C);
+        ppu.write(0x0B, 0x00);
+        ppu.write(0x2C, 0x01);
+
+        // The scroll registers share a latch, so the recording has to carry
+        // the latch state as well as the values
+        ppu.write(0x0D, 0x34);
+        ppu.write(0x0D, 0x01);
+        ppu.write(0x0E, 0x20);
+        ppu.write(0x0E, 0x00);
+        ppu.write(0x00, 0x0F);
+
+        let frame = ppu.frame_count() + 1;
+        ppu.record_frame(frame);
+        run_frame(&mut ppu);
+
+        // Change the scroll part way down the recorded frame
+        while ppu.position.v != 100 {
+            ppu.step_dot();
+        }
+
+        ppu.write(0x0D, 0x05);
+        ppu.write(0x0D, 0x00);
+        run_frame(&mut ppu);
+
+        let log = ppu.take_register_log().unwrap();
+        let expected = ppu.frame_buffer().hash();
+
+        let path = env::temp_dir().join(format!("snailemu-{}-replay.log", process::id()));
+        log.save(&path);
+        let loaded = RegisterLog::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        let mut replayed = Ppu::new();
+        replayed.replay(&loaded).unwrap();
+
+        assert_eq!(replayed.frame_buffer().hash(), expected);
+    }
+
+    #[test]
+    fn status_reads_update_open_bus() {
+        let mut ppu = ppu_with_white_color();
+
+        // $213F drives bit 5 from PPU2 open bus, so reads back what it returned
+        ppu.read(0x3B);
+        let status = ppu.read(0x3F);
+        assert_eq!(status & 0x20, 0x20);
+        assert_eq!(ppu.read(0x3F) & 0x20, 0x20);
+
+        // $213E belongs to PPU1, so leaves PPU2 open bus alone
+        ppu.read(0x3E);
+        assert_eq!(ppu.read(0x3F) & 0x20, 0x20);
+
+        // ...and drives bit 4 from PPU1 open bus instead
+        multiply(&mut ppu, 0x10);
+        ppu.read(0x34);
+        assert_eq!(ppu.read(0x3E) & 0x10, 0x10);
+        assert_eq!(ppu.read(0x3E) & 0x10, 0x10);
+        multiply(&mut ppu, 0x00);
+        ppu.read(0x34);
+        assert_eq!(ppu.read(0x3E) & 0x10, 0x00);
+    }
+
+    #[test]
+    fn status_open_bus_bits_survive_bus_reads() {
+        let mut hardware = RomBuilder::new().build_hardware();
+
+        // Set PPU1 and PPU2 open bus, then clear the CPU's MDR
+        multiply(hardware.ppu_mut(), 0x10);
+        hardware.read::<u8>(HardwareAddress::new(0x00, 0x2134));
+        hardware.write::<u8>(HardwareAddress::new(0x00, 0x2121), 0x00);
+        hardware.write::<u8>(HardwareAddress::new(0x00, 0x2122), 0xFF);
+        hardware.write::<u8>(HardwareAddress::new(0x00, 0x2122), 0x7F);
+        hardware.write::<u8>(HardwareAddress::new(0x00, 0x2121), 0x00);
+        hardware.read::<u8>(HardwareAddress::new(0x00, 0x213B));
+        hardware.write::<u8>(HardwareAddress::new(0x7E, 0x0000), 0x00);
+
+        assert_eq!(hardware.read::<u8>(HardwareAddress::new(0x00, 0x213E)) & 0x10, 0x10);
+        assert_eq!(hardware.read::<u8>(HardwareAddress::new(0x00, 0x213F)) & 0x20, 0x20);
+    }
+}
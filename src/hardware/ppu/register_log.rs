@@ -0,0 +1,154 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+const REGISTER_COUNT: usize = 0x40;
+
+// Data ports are restored from memory contents rather than from history
+const DATA_PORTS: [u8; 4] = [0x04, 0x18, 0x19, 0x22];
+
+// Scroll and Mode 7 registers take two writes to set a value
+const WRITE_TWICE: [u8; 14] = [
+    0x0D, 0x0E, 0x0F, 0x10, 0x11, 0x12, 0x13, 0x14,
+    0x1B, 0x1C, 0x1D, 0x1E, 0x1F, 0x20
+];
+
+#[derive(Copy, Clone)]
+pub struct RegisterWrite {
+    pub v: u16,
+    pub h: u16,
+    pub register: u8,
+    pub value: u8
+}
+
+// The last two values written to each register, so a recording can restore
+// register state from before the frame it covers
+pub struct RegisterHistory {
+    previous: [u8; REGISTER_COUNT],
+    last: [u8; REGISTER_COUNT],
+    written: [bool; REGISTER_COUNT]
+}
+
+// A record of every PPU register write over one frame, tagged with the dot
+// it happened on. Saved as text, one write per line: "V H 21RR VV", with
+// V and H in decimal and the register and value in hex.
+pub struct RegisterLog {
+    writes: Vec<RegisterWrite>
+}
+
+impl RegisterHistory {
+    pub fn new() -> RegisterHistory {
+        RegisterHistory {
+            previous: [0; REGISTER_COUNT],
+            last: [0; REGISTER_COUNT],
+            written: [false; REGISTER_COUNT]
+        }
+    }
+
+    pub fn record(&mut self, register: u8, value: u8) {
+        let index = register as usize % REGISTER_COUNT;
+        self.previous[index] = self.last[index];
+        self.last[index] = value;
+        self.written[index] = true;
+    }
+
+    // Writes that bring a fresh PPU's registers up to date. INIDISP ($2100)
+    // is left to the caller, as it has to come after the memory uploads.
+    pub fn restore(&self) -> Vec<(u8, u8)> {
+        let mut writes = Vec::new();
+
+        for register in 0x01..(REGISTER_COUNT as u8) {
+            let index = register as usize;
+
+            if !self.written[index] || DATA_PORTS.contains(&register) {
+                continue;
+            }
+
+            if WRITE_TWICE.contains(&register) {
+                writes.push((register, self.previous[index]));
+            }
+
+            writes.push((register, self.last[index]));
+        }
+
+        writes
+    }
+
+    pub fn last(&self, register: u8) -> Option<u8> {
+        let index = register as usize % REGISTER_COUNT;
+
+        if self.written[index] {
+            Some(self.last[index])
+        } else {
+            None
+        }
+    }
+}
+
+impl RegisterLog {
+    pub fn new() -> RegisterLog {
+        RegisterLog {
+            writes: Vec::new()
+        }
+    }
+
+    pub fn load(path: &Path) -> RegisterLog {
+        let file = File::open(path).unwrap();
+        let mut log = RegisterLog::new();
+
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.unwrap();
+            let line = line.splitn(2, '#').next().unwrap().trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+
+            let write = if fields.len() == 4 {
+                match (fields[0].parse::<u16>(),
+                    fields[1].parse::<u16>(),
+                    u16::from_str_radix(fields[2], 16),
+                    u8::from_str_radix(fields[3], 16))
+                {
+                    (Ok(v), Ok(h), Ok(register), Ok(value)) if register & 0xFFC0 == 0x2100 => {
+                        Some(RegisterWrite { v: v, h: h, register: register as u8 & 0x3F, value: value })
+                    },
+                    _ => None
+                }
+            } else {
+                None
+            };
+
+            match write {
+                Some(write) => log.push(write),
+                None => panic!("Invalid register write on line {}: {}", index + 1, line)
+            }
+        }
+
+        info!("{} PPU register writes loaded", log.writes.len());
+
+        log
+    }
+
+    pub fn save(&self, path: &Path) {
+        let mut file = BufWriter::new(File::create(path).unwrap());
+
+        for write in self.writes.iter() {
+            writeln!(file, "{} {} {:04X} {:02X}",
+                write.v,
+                write.h,
+                0x2100 | write.register as u16,
+                write.value).unwrap();
+        }
+    }
+
+    pub fn push(&mut self, write: RegisterWrite) {
+        self.writes.push(write);
+    }
+
+    pub fn writes(&self) -> &[RegisterWrite] {
+        &self.writes
+    }
+}
@@ -1,5 +1,6 @@
 use super::background_mode::Priority;
 use util::byte_access::ByteAccess;
+use util::fill_pattern::FillPattern;
 
 pub const TILE_MAP_COUNT: usize = VRAM_BYTE_SIZE / TILE_MAP_SIZE;
 
@@ -102,6 +103,35 @@ impl Vram {
         }
     }
     
+    pub fn fill(&mut self, pattern: FillPattern) {
+        let mut bytes = vec![0; VRAM_BYTE_SIZE];
+        pattern.fill(&mut bytes);
+
+        for (byte_address, &value) in bytes.iter().enumerate() {
+            if byte_address % 2 == 0 {
+                self.raw_data[byte_address >> 1].set_lower(value);
+            } else {
+                self.raw_data[byte_address >> 1].set_upper(value);
+            }
+            self.update_cache(byte_address, value);
+        }
+    }
+
+    pub fn bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(VRAM_BYTE_SIZE);
+
+        for word in self.raw_data.iter() {
+            bytes.push(word.lower());
+            bytes.push(word.upper());
+        }
+
+        bytes
+    }
+
+    pub fn address(&self) -> usize {
+        self.address
+    }
+
     pub fn set_port_control(&mut self, value: u8) {
         self.remap_mode = match value & 0x0C {
             0x00 => RemapMode::NoRemap,
@@ -123,20 +153,26 @@ impl Vram {
         };
     }
 
+    // Setting the address also fills the read buffer from the new address, so
+    // the first read after it returns valid data
     pub fn set_lower_address_byte(&mut self, value: u8) {
         self.address = (self.address & 0xFF00) | (value as usize);
+        self.prefetch();
     }
 
     pub fn set_upper_address_byte(&mut self, value: u8) {
         self.address = (self.address & 0x00FF) | ((value as usize) << 8);
+        self.prefetch();
     }
 
+    // Reads return the buffered word, which is only refilled (from the address
+    // before it increments) when the incrementing byte is read. Writes never
+    // touch the buffer, so it can go stale.
     pub fn read_low_byte(&mut self) -> u8 {
         let value = self.read_buffer.lower();
         if self.increment_mode == IncrementMode::LowByte {
-            let mapped_address = self.mapped_address();
-            self.read_buffer = self.raw_data[mapped_address];
-            self.address += self.increment_amount;
+            self.prefetch();
+            self.increment_address();
         }
         value
     }
@@ -144,9 +180,8 @@ impl Vram {
     pub fn read_high_byte(&mut self) -> u8 {
         let value = self.read_buffer.upper();
         if self.increment_mode == IncrementMode::HighByte {
-            let mapped_address = self.mapped_address();
-            self.read_buffer = self.raw_data[mapped_address];
-            self.address += self.increment_amount;
+            self.prefetch();
+            self.increment_address();
         }
         value
     }
@@ -157,7 +192,7 @@ impl Vram {
         self.raw_data[mapped_address].set_lower(value);
         self.update_cache(mapped_address << 1, value);
         if self.increment_mode == IncrementMode::LowByte {
-            self.address += self.increment_amount;
+            self.increment_address();
         }
     }
 
@@ -167,7 +202,20 @@ impl Vram {
         self.raw_data[mapped_address].set_upper(value);
         self.update_cache((mapped_address << 1) + 1, value);
         if self.increment_mode == IncrementMode::HighByte {
-            self.address += self.increment_amount;
+            self.increment_address();
+        }
+    }
+
+    // Address still increments when a write is blocked
+    pub fn skip_low_byte(&mut self) {
+        if self.increment_mode == IncrementMode::LowByte {
+            self.increment_address();
+        }
+    }
+
+    pub fn skip_high_byte(&mut self) {
+        if self.increment_mode == IncrementMode::HighByte {
+            self.increment_address();
         }
     }
 
@@ -192,6 +240,19 @@ impl Vram {
         &self.mode_7_chr_map[self.mode_7_tile_map[y * MODE_7_TILE_MAP_ROW_WIDTH + x]]
     }
 
+    pub fn mode_7_chr(&self, index: usize) -> &Character {
+        &self.mode_7_chr_map[index % MODE_7_CHR_COUNT]
+    }
+
+    fn prefetch(&mut self) {
+        let mapped_address = self.mapped_address();
+        self.read_buffer = self.raw_data[mapped_address];
+    }
+
+    fn increment_address(&mut self) {
+        self.address = (self.address + self.increment_amount) & 0xFFFF;
+    }
+
     fn mapped_address(&self) -> usize {
         let mapped_address = match self.remap_mode {
             RemapMode::NoRemap => self.address,
@@ -291,3 +352,104 @@ impl Character {
         self.pixels[y][x]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Vram;
+
+    const INCREMENT_ON_LOW_BYTE: u8 = 0x00;
+    const INCREMENT_ON_HIGH_BYTE: u8 = 0x80;
+
+    // Sets the word address through $2116/$2117
+    fn set_address(vram: &mut Vram, address: u16) {
+        vram.set_lower_address_byte(address as u8);
+        vram.set_upper_address_byte((address >> 8) as u8);
+    }
+
+    fn write_word(vram: &mut Vram, value: u16) {
+        vram.write_low_byte(value as u8);
+        vram.write_high_byte((value >> 8) as u8);
+    }
+
+    fn read_word(vram: &mut Vram) -> u16 {
+        let lower = vram.read_low_byte() as u16;
+        let upper = vram.read_high_byte() as u16;
+        (upper << 8) | lower
+    }
+
+    fn vram_with_words(words: &[u16]) -> Vram {
+        let mut vram = Vram::new();
+        vram.set_port_control(INCREMENT_ON_HIGH_BYTE);
+        set_address(&mut vram, 0x0000);
+
+        for &word in words {
+            write_word(&mut vram, word);
+        }
+
+        vram
+    }
+
+    #[test]
+    fn address_write_prefetches_word() {
+        let mut vram = vram_with_words(&[0x1234, 0x5678, 0x9ABC]);
+
+        set_address(&mut vram, 0x0001);
+
+        // The buffer is refilled from the address before it increments, so
+        // the first word read is repeated and needs a dummy read to skip
+        assert_eq!(read_word(&mut vram), 0x5678);
+        assert_eq!(read_word(&mut vram), 0x5678);
+        assert_eq!(read_word(&mut vram), 0x9ABC);
+        assert_eq!(vram.address(), 0x0004);
+    }
+
+    #[test]
+    fn writes_leave_read_buffer_stale() {
+        let mut vram = vram_with_words(&[0x1234, 0x5678]);
+
+        set_address(&mut vram, 0x0000);
+        write_word(&mut vram, 0xAAAA);
+        write_word(&mut vram, 0xBBBB);
+
+        assert_eq!(read_word(&mut vram), 0x1234);
+        assert_eq!(vram.bytes()[0..4], [0xAA, 0xAA, 0xBB, 0xBB]);
+    }
+
+    #[test]
+    fn buffer_refilled_only_on_incrementing_byte() {
+        let mut vram = vram_with_words(&[0x1234, 0x5678]);
+
+        vram.set_port_control(INCREMENT_ON_LOW_BYTE);
+        set_address(&mut vram, 0x0000);
+
+        assert_eq!(vram.read_high_byte(), 0x12);
+        assert_eq!(vram.read_high_byte(), 0x12);
+        assert_eq!(vram.address(), 0x0000);
+
+        assert_eq!(vram.read_low_byte(), 0x34);
+        assert_eq!(vram.address(), 0x0001);
+        assert_eq!(vram.read_low_byte(), 0x34);
+        assert_eq!(vram.read_high_byte(), 0x56);
+        assert_eq!(vram.address(), 0x0002);
+    }
+
+    #[test]
+    fn address_wraps_at_16_bits() {
+        let mut vram = Vram::new();
+
+        vram.set_port_control(INCREMENT_ON_HIGH_BYTE);
+        set_address(&mut vram, 0xFFFF);
+        write_word(&mut vram, 0x1234);
+
+        assert_eq!(vram.address(), 0x0000);
+        // Only 32K words exist, so $FFFF is the same word as $7FFF
+        assert_eq!(vram.bytes()[0xFFFE..0x10000], [0x34, 0x12]);
+
+        // Increment by 128
+        vram.set_port_control(INCREMENT_ON_HIGH_BYTE | 0x03);
+        set_address(&mut vram, 0xFF80);
+        read_word(&mut vram);
+
+        assert_eq!(vram.address(), 0x0000);
+    }
+}
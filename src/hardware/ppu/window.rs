@@ -1,16 +1,23 @@
-use super::ppu::Ppu;
+const WINDOW_WIDTH: usize = 256;
+const MASK_WORD_BITS: usize = 64;
+const MASK_WORD_COUNT: usize = WINDOW_WIDTH / MASK_WORD_BITS;
 
 pub struct Window {
     left: usize,
-    right: usize
+    right: usize,
+    mask: [u64; MASK_WORD_COUNT]
 }
 
+// A layer's view of the two windows. The combined mask is rebuilt by update
+// (whenever the options or either window change), so rendering only has to
+// test a bit per pixel.
 pub struct WindowMask {
     w1_enabled: bool,
     w1_inverted: bool,
     w2_enabled: bool,
     w2_inverted: bool,
-    operator: WindowMaskOperator
+    operator: WindowMaskOperator,
+    mask: [u64; MASK_WORD_COUNT]
 }
 
 enum WindowMaskOperator {
@@ -21,7 +28,7 @@ enum WindowMaskOperator {
 }
 
 #[inline]
-fn invert(value: bool, inverted: bool) -> bool {
+fn invert(value: u64, inverted: bool) -> u64 {
     if inverted {
         !value
     } else {
@@ -31,24 +38,40 @@ fn invert(value: bool, inverted: bool) -> bool {
 
 impl Window {
     pub fn new() -> Window {
-        Window {
+        let mut window = Window {
             left: 0,
-            right: 0
-        }
+            right: 0,
+            mask: [0; MASK_WORD_COUNT]
+        };
+        window.update_mask();
+        window
     }
 
     pub fn set_left(&mut self, value: u8) {
         debug!("Window Left: {:02X}", value);
         self.left = value as usize;
+        self.update_mask();
     }
 
     pub fn set_right(&mut self, value: u8) {
         debug!("Window Right: {:02X}", value);
         self.right = value as usize;
+        self.update_mask();
     }
 
-    pub fn contains(&self, x: usize) -> bool {
-        x >= self.left && x < self.right
+    fn update_mask(&mut self) {
+        // Both edges are inclusive, and the window is empty if left > right
+        for (index, word) in self.mask.iter_mut().enumerate() {
+            *word = 0;
+
+            for bit in 0..MASK_WORD_BITS {
+                let x = index * MASK_WORD_BITS + bit;
+
+                if x >= self.left && x <= self.right {
+                    *word |= 1 << bit;
+                }
+            }
+        }
     }
 }
 
@@ -59,7 +82,8 @@ impl WindowMask {
             w1_inverted: false,
             w2_enabled: false,
             w2_inverted: false,
-            operator: WindowMaskOperator::Or
+            operator: WindowMaskOperator::Or,
+            mask: [0; MASK_WORD_COUNT]
         }
     }
 
@@ -80,21 +104,83 @@ impl WindowMask {
         };
     }
 
-    pub fn contains(&self, ppu: &Ppu, x: usize) -> bool {
-        match (self.w1_enabled, self.w2_enabled) {
-            (false, false) => false,
-            (true, false) => invert(ppu.window1().contains(x), self.w1_inverted),
-            (false, true) => invert(ppu.window2().contains(x), self.w2_inverted),
-            (true, true) => {
-                let w1 = invert(ppu.window1().contains(x), self.w1_inverted);
-                let w2 = invert(ppu.window2().contains(x), self.w2_inverted);
-                match self.operator {
-                    WindowMaskOperator::Or => w1 || w2,
-                    WindowMaskOperator::And => w1 && w2,
-                    WindowMaskOperator::Xor => (w1 && !w2) || (!w1 && w2),
-                    WindowMaskOperator::Xnor => (w1 && w2) || (!w1 && !w2)
+    pub fn update(&mut self, window1: &Window, window2: &Window) {
+        for (index, word) in self.mask.iter_mut().enumerate() {
+            let w1 = invert(window1.mask[index], self.w1_inverted);
+            let w2 = invert(window2.mask[index], self.w2_inverted);
+
+            *word = match (self.w1_enabled, self.w2_enabled) {
+                (false, false) => 0,
+                (true, false) => w1,
+                (false, true) => w2,
+                (true, true) => match self.operator {
+                    WindowMaskOperator::Or => w1 | w2,
+                    WindowMaskOperator::And => w1 & w2,
+                    WindowMaskOperator::Xor => w1 ^ w2,
+                    WindowMaskOperator::Xnor => !(w1 ^ w2)
                 }
-            }
+            };
         }
     }
+
+    pub fn contains(&self, x: usize) -> bool {
+        x < WINDOW_WIDTH && self.mask[x / MASK_WORD_BITS] & (1 << (x % MASK_WORD_BITS)) != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Window, WindowMask};
+
+    fn window(left: u8, right: u8) -> Window {
+        let mut window = Window::new();
+        window.set_left(left);
+        window.set_right(right);
+        window
+    }
+
+    // A mask that uses window 1 alone
+    fn window_1_mask(left: u8, right: u8) -> WindowMask {
+        let mut mask = WindowMask::new();
+        mask.set_options(0x02);
+        mask.update(&window(left, right), &Window::new());
+        mask
+    }
+
+    #[test]
+    fn right_edge_is_inclusive() {
+        let mask = window_1_mask(0x10, 0x20);
+        assert!(!mask.contains(0x0F));
+        assert!(mask.contains(0x10));
+        assert!(mask.contains(0x20));
+        assert!(!mask.contains(0x21));
+    }
+
+    #[test]
+    fn left_past_right_is_empty() {
+        let mask = window_1_mask(0x80, 0x7F);
+        assert!((0..256).all(|x| !mask.contains(x)));
+    }
+
+    #[test]
+    fn covers_last_pixel() {
+        let mask = window_1_mask(0xF0, 0xFF);
+        assert!(mask.contains(255));
+        assert!(!mask.contains(256));
+    }
+
+    #[test]
+    fn combines_windows() {
+        // Both enabled, window 2 inverted, combined with AND
+        let mut mask = WindowMask::new();
+        mask.set_options(0x0E);
+        mask.set_operator(0x01);
+        mask.update(&window(0x10, 0x2F), &window(0x20, 0x3F));
+
+        assert!(!mask.contains(0x0F));
+        assert!(mask.contains(0x10));
+        assert!(mask.contains(0x1F));
+        assert!(!mask.contains(0x20));
+        assert!(!mask.contains(0xFF));
+    }
 }
@@ -0,0 +1,42 @@
+use std::mem;
+use std::time::{Duration, Instant};
+
+// Host time spent in each emulated subsystem since the profile was last
+// taken. Time not accounted for here is spent in the CPU or the frontend.
+#[derive(Default)]
+pub struct Profile {
+    pub ppu: Duration,
+    pub coprocessor: Duration
+}
+
+#[derive(Copy, Clone)]
+pub enum Subsystem {
+    Ppu,
+    Coprocessor
+}
+
+// Timing every tick isn't free, so this only exists while profiling
+pub struct Profiler {
+    profile: Profile
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            profile: Profile::default()
+        }
+    }
+
+    pub fn add(&mut self, subsystem: Subsystem, start: Instant) {
+        let elapsed = start.elapsed();
+
+        match subsystem {
+            Subsystem::Ppu => self.profile.ppu += elapsed,
+            Subsystem::Coprocessor => self.profile.coprocessor += elapsed
+        }
+    }
+
+    pub fn take_profile(&mut self) -> Profile {
+        mem::replace(&mut self.profile, Profile::default())
+    }
+}
@@ -1,16 +1,17 @@
-use std::rc::Rc;
 use super::hardware::HardwareBus;
 use super::io_port::IoPort;
 use super::joypad::{Joypad, JOYPAD_COUNT};
 use super::ppu::Ppu;
 use util::byte_access::ByteAccess;
+use util::json::JsonObject;
 
 const CHIP_VERSION: u8 = 0x02;
 
-const JOYPAD_AUTO_READ_LINES: u8 = 3;
+// Auto read takes 4224 master cycles, at 4 master cycles per dot
+const JOYPAD_AUTO_READ_DOTS: u16 = 1056;
 
 pub struct HardwareRegs {
-    io_port: Rc<IoPort>,
+    io_port: IoPort,
     cpu_action: CpuAction,
     vblank: bool,
     hblank: bool,
@@ -18,28 +19,34 @@ pub struct HardwareRegs {
     irq: IrqRegs,
     multiplication: MultiplicationRegs,
     division: DivisionRegs,
+    alu: Alu,
     joypad: JoypadRegs,
-    dma_channel_mask: u8
+    dma_channel_mask: u8,
+    hdma_channel_mask: u8
 }
 
 bitflags! {
     flags CpuAction: u8 {
         const NMI = 0x80,
-        const IRQ = 0x40,
         const DMA = 0x20
     }
 }
 
+// The NMI output is the $4210 flag ANDed with the $4200 enable bit. The CPU
+// latches an NMI on its rising edge, so it fires at most once per VBlank.
 struct NmiRegs {
     enabled: bool,
-    active: bool
+    active: bool,
+    line: bool,
+    fired: bool
 }
 
 struct IrqRegs {
     enabled: IrqCondition,
     row: u16,
     column: u16,
-    active: bool
+    active: bool,
+    triggered: bool
 }
 
 #[derive(Copy, Clone, Eq, PartialEq)]
@@ -60,28 +67,42 @@ struct DivisionRegs {
     result: u16
 }
 
+// Multiplication and division are performed one bit per CPU cycle, so the
+// result registers hold partial results until the operation completes
+struct Alu {
+    multiply_steps: u8,
+    divide_steps: u8,
+    shift: u32,
+    started: bool
+}
+
 struct JoypadRegs {
     auto_read_enabled: bool,
-    auto_read_active: u8,
-    button_state: [u16; JOYPAD_COUNT]
+    auto_read_active: u16,
+    button_state: [u16; JOYPAD_COUNT],
+    polled: bool,
+    lag_frames: u64
 }
 
 impl HardwareRegs {
-    pub fn new(io_port: Rc<IoPort>) -> HardwareRegs {
+    pub fn new() -> HardwareRegs {
         HardwareRegs {
-            io_port: io_port,
+            io_port: IoPort::new(),
             cpu_action: CpuAction::empty(),
             vblank: false,
             hblank: false,
             nmi: NmiRegs {
                 enabled: false,
-                active: false
+                active: false,
+                line: false,
+                fired: false
             },
             irq: IrqRegs {
                 enabled: IrqCondition::Never,
                 row: 0,
                 column: 0,
-                active: false
+                active: false,
+                triggered: false
             },
             multiplication: MultiplicationRegs {
                 lhs: 0xFF,
@@ -91,16 +112,39 @@ impl HardwareRegs {
                 lhs: 0xFFFF,
                 result: 0x0000
             },
+            alu: Alu {
+                multiply_steps: 0,
+                divide_steps: 0,
+                shift: 0,
+                started: false
+            },
             joypad: JoypadRegs {
                 auto_read_enabled: false,
                 auto_read_active: 0,
-                button_state: [0; JOYPAD_COUNT]
+                button_state: [0; JOYPAD_COUNT],
+                polled: false,
+                lag_frames: 0
             },
-            dma_channel_mask: 0x00
+            dma_channel_mask: 0x00,
+            hdma_channel_mask: 0x00
         }
     }
 
-    pub fn update(&mut self, ppu: &mut Ppu, joypad: &Joypad) {
+    // Everything goes back to its power-on value, except the blanking state
+    // (which follows the PPU) and the lag frame count
+    pub fn reset(&mut self) {
+        let vblank = self.vblank;
+        let hblank = self.hblank;
+        let lag_frames = self.joypad.lag_frames;
+
+        *self = HardwareRegs::new();
+
+        self.vblank = vblank;
+        self.hblank = hblank;
+        self.joypad.lag_frames = lag_frames;
+    }
+
+    pub fn update(&mut self, ppu: &mut Ppu, joypad: &mut Joypad) {
         let old_vblank = self.vblank;
 
         self.vblank = ppu.vblank();
@@ -111,19 +155,32 @@ impl HardwareRegs {
             self.nmi.active = self.vblank;
 
             if self.nmi.active {
-                // Start of VBlank
-                if self.nmi.enabled {
-                    self.cpu_action.insert(NMI);
+                self.nmi.fired = false;
+            }
+
+            self.update_nmi_line();
+
+            if self.nmi.active {
+                // Start of VBlank. A frame in which the game never looked at the controllers is a lag frame.
+                // Always take the serial read flag, so a read this frame can't leak into the next.
+                let serial_read = joypad.take_serial_read();
+
+                if !self.joypad.polled && !serial_read {
+                    self.joypad.lag_frames += 1;
                 }
 
+                self.joypad.polled = false;
+
                 if self.joypad.auto_read_enabled {
-                    self.joypad.auto_read_active = JOYPAD_AUTO_READ_LINES;
-                    self.joypad.button_state = joypad.read_button_state();
-                    debug!("Joypad auto read: {:04X}", self.joypad.button_state[0]);
+                    // Ask the frontend for fresh input now; it is latched when the read completes
+                    self.joypad.auto_read_active = JOYPAD_AUTO_READ_DOTS;
+                    joypad.request_poll();
                 }
             }
         }
 
+        self.irq.triggered = false;
+
         if self.irq.enabled != IrqCondition::Never && !self.irq.active {
             let position = ppu.position();
 
@@ -142,22 +199,79 @@ impl HardwareRegs {
 
             if timer_condition {
                 self.irq.active = true;
-                self.cpu_action.insert(IRQ);
+                self.irq.triggered = true;
             }
         }
 
         if self.joypad.auto_read_active > 0 {
             self.joypad.auto_read_active -= 1;
+
+            if self.joypad.auto_read_active == 0 {
+                self.joypad.button_state = joypad.auto_read();
+                debug!("Joypad auto read: {:04X}", self.joypad.button_state[0]);
+            }
         }
 
-        if self.io_port.triggered() {
-            ppu.store_position();
-            self.io_port.reset_trigger();
+        ppu.set_counter_latch_enabled(self.io_port.latch_enabled());
+    }
+
+    // True once after bit 7 of $4201 goes from 1 to 0
+    pub fn take_io_port_latch(&mut self) -> bool {
+        let triggered = self.io_port.triggered();
+        self.io_port.reset_trigger();
+        triggered
+    }
+
+    pub fn state_json(&self) -> JsonObject {
+        let irq_condition = match self.irq.enabled {
+            IrqCondition::Never => 0u8,
+            IrqCondition::MatchColumn => 1,
+            IrqCondition::MatchRow => 2,
+            IrqCondition::MatchRowAndColumn => 3
+        };
+
+        let mut joypads = Vec::new();
+
+        for &button_state in self.joypad.button_state.iter() {
+            let mut joypad = JsonObject::new();
+            joypad.number("button_state", button_state);
+            joypads.push(joypad);
         }
+
+        let mut state = JsonObject::new();
+        state.number("io_port", self.io_port.value());
+        state.boolean("nmi_enabled", self.nmi.enabled);
+        state.boolean("nmi_active", self.nmi.active);
+        state.boolean("nmi_line", self.nmi.line);
+        state.number("irq_condition", irq_condition);
+        state.number("irq_row", self.irq.row);
+        state.number("irq_column", self.irq.column);
+        state.boolean("irq_active", self.irq.active);
+        state.number("multiplication_lhs", self.multiplication.lhs);
+        state.number("multiplication_result", self.multiplication.result);
+        state.number("division_lhs", self.division.lhs);
+        state.number("division_result", self.division.result);
+        state.boolean("auto_read_enabled", self.joypad.auto_read_enabled);
+        state.number("auto_read_active", self.joypad.auto_read_active);
+        state.array("joypads", joypads);
+        state.number("dma_channel_mask", self.dma_channel_mask);
+        state.number("hdma_channel_mask", self.hdma_channel_mask);
+        state
     }
 
-    pub fn cpu_action_ready(&self) -> bool {
-        !self.cpu_action.is_empty()
+    pub fn lag_frames(&self) -> u64 {
+        self.joypad.lag_frames
+    }
+
+    fn update_nmi_line(&mut self) {
+        let line = self.nmi.enabled && self.nmi.active;
+
+        if line && !self.nmi.line && !self.nmi.fired {
+            self.cpu_action.insert(NMI);
+            self.nmi.fired = true;
+        }
+
+        self.nmi.line = line;
     }
 
     pub fn check_and_reset_nmi(&mut self) -> bool {
@@ -169,15 +283,51 @@ impl HardwareRegs {
         }
     }
 
-    pub fn check_and_reset_irq(&mut self) -> bool {
-        if self.cpu_action.contains(IRQ) {
-            self.cpu_action.remove(IRQ);
-            true
-        } else {
-            false
+    // Called once per CPU cycle. The cycle that starts an operation doesn't
+    // count, so results are ready 8 (multiply) or 16 (divide) cycles later.
+    pub fn step_alu(&mut self) {
+        if self.alu.started {
+            self.alu.started = false;
+            return;
+        }
+
+        if self.alu.multiply_steps > 0 {
+            self.alu.multiply_steps -= 1;
+
+            if (self.division.result & 0x0001) != 0 {
+                self.multiplication.result = self.multiplication.result.wrapping_add(self.alu.shift as u16);
+            }
+
+            self.division.result >>= 1;
+            self.alu.shift <<= 1;
+        }
+
+        if self.alu.divide_steps > 0 {
+            self.alu.divide_steps -= 1;
+            self.division.result <<= 1;
+            self.alu.shift >>= 1;
+
+            // Multiplication result is used to store remainder
+            if self.multiplication.result as u32 >= self.alu.shift {
+                self.multiplication.result -= self.alu.shift as u16;
+                self.division.result |= 0x0001;
+            }
         }
     }
 
+    fn alu_busy(&self) -> bool {
+        self.alu.multiply_steps > 0 || self.alu.divide_steps > 0
+    }
+
+    pub fn nmi_pending(&self) -> bool {
+        self.cpu_action.contains(NMI)
+    }
+
+    // The IRQ line is level-sensitive: it stays asserted until $4211 is read
+    pub fn irq_asserted(&self) -> bool {
+        self.irq.active
+    }
+
     pub fn check_and_reset_dma(&mut self) -> Option<u8> {
         if self.cpu_action.contains(DMA) {
             self.cpu_action.remove(DMA);
@@ -194,13 +344,18 @@ impl HardwareBus for HardwareRegs {
     fn read(&mut self, offset: usize) -> u8 {
         match offset {
             0x10 => {
+                // Acknowledges the flag, but an NMI that is already latched still fires
                 let nmi = if self.nmi.active { 0x80 } else { 0x00 };
                 self.nmi.active = false;
+                self.update_nmi_line();
                 nmi | CHIP_VERSION
             },
             0x11 => {
                 let irq = if self.irq.active { 0x80 } else { 0x00 };
-                self.irq.active = false;
+                // A read that lands on the same dot the IRQ fires does not clear the flag
+                if !self.irq.triggered {
+                    self.irq.active = false;
+                }
                 irq
             },
             0x12 => {
@@ -221,22 +376,25 @@ impl HardwareBus for HardwareRegs {
             0x15 => self.division.result.upper(),
             0x16 => self.multiplication.result.lower(),
             0x17 => self.multiplication.result.upper(),
-            0x18 => self.joypad.button_state[0].lower(),
-            0x19 => self.joypad.button_state[0].upper(),
-            0x1A => self.joypad.button_state[1].lower(),
-            0x1B => self.joypad.button_state[1].upper(),
-            0x1C => self.joypad.button_state[2].lower(),
-            0x1D => self.joypad.button_state[2].upper(),
-            0x1E => self.joypad.button_state[3].lower(),
-            0x1F => self.joypad.button_state[3].upper(),
-            _ => 0x00 // TODO: Open bus
+            0x18 | 0x19 | 0x1A | 0x1B | 0x1C | 0x1D | 0x1E | 0x1F => {
+                self.joypad.polled = true;
+                let button_state = self.joypad.button_state[(offset - 0x18) >> 1];
+                if offset & 0x01 == 0 {
+                    button_state.lower()
+                } else {
+                    button_state.upper()
+                }
+            },
+            _ => 0x00 // Open bus, filled in by Hardware
         }
     }
 
     fn write(&mut self, offset: usize, value: u8) {
         match offset {
             0x00 => {
+                // Enabling NMI while the flag is still set triggers one immediately
                 self.nmi.enabled = value & 0x80 != 0;
+                self.update_nmi_line();
                 self.joypad.auto_read_enabled = value & 0x01 != 0;
 
                 self.irq.enabled = match value & 0x30 {
@@ -245,20 +403,37 @@ impl HardwareBus for HardwareRegs {
                     0x30 => IrqCondition::MatchRowAndColumn,
                     _ => IrqCondition::Never
                 };
+
+                // Disabling H/V IRQs also acknowledges any pending IRQ
+                if self.irq.enabled == IrqCondition::Never {
+                    self.irq.active = false;
+                }
             },
             0x01 => self.io_port.set_value(value),
             0x02 => self.multiplication.lhs = value,
-            0x03 => self.multiplication.result = (self.multiplication.lhs as u16) * (value as u16),
+            0x03 => {
+                self.multiplication.result = 0x0000;
+
+                // Writes are ignored while an operation is in progress
+                if !self.alu_busy() {
+                    // The division result shifts out the multiplier bit by bit
+                    self.division.result = ((value as u16) << 8) | (self.multiplication.lhs as u16);
+                    self.alu.shift = value as u32;
+                    self.alu.multiply_steps = 8;
+                    self.alu.started = true;
+                }
+            },
             0x04 => self.division.lhs.set_lower(value),
             0x05 => self.division.lhs.set_upper(value),
             0x06 => {
-                // Multiplication result is used to store remainder
-                if value != 0 {
-                    self.division.result = self.division.lhs / (value as u16);
-                    self.multiplication.result = self.division.lhs % (value as u16);
-                } else {
-                    self.division.result = 0xFFFF;
-                    self.multiplication.result = self.division.lhs;
+                // Dividing by zero gives a result of $FFFF, with the dividend
+                // as the remainder
+                self.multiplication.result = self.division.lhs;
+
+                if !self.alu_busy() {
+                    self.alu.shift = (value as u32) << 16;
+                    self.alu.divide_steps = 16;
+                    self.alu.started = true;
                 }
             },
             0x07 => self.irq.column.set_lower(value),
@@ -270,8 +445,183 @@ impl HardwareBus for HardwareRegs {
                 if value != 0x00 {
                     self.cpu_action.insert(DMA);
                 }
-            }
+            },
+            0x0C => self.hdma_channel_mask = value,
             _ => ()
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use cpu::Cpu;
+    use hardware::{Bus, Hardware, HardwareAddress, RomBuilder};
+
+    const AUTO_READ_PORT_1: u16 = 0x4218;
+    const SERIAL_PORT_1: u16 = 0x4016;
+
+    // Starts at the beginning of a VBlank, so every frame counted is a whole one
+    fn hardware() -> Hardware {
+        let mut hardware = RomBuilder::new().build_hardware();
+        run_frame(&mut hardware);
+        hardware
+    }
+
+    // Runs up to the start of the next VBlank, where lag frames are counted
+    fn run_frame(hardware: &mut Hardware) {
+        let frame_count = hardware.frame_count();
+
+        while hardware.frame_count() == frame_count {
+            hardware.tick(8);
+        }
+    }
+
+    fn read(hardware: &mut Hardware, offset: u16) {
+        hardware.read::<u8>(HardwareAddress::new(0x00, offset));
+    }
+
+    #[test]
+    fn counts_frames_without_reads() {
+        let mut hardware = hardware();
+        let lag_frames = hardware.lag_frames();
+
+        run_frame(&mut hardware);
+        run_frame(&mut hardware);
+
+        assert_eq!(hardware.lag_frames() - lag_frames, 2);
+    }
+
+    #[test]
+    fn either_read_avoids_lag() {
+        let mut hardware = hardware();
+        let lag_frames = hardware.lag_frames();
+
+        read(&mut hardware, AUTO_READ_PORT_1);
+        run_frame(&mut hardware);
+
+        read(&mut hardware, SERIAL_PORT_1);
+        run_frame(&mut hardware);
+
+        assert_eq!(hardware.lag_frames(), lag_frames);
+    }
+
+    #[test]
+    fn reads_do_not_carry_over_to_next_frame() {
+        let mut hardware = hardware();
+        let lag_frames = hardware.lag_frames();
+
+        read(&mut hardware, AUTO_READ_PORT_1);
+        read(&mut hardware, SERIAL_PORT_1);
+        run_frame(&mut hardware);
+        assert_eq!(hardware.lag_frames(), lag_frames);
+
+        run_frame(&mut hardware);
+        assert_eq!(hardware.lag_frames() - lag_frames, 1);
+    }
+
+    // BG1 is white down to line 96 and transparent below, over a black
+    // backdrop. An IRQ at line 96 scrolls BG1 down by 128 lines, so lines
+    // 96-127 stay dark and the white part shows again from line 128. NMI
+    // scrolls it back for the next frame.
+    fn split_screen_cpu(irq_mode: u8, irq_column: u8) -> Cpu {
+        RomBuilder::new()
+            .code(&[
+                0xA9, 0x01, 0x8D, 0x21, 0x21, // lda #$01; sta $2121
+                0xA9, 0xFF, 0x8D, 0x22, 0x21, // lda #$ff; sta $2122
+                0xA9, 0x7F, 0x8D, 0x22, 0x21, // lda #$7f; sta $2122
+                0x9C, 0x05, 0x21,             // stz $2105
+                0xA9, 0x04, 0x8D, 0x07, 0x21, // lda #$04; sta $2107
+                0x9C, 0x0B, 0x21,             // stz $210b
+                0xA9, 0x80, 0x8D, 0x15, 0x21, // lda #$80; sta $2115
+                // Tile 1 is solid color 1
+                0xA9, 0x08, 0x8D, 0x16, 0x21, // lda #$08; sta $2116
+                0x9C, 0x17, 0x21,             // stz $2117
+                0xA2, 0x08,                   // ldx #$08
+                0xA9, 0xFF, 0x8D, 0x18, 0x21, // lda #$ff; sta $2118
+                0x9C, 0x19, 0x21,             // stz $2119
+                0xCA,                         // dex
+                0xD0, 0xF5,                   // bne -11
+                // The top 12 rows of the tile map are tile 1
+                0x9C, 0x16, 0x21,             // stz $2116
+                0xA9, 0x04, 0x8D, 0x17, 0x21, // lda #$04; sta $2117
+                0xC2, 0x10,                   // rep #$10
+                0xA2, 0x80, 0x01,             // ldx #$0180
+                0xA9, 0x01, 0x8D, 0x18, 0x21, // lda #$01; sta $2118
+                0x9C, 0x19, 0x21,             // stz $2119
+                0xCA,                         // dex
+                0xD0, 0xF5,                   // bne -11
+                0xE2, 0x10,                   // sep #$10
+                0xA9, 0x01, 0x8D, 0x2C, 0x21, // lda #$01; sta $212c
+                0xA9, irq_column,             // lda #irq_column
+                0x8D, 0x07, 0x42,             // sta $4207
+                0x9C, 0x08, 0x42,             // stz $4208
+                0xA9, 0x60, 0x8D, 0x09, 0x42, // lda #96; sta $4209
+                0x9C, 0x0A, 0x42,             // stz $420a
+                0xA9, 0x80 | irq_mode,        // lda #($80 | irq_mode)
+                0x8D, 0x00, 0x42,             // sta $4200
+                0xA9, 0x0F, 0x8D, 0x00, 0x21, // lda #$0f; sta $2100
+                0x58                          // cli
+            ])
+            .irq_handler(&[
+                0xAD, 0x11, 0x42,             // lda $4211
+                0xA9, 0x80, 0x8D, 0x0E, 0x21, // lda #128; sta $210e
+                0x9C, 0x0E, 0x21              // stz $210e
+            ])
+            .nmi_handler(&[
+                0x9C, 0x0E, 0x21,             // stz $210e
+                0x9C, 0x0E, 0x21              // stz $210e
+            ])
+            .build_cpu()
+    }
+
+    // Renders a whole frame after setup, and returns whether the right edge
+    // of each screen line is lit
+    fn split_screen_lines(irq_mode: u8, irq_column: u8) -> Vec<bool> {
+        let mut cpu = split_screen_cpu(irq_mode, irq_column);
+
+        while cpu.hardware().frame_count() < 3 {
+            cpu.run_frame();
+        }
+
+        let frame_buffer = cpu.hardware().frame_buffer();
+
+        // Frame buffer rows and columns are doubled, at 4 bytes per pixel
+        (0..224).map(|line| {
+            let index = 2 * line * frame_buffer.row_length() + 2 * 255 * 4;
+            frame_buffer.pixels()[index..(index + 3)].iter().any(|&byte| byte != 0)
+        }).collect()
+    }
+
+    fn all_lit(lines: &[bool]) -> bool {
+        lines.iter().all(|&lit| lit)
+    }
+
+    fn all_dark(lines: &[bool]) -> bool {
+        lines.iter().all(|&lit| !lit)
+    }
+
+    // Scanline 96 is screen line 95, and the IRQ handler changes the scroll
+    // before the right edge of it is drawn
+    fn assert_split_screen(lines: &[bool]) {
+        assert!(all_lit(&lines[0..95]), "Lines before the split should be unscrolled");
+        assert!(all_dark(&lines[95..128]), "Lines after the split should be scrolled");
+        assert!(all_lit(&lines[128..224]), "Lines after the second split should be unscrolled again");
+    }
+
+    #[test]
+    fn v_irq_splits_screen() {
+        assert_split_screen(&split_screen_lines(0x20, 0));
+    }
+
+    #[test]
+    fn hv_irq_splits_screen() {
+        assert_split_screen(&split_screen_lines(0x30, 200));
+    }
+
+    #[test]
+    fn screen_is_not_split_without_irq() {
+        let lines = split_screen_lines(0x00, 0);
+        assert!(all_lit(&lines[0..96]));
+        assert!(all_dark(&lines[96..224]));
+    }
+}
@@ -4,10 +4,17 @@ use std::io::Read;
 use std::path::Path;
 use super::hardware::HardwareBus;
 
+// Copier headers come in multiples of 512 bytes, while ROM data (nearly)
+// always comes in multiples of 32K, so a small remainder must be header
 const SMC_HEADER_SIZE: usize = 512;
+const MAX_HEADER_SIZE: usize = 0x2000;
+const ROM_BLOCK_SIZE: usize = 0x8000;
+const HI_ROM_BANK_SIZE: usize = 0x10000;
 
 pub struct Rom {
     mode: RomMode,
+    chipset: u8,
+    checksum: u16,
     data: DataBus,
     sram: SramBus
 }
@@ -24,6 +31,8 @@ pub struct SramBus(Vec<u8>);
 
 struct RomHeader {
     mode: RomMode,
+    reported_mode: RomMode,
+    chipset: u8,
     score: u32,
     title: Option<String>,
     rom_size: usize,
@@ -32,22 +41,11 @@ struct RomHeader {
 
 impl Rom {
     pub fn new(path: &Path) -> Rom {
-        let mut file = File::open(path).unwrap();
-        let mut buffer = Vec::<u8>::new();
-        let rom_size = file.read_to_end(&mut buffer).unwrap();
-
-        let rom_data = match rom_size % 1024 {
-            SMC_HEADER_SIZE => {
-                info!("Valid SMC header found");
-                buffer.split_off(SMC_HEADER_SIZE)
-            },
-            0 => {
-                info!("No SMC header found");
-                buffer
-            },
-            length @ _ => panic!("Invalid SMC header length: {}", length)
-        };
+        Rom::from_data(Rom::load_data(path))
+    }
 
+    // ROM data without a copier header, e.g. from RomBuilder
+    pub fn from_data(rom_data: Vec<u8>) -> Rom {
         let lo_rom_header = RomHeader::new(&rom_data, RomMode::LoRom);
         let hi_rom_header = RomHeader::new(&rom_data, RomMode::HiRom);
 
@@ -65,11 +63,15 @@ impl Rom {
                 None => warn!("Title is not valid ASCII")
             };
 
+            let checksum = rom_data.iter().fold(0u16, |sum, &byte| sum.wrapping_add(byte as u16));
+
             info!("ROM size: {}", header.rom_size());
             info!("SRAM size: {}", header.sram_size());
 
             Rom {
                 mode: header.mode(),
+                chipset: header.chipset,
+                checksum: checksum,
                 data: DataBus(rom_data),
                 sram: SramBus(vec![0; header.sram_size()])
             }
@@ -78,10 +80,98 @@ impl Rom {
         }
     }
 
+    // Reads a ROM file, minus any copier header, and in the order the
+    // cartridge would present it
+    pub fn load_data(path: &Path) -> Vec<u8> {
+        let mut file = File::open(path).unwrap();
+        let mut buffer = Vec::<u8>::new();
+        file.read_to_end(&mut buffer).unwrap();
+        Rom::deinterleave(Rom::strip_copier_header(buffer))
+    }
+
+    // A remainder that is an odd multiple of 512 bytes can only be a copier
+    // header. An even multiple could also be a trimmed dump, so it is only
+    // treated as a header if the data after it has a better ROM header than
+    // the data with it left in place.
+    fn strip_copier_header(mut buffer: Vec<u8>) -> Vec<u8> {
+        match buffer.len() % ROM_BLOCK_SIZE {
+            0 => {
+                info!("No SMC header found");
+                buffer
+            },
+            length if length % SMC_HEADER_SIZE == 0 && length <= MAX_HEADER_SIZE
+                && ((length / SMC_HEADER_SIZE) % 2 == 1 || Rom::header_follows(&buffer, length)) => {
+                info!("Copier header found ({} bytes)", length);
+                buffer.split_off(length)
+            },
+            length if length % 1024 == 0 => {
+                // Trimmed dump that isn't a whole number of 32K blocks
+                info!("No SMC header found");
+                buffer
+            },
+            length @ _ => {
+                // Overdumped or damaged, but the ROM header may still be intact
+                warn!("Unexpected {} bytes after the last 32K block, loading anyway", length);
+                buffer
+            }
+        }
+    }
+
+    // Some copiers store HiROM games with the upper 32K of every 64K bank
+    // first, followed by all the lower halves. The HiROM header then ends up
+    // where a LoROM header would be, while still reporting HiROM mode.
+    fn deinterleave(buffer: Vec<u8>) -> Vec<u8> {
+        if buffer.len() % HI_ROM_BANK_SIZE != 0 {
+            return buffer;
+        }
+
+        let lo_rom_header = RomHeader::new(&buffer, RomMode::LoRom);
+        let hi_rom_header = RomHeader::new(&buffer, RomMode::HiRom);
+
+        if lo_rom_header.reported_mode != RomMode::HiRom || lo_rom_header.score() <= hi_rom_header.score() {
+            return buffer;
+        }
+
+        info!("Interleaved HiROM dump found");
+
+        let bank_count = buffer.len() / HI_ROM_BANK_SIZE;
+        let mut rom_data = Vec::with_capacity(buffer.len());
+
+        for bank in 0..bank_count {
+            for &block in [bank_count + bank, bank].iter() {
+                let start = block * ROM_BLOCK_SIZE;
+                rom_data.extend_from_slice(&buffer[start..(start + ROM_BLOCK_SIZE)]);
+            }
+        }
+
+        rom_data
+    }
+
+    fn header_follows(buffer: &[u8], length: usize) -> bool {
+        let stripped_score = RomHeader::best_score(&buffer[length..]);
+        stripped_score > 0 && stripped_score > RomHeader::best_score(buffer)
+    }
+
     pub fn mode(&self) -> RomMode {
         self.mode
     }
 
+    pub fn size(&self) -> usize {
+        self.data.0.len()
+    }
+
+    // DSP-n boards report a chipset of $03-$05; ST010/ST011 report $F6
+    pub fn has_necdsp(&self) -> bool {
+        match self.chipset {
+            0x03 | 0x04 | 0x05 | 0xF6 => true,
+            _ => false
+        }
+    }
+
+    pub fn checksum(&self) -> u16 {
+        self.checksum
+    }
+
     pub fn data(&mut self) -> &mut DataBus {
         &mut self.data
     }
@@ -89,6 +179,14 @@ impl Rom {
     pub fn sram(&mut self) -> &mut SramBus {
         &mut self.sram
     }
+
+    pub fn sram_bytes(&self) -> &[u8] {
+        &self.sram.0
+    }
+
+    pub fn sram_bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.sram.0
+    }
 }
 
 impl Display for RomMode {
@@ -129,15 +227,36 @@ impl HardwareBus for SramBus {
 }
 
 impl RomHeader {
-    fn new(rom_data: &Vec<u8>, mode: RomMode) -> RomHeader {
+    fn best_score(rom_data: &[u8]) -> u32 {
+        let lo_rom_score = RomHeader::new(rom_data, RomMode::LoRom).score();
+        let hi_rom_score = RomHeader::new(rom_data, RomMode::HiRom).score();
+        lo_rom_score.max(hi_rom_score)
+    }
+
+    fn new(rom_data: &[u8], mode: RomMode) -> RomHeader {
         let mut valid = true;
         let mut score = 0;
 
-        let header = match mode {
-            RomMode::LoRom => &rom_data[0x7F00..0x8000],
-            RomMode::HiRom => &rom_data[0xFF00..0x10000]
+        let header_end = match mode {
+            RomMode::LoRom => 0x8000,
+            RomMode::HiRom => 0x10000
         };
 
+        // Too small to have a header in this mode
+        if rom_data.len() < header_end {
+            return RomHeader {
+                mode: mode,
+                reported_mode: mode,
+                chipset: 0,
+                score: 0,
+                rom_size: 0,
+                sram_size: 0,
+                title: None
+            };
+        }
+
+        let header = &rom_data[(header_end - 0x100)..header_end];
+
         // Check for valid reset vector
         let reset_vector = header[0xFD];
 
@@ -149,13 +268,13 @@ impl RomHeader {
         }
 
         // Check the reported ROM mode matches the mode we're expecting
-        let expected_rom_mode = match header[0xD5] & 0x01 {
+        let reported_mode = match header[0xD5] & 0x01 {
             0 => RomMode::LoRom,
             1 => RomMode::HiRom,
             _ => unreachable!()
         };
 
-        if expected_rom_mode == mode {
+        if reported_mode == mode {
             score += 1;
         }
 
@@ -192,6 +311,8 @@ impl RomHeader {
 
         RomHeader {
             mode: mode,
+            reported_mode: reported_mode,
+            chipset: header[0xD6],
             score: score,
             rom_size: rom_size,
             sram_size: sram_size,
@@ -219,3 +340,85 @@ impl RomHeader {
         self.sram_size
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use hardware::RomBuilder;
+    use super::{Rom, HI_ROM_BANK_SIZE, ROM_BLOCK_SIZE};
+
+    // A two bank HiROM image, with each 32K block filled with its own index
+    fn hi_rom_data() -> Vec<u8> {
+        let mut rom_data: Vec<u8> = (0..(2 * HI_ROM_BANK_SIZE)).map(|index| (index / ROM_BLOCK_SIZE) as u8).collect();
+        rom_data[0xFFD5] = 0x21; // HiROM
+        rom_data[0xFFFD] = 0x80; // Reset vector
+        rom_data
+    }
+
+    fn with_prefix(prefix_size: usize, rom_data: &[u8]) -> Vec<u8> {
+        let mut buffer = vec![0; prefix_size];
+        buffer.extend_from_slice(rom_data);
+        buffer
+    }
+
+    #[test]
+    fn strips_odd_multiple_of_512() {
+        let rom_data = RomBuilder::new().build();
+        let buffer = with_prefix(512, &rom_data);
+        assert_eq!(Rom::strip_copier_header(buffer), rom_data);
+    }
+
+    #[test]
+    fn strips_even_multiple_of_512_before_valid_header() {
+        let rom_data = RomBuilder::new().build();
+        let buffer = with_prefix(1024, &rom_data);
+        assert_eq!(Rom::strip_copier_header(buffer), rom_data);
+    }
+
+    #[test]
+    fn keeps_trailing_data_of_trimmed_dump() {
+        let mut rom_data = RomBuilder::new().build();
+        rom_data.extend_from_slice(&[0; 1024]);
+        assert_eq!(Rom::strip_copier_header(rom_data.clone()), rom_data);
+    }
+
+    #[test]
+    fn keeps_whole_blocks() {
+        let rom_data = RomBuilder::new().build();
+        assert_eq!(Rom::strip_copier_header(rom_data.clone()), rom_data);
+    }
+
+    #[test]
+    fn keeps_unexpected_remainder() {
+        let mut rom_data = RomBuilder::new().build();
+        rom_data.extend_from_slice(&[0; 100]);
+        assert_eq!(Rom::strip_copier_header(rom_data.clone()), rom_data);
+    }
+
+    #[test]
+    fn deinterleaves_hi_rom() {
+        let rom_data = hi_rom_data();
+
+        // Upper halves of each bank, then lower halves
+        let mut interleaved = Vec::new();
+
+        for &block in [1, 3, 0, 2].iter() {
+            let start = block * ROM_BLOCK_SIZE;
+            interleaved.extend_from_slice(&rom_data[start..(start + ROM_BLOCK_SIZE)]);
+        }
+
+        assert_eq!(Rom::deinterleave(interleaved), rom_data);
+    }
+
+    #[test]
+    fn keeps_hi_rom_order() {
+        let rom_data = hi_rom_data();
+        assert_eq!(Rom::deinterleave(rom_data.clone()), rom_data);
+    }
+
+    #[test]
+    fn keeps_lo_rom_order() {
+        let mut rom_data = RomBuilder::new().build();
+        rom_data.extend_from_slice(&[0; ROM_BLOCK_SIZE]);
+        assert_eq!(Rom::deinterleave(rom_data.clone()), rom_data);
+    }
+}
@@ -0,0 +1,201 @@
+// Assembles a minimal bootable LoROM image in memory, so test programs can
+// be written as a few bytes of machine code rather than shipped as binary
+// fixtures. The image is a single 32K bank mapped at $00:8000-FFFF.
+//
+// On reset, the init stub switches to native mode, sets 16-bit A/X/Y, puts
+// the stack at $1FFF and the direct page at $0000, then returns to 8-bit
+// A/X/Y, forces blank and falls through to the user code. The user code is
+// followed by an endless loop, so it doesn't have to end in one itself.
+
+use cpu::Cpu;
+use hardware::{Apu, Hardware, Joypad, Ppu, Rom, Wram};
+
+const BANK_SIZE: usize = 0x8000;
+const BANK_START: u16 = 0x8000;
+
+const HEADER_OFFSET: usize = 0x7FC0;
+const TITLE_LENGTH: usize = 21;
+
+// Native mode vectors, then emulation mode vectors
+const COP_VECTOR: usize = 0x7FE4;
+const BRK_VECTOR: usize = 0x7FE6;
+const NMI_VECTOR: usize = 0x7FEA;
+const IRQ_VECTOR: usize = 0x7FEE;
+const EMULATION_NMI_VECTOR: usize = 0x7FFA;
+const RESET_VECTOR: usize = 0x7FFC;
+const EMULATION_IRQ_VECTOR: usize = 0x7FFE;
+
+const INIT_STUB: [u8; 20] = [
+    0x78,             // sei
+    0x18,             // clc
+    0xFB,             // xce
+    0xC2, 0x30,       // rep #$30
+    0xA2, 0xFF, 0x1F, // ldx #$1fff
+    0x9A,             // txs
+    0xA9, 0x00, 0x00, // lda #$0000
+    0x5B,             // tcd
+    0xE2, 0x30,       // sep #$30
+    0xA9, 0x80,       // lda #$80
+    0x8D, 0x00, 0x21  // sta $2100
+];
+
+const LOOP_FOREVER: [u8; 2] = [0x80, 0xFE]; // bra *
+
+const RTI: u8 = 0x40;
+
+pub struct RomBuilder {
+    title: String,
+    code: Vec<u8>,
+    nmi_handler: Vec<u8>,
+    irq_handler: Vec<u8>
+}
+
+impl RomBuilder {
+    pub fn new() -> RomBuilder {
+        RomBuilder {
+            title: String::from("SNAILEMU TEST"),
+            code: Vec::new(),
+            nmi_handler: Vec::new(),
+            irq_handler: Vec::new()
+        }
+    }
+
+    pub fn title(mut self, title: &str) -> RomBuilder {
+        self.title = title.to_string();
+        self
+    }
+
+    // Appended to any code already added
+    pub fn code(mut self, code: &[u8]) -> RomBuilder {
+        self.code.extend_from_slice(code);
+        self
+    }
+
+    // Handlers are followed by an RTI, which they may also use to return early
+    pub fn nmi_handler(mut self, code: &[u8]) -> RomBuilder {
+        self.nmi_handler = code.to_vec();
+        self
+    }
+
+    pub fn irq_handler(mut self, code: &[u8]) -> RomBuilder {
+        self.irq_handler = code.to_vec();
+        self
+    }
+
+    // The address of the first byte of user code
+    pub fn code_address() -> u16 {
+        BANK_START + INIT_STUB.len() as u16
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let mut data = vec![0u8; BANK_SIZE];
+        let mut position = 0;
+
+        for chunk in [&INIT_STUB[..], &self.code, &LOOP_FOREVER[..]].iter() {
+            position = place(&mut data, position, chunk);
+        }
+
+        let nmi_address = BANK_START + position as u16;
+        position = place(&mut data, position, &self.nmi_handler);
+        position = place(&mut data, position, &[RTI]);
+
+        let irq_address = BANK_START + position as u16;
+        position = place(&mut data, position, &self.irq_handler);
+        position = place(&mut data, position, &[RTI]);
+
+        // COP and BRK share a bare RTI
+        let rti_address = BANK_START + position as u16;
+        place(&mut data, position, &[RTI]);
+
+        let title = format!("{:<width$}", self.title, width = TITLE_LENGTH);
+        data[HEADER_OFFSET..(HEADER_OFFSET + TITLE_LENGTH)].copy_from_slice(&title.as_bytes()[..TITLE_LENGTH]);
+
+        // LoROM, no coprocessor or SRAM, 32K ROM
+        data[HEADER_OFFSET + 0x15] = 0x20;
+        data[HEADER_OFFSET + 0x16] = 0x00;
+        data[HEADER_OFFSET + 0x17] = 0x05;
+        data[HEADER_OFFSET + 0x18] = 0x00;
+
+        for &(vector, address) in [
+            (COP_VECTOR, rti_address),
+            (BRK_VECTOR, rti_address),
+            (NMI_VECTOR, nmi_address),
+            (IRQ_VECTOR, irq_address),
+            (EMULATION_NMI_VECTOR, nmi_address),
+            (RESET_VECTOR, BANK_START),
+            (EMULATION_IRQ_VECTOR, irq_address)
+        ].iter() {
+            data[vector] = address as u8;
+            data[vector + 1] = (address >> 8) as u8;
+        }
+
+        // A checksum and its complement always add up to $1FE, so sum the
+        // image with placeholders that do the same
+        data[HEADER_OFFSET + 0x1C] = 0xFF;
+        data[HEADER_OFFSET + 0x1D] = 0xFF;
+
+        let checksum = data.iter().fold(0u16, |sum, &byte| sum.wrapping_add(byte as u16));
+
+        data[HEADER_OFFSET + 0x1C] = !checksum as u8;
+        data[HEADER_OFFSET + 0x1D] = (!checksum >> 8) as u8;
+        data[HEADER_OFFSET + 0x1E] = checksum as u8;
+        data[HEADER_OFFSET + 0x1F] = (checksum >> 8) as u8;
+
+        data
+    }
+
+    // Boots the image with fresh copies of the other chips
+    pub fn build_hardware(&self) -> Hardware {
+        Hardware::new(Rom::from_data(self.build()), Wram::new(), Ppu::new(), Apu::new(), Joypad::new())
+    }
+
+    pub fn build_cpu(&self) -> Cpu {
+        Cpu::new(self.build_hardware())
+    }
+}
+
+fn place(data: &mut [u8], position: usize, bytes: &[u8]) -> usize {
+    let end = position + bytes.len();
+
+    if end > HEADER_OFFSET {
+        panic!("Test program does not fit in one bank ({} bytes)", end);
+    }
+
+    data[position..end].copy_from_slice(bytes);
+
+    end
+}
+
+#[cfg(test)]
+mod tests {
+    use hardware::MemoryRegion;
+    use super::RomBuilder;
+
+    // Instructions in the init stub, before the user code starts
+    const INIT_STUB_INSTRUCTIONS: usize = 11;
+
+    #[test]
+    fn runs_user_code_after_init_stub() {
+        let mut cpu = RomBuilder::new()
+            .code(&[
+                0xA9, 0x42,       // lda #$42
+                0x8D, 0x10, 0x00, // sta $0010
+                0xA2, 0x07        // ldx #$07
+            ])
+            .build_cpu();
+
+        for _ in 0..(INIT_STUB_INSTRUCTIONS + 3) {
+            cpu.step();
+        }
+
+        assert_eq!(cpu.hardware().memory(MemoryRegion::Wram)[0x0010], 0x42);
+        assert_eq!(cpu.regs().index_x, 0x0007);
+        assert_eq!(cpu.regs().stack_pointer, 0x1FFF);
+
+        // Parked in the loop that follows the user code
+        let loop_address = RomBuilder::code_address() + 7;
+        assert_eq!(cpu.regs().program_counter, loop_address);
+        cpu.step();
+        assert_eq!(cpu.regs().program_counter, loop_address);
+    }
+}
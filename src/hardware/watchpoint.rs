@@ -0,0 +1,99 @@
+use std::fmt::{self, Display, Formatter};
+use super::hardware::HardwareAddress;
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+    Either
+}
+
+// Matches bus accesses to an inclusive range of addresses
+pub struct Watchpoint {
+    start: HardwareAddress,
+    end: HardwareAddress,
+    access: Access
+}
+
+#[derive(Copy, Clone)]
+pub struct WatchpointHit {
+    pub address: HardwareAddress,
+    pub value: u8,
+    pub access: Access,
+    pub scanline: u16,
+    pub dot: u16
+}
+
+impl Watchpoint {
+    pub fn new(start: HardwareAddress, end: HardwareAddress, access: Access) -> Watchpoint {
+        Watchpoint {
+            start: start,
+            end: end,
+            access: access
+        }
+    }
+
+    // Accepts "BB:OOOO" or "BB:OOOO-BB:OOOO", with an optional ",r", ",w"
+    // or ",rw" suffix (defaults to rw)
+    pub fn parse(value: &str) -> Option<Watchpoint> {
+        let mut parts = value.splitn(2, ',');
+        let range = parts.next().unwrap_or("");
+
+        let access = match parts.next() {
+            Some("r") => Access::Read,
+            Some("w") => Access::Write,
+            Some("rw") | None => Access::Either,
+            Some(_) => return None
+        };
+
+        let mut bounds = range.splitn(2, '-');
+
+        let start = match bounds.next().and_then(HardwareAddress::parse) {
+            Some(start) => start,
+            None => return None
+        };
+
+        let end = match bounds.next() {
+            Some(end) => match HardwareAddress::parse(end) {
+                Some(end) => end,
+                None => return None
+            },
+            None => start
+        };
+
+        Some(Watchpoint::new(start, end, access))
+    }
+
+    pub fn matches(&self, address: HardwareAddress, access: Access) -> bool {
+        let address = address.to_u32();
+
+        address >= self.start.to_u32() && address <= self.end.to_u32() &&
+            (self.access == Access::Either || self.access == access)
+    }
+}
+
+// In the same format accepted by Watchpoint::parse
+impl Display for Watchpoint {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.start != self.end {
+            write!(f, "{}-", self.start)?;
+        }
+
+        write!(f, "{},{}", self.end, match self.access {
+            Access::Read => "r",
+            Access::Write => "w",
+            Access::Either => "rw"
+        })
+    }
+}
+
+impl Display for WatchpointHit {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.access {
+            Access::Write => write!(f, "Write: {} <= {:02X}", self.address, self.value)?,
+            _ => write!(f, "Read: {} => {:02X}", self.address, self.value)?
+        }
+
+        write!(f, " V={} H={}", self.scanline, self.dot)
+    }
+}
@@ -1,6 +1,8 @@
 use super::hardware::HardwareBus;
+use util::fill_pattern::FillPattern;
 
 const WRAM_SIZE: usize = 131072;
+const WRAM_ADDRESS_MASK: usize = WRAM_SIZE - 1;
 
 pub struct Wram {
     data: WramData,
@@ -17,9 +19,26 @@ impl Wram {
         }
     }
 
+    pub fn fill(&mut self, pattern: FillPattern) {
+        pattern.fill(&mut self.data.0);
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.data.0
+    }
+
+    pub fn bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.data.0
+    }
+
     pub fn data(&mut self) -> &mut WramData {
         &mut self.data
     }
+
+    fn increment_address(&mut self) {
+        // Address is 17 bits, so wraps from $1FFFF back to $00000
+        self.address = (self.address + 1) & WRAM_ADDRESS_MASK;
+    }
 }
 
 impl HardwareBus for Wram {
@@ -27,10 +46,11 @@ impl HardwareBus for Wram {
         match offset {
             0x00 => {
                 let value = self.data.0[self.address];
-                self.address = (self.address + 1) % WRAM_SIZE;
+                self.increment_address();
                 value
             },
-            _ => 0x00 // TODO: Open bus
+            // $2181-$2183 are write-only, and nothing else is mapped up to $21BF
+            _ => 0x00 // Open bus, filled in by Hardware
         }
     }
 
@@ -38,7 +58,7 @@ impl HardwareBus for Wram {
         match offset {
             0x00 => {
                 self.data.0[self.address] = value;
-                self.address = (self.address + 1) % WRAM_SIZE;
+                self.increment_address();
             },
             0x01 => self.address = (self.address & 0x1FF00) | (value as usize),
             0x02 => self.address = (self.address & 0x100FF) | ((value as usize) << 8),
@@ -57,3 +77,83 @@ impl HardwareBus for WramData {
         self.0[offset] = value;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use hardware::{Bus, HardwareAddress, RomBuilder};
+    use hardware::hardware::HardwareBus;
+    use super::Wram;
+
+    fn set_address(wram: &mut Wram, address: usize) {
+        wram.write(0x01, address as u8);
+        wram.write(0x02, (address >> 8) as u8);
+        wram.write(0x03, (address >> 16) as u8);
+    }
+
+    #[test]
+    fn port_reads_and_writes_at_address() {
+        let mut wram = Wram::new();
+
+        set_address(&mut wram, 0x12345);
+        wram.write(0x00, 0xAB);
+        wram.write(0x00, 0xCD);
+
+        assert_eq!(wram.bytes()[0x12345], 0xAB);
+        assert_eq!(wram.bytes()[0x12346], 0xCD);
+
+        set_address(&mut wram, 0x12345);
+        assert_eq!(wram.read(0x00), 0xAB);
+        assert_eq!(wram.read(0x00), 0xCD);
+    }
+
+    #[test]
+    fn address_wraps_at_17_bits() {
+        let mut wram = Wram::new();
+
+        set_address(&mut wram, 0x1FFFF);
+        wram.write(0x00, 0x11);
+        wram.write(0x00, 0x22);
+
+        assert_eq!(wram.bytes()[0x1FFFF], 0x11);
+        assert_eq!(wram.bytes()[0x00000], 0x22);
+    }
+
+    #[test]
+    fn address_ignores_upper_bits_of_bank() {
+        let mut wram = Wram::new();
+
+        set_address(&mut wram, 0xFE0010);
+        wram.write(0x00, 0x33);
+
+        assert_eq!(wram.bytes()[0x00010], 0x33);
+    }
+
+    #[test]
+    fn writes_above_2183_are_ignored() {
+        let mut wram = Wram::new();
+
+        set_address(&mut wram, 0x00100);
+
+        for offset in 0x04..0x40 {
+            wram.write(offset, 0xFF);
+        }
+
+        wram.write(0x00, 0x44);
+
+        assert_eq!(wram.bytes()[0x00100], 0x44);
+        assert!(wram.bytes().iter().filter(|&&byte| byte != 0x00).count() == 1);
+    }
+
+    #[test]
+    fn address_registers_read_as_open_bus() {
+        let mut hardware = RomBuilder::new().build_hardware();
+
+        hardware.write::<u8>(HardwareAddress::new(0x7E, 0x0000), 0x5A);
+
+        for &offset in [0x2181, 0x2182, 0x2183].iter() {
+            // The read leaves $5A on the data bus
+            assert_eq!(hardware.read::<u8>(HardwareAddress::new(0x7E, 0x0000)), 0x5A);
+            assert_eq!(hardware.read::<u8>(HardwareAddress::new(0x00, offset)), 0x5A);
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use cpu::Cpu;
+use hardware::{ButtonState, PORT_COUNT};
+use std::io::{self, BufRead, Stdin, Write};
+
+// Characters in an input line, one per button, starting with the highest bit
+const BUTTON_COUNT: usize = 12;
+
+// Word at the start of an input line that soft resets the console
+const RESET: &'static str = "reset";
+
+// Lets another process drive the emulator through stdin and stdout. Each line
+// read from stdin gives the buttons held for one frame, with a field for each
+// port separated by '|'. Buttons are listed in the order BYsSUDLRAXlr, with a
+// '.' for each button that isn't held:
+//
+//   B..S........|............
+//
+// A line starting with 'reset' soft resets the console (as the reset button
+// would) before that frame runs:
+//
+//   reset B..S........|............
+//
+// Each completed frame is echoed to stdout as 'FRAME <number> <hash>'. Any
+// other lines on stdout are log output.
+pub struct InputPipe {
+    stdin: Stdin
+}
+
+fn parse_buttons(field: &str) -> ButtonState {
+    let bits = field.chars()
+        .take(BUTTON_COUNT)
+        .enumerate()
+        .filter(|&(_, ch)| ch != '.')
+        .fold(0, |bits, (index, _)| bits | (0x8000 >> index));
+
+    ButtonState::from_bits_truncate(bits)
+}
+
+// Applies one line of input: the reset, if any, then the buttons held on each
+// port. Returns whatever follows the buttons (a frame hash in movies).
+pub fn apply_input<'a>(cpu: &mut Cpu, line: &'a str) -> Option<&'a str> {
+    let mut fields = line.split_whitespace();
+    let mut buttons = fields.next();
+
+    if buttons == Some(RESET) {
+        info!("Reset");
+        cpu.reset();
+        buttons = fields.next();
+    }
+
+    for (port, field) in buttons.unwrap_or("").split('|').take(PORT_COUNT).enumerate() {
+        cpu.hardware_mut().joypad_mut().set_buttons(port, parse_buttons(field));
+    }
+
+    fields.next()
+}
+
+impl InputPipe {
+    pub fn new() -> InputPipe {
+        InputPipe {
+            stdin: io::stdin()
+        }
+    }
+
+    // Applies the input for the next frame. Returns false once stdin closes.
+    pub fn next_frame(&mut self, cpu: &mut Cpu) -> bool {
+        let mut line = String::new();
+
+        if self.stdin.lock().read_line(&mut line).unwrap() == 0 {
+            return false;
+        }
+
+        apply_input(cpu, &line);
+        true
+    }
+
+    pub fn echo_frame(&mut self, cpu: &Cpu) {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        let hardware = cpu.hardware();
+        writeln!(stdout, "FRAME {} {:016x}", hardware.frame_count(), hardware.frame_buffer().hash()).unwrap();
+        stdout.flush().unwrap();
+    }
+}
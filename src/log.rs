@@ -30,3 +30,7 @@ pub fn trace_mode_enabled() -> bool {
 pub fn enable_trace_mode() {
     unsafe { TRACE_MODE = true };
 }
+
+pub fn disable_trace_mode() {
+    unsafe { TRACE_MODE = false };
+}
@@ -7,44 +7,478 @@ extern crate sdl2_sys;
 #[macro_use]
 mod log;
 
+mod accuracy;
 mod cpu;
+mod crash_report;
+mod debugger;
 mod hardware;
+mod input_pipe;
+mod movie;
+mod options;
+mod patch_file;
+mod paths;
+mod profile_report;
+mod screen;
+mod session;
+mod slowdown_report;
 mod util;
+mod video_dump;
 
-use cpu::Cpu;
-use hardware::{Apu, Hardware, IoPort, Joypad, Ppu, Rom, Screen, Wram};
+use cpu::{Cpu, Tracer};
+use debugger::GdbStub;
+use hardware::{Apu, ControllerPort, Gamepad, Hardware, HardwareAddress, Joypad, NecDsp, Ppu, RegisterLog, Rom, Unplugged, Wram};
+use input_pipe::InputPipe;
+use options::{ControllerDevice, Options};
+use profile_report::ProfileReport;
+use screen::Screen;
+use slowdown_report::SlowdownReport;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
-use std::env;
-use std::path::Path;
-use std::rc::Rc;
+use std::fs::File;
+use std::io::Write;
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
+use video_dump::VideoDump;
+
+// Longest string a WDM debug print will read, in case the terminator is missing
+const WDM_MESSAGE_LIMIT: usize = 256;
 
 fn main() {
-    let rom_path = env::args_os().nth(1).unwrap();
-    let rom = Rom::new(Path::new(&rom_path));
+    let mut options = Options::from_args();
+
+    // Utility mode: write a copy of the ROM without its copier header and exit
+    if let Some(path) = options.strip_header_path() {
+        let rom_data = Rom::load_data(options.rom_path());
+        File::create(path).unwrap().write_all(&rom_data).unwrap();
+        info!("Wrote {} bytes to {}", rom_data.len(), path.display());
+        process::exit(0);
+    }
+
+    // Utility mode: run the accuracy test suite headlessly and exit
+    if let Some(path) = options.accuracy_manifest_path() {
+        accuracy::run(path);
+        process::exit(0);
+    }
+
+    // Utility mode: play back a movie into the video dump, without a window
+    if let Some(path) = options.movie_path().cloned() {
+        let mut cpu = create_cpu(&mut options);
+        // Validated by Options: movies are only played back into a dump
+        let mut video_dump = VideoDump::new(File::create(options.video_dump_path().unwrap()).unwrap());
+        let result = movie::export(&mut cpu, &path, &mut video_dump, options.frame_limit(), || create_cpu(&mut options));
+        cpu.flush_tracer();
+
+        if let Err(error) = result {
+            warn!("{}", error);
+            process::exit(1);
+        }
+
+        process::exit(0);
+    }
+
+    // Utility mode: play back a movie headlessly, checking its frame hashes
+    if let Some(path) = options.verify_movie_path().cloned() {
+        let mut cpu = create_cpu(&mut options);
+        let result = movie::verify(&mut cpu, &path, options.frame_limit(), || create_cpu(&mut options));
+        cpu.flush_tracer();
+
+        if let Err(error) = result {
+            warn!("Movie verification failed: {}", error);
+            process::exit(1);
+        }
+
+        process::exit(0);
+    }
 
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
 
     let mut event_pump = sdl_context.event_pump().unwrap();
 
-    let io_port = Rc::new(IoPort::new());
+    let mut screen = Screen::new(&video_subsystem);
+
+    if let Some(path) = options.video_dump_path() {
+        screen.set_video_dump(File::create(path).unwrap());
+    }
+
+    // Developer mode: render a recorded frame of PPU register writes on a
+    // standalone PPU, without a ROM or CPU
+    if let Some(path) = options.ppu_replay_path() {
+        let mut ppu = Ppu::new();
 
-    let ppu = Ppu::new(Screen::new(&video_subsystem), io_port.clone());
+        if let Err(error) = ppu.replay(&RegisterLog::load(path)) {
+            warn!("Could not replay PPU frame: {}", error);
+            process::exit(1);
+        }
 
-    let hardware = Hardware::new(rom, Wram::new(), ppu, Apu::new(), Joypad::new(), io_port);
+        screen.present(ppu.frame_buffer());
 
-    let mut cpu = Cpu::new(hardware);
+        // Leave the frame on screen unless it's being dumped for comparison
+        if options.video_dump_path().is_none() {
+            loop {
+                if let Event::Quit { .. } = event_pump.wait_event() {
+                    break;
+                }
+            }
+        }
+
+        process::exit(0);
+    }
+
+    let mut cpu = create_cpu(&mut options);
+
+    // Restored after the command line, so that duplicates can be skipped
+    let session_path = if options.use_session() {
+        Some(paths::file_for_rom(options.session_dir(), options.rom_path(), "session"))
+    } else {
+        None
+    };
+
+    if let Some(ref path) = session_path {
+        session::load(path, &mut cpu);
+    }
+
+    // Developer mode: carry on from the CPU and memory saved in a crash report
+    if let Some(path) = options.restore_crash_path() {
+        if let Err(error) = crash_report::restore_bundle(&mut cpu, path) {
+            warn!("Could not restore crash report: {}", error);
+            process::exit(1);
+        }
+    }
+
+    // The debugger takes over the run loop until it detaches
+    if let Some(port) = options.gdb_port() {
+        let result = GdbStub::listen(port).and_then(|mut stub| {
+            stub.run(&mut cpu, &mut |cpu| {
+                screen.present(cpu.hardware().frame_buffer());
+                for _ in event_pump.poll_iter() {}
+            })
+        });
+
+        if let Err(error) = result {
+            warn!("GDB connection error: {}", error);
+        }
+
+        cpu.flush_tracer();
+        return;
+    }
+
+    // Joypad input comes from stdin instead of the keyboard, one line per frame
+    let mut input_pipe = if options.pipe_input() {
+        let mut input_pipe = InputPipe::new();
+        if !input_pipe.next_frame(&mut cpu) {
+            return;
+        }
+        Some(input_pipe)
+    } else {
+        None
+    };
+
+    let mut slowdown_report = if options.slowdown_report() {
+        Some(SlowdownReport::new(&cpu))
+    } else {
+        None
+    };
+
+    let mut profile_report = if options.profile() {
+        Some(ProfileReport::new(&mut cpu))
+    } else {
+        None
+    };
+
+    let mut presented_frame = 0;
+    let mut paused = false;
 
     'outer: loop {
+        // While stopped at a breakpoint, wait for the user to step or continue
+        if paused {
+            let stepped = match event_pump.wait_event() {
+                Event::Quit { .. } => break 'outer,
+                Event::KeyDown { keycode: Some(Keycode::C), .. } => {
+                    paused = false;
+                    cpu.resume();
+                    false
+                },
+                Event::KeyDown { keycode: Some(Keycode::S), .. } => { cpu.step(); true },
+                Event::KeyDown { keycode: Some(Keycode::O), .. } => { cpu.step_over(); true },
+                Event::KeyDown { keycode: Some(Keycode::U), .. } => { cpu.step_out(); true },
+                // For a watchpoint that keeps firing, without restarting to drop it
+                Event::KeyDown { keycode: Some(Keycode::W), .. } => {
+                    cpu.hardware_mut().clear_watchpoints();
+                    info!("Watchpoints cleared");
+                    false
+                },
+                _ => false
+            };
+
+            if stepped {
+                if let Some(address) = cpu.take_breakpoint_hit() {
+                    info!("Breakpoint at {}", address);
+                }
+                for hit in cpu.hardware_mut().take_watchpoint_hits() {
+                    info!("Watchpoint hit: {}", hit);
+                }
+                info!("{}", cpu);
+                screen.present(cpu.hardware().frame_buffer());
+            }
+
+            continue;
+        }
+
+        // Host input is polled once per frame, and again whenever run_frame
+        // returns early because the game latched the controllers, so that
+        // games reading $4016 manually see input as late as possible. Frames
+        // end at the start of VBlank, just before auto joypad read.
         for event in event_pump.poll_iter() {
             match event {
                 Event::Quit { .. } => break 'outer,
-                Event::KeyDown { keycode: Some(Keycode::T), .. } => log::enable_trace_mode(),
-                _ => cpu.hardware_mut().joypad_mut().handle_event(event)
+                Event::KeyDown { keycode: Some(Keycode::T), .. } => {
+                    if log::trace_mode_enabled() {
+                        log::disable_trace_mode();
+                    } else {
+                        log::enable_trace_mode();
+                    }
+                },
+                Event::KeyDown { keycode: Some(Keycode::R), .. } => {
+                    info!("Reset");
+                    cpu.reset();
+                },
+                _ if input_pipe.is_none() => cpu.hardware_mut().joypad_mut().handle_event(event),
+                _ => ()
             }
         }
 
-        cpu.tick();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| cpu.run_frame()));
+
+        if let Err(payload) = result {
+            match crash_report::write_bundle(&cpu, &options, &crash_report::panic_message(&payload)) {
+                Ok(path) => warn!("Crash report written to {}", path.display()),
+                Err(error) => warn!("Could not write crash report: {}", error)
+            }
+            panic::resume_unwind(payload);
+        }
+
+        // Errors caused by the game pause emulation rather than crashing
+        if let Some(error) = cpu.take_error() {
+            match crash_report::write_bundle(&cpu, &options, &error.to_string()) {
+                Ok(path) => warn!("{} (crash report written to {})", error, path.display()),
+                Err(write_error) => warn!("{} (could not write crash report: {})", error, write_error)
+            }
+            info!("{} (press C to continue)", cpu);
+            paused = true;
+            screen.present(cpu.hardware().frame_buffer());
+            cpu.flush_tracer();
+            continue;
+        }
+
+        if let Some(address) = cpu.take_breakpoint_hit() {
+            info!("Breakpoint at {} (S/O/U to step in/over/out, C to continue)", address);
+            info!("{}", cpu);
+            paused = true;
+            screen.present(cpu.hardware().frame_buffer());
+            cpu.flush_tracer();
+            continue;
+        }
+
+        let watchpoint_hits = cpu.hardware_mut().take_watchpoint_hits();
+
+        if !watchpoint_hits.is_empty() {
+            for hit in watchpoint_hits {
+                info!("Watchpoint hit: {}", hit);
+            }
+            info!("{} (press C to continue)", cpu);
+            paused = true;
+            screen.present(cpu.hardware().frame_buffer());
+            cpu.flush_tracer();
+            continue;
+        }
+
+        let frame_count = cpu.hardware().frame_count();
+
+        if frame_count != presented_frame {
+            screen.present(cpu.hardware().frame_buffer());
+            presented_frame = frame_count;
+
+            if let Some(ref mut slowdown_report) = slowdown_report {
+                slowdown_report.update(&cpu);
+            }
+
+            if let Some(ref mut profile_report) = profile_report {
+                profile_report.update(&mut cpu);
+            }
+
+            if let Some(ref mut input_pipe) = input_pipe {
+                input_pipe.echo_frame(&cpu);
+
+                if !input_pipe.next_frame(&mut cpu) {
+                    break 'outer;
+                }
+            }
+        }
+
+        if let Some(frame_limit) = options.frame_limit() {
+            if cpu.hardware().frame_count() >= frame_limit {
+                info!("Frame limit reached: {} ({} lag frames)", frame_limit, cpu.hardware().lag_frames());
+
+                // Machine state at the end of the run, for diffing against other runs or emulators
+                if let Some(path) = options.state_dump_path() {
+                    let mut file = File::create(path).unwrap();
+                    writeln!(file, "{}", cpu.state_json()).unwrap();
+                }
+
+                if let Some(path) = options.ppu_record_path() {
+                    if let Some(register_log) = cpu.hardware_mut().ppu_mut().take_register_log() {
+                        register_log.save(path);
+                        info!("PPU register log written to {}", path.display());
+                    }
+                }
+
+                if let Some(count) = options.hotspot_count() {
+                    cpu.report_hotspots(count);
+                }
+
+                cpu.report_cycle_audit();
+                cpu.flush_tracer();
+                cpu.hardware_mut().apu_mut().flush_trace();
+
+                process::exit(0);
+            }
+        }
+    }
+
+    if let Some(count) = options.hotspot_count() {
+        cpu.report_hotspots(count);
+    }
+
+    cpu.report_cycle_audit();
+
+    if let Some(ref path) = session_path {
+        session::save(path, &cpu);
+    }
+}
+
+// Builds the emulated machine described by the command line
+fn create_cpu(options: &mut Options) -> Cpu {
+    let rom = Rom::new(options.rom_path());
+
+    // DSP chips are only emulated at a low level, from a program ROM dump
+    let necdsp = match (options.dsp_rom_path(), rom.has_necdsp()) {
+        (Some(path), true) => Some(NecDsp::load(path, rom.mode(), rom.size())),
+        (Some(_), false) => {
+            warn!("ROM header does not list a DSP chip; ignoring --dsp-rom");
+            None
+        },
+        (None, true) => {
+            warn!("This game uses a DSP chip; supply its program ROM with --dsp-rom");
+            None
+        },
+        (None, false) => None
+    };
+
+    let mut ppu = Ppu::new();
+    ppu.fill_vram(options.ram_init());
+
+    let mut wram = Wram::new();
+    wram.fill(options.ram_init());
+
+    let mut joypad = Joypad::new();
+
+    for port in 0..2 {
+        let device: Box<ControllerPort + Send> = match options.port_device(port) {
+            ControllerDevice::Gamepad => Box::new(Gamepad::new()),
+            ControllerDevice::Unplugged => Box::new(Unplugged)
+        };
+        joypad.connect(port, device);
+    }
+
+    let mut hardware = Hardware::new(rom, wram, ppu, Apu::new(), joypad);
+    hardware.apply_register_defaults(options.register_defaults());
+    hardware.set_overclock(options.overclock());
+
+    if let Some(necdsp) = necdsp {
+        hardware.coprocessor_mut().attach(Box::new(necdsp));
+    }
+
+    if options.ppu_record_path().is_some() {
+        // Validated by Options: the last frame before the frame limit
+        let frame = options.frame_limit().unwrap() - 1;
+        hardware.ppu_mut().record_frame(frame);
+    }
+
+    if let Some(path) = options.patch_path() {
+        match patch_file::load(path) {
+            Ok(patches) => for (address, value) in patches {
+                hardware.add_read_patch(address, value);
+            },
+            Err(error) => {
+                warn!("{}", error);
+                process::exit(1);
+            }
+        }
+    }
+
+    if let Some(path) = options.apu_trace_path() {
+        hardware.apu_mut().set_trace_file(File::create(path).unwrap());
+    }
+
+    for watchpoint in options.take_watchpoints() {
+        hardware.add_watchpoint(watchpoint);
+    }
+
+    let mut cpu = Cpu::new(hardware);
+
+    for watch in options.take_watches() {
+        cpu.add_watch(watch);
     }
+
+    // WDM #$00: print the zero-terminated string at $7E:X, for test ROMs
+    cpu.set_wdm_handler(0x00, Box::new(|cpu| {
+        let mut address = HardwareAddress::new(0x7E, cpu.regs().index_x);
+        let mut message = String::new();
+
+        while let Some(byte) = cpu.hardware_mut().peek(address) {
+            if byte == 0 || message.len() >= WDM_MESSAGE_LIMIT {
+                break;
+            }
+            message.push(byte as char);
+            address = address.wrapping_add(1);
+        }
+
+        info!("WDM: {}", message);
+    }));
+
+    // Homebrew debugging stubs often use COP signatures as markers. The
+    // vector is still taken, so the game runs as it would on hardware.
+    if options.log_cop() {
+        cpu.set_cop_hook(Box::new(|cpu, signature| {
+            let regs = cpu.regs();
+            info!("COP #${:02X} at {:02X}:{:04X}", signature, regs.program_bank, regs.program_counter.wrapping_sub(2));
+            false
+        }));
+    }
+
+    if let Some(path) = options.trace_file_path() {
+        cpu.set_tracer(Tracer::new(File::create(path).unwrap()));
+    }
+
+    // Without a trigger, a trace file is written from the first instruction
+    if let Some(trace_trigger) = options.take_trace_trigger() {
+        cpu.set_trace_trigger(trace_trigger);
+    } else if options.trace_file_path().is_some() {
+        log::enable_trace_mode();
+    }
+
+    if options.hotspot_count().is_some() {
+        cpu.enable_hotspots();
+    }
+
+    if options.cycle_audit() {
+        cpu.enable_cycle_audit();
+    }
+
+    for &address in options.breakpoints() {
+        cpu.breakpoints_mut().set(address);
+    }
+
+    cpu
 }
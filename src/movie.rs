@@ -0,0 +1,317 @@
+use cpu::Cpu;
+use input_pipe;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use video_dump::VideoDump;
+
+// Word at the start of a movie line that power cycles the console
+const POWER: &'static str = "power";
+
+// A movie has one line of input per frame, in the same format as
+// --pipe-input, so the lines sent to a piped session can be saved and
+// replayed to give the same frames, including any resets. A line may end
+// with the hash that --pipe-input echoed for that frame, which --verify-movie
+// checks:
+//
+//   B..S........|............ 8f6e2d1c0b9a7354
+//
+// Movies can also start a line with 'power', which power cycles the console
+// before that frame runs. The machine is rebuilt as it was at startup, so
+// memory is lost and the frame counter starts again (along with any trace
+// file, which is rewritten from that point).
+//
+// Playback ends with the movie, at the frame limit, or if the game stops the
+// CPU or hits a breakpoint or watchpoint.
+
+// Plays back a movie as fast as possible, without a window, writing every
+// frame to the video dump
+pub fn export<R>(cpu: &mut Cpu, path: &Path, video_dump: &mut VideoDump, frame_limit: Option<u64>, rebuild: R)
+    -> Result<(), String>
+    where R: FnMut() -> Cpu
+{
+    let mut frames = 0;
+
+    play(cpu, path, frame_limit, rebuild, |cpu, frame, _| {
+        video_dump.write_frame(cpu.hardware().frame_buffer());
+        frames = frame;
+        Ok(())
+    })?;
+
+    info!("Movie exported ({} frames)", frames);
+
+    Ok(())
+}
+
+// Plays back a movie without a window, checking every frame hash it contains.
+// Fails at the first mismatch, or if playback stops before the movie ends.
+pub fn verify<R>(cpu: &mut Cpu, path: &Path, frame_limit: Option<u64>, rebuild: R) -> Result<(), String>
+    where R: FnMut() -> Cpu
+{
+    let mut frames = 0;
+    let mut checked = 0;
+
+    let finished = play(cpu, path, frame_limit, rebuild, |cpu, frame, expected| {
+        frames = frame;
+
+        if let Some(expected) = expected {
+            let actual = cpu.hardware().frame_buffer().hash();
+
+            if actual != expected {
+                return Err(format!("Frame {} hash mismatch: expected {:016x}, got {:016x}",
+                    frame, expected, actual));
+            }
+
+            checked += 1;
+        }
+
+        Ok(())
+    })?;
+
+    if !finished {
+        return Err(format!("Movie playback stopped early at frame {}", frames));
+    }
+
+    if checked == 0 {
+        return Err("Movie has no frame hashes to check".to_string());
+    }
+
+    info!("Movie verified ({} frames, {} hashes)", frames, checked);
+
+    Ok(())
+}
+
+// Runs one frame per line, passing each completed frame, its number in the
+// movie and the hash given for it (if any) to on_frame. A power cycle
+// replaces the CPU with one from rebuild. Returns false if the CPU stopped
+// playback.
+fn play<R, F>(cpu: &mut Cpu, path: &Path, frame_limit: Option<u64>, mut rebuild: R, mut on_frame: F)
+    -> Result<bool, String>
+    where R: FnMut() -> Cpu, F: FnMut(&Cpu, u64, Option<u64>) -> Result<(), String>
+{
+    let file = File::open(path).map_err(|error| format!("Could not open movie: {}", error))?;
+
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        // Frames are counted by line, as a power cycle resets the frame counter
+        let frame = index as u64 + 1;
+
+        if frame_limit.map_or(false, |limit| frame > limit) {
+            break;
+        }
+
+        let line = line.map_err(|error| format!("Could not read movie: {}", error))?;
+        let mut input = &line[..];
+
+        if input.split_whitespace().next() == Some(POWER) {
+            info!("Power cycle");
+            cpu.flush_tracer();
+            *cpu = rebuild();
+            input = &input[input.find(POWER).unwrap() + POWER.len()..];
+        }
+
+        let expected = match input_pipe::apply_input(cpu, input) {
+            Some(hash) => Some(u64::from_str_radix(hash, 16)
+                .map_err(|_| format!("Invalid frame hash on line {}: {}", index + 1, hash))?),
+            None => None
+        };
+
+        // run_frame also returns early when the controllers are latched
+        let frame_count = cpu.hardware().frame_count();
+
+        while cpu.hardware().frame_count() == frame_count {
+            cpu.run_frame();
+
+            if let Some(error) = cpu.take_error() {
+                warn!("{}", error);
+                return Ok(false);
+            }
+
+            if stopped_at_breakpoint(cpu) {
+                info!("Movie playback stopped at frame {}", frame);
+                return Ok(false);
+            }
+        }
+
+        on_frame(cpu, frame, expected)?;
+    }
+
+    Ok(true)
+}
+
+// Nothing resumes the CPU during playback, so a hit ends the export
+fn stopped_at_breakpoint(cpu: &mut Cpu) -> bool {
+    let mut stopped = false;
+
+    if let Some(address) = cpu.take_breakpoint_hit() {
+        info!("Breakpoint at {}", address);
+        stopped = true;
+    }
+
+    for hit in cpu.hardware_mut().take_watchpoint_hits() {
+        info!("Watchpoint hit: {}", hit);
+        stopped = true;
+    }
+
+    stopped
+}
+
+#[cfg(test)]
+mod tests {
+    use cpu::Cpu;
+    use hardware::{HardwareAddress, RomBuilder};
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::process;
+    use video_dump::VideoDump;
+    use super::{export, play, verify};
+
+    // Visible part of a frame without overscan, at 4 bytes per pixel
+    const FRAME_SIZE: u64 = 512 * 448 * 4;
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("snailemu-{}-{}", process::id(), name))
+    }
+
+    const INPUT: &'static str = "B..S........|............";
+
+    fn write_movie(name: &str, lines: &[String]) -> PathBuf {
+        let path = temp_path(&format!("{}.movie", name));
+        let mut movie = File::create(&path).unwrap();
+
+        for line in lines {
+            writeln!(movie, "{}", line).unwrap();
+        }
+
+        path
+    }
+
+    // Returns the size of the video dump
+    fn export_movie(cpu: &mut Cpu, name: &str, frame_count: usize, frame_limit: Option<u64>) -> u64 {
+        let movie_path = write_movie(name, &vec![INPUT.to_string(); frame_count]);
+        let video_path = temp_path(&format!("{}.bgra", name));
+
+        {
+            let mut video_dump = VideoDump::new(File::create(&video_path).unwrap());
+            export(cpu, &movie_path, &mut video_dump, frame_limit, || RomBuilder::new().build_cpu()).unwrap();
+        }
+
+        let size = fs::metadata(&video_path).unwrap().len();
+        fs::remove_file(&movie_path).unwrap();
+        fs::remove_file(&video_path).unwrap();
+        size
+    }
+
+    #[test]
+    fn writes_one_frame_per_line() {
+        let mut cpu = RomBuilder::new().build_cpu();
+        assert_eq!(export_movie(&mut cpu, "lines", 3, None), 3 * FRAME_SIZE);
+    }
+
+    #[test]
+    fn stops_at_frame_limit() {
+        let mut cpu = RomBuilder::new().build_cpu();
+        assert_eq!(export_movie(&mut cpu, "limit", 5, Some(2)), 2 * FRAME_SIZE);
+    }
+
+    #[test]
+    fn stops_at_breakpoint() {
+        let mut cpu = RomBuilder::new().build_cpu();
+
+        // The loop that follows the (empty) user code
+        cpu.breakpoints_mut().set(HardwareAddress::new(0x00, RomBuilder::code_address()));
+
+        assert_eq!(export_movie(&mut cpu, "breakpoint", 3, None), 0);
+    }
+
+    #[test]
+    fn reports_missing_movie() {
+        let mut cpu = RomBuilder::new().build_cpu();
+        let video_path = temp_path("missing.bgra");
+
+        let result = {
+            let mut video_dump = VideoDump::new(File::create(&video_path).unwrap());
+            export(&mut cpu, &temp_path("missing.movie"), &mut video_dump, None, || RomBuilder::new().build_cpu())
+        };
+
+        fs::remove_file(&video_path).unwrap();
+        assert!(result.is_err());
+    }
+
+    // Movie lines for a fresh CPU, with the hash of each frame appended
+    fn hashed_lines(frame_count: usize) -> Vec<String> {
+        let movie_path = write_movie("reference", &vec![INPUT.to_string(); frame_count]);
+        let mut lines = Vec::new();
+
+        play(&mut RomBuilder::new().build_cpu(), &movie_path, None, || RomBuilder::new().build_cpu(), |cpu, _, _| {
+            lines.push(format!("{} {:016x}", INPUT, cpu.hardware().frame_buffer().hash()));
+            Ok(())
+        }).unwrap();
+
+        fs::remove_file(&movie_path).unwrap();
+        lines
+    }
+
+    fn verify_movie(name: &str, lines: &[String]) -> Result<(), String> {
+        let movie_path = write_movie(name, lines);
+        let result = verify(&mut RomBuilder::new().build_cpu(), &movie_path, None, || RomBuilder::new().build_cpu());
+        fs::remove_file(&movie_path).unwrap();
+        result
+    }
+
+    #[test]
+    fn verifies_matching_hashes() {
+        assert!(verify_movie("matching", &hashed_lines(3)).is_ok());
+    }
+
+    #[test]
+    fn verifies_periodic_hashes() {
+        let mut lines = hashed_lines(3);
+        lines[0] = INPUT.to_string();
+        assert!(verify_movie("periodic", &lines).is_ok());
+    }
+
+    #[test]
+    fn fails_on_hash_mismatch() {
+        let mut lines = hashed_lines(3);
+        lines[2] = format!("{} {:016x}", INPUT, 0);
+        assert!(verify_movie("mismatch", &lines).is_err());
+    }
+
+    #[test]
+    fn fails_without_hashes() {
+        assert!(verify_movie("unhashed", &vec![INPUT.to_string(); 3]).is_err());
+    }
+
+    fn boot_counter() -> Cpu {
+        RomBuilder::new()
+            .code(&[0xE6, 0x10]) // inc $10
+            .build_cpu()
+    }
+
+    // Returns the number of times the game booted since power on, counted in
+    // WRAM
+    fn boot_count(name: &str, lines: &[String]) -> u8 {
+        let movie_path = write_movie(name, lines);
+        let mut cpu = boot_counter();
+
+        play(&mut cpu, &movie_path, None, boot_counter, |_, _, _| Ok(())).unwrap();
+
+        fs::remove_file(&movie_path).unwrap();
+        cpu.hardware_mut().peek(HardwareAddress::new(0x7E, 0x0010)).unwrap()
+    }
+
+    #[test]
+    fn replays_resets() {
+        let reset = format!("reset {}", INPUT);
+        let without_reset = boot_count("no-reset", &vec![INPUT.to_string(); 3]);
+        let with_reset = boot_count("reset", &[INPUT.to_string(), reset.clone(), INPUT.to_string()]);
+        assert_eq!(with_reset, without_reset.wrapping_add(1));
+
+        // Power cycling loses the count kept through the soft reset
+        let power = format!("power {}", INPUT);
+        let with_power = boot_count("power", &[INPUT.to_string(), reset, power, INPUT.to_string()]);
+        assert_eq!(with_power, without_reset);
+    }
+}
@@ -0,0 +1,475 @@
+use cpu::{TraceCondition, TraceTrigger, Watch};
+use hardware::{HardwareAddress, Overclock, RegisterDefaults, Watchpoint};
+use paths;
+use std::env;
+use std::mem;
+use std::path::{Path, PathBuf};
+use util::fill_pattern::FillPattern;
+use util::json::JsonObject;
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum ControllerDevice {
+    Gamepad,
+    Unplugged
+}
+
+pub struct Options {
+    rom_path: Option<PathBuf>,
+    frame_limit: Option<u64>,
+    video_dump_path: Option<PathBuf>,
+    movie_path: Option<PathBuf>,
+    verify_movie_path: Option<PathBuf>,
+    state_dump_path: Option<PathBuf>,
+    restore_crash_path: Option<PathBuf>,
+    patch_path: Option<PathBuf>,
+    strip_header_path: Option<PathBuf>,
+    accuracy_manifest_path: Option<PathBuf>,
+    ppu_record_path: Option<PathBuf>,
+    ppu_replay_path: Option<PathBuf>,
+    trace_file_path: Option<PathBuf>,
+    apu_trace_path: Option<PathBuf>,
+    dsp_rom_path: Option<PathBuf>,
+    slowdown_report: bool,
+    profile: bool,
+    cycle_audit: bool,
+    log_cop: bool,
+    pipe_input: bool,
+    use_session: bool,
+    session_dir: Option<PathBuf>,
+    gdb_port: Option<u16>,
+    hotspot_count: Option<usize>,
+    port_devices: [ControllerDevice; 2],
+    ram_init: FillPattern,
+    register_defaults: RegisterDefaults,
+    overclock: Overclock,
+    watches: Vec<Watch>,
+    breakpoints: Vec<HardwareAddress>,
+    watchpoints: Vec<Watchpoint>,
+    trace_trigger: Option<TraceTrigger>
+}
+
+impl Options {
+    pub fn from_args() -> Options {
+        let mut rom_path = None;
+        let mut frame_limit = None;
+        let mut video_dump_path = None;
+        let mut movie_path = None;
+        let mut verify_movie_path = None;
+        let mut state_dump_path = None;
+        let mut restore_crash_path = None;
+        let mut patch_path = None;
+        let mut strip_header_path = None;
+        let mut accuracy_manifest_path = None;
+        let mut ppu_record_path = None;
+        let mut ppu_replay_path = None;
+        let mut trace_file_path = None;
+        let mut apu_trace_path = None;
+        let mut dsp_rom_path = None;
+        let mut slowdown_report = false;
+        let mut profile = false;
+        let mut cycle_audit = false;
+        let mut log_cop = false;
+        let mut pipe_input = false;
+        let mut use_session = true;
+        let mut session_dir = paths::default_dir("sessions");
+        let mut gdb_port = None;
+        let mut hotspot_count = None;
+        let mut port_devices = [ControllerDevice::Gamepad, ControllerDevice::Unplugged];
+        let mut ram_init = FillPattern::default();
+        let mut register_defaults = RegisterDefaults::Accurate;
+        let mut overclock = Overclock::default();
+        let mut watches = Vec::new();
+        let mut breakpoints = Vec::new();
+        let mut watchpoints = Vec::new();
+        let mut trace_condition = None;
+        let mut trace_limit = None;
+
+        let mut args = env::args_os().skip(1);
+
+        while let Some(arg) = args.next() {
+            match arg.to_str() {
+                Some("--frames") => {
+                    let value = args.next().expect("--frames requires a frame count");
+                    let frames = value.to_str()
+                        .and_then(|value| value.parse::<u64>().ok())
+                        .expect("--frames requires a frame count");
+                    frame_limit = Some(frames);
+                },
+                Some("--dump-video") => {
+                    let value = args.next().expect("--dump-video requires an output path");
+                    video_dump_path = Some(PathBuf::from(value));
+                },
+                Some("--movie") => {
+                    let value = args.next().expect("--movie requires a movie path");
+                    movie_path = Some(PathBuf::from(value));
+                },
+                Some("--verify-movie") => {
+                    let value = args.next().expect("--verify-movie requires a movie path");
+                    verify_movie_path = Some(PathBuf::from(value));
+                },
+                Some("--dump-state") => {
+                    let value = args.next().expect("--dump-state requires an output path");
+                    state_dump_path = Some(PathBuf::from(value));
+                },
+                Some("--restore-crash") => {
+                    let value = args.next().expect("--restore-crash requires a crash report directory");
+                    restore_crash_path = Some(PathBuf::from(value));
+                },
+                Some("--patch") => {
+                    let value = args.next().expect("--patch requires a patch file path");
+                    patch_path = Some(PathBuf::from(value));
+                },
+                Some("--strip-header") => {
+                    let value = args.next().expect("--strip-header requires an output path");
+                    strip_header_path = Some(PathBuf::from(value));
+                },
+                Some("--accuracy") => {
+                    let value = args.next().expect("--accuracy requires a manifest path");
+                    accuracy_manifest_path = Some(PathBuf::from(value));
+                },
+                Some("--record-ppu") => {
+                    let value = args.next().expect("--record-ppu requires an output path");
+                    ppu_record_path = Some(PathBuf::from(value));
+                },
+                Some("--replay-ppu") => {
+                    let value = args.next().expect("--replay-ppu requires a register log path");
+                    ppu_replay_path = Some(PathBuf::from(value));
+                },
+                Some("--port1") | Some("--port2") => {
+                    let port = if arg.to_str() == Some("--port1") { 0 } else { 1 };
+                    let value = args.next().expect("--port1/--port2 requires a device");
+                    port_devices[port] = match value.to_str() {
+                        Some("pad") => ControllerDevice::Gamepad,
+                        Some("none") => ControllerDevice::Unplugged,
+                        _ => panic!("--port1/--port2 must be one of: pad, none")
+                    };
+                },
+                Some("--ram-init") => {
+                    let value = args.next().expect("--ram-init requires a pattern");
+                    ram_init = value.to_str()
+                        .and_then(FillPattern::parse)
+                        .expect("--ram-init must be one of: zero, pattern, random[:seed]");
+                },
+                Some("--register-init") => {
+                    let value = args.next().expect("--register-init requires a mode");
+                    register_defaults = match value.to_str() {
+                        Some("zero") => RegisterDefaults::Zeroed,
+                        Some("accurate") => RegisterDefaults::Accurate,
+                        _ => panic!("--register-init must be one of: zero, accurate")
+                    };
+                },
+                Some("--overclock-fast-rom") => overclock.fast_rom = true,
+                Some("--slowdown-report") => slowdown_report = true,
+                Some("--profile") => profile = true,
+                Some("--cycle-audit") => cycle_audit = true,
+                Some("--log-cop") => log_cop = true,
+                Some("--pipe-input") => pipe_input = true,
+                Some("--no-session") => use_session = false,
+                Some("--session-dir") => {
+                    let value = args.next().expect("--session-dir requires a directory");
+                    session_dir = Some(PathBuf::from(value));
+                },
+                Some("--gdb") => {
+                    let value = args.next().expect("--gdb requires a port");
+                    gdb_port = Some(value.to_str()
+                        .and_then(|value| value.parse::<u16>().ok())
+                        .expect("--gdb requires a port"));
+                },
+                Some("--hotspots") => {
+                    let value = args.next().expect("--hotspots requires a count");
+                    hotspot_count = Some(value.to_str()
+                        .and_then(|value| value.parse::<usize>().ok())
+                        .expect("--hotspots requires a count"));
+                },
+                Some("--overclock-cpu") => {
+                    let value = args.next().expect("--overclock-cpu requires a percentage");
+                    overclock.cpu_speed = value.to_str()
+                        .and_then(|value| value.parse::<u64>().ok())
+                        .and_then(|value| if value > 0 { Some(value) } else { None })
+                        .expect("--overclock-cpu requires a percentage above 0");
+                },
+                Some("--overclock-scanline") => {
+                    let value = args.next().expect("--overclock-scanline requires a cycle count");
+                    overclock.extra_scanline_cycles = value.to_str()
+                        .and_then(|value| value.parse::<u64>().ok())
+                        .expect("--overclock-scanline requires a cycle count");
+                },
+                Some("--watch") => {
+                    let value = args.next().expect("--watch requires an address");
+                    let watch = value.to_str()
+                        .and_then(Watch::parse)
+                        .expect("--watch must be BB:OOOO[,hex8|hex16|dec8|dec16]");
+                    watches.push(watch);
+                },
+                Some("--break") => {
+                    let value = args.next().expect("--break requires an address");
+                    let address = value.to_str()
+                        .and_then(HardwareAddress::parse)
+                        .expect("--break must be BB:OOOO");
+                    breakpoints.push(address);
+                },
+                Some("--watchpoint") => {
+                    let value = args.next().expect("--watchpoint requires an address range");
+                    let watchpoint = value.to_str()
+                        .and_then(Watchpoint::parse)
+                        .expect("--watchpoint must be BB:OOOO[-BB:OOOO][,r|w|rw]");
+                    watchpoints.push(watchpoint);
+                },
+                Some("--trace-on") => {
+                    let value = args.next().expect("--trace-on requires a condition");
+                    trace_condition = Some(value.to_str()
+                        .and_then(TraceCondition::parse)
+                        .expect("--trace-on must be pc:BB:OOOO, write:BB:OOOO or nmi:N"));
+                },
+                Some("--trace-file") => {
+                    let value = args.next().expect("--trace-file requires an output path");
+                    trace_file_path = Some(PathBuf::from(value));
+                },
+                Some("--dsp-rom") => {
+                    let value = args.next().expect("--dsp-rom requires a program ROM path");
+                    dsp_rom_path = Some(PathBuf::from(value));
+                },
+                Some("--apu-trace") => {
+                    let value = args.next().expect("--apu-trace requires an output path");
+                    apu_trace_path = Some(PathBuf::from(value));
+                },
+                Some("--trace-for") => {
+                    let value = args.next().expect("--trace-for requires an instruction count");
+                    trace_limit = Some(value.to_str()
+                        .and_then(|value| value.parse::<u64>().ok())
+                        .expect("--trace-for requires an instruction count"));
+                },
+                Some(option) if option.starts_with("--") => panic!("Unknown option: {}", option),
+                _ => rom_path = Some(PathBuf::from(arg))
+            }
+        }
+
+        // Replaying a PPU register log and running the accuracy suite are the
+        // only modes that run without a ROM
+        if rom_path.is_none() && ppu_replay_path.is_none() && accuracy_manifest_path.is_none() {
+            panic!("No ROM path specified");
+        }
+
+        if movie_path.is_some() && video_dump_path.is_none() {
+            panic!("--movie plays back headlessly, so it requires --dump-video");
+        }
+
+        // Movies and scripted runs are compared against other runs, so they
+        // must stay deterministic and match real hardware
+        let scripted = movie_path.is_some() || verify_movie_path.is_some() || frame_limit.is_some() || pipe_input;
+
+        if scripted && overclock != Overclock::default() {
+            panic!("--overclock-* options are inaccurate, so can't be used with --movie, --verify-movie, --frames or --pipe-input");
+        }
+
+        if ppu_record_path.is_some() && frame_limit.map_or(true, |limit| limit == 0) {
+            panic!("--record-ppu records the last frame before --frames, which must be at least 1");
+        }
+
+        Options {
+            rom_path: rom_path,
+            frame_limit: frame_limit,
+            video_dump_path: video_dump_path,
+            movie_path: movie_path,
+            verify_movie_path: verify_movie_path,
+            state_dump_path: state_dump_path,
+            restore_crash_path: restore_crash_path,
+            patch_path: patch_path,
+            strip_header_path: strip_header_path,
+            accuracy_manifest_path: accuracy_manifest_path,
+            ppu_record_path: ppu_record_path,
+            ppu_replay_path: ppu_replay_path,
+            trace_file_path: trace_file_path,
+            apu_trace_path: apu_trace_path,
+            dsp_rom_path: dsp_rom_path,
+            slowdown_report: slowdown_report,
+            profile: profile,
+            cycle_audit: cycle_audit,
+            log_cop: log_cop,
+            pipe_input: pipe_input,
+            // Runs with a frame limit or piped input are scripted, and must
+            // not depend on (or leave behind) state from earlier interactive runs
+            use_session: use_session && frame_limit.is_none() && !pipe_input,
+            session_dir: session_dir,
+            gdb_port: gdb_port,
+            hotspot_count: hotspot_count,
+            port_devices: port_devices,
+            ram_init: ram_init,
+            register_defaults: register_defaults,
+            overclock: overclock,
+            watches: watches,
+            breakpoints: breakpoints,
+            watchpoints: watchpoints,
+            trace_trigger: trace_condition.map(|condition| TraceTrigger::new(condition, trace_limit))
+        }
+    }
+
+    // Settings that affect how the game runs, for reproducing a run elsewhere
+    pub fn settings_json(&self) -> JsonObject {
+        let mut settings = JsonObject::new();
+
+        for &(name, path) in [
+            ("rom", &self.rom_path),
+            ("patch", &self.patch_path),
+            ("dsp_rom", &self.dsp_rom_path),
+            ("movie", &self.movie_path),
+            ("verify_movie", &self.verify_movie_path)
+        ].iter() {
+            if let Some(ref path) = *path {
+                settings.string(name, &path.display().to_string());
+            }
+        }
+
+        if let Some(frame_limit) = self.frame_limit {
+            settings.number("frames", frame_limit);
+        }
+
+        for &(name, device) in [("port1", self.port_devices[0]), ("port2", self.port_devices[1])].iter() {
+            settings.string(name, match device {
+                ControllerDevice::Gamepad => "pad",
+                ControllerDevice::Unplugged => "none"
+            });
+        }
+
+        settings.string("ram_init", &self.ram_init.to_string());
+        settings.string("register_init", match self.register_defaults {
+            RegisterDefaults::Zeroed => "zero",
+            RegisterDefaults::Accurate => "accurate"
+        });
+        settings.boolean("overclock_fast_rom", self.overclock.fast_rom);
+        settings.number("overclock_cpu", self.overclock.cpu_speed);
+        settings.number("overclock_scanline", self.overclock.extra_scanline_cycles);
+        settings.boolean("pipe_input", self.pipe_input);
+        settings.boolean("use_session", self.use_session);
+
+        settings
+    }
+
+    pub fn rom_path(&self) -> &PathBuf {
+        self.rom_path.as_ref().expect("No ROM path specified")
+    }
+
+    pub fn frame_limit(&self) -> Option<u64> {
+        self.frame_limit
+    }
+
+    pub fn video_dump_path(&self) -> Option<&PathBuf> {
+        self.video_dump_path.as_ref()
+    }
+
+    pub fn movie_path(&self) -> Option<&PathBuf> {
+        self.movie_path.as_ref()
+    }
+
+    pub fn verify_movie_path(&self) -> Option<&PathBuf> {
+        self.verify_movie_path.as_ref()
+    }
+
+    pub fn state_dump_path(&self) -> Option<&PathBuf> {
+        self.state_dump_path.as_ref()
+    }
+
+    pub fn restore_crash_path(&self) -> Option<&PathBuf> {
+        self.restore_crash_path.as_ref()
+    }
+
+    pub fn patch_path(&self) -> Option<&PathBuf> {
+        self.patch_path.as_ref()
+    }
+
+    pub fn strip_header_path(&self) -> Option<&PathBuf> {
+        self.strip_header_path.as_ref()
+    }
+
+    pub fn accuracy_manifest_path(&self) -> Option<&PathBuf> {
+        self.accuracy_manifest_path.as_ref()
+    }
+
+    pub fn ppu_record_path(&self) -> Option<&PathBuf> {
+        self.ppu_record_path.as_ref()
+    }
+
+    pub fn ppu_replay_path(&self) -> Option<&PathBuf> {
+        self.ppu_replay_path.as_ref()
+    }
+
+    pub fn trace_file_path(&self) -> Option<&PathBuf> {
+        self.trace_file_path.as_ref()
+    }
+
+    pub fn dsp_rom_path(&self) -> Option<&PathBuf> {
+        self.dsp_rom_path.as_ref()
+    }
+
+    pub fn apu_trace_path(&self) -> Option<&PathBuf> {
+        self.apu_trace_path.as_ref()
+    }
+
+    pub fn slowdown_report(&self) -> bool {
+        self.slowdown_report
+    }
+
+    pub fn profile(&self) -> bool {
+        self.profile
+    }
+
+    pub fn cycle_audit(&self) -> bool {
+        self.cycle_audit
+    }
+
+    pub fn log_cop(&self) -> bool {
+        self.log_cop
+    }
+
+    pub fn pipe_input(&self) -> bool {
+        self.pipe_input
+    }
+
+    pub fn use_session(&self) -> bool {
+        self.use_session
+    }
+
+    pub fn session_dir(&self) -> Option<&Path> {
+        self.session_dir.as_ref().map(|dir| dir.as_path())
+    }
+
+    pub fn gdb_port(&self) -> Option<u16> {
+        self.gdb_port
+    }
+
+    pub fn hotspot_count(&self) -> Option<usize> {
+        self.hotspot_count
+    }
+
+    pub fn port_device(&self, port: usize) -> ControllerDevice {
+        self.port_devices[port]
+    }
+
+    pub fn ram_init(&self) -> FillPattern {
+        self.ram_init
+    }
+
+    pub fn register_defaults(&self) -> RegisterDefaults {
+        self.register_defaults
+    }
+
+    pub fn overclock(&self) -> Overclock {
+        self.overclock
+    }
+
+    pub fn take_watches(&mut self) -> Vec<Watch> {
+        mem::replace(&mut self.watches, Vec::new())
+    }
+
+    pub fn breakpoints(&self) -> &[HardwareAddress] {
+        &self.breakpoints
+    }
+
+    pub fn take_watchpoints(&mut self) -> Vec<Watchpoint> {
+        mem::replace(&mut self.watchpoints, Vec::new())
+    }
+
+    pub fn take_trace_trigger(&mut self) -> Option<TraceTrigger> {
+        self.trace_trigger.take()
+    }
+}
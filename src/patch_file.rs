@@ -0,0 +1,40 @@
+use hardware::HardwareAddress;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+// Reads a text file of "BB:OOOO=VV" lines (all hex). Blank lines and
+// anything after a '#' are ignored.
+pub fn load(path: &Path) -> Result<Vec<(HardwareAddress, u8)>, String> {
+    let file = File::open(path).map_err(|error| format!("Could not open patch file: {}", error))?;
+    let mut patches = Vec::new();
+
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|error| format!("Could not read patch file: {}", error))?;
+        let line = line.splitn(2, '#').next().unwrap().trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut parts = line.splitn(2, '=');
+
+        let patch = match (parts.next(), parts.next()) {
+            (Some(address), Some(value)) => {
+                HardwareAddress::parse(address.trim()).and_then(|address| {
+                    u8::from_str_radix(value.trim(), 16).ok().map(|value| (address, value))
+                })
+            },
+            _ => None
+        };
+
+        match patch {
+            Some(patch) => patches.push(patch),
+            None => return Err(format!("Invalid patch on line {}: {}", index + 1, line))
+        }
+    }
+
+    info!("{} patches loaded", patches.len());
+
+    Ok(patches)
+}
@@ -0,0 +1,30 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+// Per-game files are written under the user's data directory rather than
+// next to the ROM, so ROM collections can be read-only:
+//
+// $XDG_DATA_HOME/snailemu/<kind> (default ~/.local/share/snailemu/<kind>)
+//
+// Each kind of file can be pointed elsewhere from the command line. If no
+// data directory can be found, files go next to the ROM as a last resort.
+
+pub fn default_dir(kind: &str) -> Option<PathBuf> {
+    let data_home = match env::var_os("XDG_DATA_HOME") {
+        Some(ref path) if !path.is_empty() => PathBuf::from(path),
+        _ => match env::var_os("HOME") {
+            Some(home) => Path::new(&home).join(".local").join("share"),
+            None => return None
+        }
+    };
+
+    Some(data_home.join("snailemu").join(kind))
+}
+
+// e.g. ("saves/", "games/Foo.sfc", "session") => "saves/Foo.session"
+pub fn file_for_rom(dir: Option<&Path>, rom_path: &Path, extension: &str) -> PathBuf {
+    match (dir, rom_path.file_name()) {
+        (Some(dir), Some(file_name)) => dir.join(file_name).with_extension(extension),
+        _ => rom_path.with_extension(extension)
+    }
+}
@@ -0,0 +1,50 @@
+use cpu::Cpu;
+use std::time::{Duration, Instant};
+
+const REPORT_FRAMES: u64 = 60;
+
+fn seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1_000_000_000.0
+}
+
+// Logs, once a second of emulated time, the share of host time spent in each
+// subsystem. The remainder goes to the CPU, DMA and the frontend.
+pub struct ProfileReport {
+    start_time: Instant,
+    start_frame: u64
+}
+
+impl ProfileReport {
+    pub fn new(cpu: &mut Cpu) -> ProfileReport {
+        cpu.hardware_mut().enable_profiler();
+
+        ProfileReport {
+            start_time: Instant::now(),
+            start_frame: cpu.hardware().frame_count()
+        }
+    }
+
+    pub fn update(&mut self, cpu: &mut Cpu) {
+        let frames = cpu.hardware().frame_count() - self.start_frame;
+
+        if frames < REPORT_FRAMES {
+            return;
+        }
+
+        let profile = cpu.hardware_mut().take_profile().unwrap();
+        let elapsed_seconds = seconds(self.start_time.elapsed());
+        let ppu_percent = 100.0 * seconds(profile.ppu) / elapsed_seconds;
+        let coprocessor_percent = 100.0 * seconds(profile.coprocessor) / elapsed_seconds;
+        let other_percent = (100.0 - ppu_percent - coprocessor_percent).max(0.0);
+
+        info!("Profile: PPU {:.0}%, coprocessor {:.0}%, CPU/other {:.0}% ({:.2}s for {} frames)",
+            ppu_percent,
+            coprocessor_percent,
+            other_percent,
+            elapsed_seconds,
+            frames);
+
+        self.start_time = Instant::now();
+        self.start_frame = cpu.hardware().frame_count();
+    }
+}
@@ -0,0 +1,84 @@
+use hardware::{FrameBuffer, FRAME_HEIGHT, FRAME_WIDTH};
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Renderer, Texture, TextureAccess};
+use sdl2::VideoSubsystem;
+use std::fs::File;
+use video_dump::VideoDump;
+
+const DISPLAY_WIDTH: u32 = 512;
+const DISPLAY_HEIGHT: u32 = 478;
+
+// Frames alternate between two textures, so uploading a frame never has to
+// wait for the GPU to finish drawing the previous one from the same texture
+const TEXTURE_COUNT: usize = 2;
+
+pub struct Screen {
+    renderer: Renderer<'static>,
+    textures: Vec<Texture>,
+    next_texture: usize,
+    video_dump: Option<VideoDump>
+}
+
+impl Screen {
+    pub fn new(video_subsystem: &VideoSubsystem) -> Screen {
+        let window = video_subsystem
+            .window("SNAIL", DISPLAY_WIDTH, DISPLAY_HEIGHT)
+            .position_centered()
+            .build()
+            .unwrap();
+
+        let renderer = window.renderer()
+            .accelerated()
+            .build()
+            .unwrap();
+
+        let textures = (0..TEXTURE_COUNT).map(|_| {
+            let mut texture = renderer
+                .create_texture(
+                    PixelFormatEnum::ARGB8888,
+                    TextureAccess::Streaming,
+                    FRAME_WIDTH as u32,
+                    FRAME_HEIGHT as u32
+                )
+                .unwrap();
+
+            texture.set_blend_mode(BlendMode::Blend);
+            texture
+        }).collect();
+
+        Screen {
+            renderer: renderer,
+            textures: textures,
+            next_texture: 0,
+            video_dump: None
+        }
+    }
+
+    pub fn set_video_dump(&mut self, file: File) {
+        self.video_dump = Some(VideoDump::new(file));
+    }
+
+    pub fn present(&mut self, frame_buffer: &FrameBuffer) {
+        self.renderer.clear();
+
+        let index = self.next_texture;
+        self.next_texture = (index + 1) % TEXTURE_COUNT;
+
+        self.textures[index].update(None, frame_buffer.pixels(), frame_buffer.row_length()).unwrap();
+
+        let (src_rect, dst_rect) = if frame_buffer.overscan() {
+            (Rect::new(0, 0, 512, 478), Rect::new(0, 0, 512, 478))
+        } else {
+            (Rect::new(0, 0, 512, 448), Rect::new(0, 15, 512, 448))
+        };
+
+        if let Some(ref mut video_dump) = self.video_dump {
+            video_dump.write_frame(frame_buffer);
+        }
+
+        self.renderer.copy(&self.textures[index], Some(src_rect), Some(dst_rect)).unwrap();
+
+        self.renderer.present();
+    }
+}
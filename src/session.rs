@@ -0,0 +1,115 @@
+use cpu::{Cpu, Watch};
+use hardware::{HardwareAddress, Watchpoint};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+// A debugger session (breakpoints, watchpoints, watches and read patches),
+// kept per game so that it carries over between runs (see paths.rs for
+// where). One item per line, in the same formats as the matching command
+// line options:
+//
+// break 00:8000
+// watchpoint 7E:0010-7E:001F,w
+// watch 7E:0100,hex16
+// patch 00:8123=EA
+
+
+pub fn load(path: &Path, cpu: &mut Cpu) {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => return
+    };
+
+    let mut count = 0;
+
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.unwrap();
+        let line = line.splitn(2, '#').next().unwrap().trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut parts = line.splitn(2, ' ');
+
+        let loaded = match (parts.next(), parts.next().map(str::trim)) {
+            (Some("break"), Some(value)) => HardwareAddress::parse(value)
+                .map(|address| cpu.breakpoints_mut().set(address)),
+            // Skip anything already set up from the command line
+            (Some("watchpoint"), Some(value)) => Watchpoint::parse(value).map(|watchpoint| {
+                let line = watchpoint.to_string();
+                if !cpu.hardware().watchpoints().iter().any(|existing| existing.to_string() == line) {
+                    cpu.hardware_mut().add_watchpoint(watchpoint);
+                }
+            }),
+            (Some("watch"), Some(value)) => Watch::parse(value).map(|watch| {
+                let line = watch.to_string();
+                if !cpu.watches().iter().any(|existing| existing.to_string() == line) {
+                    cpu.add_watch(watch);
+                }
+            }),
+            (Some("patch"), Some(value)) => parse_patch(value)
+                .map(|(address, value)| cpu.hardware_mut().add_read_patch(address, value)),
+            _ => None
+        };
+
+        match loaded {
+            Some(()) => count += 1,
+            None => warn!("Ignoring invalid session entry on line {}: {}", index + 1, line)
+        }
+    }
+
+    info!("{} session entries restored from {}", count, path.display());
+}
+
+// An empty session removes the file rather than leaving one behind
+pub fn save(path: &Path, cpu: &Cpu) {
+    let mut lines: Vec<String> = Vec::new();
+
+    {
+        let mut add = |line: String| if !lines.contains(&line) { lines.push(line) };
+
+        for address in cpu.breakpoints().list() {
+            add(format!("break {}", address));
+        }
+
+        for watchpoint in cpu.hardware().watchpoints() {
+            add(format!("watchpoint {}", watchpoint));
+        }
+
+        for watch in cpu.watches() {
+            add(format!("watch {}", watch));
+        }
+
+        for (address, value) in cpu.hardware().read_patches() {
+            add(format!("patch {}={:02X}", address, value));
+        }
+    }
+
+    if lines.is_empty() {
+        if path.exists() {
+            fs::remove_file(path).unwrap();
+        }
+        return;
+    }
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).unwrap();
+    }
+
+    let mut file = BufWriter::new(File::create(path).unwrap());
+
+    for line in lines.iter() {
+        writeln!(file, "{}", line).unwrap();
+    }
+}
+
+fn parse_patch(value: &str) -> Option<(HardwareAddress, u8)> {
+    let mut parts = value.splitn(2, '=');
+
+    match (parts.next().and_then(HardwareAddress::parse), parts.next()) {
+        (Some(address), Some(value)) => u8::from_str_radix(value, 16).ok().map(|value| (address, value)),
+        _ => None
+    }
+}
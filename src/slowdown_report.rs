@@ -0,0 +1,67 @@
+use cpu::Cpu;
+use std::time::Instant;
+
+const REPORT_FRAMES: u64 = 60;
+
+// NTSC frame rate
+const FRAMES_PER_SECOND: f64 = 60.0988;
+
+// Below this, the host isn't keeping up with real time
+const MINIMUM_SPEED_PERCENT: f64 = 95.0;
+
+// Logs, once a second of emulated time, whether any slowdown is the game's
+// own (lag frames, with little idle time) or the emulator's (the host not
+// keeping up with real time)
+pub struct SlowdownReport {
+    start_time: Instant,
+    start_frame: u64,
+    start_lag_frames: u64,
+    start_clock: u64,
+    start_idle_cycles: u64
+}
+
+impl SlowdownReport {
+    pub fn new(cpu: &Cpu) -> SlowdownReport {
+        SlowdownReport {
+            start_time: Instant::now(),
+            start_frame: cpu.hardware().frame_count(),
+            start_lag_frames: cpu.hardware().lag_frames(),
+            start_clock: cpu.hardware().master_cycles(),
+            start_idle_cycles: cpu.idle_cycles()
+        }
+    }
+
+    pub fn update(&mut self, cpu: &Cpu) {
+        let frames = cpu.hardware().frame_count() - self.start_frame;
+
+        if frames < REPORT_FRAMES {
+            return;
+        }
+
+        let lag_frames = cpu.hardware().lag_frames() - self.start_lag_frames;
+        let cycles = cpu.hardware().master_cycles().wrapping_sub(self.start_clock);
+        let idle_cycles = cpu.idle_cycles() - self.start_idle_cycles;
+        let idle_percent = 100.0 * idle_cycles as f64 / cycles.max(1) as f64;
+
+        let elapsed = self.start_time.elapsed();
+        let elapsed_seconds = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1_000_000_000.0;
+        let speed_percent = 100.0 * (frames as f64 / FRAMES_PER_SECOND) / elapsed_seconds;
+
+        let verdict = if speed_percent < MINIMUM_SPEED_PERCENT {
+            "emulator slowdown"
+        } else if lag_frames > 0 {
+            "game slowdown"
+        } else {
+            "full speed"
+        };
+
+        info!("Slowdown: {}/{} lag frames, CPU {:.0}% idle, host at {:.0}% speed ({})",
+            lag_frames,
+            frames,
+            idle_percent,
+            speed_percent,
+            verdict);
+
+        *self = SlowdownReport::new(cpu);
+    }
+}
@@ -19,6 +19,9 @@ pub trait ByteAccess : Copy {
     }
 }
 
+// Registers accessed a byte at a time through a single address, with a byte
+// selector that flips on each access. These have their own reset rules (e.g.
+// reading $213F resets the selectors for $213C/$213D).
 pub struct WriteTwice<T: ByteAccess> {
     value: T,
     write_mask: T,
@@ -28,6 +31,14 @@ pub struct WriteTwice<T: ByteAccess> {
 // This is just an alias
 pub type ReadTwice<T> = WriteTwice<T>;
 
+// Most 16-bit PPU registers have no byte selector. Each write instead forms a
+// new value from the byte written (as the upper byte) and the previous byte
+// written to any register sharing the same latch, so write order matters
+// across registers as well as within them.
+pub struct SharedLatch {
+    previous: u8
+}
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum ByteSelector {
     Lower,
@@ -87,6 +98,12 @@ impl<T: ByteAccess> WriteTwice<T> {
         };
     }
 
+    // Bits outside the write mask aren't driven, and return open bus
+    pub fn read_with_open_bus(&mut self, open_bus: u8) -> u8 {
+        let mask = self.write_mask.get(self.byte_selector);
+        (self.read() & mask) | (open_bus & !mask)
+    }
+
     pub fn read(&mut self) -> u8 {
         match self.byte_selector {
             ByteSelector::Lower => {
@@ -100,3 +117,17 @@ impl<T: ByteAccess> WriteTwice<T> {
         }
     }
 }
+
+impl SharedLatch {
+    pub fn new() -> SharedLatch {
+        SharedLatch {
+            previous: 0x00
+        }
+    }
+
+    pub fn write(&mut self, value: u8) -> u16 {
+        let word = ((value as u16) << 8) | (self.previous as u16);
+        self.previous = value;
+        word
+    }
+}
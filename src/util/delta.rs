@@ -0,0 +1,210 @@
+// Incremental snapshots for rewind and run-ahead. Each snapshot is XORed
+// against the previous one, so unchanged bytes become zero, and the result is
+// stored as alternating runs: a 16-bit count of unchanged bytes, a 16-bit
+// count of changed bytes, then the changed (XORed) bytes themselves.
+
+use std::fmt::{self, Display, Formatter};
+
+const MAX_RUN: usize = 0xFFFF;
+
+pub struct DeltaEncoder {
+    previous: Vec<u8>
+}
+
+// Every snapshot must be the same size as the encoder
+#[derive(Debug, Eq, PartialEq)]
+pub struct SizeMismatch {
+    pub expected: usize,
+    pub actual: usize
+}
+
+impl DeltaEncoder {
+    pub fn new(size: usize) -> DeltaEncoder {
+        DeltaEncoder {
+            previous: vec![0; size]
+        }
+    }
+
+    pub fn encode(&mut self, current: &[u8]) -> Result<Vec<u8>, SizeMismatch> {
+        if current.len() != self.previous.len() {
+            return Err(SizeMismatch {
+                expected: self.previous.len(),
+                actual: current.len()
+            });
+        }
+
+        let mut output = Vec::new();
+        let mut index = 0;
+
+        while index < current.len() {
+            let mut unchanged = 0;
+
+            while index + unchanged < current.len() && unchanged < MAX_RUN &&
+                current[index + unchanged] == self.previous[index + unchanged] {
+                unchanged += 1;
+            }
+
+            index += unchanged;
+
+            let mut changed = 0;
+
+            while index + changed < current.len() && changed < MAX_RUN &&
+                current[index + changed] != self.previous[index + changed] {
+                changed += 1;
+            }
+
+            push_length(&mut output, unchanged);
+            push_length(&mut output, changed);
+
+            for offset in index..(index + changed) {
+                output.push(current[offset] ^ self.previous[offset]);
+            }
+
+            index += changed;
+        }
+
+        self.previous.copy_from_slice(current);
+
+        Ok(output)
+    }
+
+    pub fn reset(&mut self) {
+        for byte in self.previous.iter_mut() {
+            *byte = 0x00;
+        }
+    }
+}
+
+// Applies a delta produced by DeltaEncoder::encode. XOR is its own inverse,
+// so the same delta steps a snapshot both forwards and backwards in time.
+pub fn apply_delta(data: &mut [u8], delta: &[u8]) {
+    let mut index = 0;
+    let mut position = 0;
+
+    while position < delta.len() {
+        let unchanged = read_length(delta, position);
+        let changed = read_length(delta, position + 2);
+        position += 4;
+        index += unchanged;
+
+        for offset in 0..changed {
+            data[index + offset] ^= delta[position + offset];
+        }
+
+        position += changed;
+        index += changed;
+    }
+}
+
+impl Display for SizeMismatch {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Snapshot size changed from {} to {}", self.expected, self.actual)
+    }
+}
+
+fn push_length(output: &mut Vec<u8>, length: usize) {
+    output.push(length as u8);
+    output.push((length >> 8) as u8);
+}
+
+fn read_length(delta: &[u8], position: usize) -> usize {
+    (delta[position] as usize) | ((delta[position + 1] as usize) << 8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply_delta, DeltaEncoder, SizeMismatch, MAX_RUN};
+
+    // Encodes each snapshot in turn, then checks the deltas rebuild every
+    // snapshot going forwards from zero and backwards from the last one
+    fn assert_round_trip(snapshots: &[Vec<u8>]) -> Vec<Vec<u8>> {
+        let size = snapshots[0].len();
+        let mut encoder = DeltaEncoder::new(size);
+
+        let deltas: Vec<Vec<u8>> = snapshots.iter()
+            .map(|snapshot| encoder.encode(snapshot).unwrap())
+            .collect();
+
+        let mut data = vec![0; size];
+
+        for (delta, snapshot) in deltas.iter().zip(snapshots.iter()) {
+            apply_delta(&mut data, delta);
+            assert!(data == *snapshot);
+        }
+
+        for (index, delta) in deltas.iter().enumerate().rev() {
+            apply_delta(&mut data, delta);
+
+            if index > 0 {
+                assert!(data == snapshots[index - 1]);
+            } else {
+                assert!(data.iter().all(|&byte| byte == 0x00));
+            }
+        }
+
+        deltas
+    }
+
+    #[test]
+    fn round_trips_scattered_changes() {
+        let first: Vec<u8> = (0..1000).map(|index| (index % 7) as u8).collect();
+        let mut second = first.clone();
+        second[0] = 0xFF;
+        second[500] = 0xAA;
+        second[501] = 0xBB;
+        second[999] = 0x11;
+
+        assert_round_trip(&[first, second]);
+    }
+
+    #[test]
+    fn round_trips_runs_longer_than_max_run() {
+        let size = MAX_RUN * 3 + 10;
+
+        // All bytes changed, then only a tail after a long unchanged run
+        let first = vec![0x55; size];
+        let mut second = first.clone();
+        for byte in second[(MAX_RUN * 2 + 5)..].iter_mut() {
+            *byte = 0x66;
+        }
+
+        assert_round_trip(&[first, second]);
+    }
+
+    #[test]
+    fn round_trips_runs_at_max_run_boundaries() {
+        for &length in [MAX_RUN - 1, MAX_RUN, MAX_RUN + 1].iter() {
+            // A changed run of 'length', then an unchanged run of 'length'
+            let mut snapshot = vec![0x00; length * 2 + 1];
+            for byte in snapshot[..length].iter_mut() {
+                *byte = 0x01;
+            }
+            snapshot[length * 2] = 0x02;
+
+            assert_round_trip(&[snapshot]);
+        }
+    }
+
+    #[test]
+    fn unchanged_snapshot_has_no_changed_bytes() {
+        let size = MAX_RUN * 2 + 1;
+        let snapshot: Vec<u8> = (0..size).map(|index| index as u8).collect();
+
+        let deltas = assert_round_trip(&[snapshot.clone(), snapshot]);
+
+        // One header per run of at most MAX_RUN unchanged bytes
+        assert_eq!(deltas[1], vec![
+            0xFF, 0xFF, 0x00, 0x00,
+            0xFF, 0xFF, 0x00, 0x00,
+            0x01, 0x00, 0x00, 0x00
+        ]);
+    }
+
+    #[test]
+    fn rejects_snapshot_of_different_size() {
+        let mut encoder = DeltaEncoder::new(16);
+
+        assert_eq!(encoder.encode(&[0; 8]), Err(SizeMismatch { expected: 16, actual: 8 }));
+        assert!(encoder.encode(&[0; 16]).is_ok());
+    }
+}
@@ -0,0 +1,70 @@
+use std::fmt::{self, Display, Formatter};
+
+const DEFAULT_SEED: u32 = 0x2A5A_C0DE;
+
+const BANK_SIZE: usize = 0x100;
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum FillPattern {
+    Zero,
+    Banked,
+    Random(u32)
+}
+
+impl FillPattern {
+    pub fn parse(value: &str) -> Option<FillPattern> {
+        let mut parts = value.splitn(2, ':');
+
+        match (parts.next(), parts.next()) {
+            (Some("zero"), None) => Some(FillPattern::Zero),
+            (Some("pattern"), None) => Some(FillPattern::Banked),
+            (Some("random"), None) => Some(FillPattern::Random(DEFAULT_SEED)),
+            (Some("random"), Some(seed)) => seed.parse::<u32>().ok().map(FillPattern::Random),
+            _ => None
+        }
+    }
+
+    pub fn fill(&self, data: &mut [u8]) {
+        match *self {
+            FillPattern::Zero => {
+                for byte in data.iter_mut() {
+                    *byte = 0x00;
+                }
+            },
+            FillPattern::Banked => {
+                // Alternating runs of $55 and $AA, similar to what many consoles power on with
+                for (offset, byte) in data.iter_mut().enumerate() {
+                    *byte = if (offset / BANK_SIZE) % 2 == 0 { 0x55 } else { 0xAA };
+                }
+            },
+            FillPattern::Random(seed) => {
+                // Xorshift, so the same seed always gives the same memory contents
+                let mut state = if seed != 0 { seed } else { DEFAULT_SEED };
+
+                for byte in data.iter_mut() {
+                    state ^= state << 13;
+                    state ^= state >> 17;
+                    state ^= state << 5;
+                    *byte = state as u8;
+                }
+            }
+        }
+    }
+}
+
+// In the form accepted by parse
+impl Display for FillPattern {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            FillPattern::Zero => write!(f, "zero"),
+            FillPattern::Banked => write!(f, "pattern"),
+            FillPattern::Random(seed) => write!(f, "random:{}", seed)
+        }
+    }
+}
+
+impl Default for FillPattern {
+    fn default() -> FillPattern {
+        FillPattern::Zero
+    }
+}
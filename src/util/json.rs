@@ -0,0 +1,62 @@
+use std::fmt::{self, Display, Formatter};
+
+// Just enough JSON to dump machine state. Fields are written in the order
+// they are added, so two dumps can be compared with a plain text diff.
+pub struct JsonObject {
+    fields: Vec<(&'static str, String)>
+}
+
+impl JsonObject {
+    pub fn new() -> JsonObject {
+        JsonObject {
+            fields: Vec::new()
+        }
+    }
+
+    pub fn number<T: Into<u64>>(&mut self, name: &'static str, value: T) {
+        self.fields.push((name, value.into().to_string()));
+    }
+
+    pub fn boolean(&mut self, name: &'static str, value: bool) {
+        self.fields.push((name, value.to_string()));
+    }
+
+    pub fn string(&mut self, name: &'static str, value: &str) {
+        let mut escaped = String::with_capacity(value.len() + 2);
+        escaped.push('"');
+        for c in value.chars() {
+            match c {
+                '"' => escaped.push_str("\\\""),
+                '\\' => escaped.push_str("\\\\"),
+                c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+                c => escaped.push(c)
+            }
+        }
+        escaped.push('"');
+        self.fields.push((name, escaped));
+    }
+
+    pub fn object(&mut self, name: &'static str, value: JsonObject) {
+        self.fields.push((name, value.to_string()));
+    }
+
+    pub fn array(&mut self, name: &'static str, values: Vec<JsonObject>) {
+        let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+        self.fields.push((name, format!("[{}]", values.join(","))));
+    }
+}
+
+impl Display for JsonObject {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{{")?;
+
+        for (index, &(name, ref value)) in self.fields.iter().enumerate() {
+            if index > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "\"{}\":{}", name, value)?;
+        }
+
+        write!(f, "}}")
+    }
+}
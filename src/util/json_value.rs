@@ -0,0 +1,203 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+// Just enough of a JSON reader for the CPU test vectors. Numbers are all
+// parsed as f64, which holds every value the vectors use exactly.
+pub enum JsonValue {
+    Null,
+    Boolean(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<JsonValue>),
+    Object(Vec<(String, JsonValue)>)
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>
+}
+
+impl JsonValue {
+    pub fn parse(text: &str) -> Result<JsonValue, String> {
+        let mut parser = Parser {
+            chars: text.chars().peekable()
+        };
+
+        let value = parser.value()?;
+        parser.skip_whitespace();
+
+        match parser.chars.next() {
+            Some(c) => Err(format!("Unexpected '{}' after value", c)),
+            None => Ok(value)
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&JsonValue> {
+        match *self {
+            JsonValue::Object(ref fields) => fields.iter().find(|&&(ref key, _)| key == name).map(|&(_, ref value)| value),
+            _ => None
+        }
+    }
+
+    pub fn as_u32(&self) -> Option<u32> {
+        match *self {
+            JsonValue::Number(value) if value >= 0.0 && value <= u32::max_value() as f64 => Some(value as u32),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            JsonValue::String(ref value) => Some(value),
+            _ => None
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[JsonValue]> {
+        match *self {
+            JsonValue::Array(ref values) => Some(values),
+            _ => None
+        }
+    }
+}
+
+impl<'a> Parser<'a> {
+    fn value(&mut self) -> Result<JsonValue, String> {
+        self.skip_whitespace();
+
+        match self.chars.peek().cloned() {
+            Some('{') => self.object(),
+            Some('[') => self.array(),
+            Some('"') => self.string().map(JsonValue::String),
+            Some('t') => self.keyword("true", JsonValue::Boolean(true)),
+            Some('f') => self.keyword("false", JsonValue::Boolean(false)),
+            Some('n') => self.keyword("null", JsonValue::Null),
+            Some(c) if c == '-' || c.is_digit(10) => self.number(),
+            Some(c) => Err(format!("Unexpected '{}'", c)),
+            None => Err("Unexpected end of input".to_owned())
+        }
+    }
+
+    fn object(&mut self) -> Result<JsonValue, String> {
+        self.chars.next();
+        let mut fields = Vec::new();
+
+        if self.next_is('}') {
+            return Ok(JsonValue::Object(fields));
+        }
+
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.expect(':')?;
+            fields.push((key, self.value()?));
+
+            if !self.next_is(',') {
+                self.expect('}')?;
+                return Ok(JsonValue::Object(fields));
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<JsonValue, String> {
+        self.chars.next();
+        let mut values = Vec::new();
+
+        if self.next_is(']') {
+            return Ok(JsonValue::Array(values));
+        }
+
+        loop {
+            values.push(self.value()?);
+
+            if !self.next_is(',') {
+                self.expect(']')?;
+                return Ok(JsonValue::Array(values));
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        if self.chars.next() != Some('"') {
+            return Err("Expected a string".to_owned());
+        }
+
+        let mut value = String::new();
+
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(value),
+                Some('\\') => {
+                    let escaped = match self.chars.next() {
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('u') => {
+                            let digits: String = self.chars.by_ref().take(4).collect();
+                            u32::from_str_radix(&digits, 16).ok()
+                                .and_then(::std::char::from_u32)
+                                .ok_or_else(|| format!("Invalid escape \\u{}", digits))?
+                        },
+                        Some(c) => c,
+                        None => return Err("Unterminated string".to_owned())
+                    };
+                    value.push(escaped);
+                },
+                Some(c) => value.push(c),
+                None => return Err("Unterminated string".to_owned())
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<JsonValue, String> {
+        let mut text = String::new();
+
+        while let Some(&c) = self.chars.peek() {
+            if c.is_digit(10) || c == '-' || c == '+' || c == '.' || c == 'e' || c == 'E' {
+                text.push(c);
+                self.chars.next();
+            } else {
+                break;
+            }
+        }
+
+        text.parse().map(JsonValue::Number).map_err(|_| format!("Invalid number '{}'", text))
+    }
+
+    fn keyword(&mut self, keyword: &str, value: JsonValue) -> Result<JsonValue, String> {
+        for expected in keyword.chars() {
+            if self.chars.next() != Some(expected) {
+                return Err(format!("Expected '{}'", keyword));
+            }
+        }
+
+        Ok(value)
+    }
+
+    // Consumes the next non-whitespace character if it is the one given
+    fn next_is(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+
+        if self.chars.peek() == Some(&c) {
+            self.chars.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.next_is(c) {
+            Ok(())
+        } else {
+            Err(format!("Expected '{}'", c))
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.peek().map_or(false, |c| c.is_whitespace()) {
+            self.chars.next();
+        }
+    }
+}
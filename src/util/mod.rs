@@ -1,2 +1,10 @@
 pub mod byte_access;
 pub mod color;
+// Nothing drives this yet: rewind and run-ahead also need whole-machine save
+// states, which don't exist, so those features are deferred
+#[allow(dead_code)]
+pub mod delta;
+pub mod fill_pattern;
+pub mod json;
+#[cfg(test)]
+pub mod json_value;
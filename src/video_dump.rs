@@ -0,0 +1,23 @@
+use hardware::FrameBuffer;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+// Writes each frame as raw 32-bit BGRA rows, visible lines only, for
+// encoding with an external tool
+pub struct VideoDump {
+    writer: BufWriter<File>
+}
+
+impl VideoDump {
+    pub fn new(file: File) -> VideoDump {
+        VideoDump {
+            writer: BufWriter::new(file)
+        }
+    }
+
+    pub fn write_frame(&mut self, frame_buffer: &FrameBuffer) {
+        let row_length = frame_buffer.row_length();
+        let frame_size = row_length * frame_buffer.visible_height();
+        self.writer.write_all(&frame_buffer.pixels()[..frame_size]).unwrap();
+    }
+}